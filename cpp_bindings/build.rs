@@ -1,10 +0,0 @@
- 
-// extern crate cc;
-
-fn main() {
-    cc::Build::new()
-        .include("/usr/include/libnl3")
-        .file("src/nsjail_export.cpp")
-        .cpp(true)
-        .compile("nsjail_export.a");
-}
\ No newline at end of file
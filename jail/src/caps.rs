@@ -1,28 +1,205 @@
 use super::config::JailConf;
 use super::error::Result;
 
-use sys_util::bindings::CAP_LAST_CAP;
+use sys_util::bindings::{
+    __user_cap_data_struct, __user_cap_header_struct, _LINUX_CAPABILITY_U32S_3,
+    _LINUX_CAPABILITY_VERSION_3, CAP_LAST_CAP, CAP_SETPCAP,
+};
 
 use sys_util::errno::Errno;
 
+/// Capability names, indexed by their value in <linux/capability.h>
+pub const CAP_NAMES: &[&str] = &[
+    "CAP_CHOWN",
+    "CAP_DAC_OVERRIDE",
+    "CAP_DAC_READ_SEARCH",
+    "CAP_FOWNER",
+    "CAP_FSETID",
+    "CAP_KILL",
+    "CAP_SETGID",
+    "CAP_SETUID",
+    "CAP_SETPCAP",
+    "CAP_LINUX_IMMUTABLE",
+    "CAP_NET_BIND_SERVICE",
+    "CAP_NET_BROADCAST",
+    "CAP_NET_ADMIN",
+    "CAP_NET_RAW",
+    "CAP_IPC_LOCK",
+    "CAP_IPC_OWNER",
+    "CAP_SYS_MODULE",
+    "CAP_SYS_RAWIO",
+    "CAP_SYS_CHROOT",
+    "CAP_SYS_PTRACE",
+    "CAP_SYS_PACCT",
+    "CAP_SYS_ADMIN",
+    "CAP_SYS_BOOT",
+    "CAP_SYS_NICE",
+    "CAP_SYS_RESOURCE",
+    "CAP_SYS_TIME",
+    "CAP_SYS_TTY_CONFIG",
+    "CAP_MKNOD",
+    "CAP_LEASE",
+    "CAP_AUDIT_WRITE",
+    "CAP_AUDIT_CONTROL",
+    "CAP_SETFCAP",
+    "CAP_MAC_OVERRIDE",
+    "CAP_MAC_ADMIN",
+    "CAP_SYSLOG",
+    "CAP_WAKE_ALARM",
+    "CAP_BLOCK_SUSPEND",
+    "CAP_AUDIT_READ",
+    "CAP_PERFMON",
+    "CAP_BPF",
+    "CAP_CHECKPOINT_RESTORE",
+];
+
+/// Name of a capability, e.g. CAP_NET_BIND_SERVICE for 10
+pub fn cap_name(cap: u32) -> Option<&'static str> {
+    CAP_NAMES.get(cap as usize).copied()
+}
+
+/// Value of a capability from its name, case insensitive and with or without the CAP_ prefix
+pub fn cap_from_name(name: &str) -> Option<u32> {
+    let name = name.to_ascii_uppercase();
+    let name = if name.starts_with("CAP_") {
+        name
+    } else {
+        format!("CAP_{}", name)
+    };
+    CAP_NAMES
+        .iter()
+        .position(|n| *n == name)
+        .map(|i| i as u32)
+        .filter(|cap| *cap <= CAP_LAST_CAP)
+}
+
+/// Parses a capability given either by name or by number, and checks it exists on the target kernel
+pub fn parse_cap(s: &str) -> Result<i64> {
+    let cap = match s.parse::<i64>() {
+        Ok(n) => n,
+        Err(_) => match cap_from_name(s) {
+            Some(cap) => cap as i64,
+            None => return Err(format!("unknown capability {:?}", s).into()),
+        },
+    };
+    if cap < 0 || cap > CAP_LAST_CAP as i64 {
+        return Err(format!("unknown capability {}", cap).into());
+    }
+    Ok(cap)
+}
+
+/// The effective, permitted and inheritable sets of the calling thread, one bit per capability
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CapSets {
+    pub effective: u64,
+    pub permitted: u64,
+    pub inheritable: u64,
+}
+
+impl CapSets {
+    pub fn current() -> Result<CapSets> {
+        let mut hdr = cap_header();
+        let mut data = [__user_cap_data_struct {
+            effective: 0,
+            permitted: 0,
+            inheritable: 0,
+        }; _LINUX_CAPABILITY_U32S_3 as usize];
+
+        if unsafe { libc::syscall(libc::SYS_capget, &mut hdr, data.as_mut_ptr()) } == -1 {
+            return Err(("capget", Errno::last()).into());
+        }
+
+        let join = |f: fn(&__user_cap_data_struct) -> u32| {
+            (f(&data[0]) as u64) | ((f(&data[1]) as u64) << 32)
+        };
+        Ok(CapSets {
+            effective: join(|d| d.effective),
+            permitted: join(|d| d.permitted),
+            inheritable: join(|d| d.inheritable),
+        })
+    }
+
+    pub fn apply(&self) -> Result<()> {
+        let mut hdr = cap_header();
+        let mut data = [__user_cap_data_struct {
+            effective: 0,
+            permitted: 0,
+            inheritable: 0,
+        }; _LINUX_CAPABILITY_U32S_3 as usize];
+        for (i, d) in data.iter_mut().enumerate() {
+            d.effective = (self.effective >> (32 * i)) as u32;
+            d.permitted = (self.permitted >> (32 * i)) as u32;
+            d.inheritable = (self.inheritable >> (32 * i)) as u32;
+        }
+
+        if unsafe { libc::syscall(libc::SYS_capset, &mut hdr, data.as_ptr()) } == -1 {
+            return Err(("capset", Errno::last()).into());
+        }
+        Ok(())
+    }
+
+    pub fn has_effective(&self, cap: u32) -> bool {
+        self.effective & (1 << cap) != 0
+    }
+
+    pub fn has_permitted(&self, cap: u32) -> bool {
+        self.permitted & (1 << cap) != 0
+    }
+}
+
+fn cap_header() -> __user_cap_header_struct {
+    __user_cap_header_struct {
+        version: _LINUX_CAPABILITY_VERSION_3,
+        pid: 0,
+    }
+}
+
+fn display_cap(cap: u32) -> String {
+    match cap_name(cap) {
+        Some(name) => String::from(name),
+        None => format!("capability {}", cap),
+    }
+}
+
 /**
- * For now, we make sure to explicitly drop all capabilities. If one day you wish to retain some, see nsjail caps.cc
- * Also, we may not need to do this according to https://lwn.net/Articles/532593/ which states that when a process
- * with non-zero user IDs performs an execve(), the process's capability sets are cleared.
- * See http://man7.org/linux/man-pages/man7/capabilities.7.html
+ * Leaves the jail process with exactly the capabilities in jconf.caps, or all of its permitted ones with jconf.keep_caps,
+ * in its effective, permitted, inheritable and ambient sets. The ambient set is what carries them across the execve of
+ * a process with non-zero user IDs, see http://man7.org/linux/man-pages/man7/capabilities.7.html
  *
- * According to tests, the capabilities are only removed for the process launched with execv from this thread
+ * Every other capability is also dropped from the bounding set, which needs CAP_SETPCAP. It is always held in a new
+ * user namespace, without it init_ns fails unless the bounding set has nothing more than what is retained.
  *
- * See cpp_bindings::drop_all_caps
+ * WARNING: depending on the linux kernel version, there may be less or more capabilities, you must be sure to compile toastainer for the right target
+ * or it will pose a security risk. CAP_LAST_CAP is auto-generated from <linux/capability.h> bindings on the local system.
 */
 pub fn init_ns(jconf: &JailConf) -> Result<()> {
-    if jconf.keep_caps {
-        if !cpp_bindings::keep_caps() {
-            return Err("cpp_bindings::keep_caps".into());
+    let current = CapSets::current()?;
+
+    let retain = if jconf.keep_caps {
+        current.permitted
+    } else {
+        let mut retain = 0u64;
+        for &cap in jconf.caps.iter() {
+            if cap < 0 || cap > CAP_LAST_CAP as i64 {
+                return Err(format!("unknown capability {}", cap).into());
+            }
+            let cap = cap as u32;
+            if !current.has_permitted(cap) {
+                return Err(format!(
+                    "cannot retain {}, it is not in the permitted set",
+                    display_cap(cap)
+                )
+                .into());
+            }
+            retain |= 1 << cap;
         }
-        return Ok(());
-    }
+        retain
+    };
 
+    retain_only(&current, retain)
+}
+
+fn retain_only(current: &CapSets, retain: u64) -> Result<()> {
     /*
      * Remove all capabilities from the ambient set first. It works with newer kernel versions
      * only
@@ -37,22 +214,90 @@ pub fn init_ns(jconf: &JailConf) -> Result<()> {
         )
     } == -1
     {
-        return Err(Errno::last().into());
-    } // Not useful according to test in our case
+        return Err(("prctl(PR_CAP_AMBIENT_CLEAR_ALL)", Errno::last()).into());
+    }
 
     /*
-     * A higher-level interface layered on top of this operation is provided in the libcap(3) library in the form of cap_drop_bound(3).
+     * Must happen before capset since dropping from the bounding set needs CAP_SETPCAP in the effective set.
+     * Capabilities already out of the bounding set are skipped, so that a process without CAP_SETPCAP can still
+     * run a jail as long as there is nothing left to drop.
      * See http://man7.org/linux/man-pages/man3/cap_drop_bound.3.html
-     * According to test, this operations takes ~21.49µs
-     *
-     * WARNING: depending on the linux kernel version, there may be less or more capabilities, you must be sure to compile toastainer for the right target
-     * or it will pose a security risk. CAP_LAST_CAP is auto-generated from <linux/capability.h> bindings on the local system.
      */
-    for i in 0..CAP_LAST_CAP + 1 {
-        if unsafe { libc::prctl(libc::PR_CAPBSET_DROP, i, 0, 0, 0) } == -1 {
-            return Err(Errno::last().into());
+    for cap in 0..CAP_LAST_CAP + 1 {
+        if retain & (1 << cap) != 0 || unsafe { libc::prctl(libc::PR_CAPBSET_READ, cap, 0, 0, 0) } == 0 {
+            continue;
+        }
+        if !current.has_effective(CAP_SETPCAP) {
+            return Err(format!(
+                "cannot drop {} from the bounding set without CAP_SETPCAP",
+                display_cap(cap)
+            )
+            .into());
+        }
+        if unsafe { libc::prctl(libc::PR_CAPBSET_DROP, cap, 0, 0, 0) } == -1 {
+            return Err(format!(
+                "prctl(PR_CAPBSET_DROP, {}): {}",
+                display_cap(cap),
+                Errno::last()
+            )
+            .into());
+        }
+    }
+
+    CapSets {
+        effective: retain,
+        permitted: retain,
+        inheritable: retain,
+    }
+    .apply()?;
+
+    // a capability must be both permitted and inheritable to be raised in the ambient set
+    for cap in 0..CAP_LAST_CAP + 1 {
+        if retain & (1 << cap) == 0 {
+            continue;
+        }
+        if unsafe { libc::prctl(libc::PR_CAP_AMBIENT, libc::PR_CAP_AMBIENT_RAISE, cap, 0, 0) } == -1
+        {
+            return Err(format!(
+                "prctl(PR_CAP_AMBIENT_RAISE, {}): {}",
+                display_cap(cap),
+                Errno::last()
+            )
+            .into());
         }
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cap_names() {
+        // the kernel headers the bindings were generated from may predate the newest capabilities
+        assert!(CAP_NAMES.len() as u32 > CAP_LAST_CAP);
+        assert_eq!(cap_name(0), Some("CAP_CHOWN"));
+        assert_eq!(cap_name(37), Some("CAP_AUDIT_READ"));
+        assert_eq!(cap_from_name("CAP_NET_BIND_SERVICE"), Some(10));
+        assert_eq!(cap_from_name("net_bind_service"), Some(10));
+        assert_eq!(cap_from_name("cap_sys_admin"), Some(21));
+        assert_eq!(cap_from_name("CAP_NOPE"), None);
+        assert_eq!(cap_name(CAP_SETPCAP), Some("CAP_SETPCAP"));
+
+        assert_eq!(parse_cap("CAP_NET_RAW").unwrap(), 13);
+        assert_eq!(parse_cap("13").unwrap(), 13);
+        assert!(parse_cap("-1").is_err());
+        assert!(parse_cap(&(CAP_LAST_CAP + 1).to_string()).is_err());
+        assert!(parse_cap("CAP_NOPE").is_err());
+    }
+
+    #[test]
+    fn capget_capset_roundtrip() {
+        let current = CapSets::current().unwrap();
+        assert_eq!(current.effective & !current.permitted, 0);
+        current.apply().unwrap();
+        assert_eq!(CapSets::current().unwrap(), current);
+    }
+}
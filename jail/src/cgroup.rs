@@ -0,0 +1,352 @@
+// Detection of the cgroup hierarchies of the host and a resource limits API on top of cgroupv1.rs and cgroupv2.rs, so
+// that callers do not have to know whether the host runs cgroup v1, v2, or both in hybrid mode
+
+use super::cgroupv2;
+use super::config::JailConf;
+use super::error::Result;
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::OnceLock;
+
+const MOUNTINFO: &str = "/proc/self/mountinfo";
+
+// the v1 controllers JailConf has limits for, and the freezer
+const V1_CONTROLLERS: [&str; 5] = ["memory", "pids", "cpu", "net_cls", "freezer"];
+
+static HOST: OnceLock<CgroupHost> = OnceLock::new();
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CgroupMode {
+    /// no cgroup filesystem is mounted, jails cannot get resource limits
+    Unavailable,
+    V1,
+    V2,
+    /// controllers are mounted as v1 and a v2 hierarchy is mounted too, usually /sys/fs/cgroup/unified. Each controller
+    /// is bound to only one of them
+    Hybrid,
+}
+
+/// The cgroup hierarchies of the host, see host()
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CgroupHost {
+    pub mode: CgroupMode,
+    pub v2_mount: Option<String>,
+    pub v2_controllers: Vec<String>, // the cgroup.controllers of the v2 root
+    pub v1_mounts: HashMap<String, String>, // controller name to the mount point of its v1 hierarchy
+}
+
+/**
+ * Resource limits of a jail, whatever the cgroup version of the host, see apply_limits. 0 means no limit. net_cls only
+ * exists in cgroup v1
+ */
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ResourceLimits {
+    pub mem_max: u64, // bytes
+    pub pids_max: u64,
+    pub cpu_ms_per_sec: u64,
+    pub net_cls_classid: u64,
+}
+
+/// The cgroup hierarchies of the host, detected on first use. init_package detects them at init
+pub fn host() -> &'static CgroupHost {
+    HOST.get_or_init(|| {
+        let mountinfo = fs::read_to_string(MOUNTINFO).unwrap_or_default();
+        let mut host = parse_mountinfo(&mountinfo);
+        if let Some(ref mount) = host.v2_mount {
+            host.v2_controllers = fs::read_to_string(Path::new(mount).join("cgroup.controllers"))
+                .unwrap_or_default()
+                .split_whitespace()
+                .map(String::from)
+                .collect();
+        }
+        host
+    })
+}
+
+/// Sets the limits of jconf and the cgroup backend that can enforce them on this host, see CgroupHost::select_backend
+pub fn apply_limits(jconf: &mut JailConf<'_>, limits: &ResourceLimits) -> Result<()> {
+    jconf.cgroup_mem_max = limits.mem_max;
+    jconf.cgroup_pids_max = limits.pids_max;
+    jconf.cgroup_cpu_ms_per_sec = limits.cpu_ms_per_sec;
+    jconf.cgroup_net_cls_classid = limits.net_cls_classid;
+    host().select_backend(jconf)
+}
+
+/// Whether jconf sets a cgroup limit or asks for the freezer, which all need a cgroup created by init_parent
+pub fn has_limits(jconf: &JailConf) -> bool {
+    cgroupv2::has_cgroup(jconf) || jconf.cgroup_net_cls_classid != 0
+}
+
+/// Fails when jconf sets cgroup limits for a jail without a cgroup namespace, init_parent only sets up the cgroups of
+/// jails that have one so the limits would silently not apply
+pub fn check_namespace(jconf: &JailConf) -> Result<()> {
+    if has_limits(jconf) && !jconf.clone_newcgroup {
+        return Err("cgroup limits are set but the cgroup namespace is disabled".into());
+    }
+    Ok(())
+}
+
+/// The cgroup v1 and v2 mounts of a /proc/self/mountinfo, v2_controllers is left empty
+pub fn parse_mountinfo(mountinfo: &str) -> CgroupHost {
+    let mut v2_mount = None;
+    let mut v1_mounts = HashMap::new();
+
+    for line in mountinfo.lines() {
+        // <id> <parent> <major:minor> <root> <mount point> <options> [optional fields] - <fstype> <source> <super options>
+        let mut halves = line.splitn(2, " - ");
+        let (mount, fs) = match (halves.next(), halves.next()) {
+            (Some(mount), Some(fs)) => (mount, fs),
+            _ => continue,
+        };
+        let mount_point = match mount.split_whitespace().nth(4) {
+            Some(m) => m,
+            None => continue,
+        };
+        let mut fs = fs.split_whitespace();
+        match (fs.next(), fs.nth(1)) {
+            (Some("cgroup2"), _) if v2_mount.is_none() => {
+                v2_mount = Some(mount_point.to_string());
+            }
+            (Some("cgroup"), Some(options)) => {
+                for opt in options.split(',') {
+                    if V1_CONTROLLERS.contains(&opt) {
+                        v1_mounts
+                            .entry(opt.to_string())
+                            .or_insert_with(|| mount_point.to_string());
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mode = match (v2_mount.is_some(), !v1_mounts.is_empty()) {
+        (false, false) => CgroupMode::Unavailable,
+        (false, true) => CgroupMode::V1,
+        (true, false) => CgroupMode::V2,
+        (true, true) => CgroupMode::Hybrid,
+    };
+    CgroupHost {
+        mode,
+        v2_mount,
+        v2_controllers: vec![],
+        v1_mounts,
+    }
+}
+
+impl CgroupHost {
+    /// Whether jails can be frozen on this host, by the cgroup.freeze of any v2 cgroup or by the v1 freezer controller
+    pub fn has_freezer(&self) -> bool {
+        self.v2_mount.is_some() || self.v1_mounts.contains_key("freezer")
+    }
+
+    /**
+     * Points jconf at the hierarchy that has every controller its limits need, v2 when both could do: sets
+     * use_cgroupv2 and the mount points. The jail must have a cgroup namespace, see check_namespace. cgroup_freezer
+     * counts as a limit that both can enforce. The limits only available in v2, such as io_max, rule out v1, and
+     * net_cls_classid rules out v2
+     */
+    pub fn select_backend<'a>(&'a self, jconf: &mut JailConf<'a>) -> Result<()> {
+        let v2_ctrls = cgroupv2::controllers(jconf);
+        let mut v1_ctrls = vec![];
+        for (ctrl, limit) in [
+            ("memory", jconf.cgroup_mem_max),
+            ("pids", jconf.cgroup_pids_max),
+            ("cpu", jconf.cgroup_cpu_ms_per_sec),
+            ("net_cls", jconf.cgroup_net_cls_classid),
+        ]
+        .iter()
+        {
+            if *limit != 0 {
+                v1_ctrls.push(*ctrl);
+            }
+        }
+        // every v2 cgroup has cgroup.freeze, v1 needs the freezer controller
+        if jconf.cgroup_freezer {
+            v1_ctrls.push("freezer");
+        }
+        if v1_ctrls.is_empty() && v2_ctrls.is_empty() {
+            return Ok(());
+        }
+        check_namespace(jconf)?;
+
+        let v2_only_limits = [
+            jconf.cgroup_mem_high != 0,
+            jconf.cgroup_mem_swap_max >= 0,
+            jconf.cgroup_io_weight != 0,
+            !jconf.cgroup_io_max.is_empty(),
+            !jconf.cgroup_cpuset_cpus.is_empty(),
+            !jconf.cgroup_cpuset_mems.is_empty(),
+            !jconf.cgroup_hugetlb_max.is_empty(),
+        ]
+        .iter()
+        .any(|set| *set);
+
+        let mut v2_missing: Vec<&str> = v2_ctrls
+            .iter()
+            .filter(|c| !self.v2_controllers.iter().any(|have| have == *c))
+            .cloned()
+            .collect();
+        if jconf.cgroup_net_cls_classid != 0 {
+            v2_missing.push("net_cls");
+        }
+        if let (Some(mount), true) = (self.v2_mount.as_ref(), v2_missing.is_empty()) {
+            jconf.use_cgroupv2 = true;
+            jconf.cgroupv2_mount = mount;
+            return Ok(());
+        }
+
+        let v1_missing: Vec<&str> = v1_ctrls
+            .iter()
+            .filter(|c| !self.v1_mounts.contains_key(**c))
+            .cloned()
+            .collect();
+        if !v2_only_limits && v1_missing.is_empty() {
+            jconf.use_cgroupv2 = false;
+            for ctrl in v1_ctrls.iter() {
+                let mount = self.v1_mounts[*ctrl].as_str();
+                match *ctrl {
+                    "memory" => jconf.cgroup_mem_mount = mount,
+                    "pids" => jconf.cgroup_pids_mount = mount,
+                    "cpu" => jconf.cgroup_cpu_mount = mount,
+                    "freezer" => jconf.cgroup_freezer_mount = mount,
+                    _ => jconf.cgroup_net_cls_mount = mount,
+                }
+            }
+            return Ok(());
+        }
+
+        Err(format!(
+            "no cgroup hierarchy of this {:?} host has all the controllers the jail limits need: {:?} missing from v2, {}",
+            self.mode,
+            v2_missing,
+            if v2_only_limits {
+                String::from("v1 cannot enforce the cgroup v2 only limits")
+            } else {
+                format!("{:?} missing from v1", v1_missing)
+            }
+        )
+        .into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HYBRID: &str = "\
+25 30 0:23 / /sys rw,nosuid,nodev,noexec,relatime shared:7 - sysfs sysfs rw
+26 25 0:24 / /sys/fs/cgroup ro,nosuid,nodev,noexec shared:8 - tmpfs tmpfs ro,mode=755
+27 26 0:25 / /sys/fs/cgroup/unified rw,nosuid,nodev,noexec,relatime shared:9 - cgroup2 cgroup2 rw,nsdelegate
+28 26 0:26 / /sys/fs/cgroup/systemd rw,nosuid,nodev,noexec,relatime shared:10 - cgroup cgroup rw,xattr,name=systemd
+31 26 0:29 / /sys/fs/cgroup/memory rw,nosuid,nodev,noexec,relatime shared:14 - cgroup cgroup rw,memory
+32 26 0:30 / /sys/fs/cgroup/cpu,cpuacct rw,nosuid,nodev,noexec,relatime shared:15 - cgroup cgroup rw,cpu,cpuacct
+33 26 0:31 / /sys/fs/cgroup/pids rw,nosuid,nodev,noexec,relatime shared:16 - cgroup cgroup rw,pids
+";
+
+    #[test]
+    fn test_parse_mountinfo() {
+        let host = parse_mountinfo(HYBRID);
+        assert_eq!(host.mode, CgroupMode::Hybrid);
+        assert_eq!(host.v2_mount.as_deref(), Some("/sys/fs/cgroup/unified"));
+        assert_eq!(host.v1_mounts["cpu"], "/sys/fs/cgroup/cpu,cpuacct");
+        assert!(!host.v1_mounts.contains_key("net_cls"));
+        assert!(host.has_freezer());
+
+        let v2 = parse_mountinfo(
+            "30 23 0:26 / /sys/fs/cgroup rw,nosuid shared:4 - cgroup2 cgroup2 rw,nsdelegate\n",
+        );
+        assert_eq!(v2.mode, CgroupMode::V2);
+        assert_eq!(parse_mountinfo("").mode, CgroupMode::Unavailable);
+        assert!(!parse_mountinfo("").has_freezer());
+        let v1 = parse_mountinfo(
+            "31 26 0:29 / /sys/fs/cgroup/memory rw,nosuid shared:14 - cgroup cgroup rw,memory\n",
+        );
+        assert!(!v1.has_freezer());
+    }
+
+    #[test]
+    fn test_select_backend() {
+        let mut host = parse_mountinfo(HYBRID);
+        host.v2_controllers = vec![String::from("hugetlb")];
+
+        let limits = |jconf: &mut JailConf| {
+            jconf.cgroup_mem_max = 1 << 26;
+            jconf.cgroup_cpu_ms_per_sec = 500;
+            jconf.clone_newcgroup = true;
+        };
+
+        // the hybrid v2 hierarchy does not have the memory and cpu controllers
+        let mut jconf = JailConf::default();
+        limits(&mut jconf);
+        host.select_backend(&mut jconf).unwrap();
+        assert!(!jconf.use_cgroupv2);
+        assert_eq!(jconf.cgroup_cpu_mount, "/sys/fs/cgroup/cpu,cpuacct");
+
+        let mut jconf = JailConf::default();
+        limits(&mut jconf);
+        jconf.cgroup_net_cls_classid = 0x10001;
+        assert!(host.select_backend(&mut jconf).is_err());
+
+        let mut jconf = JailConf {
+            cgroup_hugetlb_max: vec![(String::from("2MB"), 0)],
+            clone_newcgroup: true,
+            ..Default::default()
+        };
+        host.select_backend(&mut jconf).unwrap();
+        assert!(jconf.use_cgroupv2);
+        assert_eq!(jconf.cgroupv2_mount, "/sys/fs/cgroup/unified");
+
+        host.v2_controllers = ["cpu", "memory", "pids"]
+            .iter()
+            .map(|c| c.to_string())
+            .collect();
+        let mut jconf = JailConf::default();
+        limits(&mut jconf);
+        host.select_backend(&mut jconf).unwrap();
+        assert!(jconf.use_cgroupv2);
+
+        // limits without a cgroup namespace would not apply, the namespace config is left alone
+        let mut jconf = JailConf::default();
+        limits(&mut jconf);
+        jconf.clone_newcgroup = false;
+        let err = host.select_backend(&mut jconf).unwrap_err();
+        assert!(err.to_string().contains("cgroup namespace"), "{}", err);
+        assert!(!jconf.clone_newcgroup);
+        assert!(check_namespace(&jconf).is_err());
+        assert!(check_namespace(&JailConf::default()).is_ok());
+
+        let mut jconf = JailConf::default();
+        host.select_backend(&mut jconf).unwrap();
+        assert!(!jconf.clone_newcgroup);
+
+        // the freezer alone, the hybrid v2 hierarchy can freeze too
+        let mut jconf = JailConf {
+            cgroup_freezer: true,
+            clone_newcgroup: true,
+            ..Default::default()
+        };
+        host.select_backend(&mut jconf).unwrap();
+        assert!(jconf.use_cgroupv2);
+
+        host.v2_mount = None;
+        let mut jconf = JailConf {
+            cgroup_freezer: true,
+            clone_newcgroup: true,
+            ..Default::default()
+        };
+        assert!(host.select_backend(&mut jconf).is_err());
+        host.v1_mounts
+            .insert(String::from("freezer"), String::from("/sys/fs/cgroup/freezer"));
+        let mut jconf = JailConf {
+            cgroup_freezer: true,
+            clone_newcgroup: true,
+            ..Default::default()
+        };
+        host.select_backend(&mut jconf).unwrap();
+        assert!(!jconf.use_cgroupv2);
+        assert_eq!(jconf.cgroup_freezer_mount, "/sys/fs/cgroup/freezer");
+    }
+}
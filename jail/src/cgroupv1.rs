@@ -73,6 +73,13 @@ impl<'a> JailConf<'a> {
         cpu_cgroup_path.push(get_folder_from_pid(pid));
         cpu_cgroup_path
     }
+
+    pub fn get_freezer_cgroup_v1_path(&self, pid: &str) -> PathBuf {
+        let mut freezer_cgroup_path = PathBuf::from_str(self.cgroup_freezer_mount).unwrap();
+        freezer_cgroup_path.push(self.cgroup_freezer_parent);
+        freezer_cgroup_path.push(get_folder_from_pid(pid));
+        freezer_cgroup_path
+    }
 }
 
 pub fn init_ns_from_parent_mem(jconf: &JailConf, pid: &str) -> Result<()> {
@@ -299,25 +306,63 @@ pub fn add_child_from_parent_cpu(jconf: &JailConf, cgroup_id: &str, pid: &str) -
     add_pid_to_task_list(&mut cpu_cgroup_path, pid)
 }
 
+pub fn init_ns_from_parent_freezer(jconf: &JailConf, pid: &str) -> Result<()> {
+    if !jconf.cgroup_freezer {
+        return Ok(());
+    }
+
+    let mut freezer_cgroup_path = jconf.get_freezer_cgroup_v1_path(pid);
+
+    // unlike the limits, the freezer is wanted by default by the scheduler, so the parent is created if missing
+    create_cgroup(&freezer_cgroup_path.parent().unwrap())?;
+    create_cgroup(&freezer_cgroup_path)?;
+
+    add_pid_to_task_list(&mut freezer_cgroup_path, pid)
+}
+
+pub fn create_ns_from_parent_freezer(jconf: &JailConf, id: &str) -> Result<()> {
+    if !jconf.cgroup_freezer {
+        return Ok(());
+    }
+
+    let freezer_cgroup_path = jconf.get_freezer_cgroup_v1_path(id);
+
+    create_cgroup(&freezer_cgroup_path.parent().unwrap())?;
+    create_cgroup(&freezer_cgroup_path)
+}
+
+pub fn add_child_from_parent_freezer(jconf: &JailConf, cgroup_id: &str, pid: &str) -> Result<()> {
+    if !jconf.cgroup_freezer {
+        return Ok(());
+    }
+
+    let mut freezer_cgroup_path = jconf.get_freezer_cgroup_v1_path(cgroup_id);
+
+    add_pid_to_task_list(&mut freezer_cgroup_path, pid)
+}
+
 pub fn init_ns_from_parent(jconf: &JailConf, pid: &str) -> Result<()> {
     init_ns_from_parent_mem(jconf, pid)?;
     init_ns_from_parent_pids(jconf, pid)?;
     init_ns_from_parent_net_cls(jconf, pid)?;
-    init_ns_from_parent_cpu(jconf, pid)
+    init_ns_from_parent_cpu(jconf, pid)?;
+    init_ns_from_parent_freezer(jconf, pid)
 }
 
 pub fn create_ns_from_parent(jconf: &JailConf, cgroup_id: &str) -> Result<()> {
     create_ns_from_parent_mem(jconf, cgroup_id)?;
     create_ns_from_parent_pids(jconf, cgroup_id)?;
     create_ns_from_parent_net_cls(jconf, cgroup_id)?;
-    create_ns_from_parent_cpu(jconf, cgroup_id)
+    create_ns_from_parent_cpu(jconf, cgroup_id)?;
+    create_ns_from_parent_freezer(jconf, cgroup_id)
 }
 
 pub fn add_child_from_parent(jconf: &JailConf, cgroup_id: &str, pid: &str) -> Result<()> {
     add_child_from_parent_mem(jconf, cgroup_id, pid)?;
     add_child_from_parent_pids(jconf, cgroup_id, pid)?;
     add_child_from_parent_net_cls(jconf, cgroup_id, pid)?;
-    add_child_from_parent_cpu(jconf, cgroup_id, pid)
+    add_child_from_parent_cpu(jconf, cgroup_id, pid)?;
+    add_child_from_parent_freezer(jconf, cgroup_id, pid)
 }
 
 pub fn join_ns(fd: i32) -> Result<()> {
@@ -361,6 +406,11 @@ pub fn finish_from_parent(jconf: &JailConf, pid: &str) -> Result<()> {
         remove_cgroup(&pat)?;
     }
 
+    if jconf.cgroup_freezer {
+        let pat = jconf.get_freezer_cgroup_v1_path(pid);
+        remove_cgroup(&pat)?;
+    }
+
     Ok(())
 }
 
@@ -7,19 +7,24 @@ Better than cgroupv1, but need to:
 */
 
 
-use super::config::JailConf;
+use super::config::{CgroupIoMax, JailConf};
+use super::error;
 use super::utils::{get_folder_from_pid, mkdir, write_buf_to_file};
 use utils::filepath::path_to_bytes;
 
 use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
 use sys_util::SyscallReturnCode;
 
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 use std::str::FromStr;
 
 impl<'a> JailConf<'a> {
     pub fn get_cgroup_v2_path(&self, pid: &str) -> PathBuf {
         let mut cgroup_path = PathBuf::from_str(self.cgroupv2_mount).unwrap();
+        cgroup_path.push(self.cgroupv2_parent);
         cgroup_path.push(get_folder_from_pid(pid));
         cgroup_path
     }
@@ -62,66 +67,387 @@ pub fn remove_cgroup<T: AsRef<Path>>(cgroup_path: &T) -> Result<(), std::io::Err
     SyscallReturnCode(unsafe { libc::rmdir(folder_name.as_ptr()) }).into_empty_result()
 }
 
-pub fn init_ns_from_parent_mem(jconf: &JailConf, pid: &str) -> Result<(), std::io::Error> {
-    if jconf.cgroup_mem_max == 0 {
-        return Ok(());
+/// The controllers the limits of jconf need, an empty list means that the jail gets no cgroup
+pub fn controllers(jconf: &JailConf) -> Vec<&'static str> {
+    let mut ctrls = vec![];
+    if jconf.cgroup_mem_max != 0 || jconf.cgroup_mem_high != 0 || jconf.cgroup_mem_swap_max >= 0 {
+        ctrls.push("memory");
+    }
+    if jconf.cgroup_pids_max != 0 {
+        ctrls.push("pids");
+    }
+    if jconf.cgroup_cpu_ms_per_sec != 0 {
+        ctrls.push("cpu");
+    }
+    if jconf.cgroup_io_weight != 0 || !jconf.cgroup_io_max.is_empty() {
+        ctrls.push("io");
     }
+    if !jconf.cgroup_cpuset_cpus.is_empty() || !jconf.cgroup_cpuset_mems.is_empty() {
+        ctrls.push("cpuset");
+    }
+    if !jconf.cgroup_hugetlb_max.is_empty() {
+        ctrls.push("hugetlb");
+    }
+    ctrls
+}
 
-    let mut cgroup_path = jconf.get_cgroup_v2_path(pid);
+/// Whether the jail gets a cgroup: for its limits, or so that it can be frozen, see freezer.rs
+pub fn has_cgroup(jconf: &JailConf) -> bool {
+    jconf.cgroup_freezer || !controllers(jconf).is_empty()
+}
 
-    create_cgroup(&cgroup_path)?;
-    add_pid_to_proc_list(&mut cgroup_path, pid)?;
-    write_to_cgroup(
-        &mut cgroup_path,
-        "memory.max",
-        &jconf.cgroup_mem_max.to_string(),
-    )
+/**
+ * Creates cgroupv2_parent and delegates ctrls down to it: a cgroup v2 controller can only be used in a cgroup if it is
+ * enabled in the cgroup.subtree_control of every ancestor. No process must live in cgroupv2_parent or in the cgroups
+ * between it and the mount point, the kernel refuses to enable controllers in a cgroup that has some
+ */
+pub fn enable_controllers(jconf: &JailConf, ctrls: &[&str]) -> Result<(), std::io::Error> {
+    let enable = ctrls
+        .iter()
+        .map(|c| format!("+{}", c))
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    let mut cgroup_path = PathBuf::from_str(jconf.cgroupv2_mount).unwrap();
+    let subtree_control = |cgroup_path: &mut PathBuf| {
+        let dir = cgroup_path.display().to_string();
+        write_to_cgroup(cgroup_path, "cgroup.subtree_control", &enable).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!(
+                    "could not enable {:?} in {}/cgroup.subtree_control: {}",
+                    enable, dir, e
+                ),
+            )
+        })
+    };
+
+    // cgroup.freeze needs no controller, a jail without limits only needs the cgroups to exist
+    if !ctrls.is_empty() {
+        subtree_control(&mut cgroup_path)?;
+    }
+    for component in Path::new(jconf.cgroupv2_parent).components() {
+        if let Component::Normal(name) = component {
+            cgroup_path.push(name);
+            create_cgroup(&cgroup_path)?;
+            if !ctrls.is_empty() {
+                subtree_control(&mut cgroup_path)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn init_ns_from_parent_mem(
+    jconf: &JailConf,
+    cgroup_path: &mut PathBuf,
+) -> Result<(), std::io::Error> {
+    if jconf.cgroup_mem_max != 0 {
+        write_to_cgroup(cgroup_path, "memory.max", &jconf.cgroup_mem_max.to_string())?;
+    }
+    if jconf.cgroup_mem_high != 0 {
+        write_to_cgroup(
+            cgroup_path,
+            "memory.high",
+            &jconf.cgroup_mem_high.to_string(),
+        )?;
+    }
+    if jconf.cgroup_mem_swap_max >= 0 {
+        write_to_cgroup(
+            cgroup_path,
+            "memory.swap.max",
+            &jconf.cgroup_mem_swap_max.to_string(),
+        )?;
+    }
+    Ok(())
 }
 
-pub fn init_ns_from_parent_pids(jconf: &JailConf, pid: &str) -> Result<(), std::io::Error> {
+pub fn init_ns_from_parent_pids(
+    jconf: &JailConf,
+    cgroup_path: &mut PathBuf,
+) -> Result<(), std::io::Error> {
     if jconf.cgroup_pids_max == 0 {
         return Ok(());
     }
 
-    let mut cgroup_path = jconf.get_cgroup_v2_path(pid);
-
-    create_cgroup(&cgroup_path)?;
-    add_pid_to_proc_list(&mut cgroup_path, pid)?;
-    write_to_cgroup(
-        &mut cgroup_path,
-        "pids.max",
-        &jconf.cgroup_pids_max.to_string(),
-    )
+    write_to_cgroup(cgroup_path, "pids.max", &jconf.cgroup_pids_max.to_string())
 }
 
-pub fn init_ns_from_parent_cpu(jconf: &JailConf, pid: &str) -> Result<(), std::io::Error> {
+pub fn init_ns_from_parent_cpu(
+    jconf: &JailConf,
+    cgroup_path: &mut PathBuf,
+) -> Result<(), std::io::Error> {
     if jconf.cgroup_cpu_ms_per_sec == 0 {
         return Ok(());
     }
 
-    let mut cgroup_path = jconf.get_cgroup_v2_path(pid);
-
-    create_cgroup(&cgroup_path)?;
-    add_pid_to_proc_list(&mut cgroup_path, pid)?;
-
     // The maximum bandwidth limit in the format: `$MAX $PERIOD`.
     // This indicates that the group may consume up to $MAX in each $PERIOD
     // duration.
     let val = format!("{} 1000000", jconf.cgroup_cpu_ms_per_sec * 1000);
 
-    write_to_cgroup(&mut cgroup_path, "cpu.max", &val)
+    write_to_cgroup(cgroup_path, "cpu.max", &val)
+}
+
+pub fn init_ns_from_parent_io(
+    jconf: &JailConf,
+    cgroup_path: &mut PathBuf,
+) -> Result<(), std::io::Error> {
+    if jconf.cgroup_io_weight != 0 {
+        // the default weight of the cgroup, per device weights are written "$MAJ:$MIN $WEIGHT"
+        write_to_cgroup(
+            cgroup_path,
+            "io.weight",
+            &format!("default {}", jconf.cgroup_io_weight),
+        )?;
+    }
+    // one line per device, the kernel only takes one at a time
+    for io_max in jconf.cgroup_io_max.iter() {
+        write_to_cgroup(cgroup_path, "io.max", &io_max_line(io_max))?;
+    }
+    Ok(())
+}
+
+pub fn init_ns_from_parent_cpuset(
+    jconf: &JailConf,
+    cgroup_path: &mut PathBuf,
+) -> Result<(), std::io::Error> {
+    if !jconf.cgroup_cpuset_cpus.is_empty() {
+        write_to_cgroup(cgroup_path, "cpuset.cpus", jconf.cgroup_cpuset_cpus)?;
+    }
+    if !jconf.cgroup_cpuset_mems.is_empty() {
+        write_to_cgroup(cgroup_path, "cpuset.mems", jconf.cgroup_cpuset_mems)?;
+    }
+    Ok(())
+}
+
+pub fn init_ns_from_parent_hugetlb(
+    jconf: &JailConf,
+    cgroup_path: &mut PathBuf,
+) -> Result<(), std::io::Error> {
+    for (page_size, max) in jconf.cgroup_hugetlb_max.iter() {
+        write_to_cgroup(
+            cgroup_path,
+            &format!("hugetlb.{}.max", page_size),
+            &max.to_string(),
+        )?;
+    }
+    Ok(())
 }
 
 pub fn init_ns_from_parent(jconf: &JailConf, pid: &str) -> Result<(), std::io::Error> {
-    init_ns_from_parent_mem(jconf, pid)?;
-    init_ns_from_parent_pids(jconf, pid)?;
-    init_ns_from_parent_cpu(jconf, pid)
+    if !has_cgroup(jconf) {
+        return Ok(());
+    }
+    let ctrls = controllers(jconf);
+
+    enable_controllers(jconf, &ctrls)?;
+
+    let mut cgroup_path = jconf.get_cgroup_v2_path(pid);
+    create_cgroup(&cgroup_path)?;
+
+    // the limits are in place before the child is moved in
+    init_ns_from_parent_mem(jconf, &mut cgroup_path)?;
+    init_ns_from_parent_pids(jconf, &mut cgroup_path)?;
+    init_ns_from_parent_cpu(jconf, &mut cgroup_path)?;
+    init_ns_from_parent_io(jconf, &mut cgroup_path)?;
+    init_ns_from_parent_cpuset(jconf, &mut cgroup_path)?;
+    init_ns_from_parent_hugetlb(jconf, &mut cgroup_path)?;
+
+    add_pid_to_proc_list(&mut cgroup_path, pid)
 }
 
 pub fn finish_from_parent(jconf: &JailConf, pid: &str) -> Result<(), std::io::Error> {
-    if jconf.cgroup_cpu_ms_per_sec != 0 || jconf.cgroup_pids_max != 0 || jconf.cgroup_mem_max != 0 {
-        remove_cgroup(&jconf.get_cgroup_v2_path(pid))?;
+    if !has_cgroup(jconf) {
+        return Ok(());
+    }
+
+    if jconf.cgroup_pids_max != 0 {
+        match read_pids_events_max(jconf, pid) {
+            Ok(n) if n > 0 => println!(
+                "pid={}: {} forks failed because of pids.max={}",
+                pid, n, jconf.cgroup_pids_max
+            ),
+            _ => {}
+        }
+    }
+
+    remove_cgroup(&jconf.get_cgroup_v2_path(pid))
+}
+
+/// The number of times a fork of the jail failed because of pids.max, to call before finish_from_parent
+pub fn read_pids_events_max(jconf: &JailConf, pid: &str) -> Result<u64, std::io::Error> {
+    let mut path = jconf.get_cgroup_v2_path(pid);
+    path.push("pids.events");
+    let content = fs::read_to_string(&path)?;
+    Ok(parse_flat_keyed(&content, "max").unwrap_or(0))
+}
+
+/// Value of key in a cgroup v2 flat keyed file, such as pids.events or memory.events, made of "<key> <value>" lines
+pub fn parse_flat_keyed(content: &str, key: &str) -> Option<u64> {
+    content.lines().find_map(|line| {
+        let mut words = line.split_whitespace();
+        match (words.next(), words.next()) {
+            (Some(k), Some(v)) if k == key => v.parse().ok(),
+            _ => None,
+        }
+    })
+}
+
+fn io_max_line(io_max: &CgroupIoMax) -> String {
+    let mut line = format!("{}:{}", io_max.major, io_max.minor);
+    for (key, val) in [
+        ("rbps", io_max.rbps),
+        ("wbps", io_max.wbps),
+        ("riops", io_max.riops),
+        ("wiops", io_max.wiops),
+    ]
+    .iter()
+    {
+        if *val != 0 {
+            line.push_str(&format!(" {}={}", key, val));
+        }
     }
+    line
+}
+
+/**
+ * Parses "<device>[,rbps=<n>][,wbps=<n>][,riops=<n>][,wiops=<n>]", bytes and operations per second. The device is a
+ * block device path such as /dev/sda, or its "<major>:<minor>" numbers
+ */
+pub fn parse_io_max(s: &str) -> error::Result<CgroupIoMax> {
+    let mut parts = s.split(',');
+    let device = parts.next().unwrap_or("");
+    let (major, minor) = device_numbers(device)?;
+
+    let mut io_max = CgroupIoMax {
+        major,
+        minor,
+        rbps: 0,
+        wbps: 0,
+        riops: 0,
+        wiops: 0,
+    };
+    for part in parts {
+        let mut kv = part.splitn(2, '=');
+        let (key, val) = (kv.next().unwrap_or(""), kv.next().unwrap_or(""));
+        let val: u64 = match val.parse() {
+            Ok(v) if v > 0 => v,
+            _ => {
+                return Err(format!(
+                    "invalid io.max {:?}: {:?} must be a positive number",
+                    s, key
+                )
+                .into())
+            }
+        };
+        match key {
+            "rbps" => io_max.rbps = val,
+            "wbps" => io_max.wbps = val,
+            "riops" => io_max.riops = val,
+            "wiops" => io_max.wiops = val,
+            _ => {
+                return Err(format!(
+                    "invalid io.max {:?}: unknown limit {:?}, expected rbps, wbps, riops or wiops",
+                    s, key
+                )
+                .into())
+            }
+        }
+    }
+
+    if io_max.rbps == 0 && io_max.wbps == 0 && io_max.riops == 0 && io_max.wiops == 0 {
+        return Err(format!("invalid io.max {:?}: no limit", s).into());
+    }
+    Ok(io_max)
+}
+
+fn device_numbers(device: &str) -> error::Result<(u32, u32)> {
+    let mut nums = device.splitn(2, ':');
+    if let (Some(major), Some(minor)) = (nums.next(), nums.next()) {
+        if let (Ok(major), Ok(minor)) = (major.parse(), minor.parse()) {
+            return Ok((major, minor));
+        }
+    }
+
+    let meta =
+        fs::metadata(device).map_err(|e| format!("invalid io.max device {:?}: {}", device, e))?;
+    if !meta.file_type().is_block_device() {
+        return Err(format!("invalid io.max device {:?}: not a block device", device).into());
+    }
+    let rdev = meta.rdev();
+    Ok((libc::major(rdev), libc::minor(rdev)))
+}
 
+/// hugetlb page sizes are written like in the names of the hugetlb.<page size>.max files, e.g. "2MB" or "1GB"
+pub fn check_hugetlb_page_size(size: &str) -> error::Result<()> {
+    let digits = size.trim_end_matches(['K', 'M', 'G', 'B']);
+    let unit = &size[digits.len()..];
+    if digits.is_empty()
+        || !digits.bytes().all(|b| b.is_ascii_digit())
+        || !["KB", "MB", "GB"].contains(&unit)
+    {
+        return Err(format!(
+            "invalid hugetlb page size {:?}, expected e.g. 2MB or 1GB",
+            size
+        )
+        .into());
+    }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_io_max() {
+        let io_max = parse_io_max("8:16,rbps=1048576,wiops=100").unwrap();
+        assert_eq!((io_max.major, io_max.minor), (8, 16));
+        assert_eq!(io_max.rbps, 1048576);
+        assert_eq!(io_max.wiops, 100);
+        assert_eq!(io_max_line(&io_max), "8:16 rbps=1048576 wiops=100");
+
+        for bad in [
+            "8:16",
+            "8:16,rbps=0",
+            "8:16,bps=10",
+            "/dev/null,rbps=10",
+            "/nonexistent,rbps=10",
+        ]
+        .iter()
+        {
+            assert!(parse_io_max(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_controllers() {
+        let mut jconf = JailConf::default();
+        assert!(controllers(&jconf).is_empty());
+
+        jconf.cgroup_mem_swap_max = 0;
+        jconf.cgroup_io_weight = 100;
+        jconf.cgroup_cpuset_cpus = "0-1";
+        assert_eq!(controllers(&jconf), vec!["memory", "io", "cpuset"]);
+        assert_eq!(
+            jconf.get_cgroup_v2_path("42"),
+            PathBuf::from("/sys/fs/cgroup/TOASTAINER/TOASTAINER.42")
+        );
+    }
+
+    #[test]
+    fn test_flat_keyed_and_page_size() {
+        assert_eq!(parse_flat_keyed("max 3\n", "max"), Some(3));
+        assert_eq!(
+            parse_flat_keyed("low 0\nhigh 2\nmax 0\noom 1\n", "oom"),
+            Some(1)
+        );
+        assert_eq!(parse_flat_keyed("", "max"), None);
+
+        assert!(check_hugetlb_page_size("2MB").is_ok());
+        assert!(check_hugetlb_page_size("1GB").is_ok());
+        assert!(check_hugetlb_page_size("2M").is_err());
+        assert!(check_hugetlb_page_size("MB").is_err());
+    }
+}
@@ -1,10 +1,13 @@
 use std::ffi::{CStr, CString};
-use std::time::SystemTime;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::{Duration, Instant, SystemTime};
 
-use seccomp::BpfProgram;
+use seccomp::learn::Recorder;
+use seccomp::{BpfProgram, SeccompFilter};
 
 use super::error::Result;
 use super::rlimit;
+use super::sandbox::SeccompViolation;
 
 pub const NSSIGS: [libc::c_int; 8] = [
     libc::SIGINT,
@@ -17,6 +20,9 @@ pub const NSSIGS: [libc::c_int; 8] = [
     libc::SIGTTOU,
 ];
 
+/// seconds a jail gets to exit after the SIGTERM sent when its tlimit is reached
+pub const DEFAULT_TLIMIT_GRACE: u64 = 2;
+
 pub static mut PID: u32 = 0;
 
 // TODO: check that using the same pivot folder for multiple jail is ok
@@ -60,10 +66,12 @@ pub struct JailConf<'a> {
     pub hostname: &'a str,
     pub cwd: String, // Directory in the namespace the process will run (default: '/')
     pub chroot: CString, // Directory containing / of the jail (default: none)
-    pub port: u32,
+    pub port: u32, // listen mode when not 0: one jail per TCP connection accepted on bind_host:port, see listen.rs
     pub bind_host: &'a str,
     pub daemonize: bool,
     pub tlimit: u64,
+    pub tlimit_grace: u64, // seconds between SIGTERM and SIGKILL once tlimit is reached
+    pub restart: Option<RestartConfig>, // if defined, run_monitor_child starts the jail again once it exits
     pub max_cpus: u16,
     pub keep_env: bool,
     pub keep_caps: bool,
@@ -98,7 +106,7 @@ pub struct JailConf<'a> {
     pub is_silent: bool,
     pub stderr_to_null: bool,
     pub skip_setsid: bool, // Don't call setsid(), allows for terminal signal handling in the sandboxed process. Dangerous
-    pub max_conns_per_ip: u32,
+    pub max_conns_per_ip: u32, // concurrent TCP connections per client IP, to port or through port_forwards, 0 for no limit
     pub proc_path: String,
     pub is_proc_rw: bool,
 
@@ -106,13 +114,20 @@ pub struct JailConf<'a> {
 
     pub iface_lo: bool,
     pub ifaces: Vec<&'a CStr>, // existing network interfaces you want to move inside the new NET namespace
-    pub iface_vs: &'a CStr, // Interface which will be cloned (MACVLAN) and put inside the subprocess' namespace as 'vs'
-    pub iface_vs_ip: CString, // IP of the 'vs' interface (e.g. \"192.168.0.1\")
-    pub iface_vs_nm: &'a CStr, // Netmask of the 'vs' interface (e.g. \"255.255.255.0\")
+    pub iface_vs: &'a CStr, // Interface which will be cloned (MACVLAN) and put inside the subprocess' namespace as 'vs', the bridge in veth mode
+    pub iface_vs_mode: NetMode, // How 'vs' is connected to iface_vs, see NetMode
+    pub iface_vs_ip: CString, // IP of the 'vs' interface (e.g. \"192.168.0.1\"), IPv4 or IPv6, may carry its prefix length (e.g. \"fd00:7a::2/64\")
+    pub iface_vs_nm: &'a CStr, // Netmask of the 'vs' interface (e.g. \"255.255.255.0\"), or a prefix length (e.g. \"24\")
     pub iface_vs_gw: &'a CStr, // Default GW for the 'vs' interface (e.g. \"192.168.0.1\")
     pub iface_vs_ma: &'a CStr, // MAC-address of the 'vs' interface (e.g. \"ba:ad:ba:be:45:00\")
+    pub iface_vs_ip6: CString, // IPv6 address of the 'vs' interface for dual-stack, with its prefix length, /64 if missing (e.g. \"fd00:7a::2/64\"), empty for none
+    pub iface_vs_gw6: &'a CStr, // IPv6 default GW for the 'vs' interface (e.g. \"fd00:7a::1\"), empty for none
+    pub iface_ipv6: bool, // false disables IPv6 inside the NET namespace, lo included
 
     pub multi_net: Option<MultiNetConfig>, // if defined, previous iface properties are ignored
+    pub egress: Option<EgressPolicy>, // if defined, what the jail may reach, enforced with nftables in its NET namespace, see firewall.rs
+    pub user_net: Option<UserNetConfig>, // if defined, the jail gets a tap device served by a userspace stack in the parent instead of iface_vs, see usernet
+    pub port_forwards: Vec<PortForward>, // host addresses proxied by the parent to ports of the jail, see portfwd.rs
 
     pub cgroup_mem_mount: &'a str,
     pub cgroup_mem_parent: &'a str,
@@ -128,6 +143,17 @@ pub struct JailConf<'a> {
     pub cgroup_cpu_ms_per_sec: u64,
     pub cgroupv2_mount: &'a str,
     pub use_cgroupv2: bool,
+    pub cgroupv2_parent: &'a str, // cgroup v2 the jail cgroups are created in, under cgroupv2_mount, may be nested
+    pub cgroup_mem_high: u64,     // cgroup v2 memory.high in bytes, the jail is throttled above it, 0 for none
+    pub cgroup_mem_swap_max: i64, // cgroup v2 memory.swap.max in bytes, -1 for none
+    pub cgroup_io_weight: u64,    // cgroup v2 io.weight, from 1 to 10000, 0 for none
+    pub cgroup_io_max: Vec<CgroupIoMax>, // cgroup v2 io.max, per block device bandwidth and iops limits
+    pub cgroup_cpuset_cpus: &'a str, // cgroup v2 cpuset.cpus, e.g. "0-3,6", empty for none
+    pub cgroup_cpuset_mems: &'a str, // cgroup v2 cpuset.mems, NUMA nodes, empty for none
+    pub cgroup_hugetlb_max: Vec<(String, u64)>, // cgroup v2 hugetlb.<page size>.max, e.g. ("2MB", 1 << 30)
+    pub cgroup_freezer: bool, // gives the jail a cgroup it can be paused with even without limits, see freezer.rs
+    pub cgroup_freezer_mount: &'a str,
+    pub cgroup_freezer_parent: &'a str,
 
     pub seccomp_log: bool,
     pub nice_level: i64,
@@ -135,7 +161,7 @@ pub struct JailConf<'a> {
     pub orig_uid: libc::uid_t,
     pub orig_euid: libc::uid_t,
     pub mountpts: Vec<MountT>,
-    pub pids: Vec<PIDT<'a>>,
+    pub pids: Vec<PIDT>,
 
     pub uids: Vec<IDMapT>,
     pub gids: Vec<IDMapT>,
@@ -146,6 +172,9 @@ pub struct JailConf<'a> {
     pub caps: Vec<i64>,
 
     pub seccomp_filter: Option<BpfProgram>,
+    pub seccomp_profile: &'a str, // a built-in profile of seccomp::profiles, e.g. "python", used when seccomp_filter is None
+    pub seccomp_notify: Option<SeccompNotifyConfig>, // if defined, the USER_NOTIF syscalls of the seccomp filter are decided by the parent, see supervisor
+    pub seccomp_learn: &'a str, // if not empty, the jail runs unfiltered but for the parent, which writes a policy allowing the syscalls it made to this file once it exits, see supervisor. Neither seccomp_filter nor seccomp_profile may be set
 
     pub fd_in: libc::c_int,
     pub fd_out: libc::c_int,
@@ -160,6 +189,16 @@ pub struct JailConf<'a> {
 
     pub child_pid: Option<libc::c_int>,
     pub child_pidfd: Option<libc::c_int>,
+    pub egress_fd: Option<libc::c_int>, // nftables socket owning the egress rules of the child, they are removed once it is closed
+    pub user_net_fd: Option<libc::c_int>, // eventfd stopping the user-mode network stack of the child
+    pub seccomp_notify_fd: Option<libc::c_int>, // listener of the seccomp filter of the child, readable when a syscall waits for supervisor::handle
+    pub seccomp_recorder: Option<Recorder>, // syscalls of a child learning its policy, see seccomp_learn
+    pub seccomp_kill_filter: Option<SeccompFilter>, // the filter of a supervised child before its kills were handed over to the supervisor, see supervisor::kill
+    pub seccomp_violation: Option<SeccompViolation>, // the last syscall the supervisor killed a process of the child for
+    pub port_forward_fd: Option<libc::c_int>, // eventfd stopping the port forwarding proxy of the child
+    pub net_ns_fd: Option<libc::c_int>, // NET namespace of the child, kept alive for clean_after_child to read its counters
+    pub limit_event_fds: Vec<libc::c_int>, // readable when the child hits a cgroup limit, see limits.rs
+    pub child_start: Option<Instant>, // when the child was started, or woken up if pooled, see usage.rs
 }
 
 // We need to implement Default trait for struct JailConf because rust won't allow empty struct or fields. In rust we cannot init a struct without giving values for any of the fields
@@ -183,6 +222,8 @@ impl<'a> Default for JailConf<'a> {
             bind_host: "::",
             daemonize: false,
             tlimit: 0,
+            tlimit_grace: DEFAULT_TLIMIT_GRACE,
+            restart: None,
             personality: 0,
 
             max_cpus: 0,
@@ -231,8 +272,15 @@ impl<'a> Default for JailConf<'a> {
             iface_vs_nm: CStr::from_bytes_with_nul("255.255.255.0\0".as_bytes()).unwrap(),
             iface_vs_gw: CStr::from_bytes_with_nul("0.0.0.0\0".as_bytes()).unwrap(),
             iface_vs_ma: CStr::from_bytes_with_nul("\0".as_bytes()).unwrap(),
+            iface_vs_mode: NetMode::Macvlan,
+            iface_vs_ip6: CString::default(),
+            iface_vs_gw6: CStr::from_bytes_with_nul("\0".as_bytes()).unwrap(),
+            iface_ipv6: true,
             ifaces: vec![],
             multi_net: None,
+            egress: None,
+            user_net: None,
+            port_forwards: Vec::new(),
 
             cgroup_mem_mount: "/sys/fs/cgroup/memory",
             cgroup_mem_parent: "TOASTAINER",
@@ -248,6 +296,17 @@ impl<'a> Default for JailConf<'a> {
             cgroup_cpu_ms_per_sec: 0,
             cgroupv2_mount: "/sys/fs/cgroup",
             use_cgroupv2: false,
+            cgroupv2_parent: "TOASTAINER",
+            cgroup_mem_high: 0,
+            cgroup_mem_swap_max: -1,
+            cgroup_io_weight: 0,
+            cgroup_io_max: Vec::new(),
+            cgroup_cpuset_cpus: "",
+            cgroup_cpuset_mems: "",
+            cgroup_hugetlb_max: Vec::new(),
+            cgroup_freezer: false,
+            cgroup_freezer_mount: "/sys/fs/cgroup/freezer",
+            cgroup_freezer_parent: "TOASTAINER",
 
             seccomp_log: false,
             nice_level: 19,
@@ -268,6 +327,9 @@ impl<'a> Default for JailConf<'a> {
             argv: None,
 
             seccomp_filter: None,
+            seccomp_profile: "",
+            seccomp_notify: None,
+            seccomp_learn: "",
             fd_in: libc::STDIN_FILENO,
             fd_out: libc::STDOUT_FILENO,
             fd_err: libc::STDERR_FILENO,
@@ -277,6 +339,16 @@ impl<'a> Default for JailConf<'a> {
 
             child_pid: None,
             child_pidfd: None,
+            egress_fd: None,
+            user_net_fd: None,
+            seccomp_notify_fd: None,
+            seccomp_recorder: None,
+            seccomp_kill_filter: None,
+            seccomp_violation: None,
+            port_forward_fd: None,
+            net_ns_fd: None,
+            limit_event_fds: vec![],
+            child_start: None,
         };
         jconf
             .with_uid(uid, uid, 1, false)
@@ -394,6 +466,37 @@ impl<'a> JailConf<'a> {
         self
     }
 
+    /// Dual-stack, see iface_vs_ip6 and iface_vs_gw6
+    pub fn with_net_config_v6(
+        &mut self,
+        iface_vs_ip6: CString,
+        iface_vs_gw6: &'a CStr,
+    ) -> &mut Self {
+        self.iface_vs_ip6 = iface_vs_ip6;
+        self.iface_vs_gw6 = iface_vs_gw6;
+        self
+    }
+
+    pub fn with_net_mode(&mut self, mode: NetMode) -> &mut Self {
+        self.iface_vs_mode = mode;
+        self
+    }
+
+    pub fn with_egress(&mut self, policy: EgressPolicy) -> &mut Self {
+        self.egress = Some(policy);
+        self
+    }
+
+    pub fn with_user_net(&mut self, user_net: UserNetConfig) -> &mut Self {
+        self.user_net = Some(user_net);
+        self
+    }
+
+    pub fn with_port_forward(&mut self, forward: PortForward) -> &mut Self {
+        self.port_forwards.push(forward);
+        self
+    }
+
     pub fn clone_newnet(&mut self) -> &mut Self {
         self.clone_newnet = true;
         self
@@ -449,6 +552,18 @@ impl<'a> JailConf<'a> {
         self
     }
 
+    /// wall-clock limit in seconds, the jail gets SIGTERM once it is reached then SIGKILL grace seconds later
+    pub fn with_tlimit(&mut self, secs: u64, grace: u64) -> &mut Self {
+        self.tlimit = secs;
+        self.tlimit_grace = grace;
+        self
+    }
+
+    pub fn with_restart(&mut self, restart: RestartConfig) -> &mut Self {
+        self.restart = Some(restart);
+        self
+    }
+
     pub fn with_rlimit_as(
         &mut self,
         hard: bool,
@@ -774,6 +889,8 @@ impl<'a> JailConf<'a> {
             bind_host: "::",
             daemonize: false,
             tlimit: 0,
+            tlimit_grace: DEFAULT_TLIMIT_GRACE,
+            restart: None,
             max_cpus: 0,
             personality: 0,
             disable_rl: false,
@@ -820,8 +937,15 @@ impl<'a> JailConf<'a> {
             iface_vs_nm: CStr::from_bytes_with_nul("255.255.255.0\0".as_bytes()).unwrap(),
             iface_vs_gw: CStr::from_bytes_with_nul("0.0.0.0\0".as_bytes()).unwrap(),
             iface_vs_ma: CStr::from_bytes_with_nul("\0".as_bytes()).unwrap(),
+            iface_vs_mode: NetMode::Macvlan,
+            iface_vs_ip6: CString::default(),
+            iface_vs_gw6: CStr::from_bytes_with_nul("\0".as_bytes()).unwrap(),
+            iface_ipv6: true,
             ifaces: vec![],
             multi_net: None,
+            egress: None,
+            user_net: None,
+            port_forwards: Vec::new(),
 
             cgroup_mem_mount: "/sys/fs/cgroup/memory",
             cgroup_mem_parent: "TOASTAINER",
@@ -837,6 +961,17 @@ impl<'a> JailConf<'a> {
             cgroup_cpu_ms_per_sec: 0,
             cgroupv2_mount: "/sys/fs/cgroup",
             use_cgroupv2: false,
+            cgroupv2_parent: "TOASTAINER",
+            cgroup_mem_high: 0,
+            cgroup_mem_swap_max: -1,
+            cgroup_io_weight: 0,
+            cgroup_io_max: Vec::new(),
+            cgroup_cpuset_cpus: "",
+            cgroup_cpuset_mems: "",
+            cgroup_hugetlb_max: Vec::new(),
+            cgroup_freezer: false,
+            cgroup_freezer_mount: "/sys/fs/cgroup/freezer",
+            cgroup_freezer_parent: "TOASTAINER",
 
             seccomp_log: false,
             nice_level: 19,
@@ -855,6 +990,9 @@ impl<'a> JailConf<'a> {
 
             env: None,
             seccomp_filter: None,
+            seccomp_profile: "",
+            seccomp_notify: None,
+            seccomp_learn: "",
             fd_in: libc::STDIN_FILENO,
             fd_out: libc::STDOUT_FILENO,
             fd_err: libc::STDERR_FILENO,
@@ -864,6 +1002,16 @@ impl<'a> JailConf<'a> {
 
             child_pid: None,
             child_pidfd: None,
+            egress_fd: None,
+            user_net_fd: None,
+            seccomp_notify_fd: None,
+            seccomp_recorder: None,
+            seccomp_kill_filter: None,
+            seccomp_violation: None,
+            port_forward_fd: None,
+            net_ns_fd: None,
+            limit_event_fds: vec![],
+            child_start: None,
         }
     }
 
@@ -891,6 +1039,8 @@ impl<'a> JailConf<'a> {
             bind_host: "::",
             daemonize: false,
             tlimit: 0,
+            tlimit_grace: DEFAULT_TLIMIT_GRACE,
+            restart: None,
             max_cpus: 0,
             personality: 0,
             disable_rl: false,
@@ -937,7 +1087,14 @@ impl<'a> JailConf<'a> {
             iface_vs_nm: iface_vs_nm,
             iface_vs_gw: iface_vs_gw,
             iface_vs_ma: iface_vs_ma,
+            iface_vs_mode: NetMode::Macvlan,
+            iface_vs_ip6: CString::default(),
+            iface_vs_gw6: CStr::from_bytes_with_nul("\0".as_bytes()).unwrap(),
+            iface_ipv6: true,
             multi_net: None,
+            egress: None,
+            user_net: None,
+            port_forwards: Vec::new(),
             ifaces: vec![],
 
             cgroup_mem_mount: "/sys/fs/cgroup/memory",
@@ -954,6 +1111,17 @@ impl<'a> JailConf<'a> {
             cgroup_cpu_ms_per_sec: 0,
             cgroupv2_mount: "/sys/fs/cgroup",
             use_cgroupv2: false,
+            cgroupv2_parent: "TOASTAINER",
+            cgroup_mem_high: 0,
+            cgroup_mem_swap_max: -1,
+            cgroup_io_weight: 0,
+            cgroup_io_max: Vec::new(),
+            cgroup_cpuset_cpus: "",
+            cgroup_cpuset_mems: "",
+            cgroup_hugetlb_max: Vec::new(),
+            cgroup_freezer: false,
+            cgroup_freezer_mount: "/sys/fs/cgroup/freezer",
+            cgroup_freezer_parent: "TOASTAINER",
 
             seccomp_log: false,
             nice_level: 19,
@@ -972,6 +1140,9 @@ impl<'a> JailConf<'a> {
 
             env: None,
             seccomp_filter: None,
+            seccomp_profile: "",
+            seccomp_notify: None,
+            seccomp_learn: "",
             fd_in: libc::STDIN_FILENO,
             fd_out: libc::STDOUT_FILENO,
             fd_err: libc::STDERR_FILENO,
@@ -981,6 +1152,16 @@ impl<'a> JailConf<'a> {
 
             child_pid: None,
             child_pidfd: None,
+            egress_fd: None,
+            user_net_fd: None,
+            seccomp_notify_fd: None,
+            seccomp_recorder: None,
+            seccomp_kill_filter: None,
+            seccomp_violation: None,
+            port_forward_fd: None,
+            net_ns_fd: None,
+            limit_event_fds: vec![],
+            child_start: None,
         }
     }
 }
@@ -1054,19 +1235,19 @@ impl Default for MountT {
 }
 
 #[derive(Clone, Debug)]
-pub struct PIDT<'a> {
+pub struct PIDT {
     pub pid: libc::pid_t,
     pub start: SystemTime,
-    pub remote_txt: &'a str,
-    // we'll not implement remote socket control for now
-    // struct sockaddr_in6 remote_addr;
+    pub remote_txt: String,
+    pub remote_addr: Option<SocketAddr>, // client of the jail in listen mode
     pub pid_syscall_fd: i32,
 }
 
-impl<'a> Default for PIDT<'a> {
-    fn default() -> PIDT<'a> {
+impl Default for PIDT {
+    fn default() -> PIDT {
         PIDT {
-            remote_txt: "",
+            remote_txt: String::new(),
+            remote_addr: None,
             pid: 0,
             start: SystemTime::now(),
             pid_syscall_fd: 0,
@@ -1093,11 +1274,196 @@ impl Default for IDMapT {
     }
 }
 
+/// io.max limits of the block device major:minor, see cgroupv2::parse_io_max. A limit of 0 is left unset
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CgroupIoMax {
+    pub major: u32,
+    pub minor: u32,
+    pub rbps: u64,
+    pub wbps: u64,
+    pub riops: u64,
+    pub wiops: u64,
+}
+
+/// Which exits of a jail are followed by a restart, see RestartConfig
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RestartPolicy {
+    Always,
+    /// only when the jail exits with a non zero status or is killed, by a signal or its tlimit
+    OnFailure,
+}
+
+/**
+ * Re-run mode, the MODE_STANDALONE_RERUN of nsjail: run_monitor_child starts the jail again once it exits, so that a
+ * sandboxed daemon recovers by itself. Each run starts from the JailConf given to run_monitor_child
+ *
+ * Restarts back off exponentially: the n-th restart in a row waits backoff_ms * 2^(n-1) milliseconds, at most
+ * backoff_max_ms, and a run that lasted longer than backoff_max_ms starts the sequence over. max_restarts counts every
+ * restart, 0 for no limit
+ */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RestartConfig {
+    pub policy: RestartPolicy,
+    pub max_restarts: u32,
+    pub backoff_ms: u64,
+    pub backoff_max_ms: u64,
+}
+
+impl Default for RestartConfig {
+    fn default() -> RestartConfig {
+        RestartConfig {
+            policy: RestartPolicy::Always,
+            max_restarts: 0,
+            backoff_ms: 1000,
+            backoff_max_ms: 60_000,
+        }
+    }
+}
+
+impl RestartConfig {
+    /// Whether a jail that exited with status, see wait::ExitReport, is started again after restarts restarts
+    pub fn restarts_after(&self, status: i32, restarts: u32) -> bool {
+        if self.max_restarts > 0 && restarts >= self.max_restarts {
+            return false;
+        }
+        match self.policy {
+            RestartPolicy::Always => true,
+            RestartPolicy::OnFailure => status != 0,
+        }
+    }
+
+    /// Delay before the n-th restart in a row, n starting at 1
+    pub fn delay(&self, n: u32) -> Duration {
+        let factor = 1u64.checked_shl(n.saturating_sub(1)).unwrap_or(u64::MAX);
+        Duration::from_millis(self.backoff_ms.saturating_mul(factor).min(self.backoff_max_ms))
+    }
+}
+
+/// How the 'vs' interfaces of a jail reach the host network
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NetMode {
+    /// iface_vs is a host interface cloned as MACVLAN inside the jail. The jail cannot talk to the host through it
+    Macvlan,
+    /**
+     * iface_vs is a host bridge, created if it does not exist. The jail gets one end of a veth pair, the other end stays
+     * on the host attached to the bridge, so the jail reaches the host on the bridge address and anything the bridge is
+     * routed to
+     */
+    Veth,
+}
+
 #[derive(Clone, Debug)]
 pub struct MultiNetConfig {
-    pub iface_vs: Vec<CString>, // Interface which will be cloned (MACVLAN) and put inside the subprocess' namespace as 'vs'
-    pub iface_vs_ip: Vec<CString>, // IP of the 'vs' interface (e.g. \"192.168.0.1\")
-    pub iface_vs_nm: Vec<CString>, // Netmask of the 'vs' interface (e.g. \"255.255.255.0\")
+    pub iface_vs: Vec<CString>, // Interface which will be cloned (MACVLAN) and put inside the subprocess' namespace as 'vs', the bridge in veth mode
+    pub iface_vs_mode: Vec<NetMode>, // How each 'vs' is connected to its iface_vs, JailConf.iface_vs_mode when missing
+    pub iface_vs_ip: Vec<CString>, // IP of the 'vs' interface (e.g. \"192.168.0.1\"), see JailConf.iface_vs_ip
+    pub iface_vs_nm: Vec<CString>, // Netmask of the 'vs' interface (e.g. \"255.255.255.0\"), or a prefix length
     pub iface_vs_gw: Vec<CString>, // Default GW for the 'vs' interface (e.g. \"192.168.0.1\")
     pub iface_vs_ma: Vec<CString>, // MAC-address of the 'vs' interface (e.g. \"ba:ad:ba:be:45:00\")
+    pub iface_vs_ip6: Vec<CString>, // IPv6 address/prefix length of the 'vs' interface, none when missing or empty
+    pub iface_vs_gw6: Vec<CString>, // IPv6 default GW of the 'vs' interface, none when missing or empty
+}
+
+/**
+ * User-mode network of a jail, for jails started without root. The jail gets a tap device with ip/prefix_len and a
+ * default route through gateway, the parent forwards the TCP connections and UDP flows the jail opens with its own
+ * sockets. dns leads to the first nameserver of the host /etc/resolv.conf and gateway to the host loopback if
+ * host_loopback is set. The jail is not reachable from the outside and only TCP, UDP and ping of gateway and dns work
+ */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UserNetConfig {
+    pub ip: Ipv4Addr,
+    pub prefix_len: u8,
+    pub gateway: Ipv4Addr,
+    pub dns: Ipv4Addr,
+    pub host_loopback: bool,
+}
+
+impl Default for UserNetConfig {
+    fn default() -> UserNetConfig {
+        UserNetConfig {
+            ip: Ipv4Addr::new(10, 0, 2, 100),
+            prefix_len: 24,
+            gateway: Ipv4Addr::new(10, 0, 2, 2),
+            dns: Ipv4Addr::new(10, 0, 2, 3),
+            host_loopback: false,
+        }
+    }
+}
+
+/**
+ * What the seccomp supervisor of a jail accepts, for the syscalls its filter hands over with USER_NOTIF, e.g.
+ * `USER_NOTIF { mount, umount2, connect }`. The parent runs the mounts listed in mounts, and the umount2 of their dst,
+ * inside the jail, and the connect to the destinations connect lists, with the socket of the jail. Any other syscall
+ * handed over fails with EPERM
+ */
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SeccompNotifyConfig {
+    pub mounts: Vec<NotifyMount>,
+    pub connect: Vec<EgressRule>, // IPv4 and IPv6 destinations, connecting a unix socket always fails
+}
+
+/// A mount(2) the jail may run, paths are absolute and seen from the jail
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct NotifyMount {
+    pub src: String, // any source if empty
+    pub dst: String,
+    pub fstype: String, // empty for bind mounts and remounts
+    pub flags: libc::c_ulong, // the MS_* flags the jail may use, the mount fails if it asks for others
+    pub data: String, // the options the mount must have, none if empty
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ForwardProto {
+    Tcp,
+    Udp,
+}
+
+/**
+ * Inbound forwarding of a host address to a port of the jail. The parent listens on host and opens a socket inside the
+ * NET namespace of the jail for each TCP connection or UDP client, connected to 127.0.0.1:jail_port, so the service of
+ * the jail must listen on its loopback too, e.g. on 0.0.0.0
+ */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PortForward {
+    pub proto: ForwardProto,
+    pub host: SocketAddr,
+    pub jail_port: u16,
+}
+
+/**
+ * Egress firewall of a jail. Loopback traffic, replies of accepted connections and the ICMPv6 needed by neighbour
+ * discovery always pass, then link-local destinations are dropped if block_link_local is set. Everything else is
+ * accepted when allow is empty and dns_only unset, otherwise only what allow lists is.
+ */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EgressPolicy {
+    pub allow: Vec<EgressRule>,
+    pub dns_only: bool, // only DNS over UDP and TCP, to the networks in allow or to any server if allow is empty. Ports and protocols in allow are ignored
+    pub block_link_local: bool, // 169.254.0.0/16, fe80::/10 and fd00:ec2::254, where cloud metadata services such as 169.254.169.254 live
+}
+
+impl Default for EgressPolicy {
+    fn default() -> EgressPolicy {
+        EgressPolicy {
+            allow: Vec::new(),
+            dns_only: false,
+            block_link_local: true,
+        }
+    }
+}
+
+/// Destinations an EgressPolicy accepts, written "<cidr>[,tcp|udp][,<port>[-<port>]]", e.g. "10.0.0.0/8,tcp,443"
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EgressRule {
+    pub net: IpAddr,
+    pub prefix_len: u8,
+    pub proto: Option<EgressProto>, // TCP and UDP when None, any protocol if ports is None too
+    pub ports: Option<(u16, u16)>, // inclusive range of destination ports
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EgressProto {
+    Tcp,
+    Udp,
 }
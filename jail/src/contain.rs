@@ -381,8 +381,9 @@ pub fn contain_proc(jconf: &mut JailConf) -> Result<()> {
     } else {
         contain_user_ns(jconf)?;
         contain_init_pid_ns(jconf).map_err(|e| format!("contain_init_pid_ns: {}", e))?;
-        contain_init_mount_ns(jconf).map_err(|e| format!("contain_init_mount_ns: {}", e))?;
+        // before the mount namespace, the IPv6 sysctls are written through /proc which may then be read-only
         contain_init_net_ns(jconf).map_err(|e| format!("contain_init_net_ns: {}", e))?;
+        contain_init_mount_ns(jconf).map_err(|e| format!("contain_init_mount_ns: {}", e))?;
         contain_init_uts_ns(jconf).map_err(|e| format!("contain_init_uts_ns: {}", e))?;
         contain_init_cgroup_ns(jconf).map_err(|e| format!("contain_init_cgroup_ns: {}", e))?;
     }
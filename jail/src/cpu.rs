@@ -1,8 +1,70 @@
 use super::config::JailConf;
 
-// TODO: see how to implement all calls from nsjail:cpu.cc which is not the case right now, (for example libc lacks CPU_ALLOC and CPU_ALLOC_SIZE)
-// For now it is implemented through cpp_bindings package
-// see https://linux.die.net/man/3/cpu_alloc
+use std::mem;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// MMIX LCG PRNG, like nsjail:util.cc rnd64, we only need it to spread jails over the CPUs
+const LCG_A: u64 = 6364136223846793005;
+const LCG_C: u64 = 1442695040888963407;
+
+/**
+ * Port of nsjail:cpu.cc, restricts the jail to max_cpus CPUs picked at random among the online ones
+ *
+ * libc lacks CPU_ALLOC and CPU_ALLOC_SIZE, see https://linux.die.net/man/3/cpu_alloc, so the mask is built by hand:
+ * a cpu_set_t is an array of unsigned long with one bit per CPU
+ */
 pub fn init_cpu(jconf: &JailConf) -> bool {
-    cpp_bindings::init_cpu(jconf.num_cpus as libc::c_int, jconf.max_cpus as libc::c_int)
-}
\ No newline at end of file
+    let num_cpus = jconf.num_cpus;
+    let max_cpus = jconf.max_cpus as libc::c_long;
+
+    if num_cpus < 0 {
+        return false; // sysconf(_SC_NPROCESSORS_ONLN) failed
+    }
+    if max_cpus == 0 || max_cpus >= num_cpus {
+        return true;
+    }
+
+    let bits = 8 * mem::size_of::<libc::c_ulong>();
+    let mut mask: Vec<libc::c_ulong> = vec![0; (num_cpus as usize).div_ceil(bits)];
+
+    let mut rnd = random_seed();
+    let mut set = 0;
+    while set < max_cpus {
+        rnd = rnd.wrapping_mul(LCG_A).wrapping_add(LCG_C);
+        let n = (rnd % num_cpus as u64) as usize;
+        let bit: libc::c_ulong = 1 << (n % bits);
+        if mask[n / bits] & bit == 0 {
+            mask[n / bits] |= bit;
+            set += 1;
+        }
+    }
+
+    unsafe {
+        libc::syscall(
+            libc::SYS_sched_setaffinity,
+            0,
+            mask.len() * mem::size_of::<libc::c_ulong>(),
+            mask.as_ptr(),
+        ) == 0
+    }
+}
+
+fn random_seed() -> u64 {
+    let mut seed: u64 = 0;
+    let n = unsafe {
+        libc::syscall(
+            libc::SYS_getrandom,
+            &mut seed as *mut u64,
+            mem::size_of::<u64>(),
+            0,
+        )
+    };
+    if n == mem::size_of::<u64>() as libc::c_long {
+        return seed;
+    }
+
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_nanos() as u64,
+        Err(_) => 0,
+    }
+}
@@ -1,5 +1,6 @@
 use std::{error, fmt, io, result};
 use sys_util::errno::Errno;
+use sys_util::{nftables, rtnetlink};
 use cmd::exec::CommandError;
 use std::env;
 
@@ -13,6 +14,8 @@ pub enum Error {
     ParseString(String),
     ParseErrno(&'static str, Errno),
     ParseErrnoAlone(Errno),
+    ParseNetlink(rtnetlink::Error),
+    ParseNftables(nftables::Error),
     ParseCMD(CommandError),
     ParseVarErr(env::VarError),
     EOF,
@@ -28,6 +31,8 @@ impl fmt::Display for Error {
             Error::ParseString(ref e) => e.fmt(f),
             Error::ParseErrno(ref s, ref e) => write!(f, "Message: {} < || > Errno: {}", s, e),
             Error::ParseErrnoAlone(ref e) => e.fmt(f),
+            Error::ParseNetlink(ref e) => e.fmt(f),
+            Error::ParseNftables(ref e) => e.fmt(f),
             Error::ParseCMD(ref e) => e.fmt(f),
             Error::ParseVarErr(ref e) => e.fmt(f),
             Error::EOF => write!(f, "EOF"),
@@ -48,6 +53,8 @@ impl error::Error for Error {
             Error::ParseString(_) => None,
             Error::ParseErrno(_, ref e) => Some(e),
             Error::ParseErrnoAlone(ref e) => Some(e),
+            Error::ParseNetlink(ref e) => Some(e),
+            Error::ParseNftables(ref e) => Some(e),
             Error::ParseCMD(ref e) => Some(e),
             Error::ParseVarErr(ref e) => Some(e),
             Error::EOF => None,
@@ -106,3 +113,15 @@ impl From<Errno> for Error {
         Error::ParseErrnoAlone(err)
     }
 }
+
+impl From<rtnetlink::Error> for Error {
+    fn from(err: rtnetlink::Error) -> Error {
+        Error::ParseNetlink(err)
+    }
+}
+
+impl From<nftables::Error> for Error {
+    fn from(err: nftables::Error) -> Error {
+        Error::ParseNftables(err)
+    }
+}
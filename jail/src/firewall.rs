@@ -0,0 +1,311 @@
+use super::config::{EgressPolicy, EgressProto, EgressRule, JailConf};
+use super::error::Result;
+use super::net;
+
+use std::net::IpAddr;
+use std::thread;
+use sys_util::fcntl;
+use sys_util::nftables::{self, Batch, Expr, NfTables, NFPROTO_INET, NFPROTO_IPV6};
+use sys_util::sched::setns;
+
+const TABLE: &str = "jail_egress";
+const CHAIN: &str = "output";
+const LO: &str = "lo";
+const DNS_PORT: u16 = 53;
+
+// link-local networks, the last one is the IPv6 endpoint of the AWS instance metadata service
+const LINK_LOCAL: &[(&str, u8)] = &[("169.254.0.0", 16), ("fe80::", 10), ("fd00:ec2::254", 128)];
+
+// ICMPv6 router solicitation to neighbour advertisement, without them the jail cannot resolve its IPv6 gateway
+const ND_FIRST_TYPE: u8 = 133;
+const ND_LAST_TYPE: u8 = 136;
+
+/// Parses "<cidr>[,tcp|udp][,<port>[-<port>]]", a missing prefix length means a single host
+pub fn parse_egress_rule(s: &str) -> Result<EgressRule> {
+    let invalid = || {
+        format!(
+            "invalid egress rule {:?}, expected <cidr>[,tcp|udp][,<port>[-<port>]]",
+            s
+        )
+    };
+
+    let mut parts = s.split(',').map(str::trim);
+    let cidr = parts.next().unwrap_or_default();
+    let (net, prefix_len) = match cidr.parse::<IpAddr>() {
+        Ok(IpAddr::V4(a)) => (IpAddr::V4(a), 32),
+        Ok(IpAddr::V6(a)) => (IpAddr::V6(a), 128),
+        Err(_) => net::parse_cidr(cidr, None).ok_or_else(invalid)?,
+    };
+
+    let mut rule = EgressRule {
+        net,
+        prefix_len,
+        proto: None,
+        ports: None,
+    };
+    for part in parts {
+        match part {
+            "tcp" if rule.proto.is_none() && rule.ports.is_none() => {
+                rule.proto = Some(EgressProto::Tcp)
+            }
+            "udp" if rule.proto.is_none() && rule.ports.is_none() => {
+                rule.proto = Some(EgressProto::Udp)
+            }
+            _ if rule.ports.is_none() => {
+                let mut range = part.splitn(2, '-');
+                let from = range
+                    .next()
+                    .and_then(|p| p.parse::<u16>().ok())
+                    .ok_or_else(invalid)?;
+                let to = match range.next() {
+                    Some(p) => p.parse::<u16>().map_err(|_| invalid())?,
+                    None => from,
+                };
+                if from == 0 || to < from {
+                    return Err(invalid().into());
+                }
+                rule.ports = Some((from, to));
+            }
+            _ => return Err(invalid().into()),
+        }
+    }
+
+    Ok(rule)
+}
+
+/// Rules that accept the traffic allowed by rule, one per transport protocol when it has ports
+fn allow_rules(rule: &EgressRule, dns_only: bool) -> Vec<Vec<Expr>> {
+    let (proto, ports) = if dns_only {
+        (None, Some((DNS_PORT, DNS_PORT)))
+    } else {
+        (rule.proto, rule.ports)
+    };
+    let protos: Vec<Option<EgressProto>> = match (proto, ports) {
+        (Some(p), _) => vec![Some(p)],
+        (None, Some(_)) => vec![Some(EgressProto::Tcp), Some(EgressProto::Udp)],
+        (None, None) => vec![None],
+    };
+
+    protos
+        .into_iter()
+        .map(|proto| {
+            let mut exprs = nftables::match_daddr(rule.net, rule.prefix_len);
+            if let Some(proto) = proto {
+                exprs.extend(nftables::match_l4proto(match proto {
+                    EgressProto::Tcp => libc::IPPROTO_TCP as u8,
+                    EgressProto::Udp => libc::IPPROTO_UDP as u8,
+                }));
+            }
+            if let Some((from, to)) = ports {
+                exprs.extend(nftables::match_dport(from, to));
+            }
+            exprs.push(Expr::Verdict(nftables::NF_ACCEPT));
+            exprs
+        })
+        .collect()
+}
+
+fn restricts(policy: &EgressPolicy) -> bool {
+    policy.dns_only || !policy.allow.is_empty()
+}
+
+/// The rules of the output chain in order, see EgressPolicy
+pub fn egress_rules(policy: &EgressPolicy) -> Vec<Vec<Expr>> {
+    let mut rules = Vec::new();
+
+    let mut lo = nftables::match_oifname(LO);
+    lo.push(Expr::Verdict(nftables::NF_ACCEPT));
+    rules.push(lo);
+
+    let mut established = nftables::match_ct_established();
+    established.push(Expr::Verdict(nftables::NF_ACCEPT));
+    rules.push(established);
+
+    let mut nd = nftables::match_nfproto(NFPROTO_IPV6);
+    nd.extend(nftables::match_l4proto(libc::IPPROTO_ICMPV6 as u8));
+    nd.extend(nftables::match_l4_type(ND_FIRST_TYPE, ND_LAST_TYPE));
+    nd.push(Expr::Verdict(nftables::NF_ACCEPT));
+    rules.push(nd);
+
+    if policy.block_link_local {
+        for &(net, prefix_len) in LINK_LOCAL {
+            let mut exprs = nftables::match_daddr(net.parse().unwrap(), prefix_len);
+            exprs.push(Expr::Verdict(nftables::NF_DROP));
+            rules.push(exprs);
+        }
+    }
+
+    if policy.dns_only && policy.allow.is_empty() {
+        let any = ["0.0.0.0", "::"].iter().map(|net| EgressRule {
+            net: net.parse().unwrap(),
+            prefix_len: 0,
+            proto: None,
+            ports: None,
+        });
+        for rule in any {
+            rules.extend(allow_rules(&rule, true));
+        }
+    }
+    for rule in policy.allow.iter() {
+        rules.extend(allow_rules(rule, policy.dns_only));
+    }
+
+    rules
+}
+
+/**
+ * Opens an nftables socket in the NET namespace of pid. A socket stays bound to the namespace it was created in, so it
+ * is opened by a thread that joins the namespace and exits with it instead of going back to the one of the caller
+ */
+fn connect_in_ns(pid: libc::pid_t) -> Result<NfTables> {
+    let target = fcntl::open(
+        &format!("/proc/{}/ns/net", pid),
+        libc::O_RDONLY | libc::O_CLOEXEC,
+        0,
+    )
+    .map_err(|e| ("could not open the NET namespace of the child", e))?;
+
+    let connector = thread::Builder::new().spawn(move || -> Result<NfTables> {
+        setns(target, libc::CLONE_NEWNET)
+            .map_err(|e| ("could not join the NET namespace of the child", e))?;
+        Ok(NfTables::connect()?)
+    });
+    let res = match connector {
+        Ok(handle) => handle
+            .join()
+            .unwrap_or_else(|_| Err("the thread opening the nftables socket panicked".into())),
+        Err(e) => Err(e.into()),
+    };
+
+    unsafe { libc::close(target) };
+    res
+}
+
+/**
+ * Installs jconf.egress as an nftables table in the NET namespace of the child. The table is owned by a socket of the
+ * parent, kept in jconf.egress_fd until clean_after_child, so the child cannot flush or delete it even with
+ * CAP_NET_ADMIN in its user namespace, and any table it adds can only drop more since a drop verdict is final.
+ * It must run before the child is told to go on, see init_parent
+ */
+pub fn init_ns_from_parent(jconf: &mut JailConf, pid: libc::pid_t) -> Result<()> {
+    let policy = match jconf.egress {
+        Some(ref policy) => policy,
+        None => return Ok(()),
+    };
+    if !jconf.clone_newnet {
+        return Err("an egress policy needs the child to have its own NET namespace".into());
+    }
+
+    let mut batch = Batch::new();
+    batch.add_table(NFPROTO_INET, TABLE, true).add_filter_chain(
+        NFPROTO_INET,
+        TABLE,
+        CHAIN,
+        nftables::NF_INET_LOCAL_OUT,
+        0,
+        if restricts(policy) {
+            nftables::NF_DROP
+        } else {
+            nftables::NF_ACCEPT
+        },
+    );
+    for rule in egress_rules(policy) {
+        batch.add_rule(NFPROTO_INET, TABLE, CHAIN, &rule);
+    }
+
+    let mut nft = connect_in_ns(pid)?;
+    nft.apply(batch)
+        .map_err(|e| format!("Could not install the egress policy: {}", e))?;
+    jconf.egress_fd = Some(nft.into_raw_fd());
+
+    Ok(())
+}
+
+/// Closes the socket owning the egress rules, the kernel then removes them if the NET namespace still exists
+pub fn finish_from_parent(jconf: &JailConf) {
+    if let Some(fd) = jconf.egress_fd {
+        unsafe { libc::close(fd) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_egress_rule() {
+        assert_eq!(
+            parse_egress_rule("10.0.0.0/8,tcp,443").unwrap(),
+            EgressRule {
+                net: "10.0.0.0".parse().unwrap(),
+                prefix_len: 8,
+                proto: Some(EgressProto::Tcp),
+                ports: Some((443, 443)),
+            }
+        );
+        assert_eq!(
+            parse_egress_rule("fd00::/64, 1000-2000").unwrap(),
+            EgressRule {
+                net: "fd00::".parse().unwrap(),
+                prefix_len: 64,
+                proto: None,
+                ports: Some((1000, 2000)),
+            }
+        );
+        let host = parse_egress_rule("1.1.1.1,udp").unwrap();
+        assert_eq!(host.prefix_len, 32);
+        assert_eq!(host.ports, None);
+
+        for bad in &[
+            "",
+            "10.0.0.0/33",
+            "10.0.0.0/8,sctp",
+            "10.0.0.0/8,443,tcp",
+            "1.1.1.1,2-1",
+            "1.1.1.1,0",
+        ] {
+            assert!(parse_egress_rule(bad).is_err(), "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn test_egress_rules() {
+        let open = EgressPolicy::default();
+        assert!(!restricts(&open));
+        // lo, established, neighbour discovery then the 3 link-local networks
+        assert_eq!(egress_rules(&open).len(), 6);
+
+        let dns = EgressPolicy {
+            dns_only: true,
+            block_link_local: false,
+            ..Default::default()
+        };
+        assert!(restricts(&dns));
+        // TCP and UDP to any IPv4 and any IPv6 server
+        let rules = egress_rules(&dns);
+        assert_eq!(rules.len(), 3 + 4);
+        assert!(rules[3..]
+            .iter()
+            .all(|r| r.ends_with(&[Expr::Verdict(nftables::NF_ACCEPT)])));
+
+        let allow = EgressPolicy {
+            allow: vec![
+                parse_egress_rule("10.0.0.0/8").unwrap(),
+                parse_egress_rule("192.168.1.1,443").unwrap(),
+            ],
+            ..Default::default()
+        };
+        let rules = egress_rules(&allow);
+        assert_eq!(rules.len(), 6 + 1 + 2);
+        // any protocol to 10.0.0.0/8
+        assert_eq!(
+            rules[6].len(),
+            nftables::match_daddr("10.0.0.0".parse().unwrap(), 8).len() + 1
+        );
+        assert!(rules[8].ends_with(&[
+            nftables::match_dport(443, 443)[0].clone(),
+            nftables::match_dport(443, 443)[1].clone(),
+            Expr::Verdict(nftables::NF_ACCEPT),
+        ]));
+    }
+}
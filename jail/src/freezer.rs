@@ -0,0 +1,94 @@
+// Pausing of a jail: the cgroup.freeze file of its cgroup v2, or the freezer controller on cgroup v1, stops every process
+// of the jail at once without them being able to notice or to block it, unlike SIGSTOP. The jail needs a cgroup, see
+// JailConf.cgroup_freezer, and the cgroup is the one created by init_parent for the pid of the jail
+
+use super::cgroupv2::{self, parse_flat_keyed};
+use super::config::JailConf;
+use super::error::Result;
+
+use std::fs;
+use std::path::PathBuf;
+
+/// The file that freezes the cgroup of the jail pid, cgroup.freeze or freezer.state
+fn state_file(jconf: &JailConf, pid: &str) -> Result<PathBuf> {
+    if !jconf.clone_newcgroup {
+        return Err(format!("jail {} has no cgroup, it cannot be frozen", pid).into());
+    }
+    if jconf.use_cgroupv2 {
+        if !cgroupv2::has_cgroup(jconf) {
+            return Err(format!("jail {} has no cgroup, it cannot be frozen", pid).into());
+        }
+        Ok(jconf.get_cgroup_v2_path(pid).join("cgroup.freeze"))
+    } else {
+        if !jconf.cgroup_freezer {
+            return Err(format!("jail {} has no freezer cgroup", pid).into());
+        }
+        Ok(jconf.get_freezer_cgroup_v1_path(pid).join("freezer.state"))
+    }
+}
+
+fn write_state(jconf: &JailConf, pid: &str, frozen: bool) -> Result<()> {
+    let path = state_file(jconf, pid)?;
+    let value = match (jconf.use_cgroupv2, frozen) {
+        (true, true) => "1",
+        (true, false) => "0",
+        (false, true) => "FROZEN",
+        (false, false) => "THAWED",
+    };
+    fs::write(&path, value)
+        .map_err(|e| format!("could not write {} to {:?}: {}", value, path, e).into())
+}
+
+/// Stops every process of the jail pid. The freezing is asynchronous, is_frozen tells when it is done
+pub fn freeze(jconf: &JailConf, pid: &str) -> Result<()> {
+    write_state(jconf, pid, true)
+}
+
+/// Lets the processes of the jail pid run again
+pub fn thaw(jconf: &JailConf, pid: &str) -> Result<()> {
+    write_state(jconf, pid, false)
+}
+
+/// Whether every process of the jail pid is frozen, false while the freezing is in progress
+pub fn is_frozen(jconf: &JailConf, pid: &str) -> Result<bool> {
+    let path = state_file(jconf, pid)?;
+    if jconf.use_cgroupv2 {
+        // cgroup.freeze is what was asked for, cgroup.events tells whether it is done
+        let events = path.with_file_name("cgroup.events");
+        let content = fs::read_to_string(&events)
+            .map_err(|e| format!("could not read {:?}: {}", events, e))?;
+        Ok(parse_flat_keyed(&content, "frozen") == Some(1))
+    } else {
+        let content =
+            fs::read_to_string(&path).map_err(|e| format!("could not read {:?}: {}", path, e))?;
+        Ok(content.trim() == "FROZEN")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_state_file() {
+        let mut jconf = JailConf {
+            clone_newcgroup: true,
+            ..Default::default()
+        };
+        assert!(state_file(&jconf, "42").is_err());
+
+        jconf.cgroup_freezer = true;
+        assert!(state_file(&jconf, "42")
+            .unwrap()
+            .ends_with("freezer/TOASTAINER/TOASTAINER.42/freezer.state"));
+
+        jconf.use_cgroupv2 = true;
+        assert!(state_file(&jconf, "42")
+            .unwrap()
+            .ends_with("TOASTAINER/TOASTAINER.42/cgroup.freeze"));
+
+        // a cgroup that does not exist
+        assert!(freeze(&jconf, "42").is_err());
+        assert!(is_frozen(&jconf, "42").is_err());
+    }
+}
@@ -1,19 +1,30 @@
 pub mod caps;
+pub mod cgroup;
 pub mod cgroupv1;
 pub mod cgroupv2;
 pub mod config;
 pub mod contain;
 pub mod cpu;
 pub mod error;
+pub mod firewall;
+pub mod freezer;
 pub mod ipc;
+pub mod limits;
+pub mod listen;
 pub mod mnt;
 pub mod net;
+pub mod nsjail_config;
 pub mod pid;
+pub mod portfwd;
+pub mod profile;
 pub mod protobuf;
 pub mod rlimit;
 pub mod sandbox;
 pub mod subproc;
+pub mod supervisor;
+pub mod usage;
 pub mod user;
+pub mod usernet;
 pub mod utils;
 pub mod uts;
 pub mod wait;
@@ -21,6 +32,7 @@ pub mod wait;
 /// init_package must be called only once and not concurrently at beginning of time before any execution
 pub fn init_package(non_root_owner: libc::uid_t, non_root_group: libc::gid_t) {
     unsafe { config::init_statics() };
+    cgroup::host();
 
     mnt::init_mount_folder(non_root_owner, non_root_group);
 }
@@ -0,0 +1,188 @@
+// Detection of the cgroup limits a jail ran into: the OOM killer of its memory cgroup and the forks refused by pids.max.
+// The counters are read once the jail exited, see usage.rs, and a monitor with an event loop, like the scheduler, can
+// also learn about them as they happen through the fds watch_from_parent opens
+
+use super::cgroupv2::parse_flat_keyed;
+use super::config::JailConf;
+use super::error::Result;
+
+use std::ffi::CString;
+use std::fmt;
+use std::fs;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+
+use sys_util::errno::Errno;
+use utils::filepath::path_to_bytes;
+
+/// Limits of the cgroups of a jail that were hit, as counted by the kernel
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LimitHits {
+    pub oom_kills: u64, // processes killed by the OOM killer of the memory cgroup
+    pub pids_max: u64,  // forks that failed because of pids.max
+}
+
+impl LimitHits {
+    pub fn any(&self) -> bool {
+        self.oom_kills > 0 || self.pids_max > 0
+    }
+}
+
+impl fmt::Display for LimitHits {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} OOM kills, {} forks refused by pids.max",
+            self.oom_kills, self.pids_max
+        )
+    }
+}
+
+/// The memory.events, or memory.oom_control on cgroup v1, and the pids.events of the cgroups of pid that exist
+fn event_files(jconf: &JailConf, pid: &str) -> (Option<PathBuf>, Option<PathBuf>) {
+    if !jconf.clone_newcgroup {
+        return (None, None);
+    }
+    if jconf.use_cgroupv2 {
+        let dir = jconf.get_cgroup_v2_path(pid);
+        (
+            (jconf.cgroup_mem_max != 0 || jconf.cgroup_mem_high != 0)
+                .then(|| dir.join("memory.events")),
+            (jconf.cgroup_pids_max != 0).then(|| dir.join("pids.events")),
+        )
+    } else {
+        (
+            // the oom_kill count of memory.oom_control needs linux 4.13
+            (jconf.cgroup_mem_max != 0)
+                .then(|| jconf.get_mem_cgroup_v1_path(pid).join("memory.oom_control")),
+            (jconf.cgroup_pids_max != 0)
+                .then(|| jconf.get_pids_cgroup_v1_path(pid).join("pids.events")),
+        )
+    }
+}
+
+/// Current counters of the cgroups of pid, to call before they are removed. Unreadable files count as 0
+pub fn read_hits(jconf: &JailConf, pid: &str) -> LimitHits {
+    let read = |path: &Path, key: &str| {
+        fs::read_to_string(path)
+            .ok()
+            .and_then(|c| parse_flat_keyed(&c, key))
+            .unwrap_or(0)
+    };
+    let (mem, pids) = event_files(jconf, pid);
+    LimitHits {
+        oom_kills: mem.map(|path| read(&path, "oom_kill")).unwrap_or(0),
+        pids_max: pids.map(|path| read(&path, "max")).unwrap_or(0),
+    }
+}
+
+/**
+ * Opens in jconf.limit_event_fds the non blocking fds that become readable when the jail hits a limit: an inotify of
+ * the modifications of memory.events and pids.events on cgroup v2, and on cgroup v1 an eventfd registered in
+ * cgroup.event_control for OOM notifications along with an inotify of pids.events. After each wake-up, drain the fd
+ * and call read_hits. The fds are closed by clean_after_child
+ */
+pub fn watch_from_parent(jconf: &mut JailConf, pid: &str) -> Result<()> {
+    let (mem, pids) = event_files(jconf, pid);
+
+    let mut inotify_paths = vec![];
+    if let Some(pids) = pids {
+        inotify_paths.push(pids);
+    }
+    match mem {
+        Some(path) if jconf.use_cgroupv2 => inotify_paths.push(path),
+        Some(path) => {
+            let efd = oom_eventfd(&path)?;
+            jconf.limit_event_fds.push(efd);
+        }
+        None => {}
+    }
+
+    if !inotify_paths.is_empty() {
+        let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err((
+                "could not create an inotify for the cgroup events",
+                Errno::last(),
+            )
+                .into());
+        }
+        jconf.limit_event_fds.push(fd);
+        for path in inotify_paths.iter() {
+            let cpath = CString::new(path_to_bytes(path)).unwrap();
+            if unsafe { libc::inotify_add_watch(fd, cpath.as_ptr(), libc::IN_MODIFY) } < 0 {
+                return Err(format!("could not watch {:?}: {}", path, Errno::last()).into());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// cgroup v1 OOM notifications: writing "<eventfd> <fd of memory.oom_control>" to cgroup.event_control
+fn oom_eventfd(oom_control: &Path) -> Result<libc::c_int> {
+    let efd = unsafe { libc::eventfd(0, libc::EFD_NONBLOCK | libc::EFD_CLOEXEC) };
+    if efd < 0 {
+        return Err(("could not create the OOM eventfd", Errno::last()).into());
+    }
+    let res = fs::File::open(oom_control).and_then(|oom| {
+        fs::write(
+            oom_control.with_file_name("cgroup.event_control"),
+            format!("{} {}", efd, oom.as_raw_fd()),
+        )
+    });
+    if let Err(e) = res {
+        unsafe { libc::close(efd) };
+        return Err(format!(
+            "could not register for the OOM events of {:?}: {}",
+            oom_control, e
+        )
+        .into());
+    }
+    Ok(efd)
+}
+
+/// Consumes what made fd readable, one of jconf.limit_event_fds
+pub fn drain(fd: libc::c_int) {
+    let mut buf = [0u8; 4096];
+    while unsafe { libc::read(fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) } > 0 {}
+}
+
+pub fn finish_from_parent(jconf: &JailConf) {
+    for fd in jconf.limit_event_fds.iter() {
+        unsafe { libc::close(*fd) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_files() {
+        let mut jconf = JailConf {
+            clone_newcgroup: true,
+            cgroup_mem_max: 1 << 26,
+            cgroup_pids_max: 16,
+            ..Default::default()
+        };
+        let (mem, pids) = event_files(&jconf, "42");
+        let mem = mem.unwrap();
+        assert!(
+            mem.ends_with("TOASTAINER.42/memory.oom_control"),
+            "{:?}",
+            mem
+        );
+        assert!(pids.unwrap().ends_with("TOASTAINER.42/pids.events"));
+
+        jconf.use_cgroupv2 = true;
+        jconf.cgroup_pids_max = 0;
+        let (mem, pids) = event_files(&jconf, "42");
+        assert!(mem.unwrap().ends_with("TOASTAINER.42/memory.events"));
+        assert!(pids.is_none());
+
+        // counters of a cgroup that does not exist
+        assert_eq!(read_hits(&jconf, "42"), LimitHits::default());
+        assert!(!LimitHits::default().any());
+    }
+}
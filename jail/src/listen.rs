@@ -0,0 +1,380 @@
+// Listen mode, the MODE_LISTEN_TCP of nsjail: the monitor accepts TCP connections on bind_host:port and clones a fresh
+// jail for every one of them, with the connection as its stdin, stdout and stderr, like inetd. Meant for CTF-style
+// challenges where each player gets a jail of their own
+
+use super::config::JailConf;
+use super::error::{Error, Result};
+use super::subproc;
+use super::supervisor;
+use super::usage::ResourceUsage;
+use super::wait::{self, ExitReport};
+
+use std::collections::HashMap;
+use std::io;
+use std::mem;
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::time::{Duration, Instant};
+
+use sys_util::errno::Errno;
+
+// how often jails are reaped and their time limit checked when no connection comes in
+const TICK_MS: libc::c_int = 200;
+
+/// A jail serving one connection
+struct Conn<'a> {
+    jconf: JailConf<'a>,
+    start: Instant,
+    // next step of the time limit: SIGTERM at tlimit, then SIGKILL tlimit_grace seconds later
+    deadline: Option<Instant>,
+    timed_out: bool,
+    // seccomp listener of the jail, -1 once every process of the jail is gone or when it has no seccomp supervisor
+    listener: RawFd,
+}
+
+/// Address listened on in listen mode
+pub fn listen_addr(jconf: &JailConf) -> Result<SocketAddr> {
+    let ip: IpAddr = jconf.bind_host.parse().map_err(|_| {
+        format!(
+            "invalid bind_host {:?}, expected an IP address",
+            jconf.bind_host
+        )
+    })?;
+    if jconf.port == 0 || jconf.port > u16::MAX as u32 {
+        return Err(format!("invalid listen port {}", jconf.port).into());
+    }
+    Ok(SocketAddr::new(ip, jconf.port as u16))
+}
+
+/// IPv4 clients of a socket listening on [::] show up as ::ffff:a.b.c.d, they are counted and logged as a.b.c.d
+fn client_addr(addr: SocketAddr) -> SocketAddr {
+    match addr.ip() {
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(v4) => SocketAddr::new(IpAddr::V4(v4), addr.port()),
+            None => addr,
+        },
+        IpAddr::V4(_) => addr,
+    }
+}
+
+/// Number of running jails serving a client at ip
+pub fn conns_from(jconf: &JailConf, ip: IpAddr) -> usize {
+    jconf
+        .pids
+        .iter()
+        .filter(|p| p.remote_addr.map(|a| a.ip()) == Some(ip))
+        .count()
+}
+
+/**
+ * Serves bind_host:port forever, one jail per connection. Each jail runs with a clone of jconf and is recorded in
+ * jconf.pids along with the address of its client, at most max_conns_per_ip at the same time per client address
+ *
+ * tlimit applies to every jail on its own, and so does the seccomp supervisor: with seccomp_learn every jail writes
+ * the policy it learnt once it exits. Only an error on the listening socket makes run_listen return, a jail that
+ * cannot be started is logged and its connection closed
+ */
+pub fn run_listen(
+    jconf: &mut JailConf,
+    callback: extern "C" fn(*mut libc::c_void) -> libc::c_int,
+) -> Result<()> {
+    // as in run_monitor_child, killing the init of a jail must take all its processes down with it
+    jconf.clone_newpid();
+
+    let addr = listen_addr(jconf)?;
+    let listener =
+        TcpListener::bind(addr).map_err(|e| format!("could not listen on {}: {}", addr, e))?;
+    listener.set_nonblocking(true)?;
+    println!("listening on {}", addr);
+
+    let mut conns: HashMap<libc::pid_t, Conn> = HashMap::new();
+    loop {
+        poll(listener.as_raw_fd(), &mut conns, TICK_MS)?;
+
+        loop {
+            match listener.accept() {
+                Ok((stream, remote)) => {
+                    spawn(jconf, &mut conns, stream, client_addr(remote), callback)
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    // e.g. out of fds, the connection waits in the backlog until a jail exits
+                    println!("could not accept a connection: {}", e);
+                    break;
+                }
+            }
+        }
+
+        reap(jconf, &mut conns);
+        enforce_tlimit(&mut conns, jconf.tlimit_grace);
+    }
+}
+
+/**
+ * Waits for a connection or a syscall waiting for the seccomp supervisor of a jail, at most timeout milliseconds, and
+ * answers the syscalls as wait::wait_loop does for a single jail
+ */
+fn poll(
+    listener: RawFd,
+    conns: &mut HashMap<libc::pid_t, Conn>,
+    timeout: libc::c_int,
+) -> Result<()> {
+    let supervised: Vec<libc::pid_t> = conns
+        .iter()
+        .filter(|(_, conn)| conn.listener >= 0)
+        .map(|(pid, _)| *pid)
+        .collect();
+    let mut pfds: Vec<libc::pollfd> = Some(listener)
+        .into_iter()
+        .chain(supervised.iter().map(|pid| conns[pid].listener))
+        .map(|fd| libc::pollfd {
+            fd,
+            events: libc::POLLIN,
+            revents: 0,
+        })
+        .collect();
+    if unsafe { libc::poll(pfds.as_mut_ptr(), pfds.len() as libc::nfds_t, timeout) } < 0 {
+        if Errno::last() == Errno::EINTR {
+            return Ok(());
+        }
+        return Err(format!("could not poll the listening socket: {}", Errno::last()).into());
+    }
+
+    for (pid, pfd) in supervised.iter().zip(pfds[1..].iter()) {
+        let conn = conns.get_mut(pid).unwrap();
+        if pfd.revents & libc::POLLIN != 0 {
+            if let Err(e) = supervisor::handle(&mut conn.jconf) {
+                // the syscalls waiting for it fail with ENOSYS rather than forever
+                println!("WARNING: stopping the seccomp supervisor of {}: {}", pid, e);
+                supervisor::finish_from_parent(&conn.jconf);
+                conn.jconf.seccomp_notify_fd = None;
+                conn.listener = -1;
+            }
+        } else if pfd.revents != 0 {
+            conn.listener = -1;
+        }
+    }
+    Ok(())
+}
+
+fn spawn<'a>(
+    jconf: &mut JailConf<'a>,
+    conns: &mut HashMap<libc::pid_t, Conn<'a>>,
+    stream: TcpStream,
+    remote: SocketAddr,
+    callback: extern "C" fn(*mut libc::c_void) -> libc::c_int,
+) {
+    if jconf.max_conns_per_ip > 0
+        && conns_from(jconf, remote.ip()) >= jconf.max_conns_per_ip as usize
+    {
+        println!("{}: too many connections from this address, closed", remote);
+        return;
+    }
+
+    let mut conf = jconf.clone();
+    conf.pids = vec![];
+    let fd = stream.as_raw_fd();
+    conf.fd_in = fd;
+    conf.fd_out = fd;
+    conf.fd_err = fd;
+
+    let res = subproc::run_child(&mut conf, callback);
+    // the jail has its own copy of the connection
+    drop(stream);
+
+    let pid = match res {
+        Ok(pid) => pid,
+        Err(Error::ParsePid((pid, _, e))) => {
+            println!("{}: could not start a jail: {}", remote, e);
+            wait::signal_jail(pid, libc::SIGKILL);
+            unsafe { libc::waitpid(pid, std::ptr::null_mut(), 0) };
+            if let Err(e) = subproc::clean_after_child(&conf, pid) {
+                println!("{}: could not clean jail {}: {}", remote, pid, e);
+            }
+            return;
+        }
+        Err(e) => {
+            println!("{}: could not start a jail: {}", remote, e);
+            return;
+        }
+    };
+
+    if let Err(e) = subproc::add_proc_remote(jconf, pid, Some(remote)) {
+        println!("{}: could not record jail {}: {}", remote, pid, e);
+    }
+    println!("{}: jail {} started", remote, pid);
+
+    let start = Instant::now();
+    conns.insert(
+        pid,
+        Conn {
+            listener: conf.seccomp_notify_fd.unwrap_or(-1),
+            jconf: conf,
+            start,
+            deadline: if jconf.tlimit > 0 {
+                Some(start + Duration::from_secs(jconf.tlimit))
+            } else {
+                None
+            },
+            timed_out: false,
+        },
+    );
+}
+
+fn reap(jconf: &mut JailConf, conns: &mut HashMap<libc::pid_t, Conn>) {
+    loop {
+        let mut wait_status: i32 = 0;
+        let mut ru: libc::rusage = unsafe { mem::zeroed() };
+        let pid = unsafe { libc::wait4(-1, &mut wait_status, libc::WNOHANG, &mut ru) };
+        if pid <= 0 {
+            return;
+        }
+        let conn = match conns.remove(&pid) {
+            Some(conn) => conn,
+            None => continue,
+        };
+
+        let mut report = ExitReport::from_wait_status(
+            pid,
+            wait_status,
+            conn.timed_out,
+            jconf.tlimit,
+            conn.start.elapsed(),
+        );
+        report.usage.update(&ResourceUsage::from_rusage(&ru));
+        report.add_supervised_violation(&conn.jconf);
+        report.add_seccomp_violation();
+        let remote = jconf
+            .pids
+            .iter()
+            .find(|p| p.pid == pid)
+            .map(|p| p.remote_txt.clone())
+            .unwrap_or_default();
+
+        match subproc::clean_after_child(&conn.jconf, pid) {
+            Ok(usage) => report.add_usage(&usage),
+            Err(e) => println!("{}: could not clean jail {}: {}", remote, pid, e),
+        }
+        println!("{}: {}, {}", remote, report, report.usage);
+        subproc::remove_proc(jconf, pid);
+    }
+}
+
+fn enforce_tlimit(conns: &mut HashMap<libc::pid_t, Conn>, grace: u64) {
+    let now = Instant::now();
+    for (pid, conn) in conns.iter_mut() {
+        match conn.deadline {
+            Some(d) if now >= d => {}
+            _ => continue,
+        }
+        if !conn.timed_out && grace > 0 {
+            wait::signal_jail(*pid, libc::SIGTERM);
+            conn.deadline = Some(now + Duration::from_secs(grace));
+        } else {
+            wait::signal_jail(*pid, libc::SIGKILL);
+            conn.deadline = None;
+        }
+        conn.timed_out = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::PIDT;
+    use seccomp::policy::compile_policy;
+    use seccomp::SeccompFilter;
+    use std::fs;
+    use std::sync::mpsc;
+    use std::thread;
+
+    #[test]
+    fn test_listen_addr() {
+        let mut jconf = JailConf {
+            port: 31337,
+            ..Default::default()
+        };
+        assert_eq!(
+            listen_addr(&jconf).unwrap(),
+            "[::]:31337".parse::<SocketAddr>().unwrap()
+        );
+
+        jconf.bind_host = "127.0.0.1";
+        assert_eq!(listen_addr(&jconf).unwrap().to_string(), "127.0.0.1:31337");
+
+        jconf.port = 70000;
+        assert!(listen_addr(&jconf).is_err());
+
+        jconf.port = 80;
+        jconf.bind_host = "localhost";
+        let err = listen_addr(&jconf).unwrap_err();
+        assert!(err.to_string().contains("bind_host"), "{}", err);
+    }
+
+    #[test]
+    fn test_conns_from() {
+        let mut jconf = JailConf::default();
+        let remotes = [
+            "[::ffff:10.0.0.1]:4000",
+            "10.0.0.1:4001",
+            "[2001:db8::1]:4000",
+        ];
+        for (i, r) in remotes.iter().enumerate() {
+            let addr = client_addr(r.parse().unwrap());
+            jconf.pids.push(PIDT {
+                pid: i as libc::pid_t + 1,
+                remote_txt: addr.to_string(),
+                remote_addr: Some(addr),
+                ..Default::default()
+            });
+        }
+
+        assert_eq!(jconf.pids[0].remote_txt, "10.0.0.1:4000");
+        assert_eq!(conns_from(&jconf, "10.0.0.1".parse().unwrap()), 2);
+        assert_eq!(conns_from(&jconf, "2001:db8::1".parse().unwrap()), 1);
+        assert_eq!(conns_from(&jconf, "10.0.0.2".parse().unwrap()), 0);
+    }
+
+    #[test]
+    fn test_poll_supervises() {
+        let path = std::env::temp_dir().join(format!("listen-learn-{}.policy", std::process::id()));
+        let mut jconf = JailConf {
+            seccomp_learn: path.to_str().unwrap(),
+            ..Default::default()
+        };
+
+        let (tx, rx) = mpsc::channel();
+        let supervised = thread::spawn(move || {
+            let filter = compile_policy("USER_NOTIF { getppid } DEFAULT ALLOW").unwrap();
+            tx.send(SeccompFilter::apply_with_listener(filter).unwrap())
+                .unwrap();
+            unsafe { libc::getppid() };
+        });
+        supervisor::start_from_parent(&mut jconf, rx.recv().unwrap()).unwrap();
+
+        let mut conns = HashMap::new();
+        conns.insert(
+            1,
+            Conn {
+                listener: jconf.seccomp_notify_fd.unwrap(),
+                jconf,
+                start: Instant::now(),
+                deadline: None,
+                timed_out: false,
+            },
+        );
+        // no connection to accept, the getppid of the jail is answered
+        poll(-1, &mut conns, 5000).unwrap();
+        supervised.join().unwrap();
+
+        // the listener hangs up once the filtered thread is gone
+        poll(-1, &mut conns, 5000).unwrap();
+        assert_eq!(conns[&1].listener, -1);
+
+        supervisor::finish_from_parent(&conns[&1].jconf);
+        let policy = fs::read_to_string(&path).unwrap();
+        assert!(policy.contains("getppid"), "{}", policy);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,306 +1,760 @@
-use std::env;
-use std::ffi::{CStr, CString};
+use std::process;
 
-use jail::config::{IDMapT, JailConf, MountT, MultiNetConfig, NSSIGS, PIDT};
+use clap::{App, AppSettings, Arg, ArgMatches};
+
+use jail::config::JailConf;
+use jail::error::Result;
 use jail::init_package;
-use jail::subproc::{
-    child, run_monitor_child, subproc_new_proc, subproc_new_proc_exec, subproc_new_proc_setup,
+use jail::listen;
+use jail::nsjail_config;
+use jail::profile::{
+    CapValue, EgressProfile, IdMapProfile, JailProfile, MacvlanProfile, MountProfile,
+    NamespacesProfile, RestartProfile, RlimitValue, RlimitsProfile, SeccompProfile, UserNetProfile,
+    VethProfile,
 };
+use jail::subproc::{child, run_monitor_child, subproc_new_proc_exec, subproc_new_proc_setup};
+use jail::utils::write_to_fd;
+use jail::wait::TermReason;
 
-use jail::contain::{self, setup_fd};
-use jail::error::{Error, Result};
-use jail::protobuf::create_pooled_wake_up_mess;
-use jail::utils::{
-    read_from_fd_ignore_err, to_exec_array, to_exec_array_cstring, write_message_to_fd, write_to_fd,
-};
-use jail::{cgroupv1, cgroupv2, net, sandbox, user};
+fn main() {
+    init_package(1000, 1000);
 
-use sys_util::execv;
+    let matches = cli().get_matches();
 
-use seccomp::*;
+    match run(&matches) {
+        Ok(code) => process::exit(code),
+        Err(e) => {
+            eprintln!("nsjail: {}", e);
+            process::exit(255);
+        }
+    }
+}
 
-use std::convert::TryInto;
+// boolean options take an optional value, so both `--keep_caps` and `--keep_caps false` work
+fn bool_arg(name: &'static str) -> Arg<'static, 'static> {
+    Arg::with_name(name)
+        .long(name)
+        .takes_value(true)
+        .min_values(0)
+        .max_values(1)
+        .value_name("BOOL")
+}
 
-fn main() {
-    init_package(1000, 1000);
+fn value_arg(name: &'static str, value_name: &'static str) -> Arg<'static, 'static> {
+    Arg::with_name(name)
+        .long(name)
+        .takes_value(true)
+        .value_name(value_name)
+}
+
+fn multi_arg(name: &'static str, value_name: &'static str) -> Arg<'static, 'static> {
+    value_arg(name, value_name)
+        .multiple(true)
+        .number_of_values(1)
+}
+
+fn cli() -> App<'static, 'static> {
+    App::new("nsjail")
+        .about("Runs a command inside a toastainer jail")
+        .usage("nsjail [OPTIONS] [-- <command> [args]...]")
+        .setting(AppSettings::UnifiedHelpMessage)
+        .setting(AppSettings::DeriveDisplayOrder)
+        .args(&[
+            value_arg("config", "FILE").help(
+                "Profile to start from (.toml, .json, or an nsjail .cfg text-proto), flags override it",
+            ),
+            // command
+            value_arg("command", "PATH").help("Binary to execute (default: /bin/sh -i)"),
+            multi_arg("commandArg", "ARG").help("argv of the command, argv[0] included"),
+            multi_arg("env", "VAR=value").help("Environment variable, can be repeated"),
+            bool_arg("keep_env").help("Pass the current environment to the jail (default: true)"),
+            bool_arg("do_not_keep_env").help("Same as --keep_env false"),
+            value_arg("cwd", "DIR").help("Directory the command runs in (default: /)"),
+            value_arg("time_limit", "SECS").help("Wall time limit, 0 means no limit"),
+            value_arg("time_limit_grace", "SECS")
+                .help("Seconds between the SIGTERM and the SIGKILL once time_limit is reached (default: 2)"),
+            value_arg("restart", "POLICY")
+                .possible_values(&["always", "on_failure"])
+                .help("Start the command again once it exits, or only when it fails"),
+            value_arg("max_restarts", "N").help("Stop restarting after N restarts (default: 0, no limit)"),
+            value_arg("restart_backoff", "MS")
+                .help("Delay before the first restart in a row, doubled for each next one (default: 1000)"),
+            value_arg("restart_backoff_max", "MS").help("Maximum delay between two restarts (default: 60000)"),
+            // filesystem
+            value_arg("chroot", "DIR").help("Directory containing / of the jail (default: /)"),
+            bool_arg("rw").help("Mount the chroot read/write (default: true)"),
+            bool_arg("proc_rw").help("Mount /proc read/write (default: true)"),
+            bool_arg("disable_proc").help("Do not mount /proc"),
+            multi_arg("bindmount", "SRC[:DST]").help("Read/write bind mount"),
+            multi_arg("bindmount_ro", "SRC[:DST]").help("Read only bind mount"),
+            multi_arg("mount_readonly", "SRC:DST").help("Same as --bindmount_ro"),
+            multi_arg("tmpfsmount", "DST[:OPTIONS]")
+                .help("tmpfs mount, e.g. /tmp:size=16m (default options: size=4194304)"),
+            bool_arg("mount_dev_pts").help("Mount a devpts filesystem on /dev/pts"),
+            // identity and privileges
+            value_arg("hostname", "NAME").help("UTS hostname (default: toastate)"),
+            value_arg("inside_uid", "UID").help("uid of the command inside the jail"),
+            value_arg("inside_gid", "GID").help("gid of the command inside the jail"),
+            value_arg("uid_map", "IN:OUT[:COUNT],...").help("uid mappings (default: 0:0,1000:1000)"),
+            value_arg("gid_map", "IN:OUT[:COUNT],...").help("gid mappings (default: 0:0,1000:1000)"),
+            bool_arg("keep_caps").help("Keep the capabilities of the parent (default: true)"),
+            multi_arg("cap", "CAP").help(
+                "Capability to retain, by name (CAP_NET_BIND_SERVICE) or number, can be repeated, implies --keep_caps false unless given",
+            ),
+            value_arg("nice_level", "NICE").help("Niceness, from -20 to 19"),
+            value_arg("max_cpus", "NUM").help("Maximum number of CPUs the jail can use"),
+            // namespaces
+            bool_arg("clone_newnet").help("New network namespace (default: true)"),
+            bool_arg("clone_newuser").help("New user namespace (default: false)"),
+            bool_arg("clone_newns").help("New mount namespace (default: true)"),
+            bool_arg("clone_newpid").help("New pid namespace (default: true)"),
+            bool_arg("clone_newipc").help("New ipc namespace (default: true)"),
+            bool_arg("clone_newuts").help("New uts namespace (default: true)"),
+            bool_arg("clone_newcgroup").help("New cgroup namespace (default: true)"),
+            // rlimits
+            bool_arg("disable_rlimits").help("Do not set any rlimit"),
+            value_arg("rlimit_as", "MB|soft|hard|inf").help("RLIMIT_AS in MB (default: hard)"),
+            value_arg("rlimit_core", "MB|soft|hard|inf").help("RLIMIT_CORE in MB"),
+            value_arg("rlimit_cpu", "SECS|soft|hard|inf").help("RLIMIT_CPU in seconds"),
+            value_arg("rlimit_fsize", "MB|soft|hard|inf").help("RLIMIT_FSIZE in MB"),
+            value_arg("rlimit_nofile", "NUM|soft|hard|inf").help("RLIMIT_NOFILE"),
+            value_arg("rlimit_nproc", "NUM|soft|hard|inf").help("RLIMIT_NPROC"),
+            value_arg("rlimit_stack", "MB|soft|hard|inf").help("RLIMIT_STACK in MB"),
+            // cgroups
+            bool_arg("use_cgroupv2")
+                .help("Use cgroup v2 instead of v1, detected from the host when not set"),
+            value_arg("cgroupv2_mount", "DIR").help("cgroup v2 mount point"),
+            value_arg("cgroup_mem_max", "BYTES").help("Memory limit, 0 means none"),
+            value_arg("cgroup_mem_mount", "DIR"),
+            value_arg("cgroup_mem_parent", "NAME"),
+            value_arg("cgroup_pids_max", "NUM").help("Maximum number of pids, 0 means none"),
+            value_arg("cgroup_pids_mount", "DIR"),
+            value_arg("cgroup_pids_parent", "NAME"),
+            value_arg("cgroup_net_cls_classid", "ID").help("net_cls classid, 0 means none"),
+            value_arg("cgroup_net_cls_mount", "DIR"),
+            value_arg("cgroup_net_cls_parent", "NAME"),
+            value_arg("cgroup_cpu_ms_per_sec", "MS").help("CPU time per second, 0 means none"),
+            value_arg("cgroup_cpu_mount", "DIR"),
+            value_arg("cgroup_cpu_parent", "NAME"),
+            value_arg("cgroupv2_parent", "PATH")
+                .help("cgroup v2 the jail cgroups are created in, may be nested (default: TOASTAINER)"),
+            value_arg("cgroup_mem_high", "BYTES").help("cgroup v2 memory.high, 0 means none"),
+            value_arg("cgroup_mem_swap_max", "BYTES").help("cgroup v2 memory.swap.max"),
+            value_arg("cgroup_io_weight", "1-10000").help("cgroup v2 io.weight, 0 means none"),
+            multi_arg("cgroup_io_max", "DEVICE[,rbps=N][,wbps=N][,riops=N][,wiops=N]")
+                .help("cgroup v2 io.max of a block device, e.g. /dev/sda,wbps=1048576"),
+            value_arg("cgroup_cpuset_cpus", "CPUS").help("cgroup v2 cpuset.cpus, e.g. 0-3"),
+            value_arg("cgroup_cpuset_mems", "NODES").help("cgroup v2 cpuset.mems"),
+            multi_arg("cgroup_hugetlb_max", "SIZE=BYTES").help("cgroup v2 hugetlb limit, e.g. 2MB=134217728"),
+            // seccomp
+            value_arg("seccomp_policy", "FILE")
+                .help("Seccomp policy, the [seccomp] table of a profile in its own .toml/.json file"),
+            value_arg("seccomp_string", "POLICY")
+                .help("Seccomp policy in the kafel-like language of seccomp::policy, e.g. 'ERRNO(1) { ptrace } DEFAULT ALLOW'"),
+            value_arg("seccomp_profile", "NAME")
+                .help("Built-in seccomp profile: default, static, python, nodejs or no-network"),
+            bool_arg("seccomp_log").help("Log seccomp violations"),
+            value_arg("seccomp_learn", "FILE")
+                .help("Let every syscall through and write a policy allowing the ones the jail made to FILE once it exits, without any other seccomp option"),
+            // network
+            bool_arg("iface_no_lo").help("Do not bring up the lo interface"),
+            multi_arg("iface_vs", "IFACE").help("Interface cloned (MACVLAN) inside the jail"),
+            multi_arg("iface_vs_ip", "IP").help("IP of the matching --iface_vs"),
+            multi_arg("iface_vs_nm", "NETMASK").help("Netmask of the matching --iface_vs"),
+            multi_arg("iface_vs_gw", "IP").help("Gateway of the matching --iface_vs"),
+            multi_arg("iface_vs_ma", "MAC").help("MAC address of the matching --iface_vs"),
+            multi_arg("iface_vs_ip6", "IP/LEN")
+                .help("IPv6 address of the matching --iface_vs for dual-stack, /64 if the prefix length is missing"),
+            multi_arg("iface_vs_gw6", "IP").help("IPv6 gateway of the matching --iface_vs"),
+            bool_arg("iface_no_ipv6").help("Disable IPv6 inside the jail"),
+            multi_arg("iface_vs_mode", "MODE")
+                .possible_values(&["macvlan", "veth"])
+                .help("macvlan (default), or veth to attach the matching --iface_vs bridge through a veth pair. Veth interfaces are numbered after the macvlan ones"),
+            multi_arg("egress_allow", "CIDR[,tcp|udp][,PORT[-PORT]]")
+                .help("Only let the jail reach these destinations, e.g. 10.0.0.0/8,tcp,443"),
+            bool_arg("egress_dns_only").help("Only let the jail send DNS queries, to the --egress_allow networks if any"),
+            bool_arg("egress_allow_link_local")
+                .help("Do not block link-local destinations such as the 169.254.169.254 metadata service when an egress policy is set"),
+            bool_arg("user_net")
+                .help("User-mode network without root: a tap device served by the monitor, with the network access of the calling user"),
+            value_arg("user_net_ip", "IP/LEN").help("Address of the jail on the user-mode network (default: 10.0.2.100/24)"),
+            bool_arg("user_net_host_loopback")
+                .help("Let the jail reach the host loopback through the user-mode network gateway, 10.0.2.2 by default"),
+            multi_arg("forward", "[HOST_IP:]HOST_PORT:JAIL_PORT[/tcp|/udp]")
+                .help("Forward a host port to a port of the jail loopback, e.g. 8080:80"),
+            value_arg("port", "PORT")
+                .help("Listen mode: accept TCP connections on --bind_host:PORT and run one jail per connection, on its stdin/stdout/stderr"),
+            value_arg("bind_host", "IP").help("Host address of --port and of the --forward without one (default: ::)"),
+            value_arg("max_conns_per_ip", "N")
+                .help("Maximum concurrent TCP connections per client IP, to --port or forwarded (default: 0, no limit)"),
+            // misc
+            bool_arg("debug").help("Print debug information (default: true)"),
+            bool_arg("silent").help("Redirect the command stdio to /dev/null"),
+            bool_arg("handle_double_virt").help("Let the command create its own mount namespaces"),
+            value_arg("self_path", "PATH").hidden(true), // kept for compatibility, unused
+            Arg::with_name("cmd")
+                .multiple(true)
+                .last(true)
+                .value_name("command")
+                .help("Command and its arguments, instead of --command/--commandArg"),
+        ])
+}
 
-    let args: Vec<String> = env::args().collect();
-
-    let mut command = CString::new("/bin/sh").unwrap();
-    let mut arguments = vec![
-        CString::new("/bin/sh").unwrap(),
-        CString::new("-i").unwrap(),
-    ];
-    let mut chroot = "/";
-    let mut mount_dev_pts = false;
-    let mut mount_readonly: Vec<CString> = vec![];
-    let mut cwd = "/";
-    let mut selfPath = "";
-    let mut do_not_keep_env = false;
-    let mut handle_double_virt = false;
-    let mut inside_uid = 0;
-    let mut inside_gid = 0;
-    let mut uid_map = vec![0, 0, 1000, 1000];
-    let mut gid_map = vec![0, 0, 1000, 1000];
-    let mut multi_net = MultiNetConfig {
-        iface_vs: vec![],
-        iface_vs_ip: vec![],
-        iface_vs_nm: vec![],
-        iface_vs_gw: vec![],
-        iface_vs_ma: vec![],
+fn run(m: &ArgMatches) -> Result<i32> {
+    let mut profile = match m.value_of("config") {
+        Some(path) if is_nsjail_config(path) => {
+            let imp = nsjail_config::import_file(path)?;
+            for w in imp.warnings.iter() {
+                println!("WARNING: {}", w);
+            }
+            imp.profile
+        }
+        Some(path) => JailProfile::from_file(path)?,
+        None => cli_defaults(),
     };
-    let mut env: Vec<CString> = vec![];
 
-    if args.len() < 3 {
-        panic!("not enough arguments")
+    apply_flags(m, &mut profile)?;
+
+    let mut jconf = profile.to_jail_conf()?;
+    jconf.prepare_env_in_child = true;
+
+    let callback = if !parse_bool(m, "handle_double_virt")?.unwrap_or(false) {
+        child
+    } else {
+        unsecure_double_virt_child
+    };
+    if jconf.port != 0 {
+        listen::run_listen(&mut jconf, callback)?;
+        return Ok(0);
     }
 
-    if (args.len() - 1) % 2 != 0 {
-        panic!("odd number of arguments: {}", args.len());
+    let report = run_monitor_child(&mut jconf, callback)?;
+    // run_monitor_child already printed every exit of a restarted jail
+    if report.reason != TermReason::Exited && jconf.restart.is_none() {
+        eprintln!("nsjail: {}", report);
     }
 
-    for i in (1..args.len()).step_by(2) {
-        match args[i].as_str() {
-            "--mount_readonly" => {
-                // src:dst
-                let spl: Vec<&str> = args[i + 1].split(":").collect();
-                if spl.len() != 2 {
-                    panic!(
-                        "invalid --mount_readonly argument: {} {}",
-                        args[i + 1],
-                        spl.len()
-                    );
-                }
-                mount_readonly.push(CString::new(spl[0]).unwrap());
-                mount_readonly.push(CString::new(spl[1]).unwrap());
-            }
-            "--mount_dev_pts" => mount_dev_pts = true,
-            "--command" => {
-                command = CString::new(args[i + 1].clone()).unwrap();
-                arguments = vec![];
-            }
-            "--commandArg" => {
-                arguments.push(CString::new(args[i + 1].clone()).unwrap());
-            }
-            "--chroot" => {
-                chroot = &args[i + 1];
-            }
-            "--inside_uid" => {
-                inside_uid = args[i + 1].parse().unwrap();
-            }
-            "--inside_gid" => {
-                inside_gid = args[i + 1].parse().unwrap();
-            }
-            "--uid_map" => {
-                uid_map = vec![];
-                for pair in args[i + 1].split(",").into_iter() {
-                    let spl: Vec<&str> = pair.split(":").collect();
-                    uid_map.push(spl[0].parse::<u32>().unwrap());
-                    uid_map.push(spl[1].parse::<u32>().unwrap());
-                }
-            }
-            "--gid_map" => {
-                gid_map = vec![];
-                for pair in args[i + 1].split(",").into_iter() {
-                    let spl: Vec<&str> = pair.split(":").collect();
-                    gid_map.push(spl[0].parse::<u32>().unwrap());
-                    gid_map.push(spl[1].parse::<u32>().unwrap());
-                }
-            }
-            "--cwd" => {
-                cwd = &args[i + 1];
-            }
-            "--iface_vs" => {
-                multi_net
-                    .iface_vs
-                    .push(CString::new(args[i + 1].to_owned()).unwrap());
+    Ok(report.status)
+}
+
+fn is_nsjail_config(path: &str) -> bool {
+    path.ends_with(".cfg") || path.ends_with(".proto") || path.ends_with(".pb.txt")
+}
+
+/// what the jail binary does without --config
+fn cli_defaults() -> JailProfile {
+    let hard = || Some(RlimitValue::Special(String::from("hard")));
+    let id_maps = || {
+        vec![
+            IdMapProfile {
+                inside: 0,
+                outside: 0,
+                count: 1,
+                use_newidmap: false,
+            },
+            IdMapProfile {
+                inside: 1000,
+                outside: 1000,
+                count: 1,
+                use_newidmap: false,
+            },
+        ]
+    };
+
+    JailProfile {
+        debug: true,
+        hostname: Some(String::from("toastate")),
+        chroot: Some(String::from("/")),
+        chroot_rw: true,
+        proc_rw: true,
+        keep_env: true,
+        keep_caps: true,
+        skip_setsid: true,
+        disable_no_new_privs: true,
+        inside_uid: Some(0),
+        inside_gid: Some(0),
+        exec_file: Some(String::from("/bin/sh")),
+        argv: vec![String::from("/bin/sh"), String::from("-i")],
+        namespaces: NamespacesProfile {
+            net: true,
+            user: false,
+            mount: true,
+            pid: true,
+            ipc: true,
+            uts: true,
+            cgroup: true,
+        },
+        uid_map: id_maps(),
+        gid_map: id_maps(),
+        // set all soft limits to the hard one
+        rlimits: Some(RlimitsProfile {
+            as_: hard(),
+            core: hard(),
+            cpu: hard(),
+            fsize: hard(),
+            nofile: hard(),
+            nproc: hard(),
+            stack: hard(),
+        }),
+        ..Default::default()
+    }
+}
+
+fn parse_bool(m: &ArgMatches, name: &str) -> Result<Option<bool>> {
+    if !m.is_present(name) {
+        return Ok(None);
+    }
+    match m.value_of(name) {
+        None | Some("true") | Some("1") | Some("yes") => Ok(Some(true)),
+        Some("false") | Some("0") | Some("no") => Ok(Some(false)),
+        Some(v) => Err(format!("--{}: expected true or false, got {:?}", name, v).into()),
+    }
+}
+
+fn parse_num<T: std::str::FromStr>(m: &ArgMatches, name: &str) -> Result<Option<T>> {
+    match m.value_of(name) {
+        None => Ok(None),
+        Some(v) => v
+            .parse::<T>()
+            .map(Some)
+            .map_err(|_| format!("--{}: invalid number {:?}", name, v).into()),
+    }
+}
+
+fn values(m: &ArgMatches, name: &str) -> Vec<String> {
+    m.values_of(name)
+        .map(|v| v.map(String::from).collect())
+        .unwrap_or_default()
+}
+
+fn parse_rlimit(m: &ArgMatches, name: &str) -> Result<Option<RlimitValue>> {
+    match m.value_of(name) {
+        None => Ok(None),
+        Some(v @ "soft") | Some(v @ "hard") | Some(v @ "inf") => {
+            Ok(Some(RlimitValue::Special(v.to_owned())))
+        }
+        Some(_) => Ok(parse_num(m, name)?.map(RlimitValue::Value)),
+    }
+}
+
+fn parse_id_maps(name: &str, v: &str) -> Result<Vec<IdMapProfile>> {
+    let mut maps = vec![];
+    for pair in v.split(',') {
+        let spl: Vec<&str> = pair.split(':').collect();
+        let num = |s: &str| {
+            s.parse::<u32>()
+                .map_err(|_| format!("--{}: invalid mapping {:?}", name, pair))
+        };
+        if spl.len() != 2 && spl.len() != 3 {
+            return Err(format!("--{}: invalid mapping {:?}, expected IN:OUT[:COUNT]", name, pair).into());
+        }
+        maps.push(IdMapProfile {
+            inside: num(spl[0])?,
+            outside: num(spl[1])?,
+            count: if spl.len() == 3 { num(spl[2])? as u64 } else { 1 },
+            use_newidmap: false,
+        });
+    }
+    Ok(maps)
+}
+
+fn bind_mount(name: &str, v: &str, rw: bool) -> Result<MountProfile> {
+    let mut spl = v.splitn(2, ':');
+    let src = spl.next().unwrap_or("");
+    let dst = spl.next().unwrap_or(src);
+    if src.is_empty() || dst.is_empty() {
+        return Err(format!("--{}: invalid mount {:?}, expected SRC[:DST]", name, v).into());
+    }
+    Ok(MountProfile {
+        src: Some(src.to_owned()),
+        dst: dst.to_owned(),
+        fstype: None,
+        options: None,
+        rw,
+        bind: Some(true),
+        is_dir: None,
+        is_symlink: false,
+        mandatory: true,
+        nosuid: false,
+        nodev: false,
+        noexec: false,
+    })
+}
+
+fn fs_mount(dst: &str, fstype: &str, options: &str) -> MountProfile {
+    MountProfile {
+        src: None,
+        dst: dst.to_owned(),
+        fstype: Some(fstype.to_owned()),
+        options: Some(options.to_owned()),
+        rw: true,
+        bind: Some(false),
+        is_dir: Some(true),
+        is_symlink: false,
+        mandatory: true,
+        nosuid: false,
+        nodev: false,
+        noexec: false,
+    }
+}
+
+/// flags override the profile, repeated flags (mounts, env, caps) are appended to it
+fn apply_flags(m: &ArgMatches, p: &mut JailProfile) -> Result<()> {
+    // command
+    let cmd = values(m, "cmd");
+    if !cmd.is_empty() {
+        p.exec_file = Some(cmd[0].clone());
+        p.argv = cmd;
+    } else if let Some(command) = m.value_of("command") {
+        let mut argv = values(m, "commandArg");
+        if argv.is_empty() {
+            argv.push(command.to_owned());
+        }
+        p.exec_file = Some(command.to_owned());
+        p.argv = argv;
+    } else if m.is_present("commandArg") {
+        p.argv.extend(values(m, "commandArg"));
+    }
+    p.env.extend(values(m, "env"));
+    if let Some(b) = parse_bool(m, "keep_env")? {
+        p.keep_env = b;
+    }
+    if let Some(b) = parse_bool(m, "do_not_keep_env")? {
+        p.keep_env = !b;
+    }
+    if let Some(cwd) = m.value_of("cwd") {
+        p.cwd = Some(cwd.to_owned());
+    }
+    if let Some(t) = parse_num(m, "time_limit")? {
+        p.tlimit = t;
+    }
+    if let Some(t) = parse_num(m, "time_limit_grace")? {
+        p.tlimit_grace = Some(t);
+    }
+    let max_restarts = parse_num(m, "max_restarts")?;
+    let backoff = parse_num(m, "restart_backoff")?;
+    let backoff_max = parse_num(m, "restart_backoff_max")?;
+    if m.is_present("restart")
+        || max_restarts.is_some()
+        || backoff.is_some()
+        || backoff_max.is_some()
+    {
+        let restart = p.restart.get_or_insert_with(RestartProfile::default);
+        if let Some(policy) = m.value_of("restart") {
+            restart.policy = policy.to_string();
+        }
+        if let Some(n) = max_restarts {
+            restart.max_restarts = n;
+        }
+        if let Some(ms) = backoff {
+            restart.backoff_ms = ms;
+        }
+        if let Some(ms) = backoff_max {
+            restart.backoff_max_ms = ms;
+        }
+    }
+
+    // filesystem
+    if let Some(chroot) = m.value_of("chroot") {
+        p.chroot = Some(chroot.to_owned());
+    }
+    if let Some(b) = parse_bool(m, "rw")? {
+        p.chroot_rw = b;
+    }
+    if let Some(b) = parse_bool(m, "proc_rw")? {
+        p.proc_rw = b;
+    }
+    if parse_bool(m, "disable_proc")? == Some(true) {
+        p.proc_path = Some(String::new());
+    }
+    for v in values(m, "bindmount") {
+        p.mounts.push(bind_mount("bindmount", &v, true)?);
+    }
+    for v in values(m, "bindmount_ro") {
+        p.mounts.push(bind_mount("bindmount_ro", &v, false)?);
+    }
+    for v in values(m, "mount_readonly") {
+        if !v.contains(':') {
+            return Err(format!("invalid --mount_readonly argument: {}", v).into());
+        }
+        p.mounts.push(bind_mount("mount_readonly", &v, false)?);
+    }
+    for v in values(m, "tmpfsmount") {
+        let mut spl = v.splitn(2, ':');
+        let dst = spl.next().unwrap_or("");
+        let options = spl.next().unwrap_or("size=4194304");
+        p.mounts.push(fs_mount(dst, "tmpfs", options));
+    }
+    if parse_bool(m, "mount_dev_pts")? == Some(true) {
+        p.mounts.push(fs_mount("/dev/pts", "devpts", "gid=4,mode=620"));
+    }
+
+    // identity and privileges
+    if let Some(h) = m.value_of("hostname") {
+        p.hostname = Some(h.to_owned());
+    }
+    if let Some(uid) = parse_num(m, "inside_uid")? {
+        p.inside_uid = Some(uid);
+    }
+    if let Some(gid) = parse_num(m, "inside_gid")? {
+        p.inside_gid = Some(gid);
+    }
+    if let Some(v) = m.value_of("uid_map") {
+        p.uid_map = parse_id_maps("uid_map", v)?;
+    }
+    if let Some(v) = m.value_of("gid_map") {
+        p.gid_map = parse_id_maps("gid_map", v)?;
+    }
+    for v in values(m, "cap") {
+        p.caps.push(CapValue::Name(v.to_owned()));
+        p.keep_caps = false;
+    }
+    if let Some(b) = parse_bool(m, "keep_caps")? {
+        p.keep_caps = b;
+    }
+    if let Some(n) = parse_num(m, "nice_level")? {
+        p.nice_level = Some(n);
+    }
+    if let Some(n) = parse_num(m, "max_cpus")? {
+        p.max_cpus = n;
+    }
+
+    // namespaces
+    let ns = &mut p.namespaces;
+    for (name, field) in [
+        ("clone_newnet", &mut ns.net),
+        ("clone_newuser", &mut ns.user),
+        ("clone_newns", &mut ns.mount),
+        ("clone_newpid", &mut ns.pid),
+        ("clone_newipc", &mut ns.ipc),
+        ("clone_newuts", &mut ns.uts),
+        ("clone_newcgroup", &mut ns.cgroup),
+    ]
+    .iter_mut()
+    {
+        if let Some(b) = parse_bool(m, name)? {
+            **field = b;
+        }
+    }
+
+    // rlimits
+    if parse_bool(m, "disable_rlimits")? == Some(true) {
+        p.rlimits = None;
+    } else {
+        let rl_flags = [
+            "rlimit_as",
+            "rlimit_core",
+            "rlimit_cpu",
+            "rlimit_fsize",
+            "rlimit_nofile",
+            "rlimit_nproc",
+            "rlimit_stack",
+        ];
+        if rl_flags.iter().any(|f| m.is_present(f)) {
+            let rl = p.rlimits.get_or_insert_with(RlimitsProfile::default);
+            if let Some(v) = parse_rlimit(m, "rlimit_as")? {
+                rl.as_ = Some(v);
             }
-            "--iface_vs_ip" => {
-                multi_net
-                    .iface_vs_ip
-                    .push(CString::new(args[i + 1].to_owned()).unwrap());
+            if let Some(v) = parse_rlimit(m, "rlimit_core")? {
+                rl.core = Some(v);
             }
-            "--iface_vs_nm" => {
-                multi_net
-                    .iface_vs_nm
-                    .push(CString::new(args[i + 1].to_owned()).unwrap());
+            if let Some(v) = parse_rlimit(m, "rlimit_cpu")? {
+                rl.cpu = Some(v);
             }
-            "--iface_vs_gw" => {
-                multi_net
-                    .iface_vs_gw
-                    .push(CString::new(args[i + 1].to_owned()).unwrap());
+            if let Some(v) = parse_rlimit(m, "rlimit_fsize")? {
+                rl.fsize = Some(v);
             }
-            "--env" => {
-                env.push(CString::new(CString::new(args[i + 1].to_owned()).unwrap()).unwrap());
+            if let Some(v) = parse_rlimit(m, "rlimit_nofile")? {
+                rl.nofile = Some(v);
             }
-            "--do_not_keep_env" => {
-                do_not_keep_env = true;
+            if let Some(v) = parse_rlimit(m, "rlimit_nproc")? {
+                rl.nproc = Some(v);
             }
-            "--self_path" => {
-                selfPath = &args[i + 1];
+            if let Some(v) = parse_rlimit(m, "rlimit_stack")? {
+                rl.stack = Some(v);
             }
-            "--handle_double_virt" => {
-                handle_double_virt = true;
+        }
+    }
+
+    // cgroups
+    let cg = &mut p.cgroup;
+    if let Some(b) = parse_bool(m, "use_cgroupv2")? {
+        cg.use_v2 = Some(b);
+    }
+    for (name, field) in [
+        ("cgroup_mem_max", &mut cg.mem_max),
+        ("cgroup_pids_max", &mut cg.pids_max),
+        ("cgroup_net_cls_classid", &mut cg.net_cls_classid),
+        ("cgroup_cpu_ms_per_sec", &mut cg.cpu_ms_per_sec),
+        ("cgroup_mem_high", &mut cg.mem_high),
+        ("cgroup_io_weight", &mut cg.io_weight),
+    ]
+    .iter_mut()
+    {
+        if let Some(v) = parse_num(m, name)? {
+            **field = v;
+        }
+    }
+    for (name, field) in [
+        ("cgroupv2_mount", &mut cg.v2_mount),
+        ("cgroup_mem_mount", &mut cg.mem_mount),
+        ("cgroup_mem_parent", &mut cg.mem_parent),
+        ("cgroup_pids_mount", &mut cg.pids_mount),
+        ("cgroup_pids_parent", &mut cg.pids_parent),
+        ("cgroup_net_cls_mount", &mut cg.net_cls_mount),
+        ("cgroup_net_cls_parent", &mut cg.net_cls_parent),
+        ("cgroup_cpu_mount", &mut cg.cpu_mount),
+        ("cgroup_cpu_parent", &mut cg.cpu_parent),
+        ("cgroupv2_parent", &mut cg.v2_parent),
+        ("cgroup_cpuset_cpus", &mut cg.cpuset_cpus),
+        ("cgroup_cpuset_mems", &mut cg.cpuset_mems),
+    ]
+    .iter_mut()
+    {
+        if let Some(v) = m.value_of(name) {
+            **field = Some(v.to_owned());
+        }
+    }
+    if let Some(v) = parse_num(m, "cgroup_mem_swap_max")? {
+        cg.mem_swap_max = Some(v);
+    }
+    cg.io_max.extend(values(m, "cgroup_io_max"));
+    for v in values(m, "cgroup_hugetlb_max") {
+        let mut kv = v.splitn(2, '=');
+        match (kv.next(), kv.next().map(str::parse::<u64>)) {
+            (Some(size), Some(Ok(max))) => {
+                cg.hugetlb_max.insert(size.to_owned(), max);
             }
             _ => {
-                panic!("invalid argument {}", args[i]);
+                return Err(format!(
+                    "--cgroup_hugetlb_max: invalid limit {:?}, expected SIZE=BYTES",
+                    v
+                )
+                .into())
             }
-        };
+        }
     }
 
-    // println!("Chroot: {},CWD: {}, Multinet: {:?}", chroot, cwd, multi_net);
-
-    // Will not be used since multi_net will be defined
-    let iface_vs = CStr::from_bytes_with_nul("\0".as_bytes()).unwrap();
-    let iface_vs_ip = CString::new("0.0.0.0").unwrap();
-    let iface_vs_nm = CStr::from_bytes_with_nul("255.255.255.0\0".as_bytes()).unwrap();
-    let iface_vs_gw = CStr::from_bytes_with_nul("0.0.0.0\0".as_bytes()).unwrap();
-    let iface_vs_ma = CStr::from_bytes_with_nul("\0".as_bytes()).unwrap();
-
-    let mut jconf = JailConf::new_from_root(
-        CString::new(chroot).unwrap(),
-        iface_vs,
-        iface_vs_ip,
-        iface_vs_nm,
-        iface_vs_gw,
-        iface_vs_ma,
-    );
-    jconf.debug = true;
-
-    let filter = SeccompFilter::new(vec![].into_iter().collect(), SeccompAction::Allow)
-        .unwrap()
-        .try_into()
-        .unwrap();
-
-    for i in (0..uid_map.len()).step_by(2) {
-        jconf.uids.push(IDMapT {
-            inside_id: uid_map[i],
-            outside_id: uid_map[i + 1],
-            count: 1,
-            is_newidmap: false,
-        });
+    // seccomp
+    if let Some(path) = m.value_of("seccomp_policy") {
+        p.seccomp = Some(SeccompProfile::from_file(path)?);
     }
-
-    for i in (0..gid_map.len()).step_by(2) {
-        jconf.gids.push(IDMapT {
-            inside_id: gid_map[i],
-            outside_id: gid_map[i + 1],
-            count: 1,
-            is_newidmap: false,
-        });
+    if let Some(policy) = m.value_of("seccomp_string") {
+        p.seccomp.get_or_insert_with(SeccompProfile::default).policy = policy.to_string();
     }
-
-    jconf.user_inside_uid = inside_uid;
-    jconf.user_inside_gid = inside_gid;
-
-    jconf.clone_newipc = true;
-    jconf.clone_newnet = true;
-    jconf.clone_newns = true;
-    jconf.clone_newcgroup = true;
-    jconf.clone_newpid = true;
-
-    jconf.seccomp_filter = Some(filter);
-
-    jconf.clone_newuser = false;
-
-    jconf.prepare_env_in_child = true;
-
-    jconf.keep_caps = true;
-    jconf.keep_env = !do_not_keep_env;
-    jconf.skip_setsid = true;
-    jconf.disable_no_new_privs = true;
-
-    jconf.mnt_ms_slave = false;
-
-    jconf.clone_newuts = true;
-    jconf.hostname = "toastate";
-
-    jconf.is_root_rw = true;
-    jconf.is_proc_rw = true;
-
-    // set all soft limits to the hard one
-    jconf.disable_rl = false;
-    jconf.rl_as = jail::rlimit::get_rlimit64(libc::RLIMIT_AS)
-        .unwrap()
-        .rlim_max;
-    jconf.rl_core = jail::rlimit::get_rlimit64(libc::RLIMIT_CORE)
-        .unwrap()
-        .rlim_max;
-    jconf.rl_cpu = jail::rlimit::get_rlimit64(libc::RLIMIT_CPU)
-        .unwrap()
-        .rlim_max;
-    jconf.rl_fsize = jail::rlimit::get_rlimit64(libc::RLIMIT_FSIZE)
-        .unwrap()
-        .rlim_max;
-    jconf.rl_nofile = jail::rlimit::get_rlimit64(libc::RLIMIT_NOFILE)
-        .unwrap()
-        .rlim_max;
-    jconf.rl_nproc = jail::rlimit::get_rlimit64(libc::RLIMIT_NPROC)
-        .unwrap()
-        .rlim_max;
-    jconf.rl_stack = jail::rlimit::get_rlimit64(libc::RLIMIT_STACK)
-        .unwrap()
-        .rlim_max;
-
-    jconf.cwd = cwd.to_owned();
-
-    jconf.num_cpus = unsafe { libc::sysconf(libc::_SC_NPROCESSORS_ONLN) };
-
-    jconf.iface_lo = true;
-    jconf.multi_net = Some(multi_net);
-
-    jconf.with_default_mounts();
-
-    if mount_dev_pts {
-        jconf.mountpts.push(MountT {
-            src: None,
-            dst: MountT::transform_dst(&jconf, "dev/pts"), // empty string means /, see mnt.rs
-            dst_in_pivot: CString::new("/dev/pts").unwrap(),
-            fs_type: Some(CString::new("devpts").unwrap()),
-            options: Some(CString::new("gid=4,mode=620").unwrap()),
-            flags: 0,
-            is_dir: true,
-            is_mandatory: true,
-            is_symlink: false,
-            mounted: false,
-        });
+    if let Some(name) = m.value_of("seccomp_profile") {
+        p.seccomp.get_or_insert_with(SeccompProfile::default).profile = name.to_string();
+    }
+    if let Some(b) = parse_bool(m, "seccomp_log")? {
+        p.seccomp.get_or_insert_with(SeccompProfile::default).log = b;
+    }
+    if let Some(file) = m.value_of("seccomp_learn") {
+        p.seccomp.get_or_insert_with(SeccompProfile::default).learn = file.to_string();
     }
 
-    if mount_readonly.len() > 0 {
-        for i in (0..mount_readonly.len()).step_by(2) {
-            let src = mount_readonly[i].clone();
-            let mut dst = mount_readonly[i + 1].as_bytes();
-
-            if dst[0] == '/' as u8 {
-                dst = &dst[1..];
+    // network
+    if let Some(b) = parse_bool(m, "iface_no_lo")? {
+        p.net.lo = !b;
+    }
+    if let Some(b) = parse_bool(m, "iface_no_ipv6")? {
+        p.net.ipv6 = !b;
+    }
+    let ifaces = values(m, "iface_vs");
+    if !ifaces.is_empty() {
+        let ips = values(m, "iface_vs_ip");
+        let nms = values(m, "iface_vs_nm");
+        let gws = values(m, "iface_vs_gw");
+        let mas = values(m, "iface_vs_ma");
+        if ips.len() != ifaces.len() {
+            return Err("each --iface_vs needs a matching --iface_vs_ip".into());
+        }
+        let ip6s = values(m, "iface_vs_ip6");
+        let gw6s = values(m, "iface_vs_gw6");
+        let modes = values(m, "iface_vs_mode");
+        for (i, iface) in ifaces.into_iter().enumerate() {
+            let netmask = nms
+                .get(i)
+                .cloned()
+                .unwrap_or_else(|| String::from("255.255.255.0"));
+            let gateway = gws
+                .get(i)
+                .cloned()
+                .unwrap_or_else(|| String::from("0.0.0.0"));
+            let mac = mas.get(i).cloned().unwrap_or_default();
+            let ip6 = ip6s.get(i).cloned().unwrap_or_default();
+            let gateway6 = gw6s.get(i).cloned().unwrap_or_default();
+            if modes.get(i).map(String::as_str) == Some("veth") {
+                p.net.veth.push(VethProfile {
+                    bridge: iface,
+                    ip: ips[i].clone(),
+                    netmask,
+                    gateway,
+                    mac,
+                    ip6,
+                    gateway6,
+                });
+            } else {
+                p.net.macvlan.push(MacvlanProfile {
+                    iface,
+                    ip: ips[i].clone(),
+                    netmask,
+                    gateway,
+                    mac,
+                    ip6,
+                    gateway6,
+                });
             }
+        }
+    }
+    let egress_allow = values(m, "egress_allow");
+    let egress_dns_only = parse_bool(m, "egress_dns_only")?;
+    let egress_allow_link_local = parse_bool(m, "egress_allow_link_local")?;
+    if !egress_allow.is_empty() || egress_dns_only.is_some() || egress_allow_link_local.is_some() {
+        let egress = p.net.egress.get_or_insert_with(EgressProfile::default);
+        egress.allow.extend(egress_allow);
+        if let Some(b) = egress_dns_only {
+            egress.dns_only = b;
+        }
+        if let Some(b) = egress_allow_link_local {
+            egress.block_link_local = !b;
+        }
+    }
 
-            let dst2 = [b"/", dst].concat();
-
-            jconf.mountpts.push(MountT {
-                src: Some(src),
-                dst: MountT::transform_dst_bytes(&jconf, dst), // empty string means /, see mnt.rs
-                dst_in_pivot: CString::new(dst2).unwrap(),
-                fs_type: None,
-                options: None,
-                flags: libc::MS_BIND | libc::MS_REC | libc::MS_PRIVATE | libc::MS_RDONLY,
-                is_dir: true,
-                is_mandatory: true,
-                is_symlink: false,
-                mounted: false,
-            });
+    let user_net_ip = m.value_of("user_net_ip").map(String::from);
+    let user_net_host_loopback = parse_bool(m, "user_net_host_loopback")?;
+    if parse_bool(m, "user_net")? == Some(true)
+        || user_net_ip.is_some()
+        || user_net_host_loopback.is_some()
+    {
+        let user = p.net.user.get_or_insert_with(UserNetProfile::default);
+        if let Some(ip) = user_net_ip {
+            user.ip = ip;
+        }
+        if let Some(b) = user_net_host_loopback {
+            user.host_loopback = b;
         }
     }
 
-    jconf.exec_file = Some(command);
-    jconf.argv = Some(arguments);
-    jconf.env = Some(env);
+    if let Some(n) = parse_num(m, "port")? {
+        p.port = n;
+    }
+    p.net.forward.extend(values(m, "forward"));
+    if let Some(h) = m.value_of("bind_host") {
+        p.bind_host = Some(h.to_string());
+    }
+    if let Some(n) = parse_num(m, "max_conns_per_ip")? {
+        p.max_conns_per_ip = n;
+    }
 
-    if !handle_double_virt {
-        std::process::exit(run_monitor_child(&mut jconf, child).unwrap());
-    } else {
-        std::process::exit(run_monitor_child(&mut jconf, unsecure_double_virt_child).unwrap());
+    // misc
+    if let Some(b) = parse_bool(m, "debug")? {
+        p.debug = b;
     }
+    if let Some(b) = parse_bool(m, "silent")? {
+        p.silent = b;
+    }
+
+    Ok(())
 }
 
 extern "C" fn unsecure_double_virt_child(data: *mut libc::c_void) -> libc::c_int {
@@ -1,101 +1,163 @@
-use super::config::JailConf;
+use super::config::{JailConf, NetMode};
 use super::error::Result;
+use super::usernet;
 use std::ffi::CStr;
-use sys_util::errno::Errno;
-use sys_util::libnl::{link, socket};
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr};
+use sys_util::rtnetlink::{self, NetNs, RtNetlink};
 use sys_util::sched::setns;
 
-const IFACE_NAME_UNDERSCORE: &[u8] = "vs_".as_bytes();
-const IFACE_NAME: &[u8] = "vs\0".as_bytes();
-const LO: &[u8] = "lo\0".as_bytes();
+const IFACE_NAME_UNDERSCORE: &str = "vs_";
+const IFACE_NAME: &str = "vs";
+const VETH_HOST_PREFIX: &str = "vh"; // host end of a veth pair, vh<pid>_<n> so it is unique on the host
+const LO: &str = "lo";
+pub const DEFAULT_IPV6_PREFIX_LEN: u8 = 64;
+// per NET namespace, writing all also disables IPv6 on the interfaces that already exist, lo and the cloned ones
+const DISABLE_IPV6: &[&str] = &[
+    "/proc/sys/net/ipv6/conf/all/disable_ipv6",
+    "/proc/sys/net/ipv6/conf/default/disable_ipv6",
+];
+
+fn cstr<'a>(what: &str, s: &'a CStr) -> Result<&'a str> {
+    s.to_str()
+        .map_err(|_| format!("{}: {:?} is not valid UTF-8", what, s).into())
+}
 
-pub fn clone_iface<'a>(
-    iface_name: &'a CStr,
-    iface_vs: &'a CStr,
-    iface_vs_ma: &'a CStr,
-    sk: &socket::NetlinkSocket,
-    link_cache: &link::NetlinkCache,
-    pid: libc::pid_t,
-) -> bool {
-    let rmv = match link::link_macvlan_alloc() {
-        None => return false,
-        Some(link) => link,
-    };
+fn parse_ip(what: &str, s: &CStr) -> Result<IpAddr> {
+    let s = cstr(what, s)?;
+    s.parse()
+        .map_err(|_| format!("{}: invalid IP address {:?}", what, s).into())
+}
 
-    let master_index = match link::link_name2i(link_cache, iface_vs) {
-        None => {
-            link::link_put(rmv);
-            return false;
-        }
-        Some(index) => index,
+/// Parses "<address>/<prefix length>", or a bare address which then gets default_len, e.g. "fd00:7a::2/64"
+pub fn parse_cidr(s: &str, default_len: Option<u8>) -> Option<(IpAddr, u8)> {
+    let mut parts = s.splitn(2, '/');
+    let addr: IpAddr = parts.next()?.parse().ok()?;
+    let len = match parts.next() {
+        Some(len) => len.parse().ok()?,
+        None => default_len?,
     };
-
-    link::link_set_name(&rmv, iface_name);
-    link::link_set_link(&rmv, master_index);
-    link::link_set_ns_pid(&rmv, pid);
-
-    if !iface_vs_ma.to_bytes().is_empty() {
-        // iface_vs_ma, which is --macvlan_vs_ma in nsjail cmdline, MAC-address of the 'vs' interface (e.g. "ba:ad:ba:be:45:00"),
-        // is empty for now when executing a Toaster. So,
-        // Todo: complete
-
-        /* nsjail
-            struct nl_addr* nladdr = nullptr;
-            if ((err = nl_addr_parse(nsjconf->iface_vs_ma.c_str(), AF_LLC, &nladdr)) < 0) {
-                LOG_E("nl_addr_parse('%s', AF_LLC) failed: %s",
-                    nsjconf->iface_vs_ma.c_str(), nl_geterror(err));
-                return false;
-            }
-            rtnl_link_set_addr(rmv, nladdr);
-            nl_addr_put(nladdr);
-        */
-
-        panic!("net->clone_iface->!iface_vs_ma.is_empty(): not implemented");
+    let max = if addr.is_ipv4() { 32 } else { 128 };
+    if len > max {
+        return None;
     }
+    Some((addr, len))
+}
 
-    if !link::link_add(sk, &rmv, link::global_nlm_f_create()) {
-        link::link_put(rmv);
-        return false;
+/// A netmask is either written as an address, e.g. "255.255.255.0" or "ffff:ffff:ffff:ffff::", or as a prefix length
+pub fn parse_netmask(s: &str) -> Option<u8> {
+    match s.parse::<u8>() {
+        Ok(len) if len <= 128 => Some(len),
+        Ok(_) => None,
+        Err(_) => rtnetlink::prefix_len(s.parse().ok()?),
     }
+}
 
-    link::link_put(rmv);
+/// The address and prefix length of the main ip of an interface, with its netmask when it has no prefix length
+fn address_prefix(ip: &str, mask: &str) -> Option<(IpAddr, u8)> {
+    let default_len = match ip.parse::<IpAddr>() {
+        Ok(IpAddr::V6(_)) if mask.parse::<Ipv4Addr>().is_ok() => Some(DEFAULT_IPV6_PREFIX_LEN),
+        _ => parse_netmask(mask),
+    };
+    parse_cidr(ip, default_len)
+}
 
-    true
+fn parse_mac(iface_vs_ma: &CStr) -> Result<Option<[u8; 6]>> {
+    if iface_vs_ma.to_bytes().is_empty() {
+        return Ok(None);
+    }
+    let ma = cstr("macvlan_vs_ma", iface_vs_ma)?;
+    match rtnetlink::parse_mac(ma) {
+        Some(mac) => Ok(Some(mac)),
+        None => Err(format!("macvlan_vs_ma: invalid MAC address {:?}", ma).into()),
+    }
 }
 
-// only used to move existing network interface to the new NET namespace. See comment above in init_ns_from_parent.
-pub fn move_to_ns(
-    iface: &CStr,
-    sk: &socket::NetlinkSocket,
-    link_cache: &link::NetlinkCache,
+pub fn clone_iface(
+    nl: &mut RtNetlink,
+    iface_name: &str,
+    iface_vs: &CStr,
+    iface_vs_ma: &CStr,
     pid: libc::pid_t,
-) -> bool {
-    let orig_link = match link::link_get_by_name(link_cache, iface) {
-        None => return false,
-        Some(link) => link,
-    };
+) -> Result<()> {
+    // iface_vs_ma, which is --macvlan_vs_ma in nsjail cmdline, MAC-address of the 'vs' interface (e.g. "ba:ad:ba:be:45:00"),
+    // the kernel picks a random one when it is empty
+    nl.add_macvlan(
+        iface_name,
+        cstr("macvlan_iface", iface_vs)?,
+        parse_mac(iface_vs_ma)?,
+        Some(NetNs::Pid(pid)),
+    )?;
 
-    let new_link = match link::link_alloc() {
-        None => {
-            link::link_put(orig_link);
-            return false;
-        }
-        Some(link) => link,
-    };
+    Ok(())
+}
 
-    link::link_set_ns_pid(&new_link, pid);
+/**
+ * Creates a veth pair, iface_name goes in the NET namespace of pid and the host end is attached to the bridge,
+ * which is created if it does not exist yet. The host end disappears with the namespace, so there is nothing to clean up
+ */
+pub fn veth_iface(
+    nl: &mut RtNetlink,
+    iface_name: &str,
+    host_name: &str,
+    bridge: &CStr,
+    iface_vs_ma: &CStr,
+    pid: libc::pid_t,
+) -> Result<()> {
+    let bridge = cstr("bridge", bridge)?;
+    match nl.link_index(bridge) {
+        Ok(_) => {}
+        Err(rtnetlink::Error::NoSuchLink(_)) => {
+            nl.add_bridge(bridge)?;
+            nl.set_link_up(bridge)?;
+        }
+        Err(e) => return Err(e.into()),
+    }
 
-    if !link::link_change(sk, &orig_link, &new_link, link::global_rtm_setlink()) {
-        // link::global_rtm_setlink() returns libnl global constants RTM_SETLINK defined in rtnetlink.h
-        link::link_put(new_link);
-        link::link_put(orig_link);
-        return false;
+    nl.add_veth(
+        host_name,
+        iface_name,
+        parse_mac(iface_vs_ma)?,
+        Some(NetNs::Pid(pid)),
+    )?;
+    if let Err(e) = nl
+        .set_link_master(host_name, bridge)
+        .and_then(|_| nl.set_link_up(host_name))
+    {
+        // the pair is only removed with the namespace, do not leave the host end dangling if the jail goes on without it
+        let _ = nl.delete_link(host_name);
+        return Err(e.into());
     }
 
-    link::link_put(new_link);
-    link::link_put(orig_link);
+    Ok(())
+}
+
+fn add_iface(
+    nl: &mut RtNetlink,
+    mode: NetMode,
+    iface_name: &str,
+    iface_vs: &CStr,
+    iface_vs_ma: &CStr,
+    pid: libc::pid_t,
+    n: usize,
+) -> Result<()> {
+    match mode {
+        NetMode::Macvlan => clone_iface(nl, iface_name, iface_vs, iface_vs_ma, pid),
+        NetMode::Veth => veth_iface(
+            nl,
+            iface_name,
+            &format!("{}{}_{}", VETH_HOST_PREFIX, pid, n),
+            iface_vs,
+            iface_vs_ma,
+            pid,
+        ),
+    }
+}
 
-    true
+// only used to move existing network interface to the new NET namespace. See comment above in init_ns_from_parent.
+pub fn move_to_ns(nl: &mut RtNetlink, iface: &CStr, pid: libc::pid_t) -> Result<()> {
+    nl.set_link_ns(cstr("iface", iface)?, NetNs::Pid(pid))?;
+    Ok(())
 }
 
 // we must first init_ns_from_parent before child init_ns_from_child, if init_ns_from_child is first
@@ -106,50 +168,18 @@ pub fn init_ns_from_parent(jconf: &JailConf, pid: libc::pid_t) -> Result<()> {
         return Ok(());
     }
 
-    let mut sk = match socket::alloc() {
-        // libnl->nl_socket_alloc
-        None => {
-            return Err((
-                "Could not allocate socket with nl_socket_alloc()",
-                Errno::last(),
-            )
-                .into())
-        }
-        Some(sock) => sock,
-    };
-
-    let res_sock_connect = socket::connect(&mut sk, libc::NETLINK_ROUTE);
-    if res_sock_connect < 0 {
-        // libnl->nl_connect(sk, NETLINK_ROUTE)
-        socket::free(sk); // libnl->nl_socket_free
-        return Err(format!(
-            "Unable to connect socket: {}",
-            socket::get_error(res_sock_connect)
-        )
-        .into());
-    }
-
-    let link_cache = match link::alloc_cache(&mut sk, libc::AF_UNSPEC) {
-        // rtnl_link_alloc_cache
-        None => {
-            socket::free(sk); // libnl->nl_socket_free
-            return Err(format!(
-                "rtnl_link_alloc_cache(): {}",
-                socket::get_error(res_sock_connect)
-            )
-            .into());
-        }
-        Some(cache) => cache,
-    };
+    let mut nl = RtNetlink::connect()?;
 
     // ifaces is populated in nsjail with existing network interface that you want to move into the new NET namespace. In toaster exe, for now
     // we do not use it, but it may be useful for virtio and tun/tap
     for iface in jconf.ifaces.iter() {
-        if !move_to_ns(iface, &sk, &link_cache, pid) {
-            link::free_cache(link_cache); // libnl->nl_cache_free
-            socket::free(sk); // libnl->nl_socket_free
-            return Err(format!("Could not move {:?} to NS", iface).into());
-        }
+        move_to_ns(&mut nl, iface, pid)
+            .map_err(|e| format!("Could not move {:?} to NS: {}", iface, e))?;
+    }
+
+    // with a user-mode network the child creates its own tap device, see usernet
+    if jconf.user_net.is_some() {
+        return Ok(());
     }
 
     if let Some(ref multi_net) = jconf.multi_net {
@@ -158,66 +188,133 @@ pub fn init_ns_from_parent(jconf: &JailConf, pid: libc::pid_t) -> Result<()> {
                 panic!("no more than 255 network interfaces are supported");
             }
 
-            let iface_name = [
-                IFACE_NAME_UNDERSCORE,
-                i.to_string().as_bytes(),
-                "\0".as_bytes(),
-            ]
-            .concat();
-
-            if !clone_iface(
-                unsafe { CStr::from_bytes_with_nul_unchecked(&iface_name) },
+            let iface_vs_ma = multi_net
+                .iface_vs_ma
+                .get(i)
+                .map(|ma| ma.as_c_str())
+                .unwrap_or(jconf.iface_vs_ma);
+
+            let mode = multi_net
+                .iface_vs_mode
+                .get(i)
+                .cloned()
+                .unwrap_or(jconf.iface_vs_mode);
+
+            add_iface(
+                &mut nl,
+                mode,
+                &format!("{}{}", IFACE_NAME_UNDERSCORE, i),
                 &multi_net.iface_vs[i],
-                jconf.iface_vs_ma,
-                &sk,
-                &link_cache,
+                iface_vs_ma,
                 pid,
-            ) {
-                link::free_cache(link_cache); // libnl->nl_cache_free
-                socket::free(sk); // libnl->nl_socket_free
-                return Err(format!(
-                    "Could not clone one of many iface from JailConf: {}",
-                    Errno::last()
-                )
-                .into());
-            }
-        }
-    } else {
-        if !jconf.iface_vs.to_bytes().is_empty()
-            && !clone_iface(
-                unsafe { CStr::from_bytes_with_nul_unchecked(IFACE_NAME) },
-                jconf.iface_vs,
-                jconf.iface_vs_ma,
-                &sk,
-                &link_cache,
-                pid,
-            )
-        {
-            link::free_cache(link_cache); // libnl->nl_cache_free
-            socket::free(sk); // libnl->nl_socket_free
-            return Err(format!(
-                "Could not clone single iface from JailConf: {}",
-                Errno::last()
+                i,
             )
-            .into());
+            .map_err(|e| format!("Could not clone one of many iface from JailConf: {}", e))?;
         }
+    } else if !jconf.iface_vs.to_bytes().is_empty() {
+        add_iface(
+            &mut nl,
+            jconf.iface_vs_mode,
+            IFACE_NAME,
+            jconf.iface_vs,
+            jconf.iface_vs_ma,
+            pid,
+            0,
+        )
+        .map_err(|e| format!("Could not clone single iface from JailConf: {}", e))?;
     }
 
-    link::free_cache(link_cache); // libnl->nl_cache_free
-    socket::free(sk); // libnl->nl_socket_free
-
     Ok(())
 }
 
-pub fn iface_config(iface: &CStr, ip: &CStr, mask: &CStr, gw: &CStr) -> Result<()> {
-    let b = cpp_bindings::iface_config(iface, ip, mask, gw); // todo: port to native rust
-    if !b {
-        return Err(Errno::last().into());
+/**
+ * Sets ip/mask on iface, brings it up and adds a default route through gw, like nsjail an unspecified ip or gw skips the step.
+ * ip is IPv4 or IPv6 and may carry its prefix length, e.g. "fd00:7a::2/64", which then takes precedence over mask.
+ * An IPv6 ip without one gets /64 when mask is an IPv4 netmask, such as the default 255.255.255.0.
+ *
+ * For dual-stack, ip6 is an IPv6 address with its prefix length, /64 if missing, and gw6 its default gateway, both
+ * are skipped when empty
+ */
+pub fn iface_config(
+    nl: &mut RtNetlink,
+    iface: &str,
+    ip: &CStr,
+    mask: &CStr,
+    gw: &CStr,
+    ip6: &CStr,
+    gw6: &CStr,
+) -> Result<()> {
+    let ip_str = cstr("ip", ip)?;
+    let mask_str = cstr("netmask", mask)?;
+    let (ip, prefix) = match address_prefix(ip_str, mask_str) {
+        Some(v) => v,
+        None if parse_cidr(ip_str, Some(0)).is_some() => {
+            return Err(format!("netmask: {:?} is not a valid netmask", mask_str).into())
+        }
+        None => return Err(format!("ip: invalid IP address {:?}", ip_str).into()),
+    };
+    let ip6 = cstr("ip6", ip6)?;
+
+    if ip.is_unspecified() && ip6.is_empty() {
+        return Ok(());
+    }
+    nl.set_link_up(iface)?;
+
+    if !ip.is_unspecified() {
+        nl.add_address(iface, ip, prefix)?;
+
+        let gw = parse_ip("gateway", gw)?;
+        if !gw.is_unspecified() {
+            if gw.is_ipv4() != ip.is_ipv4() {
+                return Err(
+                    format!("gateway: {} and ip {} are not of the same family", gw, ip).into(),
+                );
+            }
+            nl.add_default_route(iface, gw)?;
+        }
+    }
+
+    if !ip6.is_empty() {
+        let (ip6, prefix6) = match parse_cidr(ip6, Some(DEFAULT_IPV6_PREFIX_LEN)) {
+            Some((IpAddr::V6(a), len)) => (IpAddr::V6(a), len),
+            _ => return Err(format!("ip6: invalid IPv6 address {:?}", ip6).into()),
+        };
+        nl.add_address(iface, ip6, prefix6)?;
+
+        if !gw6.to_bytes().is_empty() {
+            let gw6 = parse_ip("gateway6", gw6)?;
+            if !gw6.is_ipv6() {
+                return Err(format!("gateway6: {} is not an IPv6 address", gw6).into());
+            }
+            nl.add_default_route(iface, gw6)?;
+        }
     }
 
     Ok(())
 }
 
+fn disable_ipv6() -> Result<()> {
+    for path in DISABLE_IPV6 {
+        fs::write(path, "1").map_err(|e| format!("could not write {}: {}", path, e))?;
+    }
+    Ok(())
+}
+
+fn uses_ipv6(jconf: &JailConf) -> bool {
+    let is_v6 = |ip: &CStr| ip.to_str().map(|s| s.contains(':')).unwrap_or(false);
+
+    match jconf.multi_net {
+        Some(ref multi_net) => {
+            multi_net
+                .iface_vs_ip6
+                .iter()
+                .any(|ip6| !ip6.as_bytes().is_empty())
+                || multi_net.iface_vs_ip.iter().any(|ip| is_v6(ip))
+        }
+        None => !jconf.iface_vs_ip6.as_bytes().is_empty() || is_v6(&jconf.iface_vs_ip),
+    }
+}
+
 // we must first init_ns_from_parent before child init_ns_from_child, if init_ns_from_child is first
 // it will fail since lo will be in state down in the absence of additional “virtual” network interfaces (tveth1)
 // see https://www.toptal.com/linux/separation-anxiety-isolating-your-system-with-linux-namespaces
@@ -225,49 +322,65 @@ pub fn init_ns_from_child(jconf: &JailConf) -> Result<()> {
     if !jconf.clone_newnet {
         return Ok(());
     }
-    
-    if jconf.iface_lo && !cpp_bindings::iface_up(unsafe { CStr::from_bytes_with_nul_unchecked(LO) })
-    {
-        return Err("lo is not upt".into());
+
+    if !jconf.iface_ipv6 {
+        if uses_ipv6(jconf) {
+            return Err("IPv6 is disabled but an IPv6 address is configured".into());
+        }
+        disable_ipv6()?;
     }
 
-    if let Some(ref multi_net) = jconf.multi_net {
-        for i in 0..multi_net.iface_vs.len() {
-            let iface_name = [
-                IFACE_NAME_UNDERSCORE,
-                i.to_string().as_bytes(),
-                "\0".as_bytes(),
-            ]
-            .concat();
+    let mut nl = RtNetlink::connect()?;
+
+    if jconf.iface_lo {
+        nl.set_link_up(LO)
+            .map_err(|e| format!("could not bring lo up: {}", e))?;
+    }
 
+    if jconf.user_net.is_some() {
+        usernet::init_ns_from_child(jconf, &mut nl)?;
+    } else if let Some(ref multi_net) = jconf.multi_net {
+        for i in 0..multi_net.iface_vs.len() {
             iface_config(
-                unsafe { CStr::from_bytes_with_nul_unchecked(&iface_name) },
+                &mut nl,
+                &format!("{}{}", IFACE_NAME_UNDERSCORE, i),
                 &multi_net.iface_vs_ip[i],
                 &multi_net.iface_vs_nm[i],
                 &multi_net.iface_vs_gw[i],
+                multi_net
+                    .iface_vs_ip6
+                    .get(i)
+                    .map(|ip6| ip6.as_c_str())
+                    .unwrap_or_default(),
+                multi_net
+                    .iface_vs_gw6
+                    .get(i)
+                    .map(|gw6| gw6.as_c_str())
+                    .unwrap_or_default(),
             )
             .map_err(|e| {
                 format!(
-                    "iface_config failed for {:?} - {:?} - {:?}: {:?}",
+                    "iface_config failed for {:?} - {:?} - {:?}: {}",
                     multi_net.iface_vs_ip[i], multi_net.iface_vs_nm[i], multi_net.iface_vs_gw[i], e
                 )
             })?;
         }
-    } else {
-        if !jconf.iface_vs.to_bytes().is_empty() {
-            iface_config(
-                unsafe { CStr::from_bytes_with_nul_unchecked(IFACE_NAME) },
-                &jconf.iface_vs_ip,
-                jconf.iface_vs_nm,
-                &jconf.iface_vs_gw,
+    } else if !jconf.iface_vs.to_bytes().is_empty() {
+        iface_config(
+            &mut nl,
+            IFACE_NAME,
+            &jconf.iface_vs_ip,
+            jconf.iface_vs_nm,
+            jconf.iface_vs_gw,
+            &jconf.iface_vs_ip6,
+            jconf.iface_vs_gw6,
+        )
+        .map_err(|e| {
+            format!(
+                "iface_config failed for {:?} - {:?} - {:?}: {}",
+                jconf.iface_vs_ip, jconf.iface_vs_nm, jconf.iface_vs_gw, e
             )
-            .map_err(|e| {
-                format!(
-                    "iface_config failed for {:?} - {:?} - {:?}: {:?}",
-                    jconf.iface_vs_ip, jconf.iface_vs_nm, jconf.iface_vs_gw, e
-                )
-            })?;
-        }
+        })?;
     }
 
     Ok(())
@@ -277,3 +390,47 @@ pub fn join_ns(fd: i32) -> Result<()> {
     setns(fd, libc::CLONE_NEWNET)?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_cidr_and_netmask() {
+        assert_eq!(
+            parse_cidr("fd00:7a::2/64", None),
+            Some(("fd00:7a::2".parse().unwrap(), 64))
+        );
+        assert_eq!(
+            parse_cidr("10.166.0.2", Some(16)),
+            Some(("10.166.0.2".parse().unwrap(), 16))
+        );
+        assert_eq!(parse_cidr("10.166.0.2", None), None);
+        assert_eq!(parse_cidr("10.166.0.2/33", None), None);
+        assert_eq!(parse_cidr("fd00::2/129", None), None);
+        assert_eq!(parse_cidr("fd00::2/x", None), None);
+
+        assert_eq!(parse_netmask("255.255.0.0"), Some(16));
+        assert_eq!(parse_netmask("ffff:ffff:ffff:ffff::"), Some(64));
+        assert_eq!(parse_netmask("24"), Some(24));
+        assert_eq!(parse_netmask("129"), None);
+        assert_eq!(parse_netmask("255.0.255.0"), None);
+
+        assert_eq!(
+            address_prefix("10.166.0.2", "255.255.0.0"),
+            Some(("10.166.0.2".parse().unwrap(), 16))
+        );
+        assert_eq!(
+            address_prefix("fd00:7a::2", "255.255.255.0"),
+            Some(("fd00:7a::2".parse().unwrap(), 64))
+        );
+        assert_eq!(
+            address_prefix("fd00:7a::2", "ffff:ffff:ffff:ff00::"),
+            Some(("fd00:7a::2".parse().unwrap(), 56))
+        );
+        assert_eq!(
+            address_prefix("fd00:7a::2/48", "255.255.255.0"),
+            Some(("fd00:7a::2".parse().unwrap(), 48))
+        );
+    }
+}
@@ -0,0 +1,939 @@
+use std::ffi::CString;
+use std::fs;
+use std::path::Path;
+
+use super::error::Result;
+use super::profile::{
+    CapValue, CgroupProfile, IdMapProfile, JailProfile, MacvlanProfile, MountProfile,
+    NamespacesProfile, RestartProfile, RlimitValue, RlimitsProfile, SeccompProfile,
+};
+
+/**
+ * Importer for nsjail text-proto configuration files (see nsjail config.proto and its configs/ folder).
+ *
+ * The file is mapped onto a JailProfile, which then builds the JailConf, MountT, IDMapT and MultiNetConfig
+ * with profile::JailProfile::to_jail_conf. nsjail defaults are applied for the fields left out
+ * (every namespace enabled, hostname NSJAIL, time_limit 600, nsjail rlimits), so a profile behaves
+ * as it would under nsjail.
+ *
+ * Options we do not support yet are skipped and reported in NsjailImport.warnings, never silently dropped.
+ */
+#[derive(Debug)]
+pub struct NsjailImport {
+    pub profile: JailProfile,
+    pub warnings: Vec<String>,
+}
+
+pub fn import_file<P: AsRef<Path>>(path: P) -> Result<NsjailImport> {
+    let path = path.as_ref();
+    let content = fs::read_to_string(path)
+        .map_err(|e| format!("could not read nsjail config {}: {}", path.display(), e))?;
+    import(&content)
+}
+
+pub fn import(content: &str) -> Result<NsjailImport> {
+    let fields = Parser::new(content).parse_message(None)?;
+
+    let mut imp = Importer {
+        profile: nsjail_defaults(),
+        warnings: vec![],
+        mount_proc: false,
+        proc_mounted: false,
+        macvlan: MacvlanProfile {
+            iface: String::new(),
+            ip: String::from("192.168.0.2"),
+            netmask: String::from("255.255.255.0"),
+            gateway: String::from("192.168.0.1"),
+            mac: String::new(),
+            ip6: String::new(),
+            gateway6: String::new(),
+        },
+        rlimits: [
+            ("as", 4096, "VALUE"),
+            ("core", 0, "VALUE"),
+            ("cpu", 600, "VALUE"),
+            ("fsize", 1, "VALUE"),
+            ("nofile", 32, "VALUE"),
+            ("nproc", 1024, "SOFT"),
+            ("stack", 8, "SOFT"),
+        ]
+        .iter()
+        .map(|(n, v, t)| (*n, *v, String::from(*t)))
+        .collect(),
+        disable_rl: false,
+        listen: false,
+    };
+
+    for (name, value) in fields.iter() {
+        imp.field(name, value)?;
+    }
+    imp.finish()
+}
+
+fn nsjail_defaults() -> JailProfile {
+    JailProfile {
+        hostname: Some(String::from("NSJAIL")),
+        tlimit: 600,
+        nice_level: Some(19),
+        proc_path: Some(String::new()), // mount_proc defaults to false
+        cgroup: CgroupProfile {
+            use_v2: Some(false), // nsjail only uses cgroup v2 with use_cgroupv2 or detect_cgroupv2
+            ..Default::default()
+        },
+        namespaces: NamespacesProfile {
+            net: true,
+            user: true,
+            mount: true,
+            pid: true,
+            ipc: true,
+            uts: true,
+            cgroup: true,
+        },
+        ..Default::default()
+    }
+}
+
+/*
+ * Text format protobuf
+ */
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProtoValue {
+    Ident(String), // numbers, booleans and enum values
+    Str(String),
+    Message(Vec<(String, ProtoValue)>),
+}
+
+struct Parser<'a> {
+    src: &'a [u8],
+    pos: usize,
+    line: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(src: &'a str) -> Parser<'a> {
+        Parser {
+            src: src.as_bytes(),
+            pos: 0,
+            line: 1,
+        }
+    }
+
+    fn err<T>(&self, msg: &str) -> Result<T> {
+        Err(format!("nsjail config: line {}: {}", self.line, msg).into())
+    }
+
+    fn skip_blanks(&mut self) {
+        while self.pos < self.src.len() {
+            match self.src[self.pos] {
+                b'\n' => {
+                    self.line += 1;
+                    self.pos += 1;
+                }
+                b' ' | b'\t' | b'\r' => self.pos += 1,
+                b'#' => {
+                    while self.pos < self.src.len() && self.src[self.pos] != b'\n' {
+                        self.pos += 1;
+                    }
+                }
+                _ => return,
+            }
+        }
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        self.skip_blanks();
+        self.src.get(self.pos).cloned()
+    }
+
+    fn ident(&mut self) -> Result<String> {
+        self.skip_blanks();
+        let start = self.pos;
+        while self.pos < self.src.len() {
+            let c = self.src[self.pos];
+            if c.is_ascii_alphanumeric() || c == b'_' || c == b'.' || c == b'-' || c == b'+' {
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+        if start == self.pos {
+            return self.err("expected an identifier or a number");
+        }
+        Ok(String::from_utf8_lossy(&self.src[start..self.pos]).into_owned())
+    }
+
+    // adjacent quoted strings are concatenated, as in C
+    fn string(&mut self) -> Result<String> {
+        let mut out: Vec<u8> = vec![];
+        while let Some(quote) = self.peek() {
+            if quote != b'"' && quote != b'\'' {
+                break;
+            }
+            self.pos += 1;
+            loop {
+                let c = match self.src.get(self.pos) {
+                    Some(c) => *c,
+                    None => return self.err("unterminated string"),
+                };
+                self.pos += 1;
+                if c == quote {
+                    break;
+                }
+                if c == b'\n' {
+                    return self.err("newline in string");
+                }
+                if c != b'\\' {
+                    out.push(c);
+                    continue;
+                }
+                let e = match self.src.get(self.pos) {
+                    Some(e) => *e,
+                    None => return self.err("unterminated string"),
+                };
+                self.pos += 1;
+                match e {
+                    b'n' => out.push(b'\n'),
+                    b't' => out.push(b'\t'),
+                    b'r' => out.push(b'\r'),
+                    b'a' => out.push(0x07),
+                    b'b' => out.push(0x08),
+                    b'f' => out.push(0x0c),
+                    b'v' => out.push(0x0b),
+                    b'x' => {
+                        let start = self.pos;
+                        while self.pos < self.src.len()
+                            && self.pos - start < 2
+                            && self.src[self.pos].is_ascii_hexdigit()
+                        {
+                            self.pos += 1;
+                        }
+                        let hex = std::str::from_utf8(&self.src[start..self.pos]).unwrap_or("");
+                        match u8::from_str_radix(hex, 16) {
+                            Ok(b) => out.push(b),
+                            Err(_) => return self.err("invalid \\x escape"),
+                        }
+                    }
+                    b'0'..=b'7' => {
+                        let start = self.pos - 1;
+                        while self.pos < self.src.len()
+                            && self.pos - start < 3
+                            && (b'0'..=b'7').contains(&self.src[self.pos])
+                        {
+                            self.pos += 1;
+                        }
+                        let oct = std::str::from_utf8(&self.src[start..self.pos]).unwrap_or("");
+                        match u8::from_str_radix(oct, 8) {
+                            Ok(b) => out.push(b),
+                            Err(_) => return self.err("invalid octal escape"),
+                        }
+                    }
+                    _ => out.push(e),
+                }
+            }
+        }
+        match String::from_utf8(out) {
+            Ok(s) => Ok(s),
+            Err(_) => self.err("string is not valid UTF-8"),
+        }
+    }
+
+    fn scalar(&mut self) -> Result<ProtoValue> {
+        match self.peek() {
+            Some(b'"') | Some(b'\'') => Ok(ProtoValue::Str(self.string()?)),
+            Some(_) => Ok(ProtoValue::Ident(self.ident()?)),
+            None => self.err("unexpected end of file"),
+        }
+    }
+
+    /// parses fields until `end` (or EOF for the top level message)
+    fn parse_message(&mut self, end: Option<u8>) -> Result<Vec<(String, ProtoValue)>> {
+        let mut fields = vec![];
+        loop {
+            match self.peek() {
+                None => {
+                    if end.is_some() {
+                        return self.err("unexpected end of file, missing closing bracket");
+                    }
+                    return Ok(fields);
+                }
+                Some(c) if Some(c) == end => {
+                    self.pos += 1;
+                    return Ok(fields);
+                }
+                Some(b',') | Some(b';') => {
+                    self.pos += 1;
+                    continue;
+                }
+                _ => {}
+            }
+
+            let name = self.ident()?;
+            let mut colon = false;
+            if self.peek() == Some(b':') {
+                self.pos += 1;
+                colon = true;
+            }
+
+            match self.peek() {
+                Some(b'{') => {
+                    self.pos += 1;
+                    fields.push((name, ProtoValue::Message(self.parse_message(Some(b'}'))?)));
+                }
+                Some(b'<') => {
+                    self.pos += 1;
+                    fields.push((name, ProtoValue::Message(self.parse_message(Some(b'>'))?)));
+                }
+                Some(b'[') if colon => {
+                    // repeated scalar shorthand: name: [a, b, c]
+                    self.pos += 1;
+                    loop {
+                        match self.peek() {
+                            Some(b']') => {
+                                self.pos += 1;
+                                break;
+                            }
+                            Some(b',') => self.pos += 1,
+                            _ => {
+                                let v = self.scalar()?;
+                                fields.push((name.clone(), v));
+                            }
+                        }
+                    }
+                }
+                _ if colon => {
+                    let v = self.scalar()?;
+                    fields.push((name, v));
+                }
+                _ => return self.err(&format!("expected ':' or '{{' after {}", name)),
+            }
+        }
+    }
+}
+
+/*
+ * Mapping onto JailProfile
+ */
+
+struct Importer {
+    profile: JailProfile,
+    warnings: Vec<String>,
+    mount_proc: bool,
+    proc_mounted: bool,
+    macvlan: MacvlanProfile,
+    rlimits: Vec<(&'static str, u64, String)>, // name, value, nsjail RLimit type
+    disable_rl: bool,
+    listen: bool, // mode: LISTEN
+}
+
+fn key_err(key: &str, msg: &str) -> super::error::Error {
+    format!("nsjail config: {}: {}", key, msg).into()
+}
+
+fn as_str(key: &str, v: &ProtoValue) -> Result<String> {
+    match v {
+        ProtoValue::Str(s) => Ok(s.clone()),
+        _ => Err(key_err(key, "expected a quoted string")),
+    }
+}
+
+fn as_bool(key: &str, v: &ProtoValue) -> Result<bool> {
+    match v {
+        ProtoValue::Ident(s) => match s.as_str() {
+            "true" | "True" | "t" | "1" => Ok(true),
+            "false" | "False" | "f" | "0" => Ok(false),
+            _ => Err(key_err(key, &format!("expected a boolean, got {}", s))),
+        },
+        _ => Err(key_err(key, "expected a boolean")),
+    }
+}
+
+fn as_u64(key: &str, v: &ProtoValue) -> Result<u64> {
+    let parsed = match v {
+        ProtoValue::Ident(s) if s.starts_with("0x") || s.starts_with("0X") => {
+            u64::from_str_radix(&s[2..], 16).ok()
+        }
+        ProtoValue::Ident(s) => s.parse::<u64>().ok(),
+        _ => None,
+    };
+    parsed.ok_or_else(|| key_err(key, "expected an unsigned integer"))
+}
+
+fn as_i64(key: &str, v: &ProtoValue) -> Result<i64> {
+    match v {
+        ProtoValue::Ident(s) => s
+            .parse::<i64>()
+            .map_err(|_| key_err(key, "expected an integer")),
+        _ => Err(key_err(key, "expected an integer")),
+    }
+}
+
+fn as_enum(key: &str, v: &ProtoValue) -> Result<String> {
+    match v {
+        ProtoValue::Ident(s) => Ok(s.clone()),
+        _ => Err(key_err(key, "expected an enum value")),
+    }
+}
+
+fn as_message<'v>(key: &str, v: &'v ProtoValue) -> Result<&'v [(String, ProtoValue)]> {
+    match v {
+        ProtoValue::Message(m) => Ok(m),
+        _ => Err(key_err(key, "expected a message in { }")),
+    }
+}
+
+fn getpw_id(name: &str, group: bool) -> Option<libc::uid_t> {
+    let cname = CString::new(name).ok()?;
+    unsafe {
+        if group {
+            let gr = libc::getgrnam(cname.as_ptr());
+            if gr.is_null() {
+                return None;
+            }
+            Some((*gr).gr_gid)
+        } else {
+            let pw = libc::getpwnam(cname.as_ptr());
+            if pw.is_null() {
+                return None;
+            }
+            Some((*pw).pw_uid)
+        }
+    }
+}
+
+/// nsjail ids are strings: empty means the current one, otherwise a number or a user/group name
+fn parse_id(key: &str, id: &str, group: bool) -> Result<libc::uid_t> {
+    if id.is_empty() {
+        return Ok(unsafe {
+            if group {
+                libc::getgid()
+            } else {
+                libc::getuid()
+            }
+        });
+    }
+    if let Ok(n) = id.parse::<libc::uid_t>() {
+        return Ok(n);
+    }
+    getpw_id(id, group).ok_or_else(|| key_err(key, &format!("unknown user or group {:?}", id)))
+}
+
+impl Importer {
+    fn warn(&mut self, msg: String) {
+        self.warnings.push(msg);
+    }
+
+    fn unsupported(&mut self, key: &str) {
+        self.warn(format!("{}: not supported yet, ignored", key));
+    }
+
+    fn field(&mut self, name: &str, v: &ProtoValue) -> Result<()> {
+        let p = &mut self.profile;
+        match name {
+            "name" | "description" => {}
+            "mode" => {
+                let mode = as_enum(name, v)?;
+                let rerun = mode == "RERUN" || mode == "r";
+                if rerun {
+                    p.restart = Some(RestartProfile::default());
+                }
+                self.listen = mode == "LISTEN" || mode == "l";
+                if !self.listen && !rerun && mode != "ONCE" && mode != "o" {
+                    self.warn(format!(
+                        "mode: {} is not supported yet, the jail will run once",
+                        mode
+                    ));
+                }
+            }
+            "hostname" => p.hostname = Some(as_str(name, v)?),
+            "cwd" => p.cwd = Some(as_str(name, v)?),
+            "port" => p.port = as_u64(name, v)? as u32,
+            "bind_host" => p.bind_host = Some(as_str(name, v)?),
+            "max_conns_per_ip" => p.max_conns_per_ip = as_u64(name, v)? as u32,
+            "time_limit" => p.tlimit = as_u64(name, v)?,
+            "daemon" => p.daemonize = as_bool(name, v)?,
+            "max_cpus" => p.max_cpus = as_u64(name, v)? as u16,
+            "keep_env" => p.keep_env = as_bool(name, v)?,
+            "envar" => p.env.push(as_str(name, v)?),
+            "keep_caps" => p.keep_caps = as_bool(name, v)?,
+            "silent" => p.silent = as_bool(name, v)?,
+            "skip_setsid" => p.skip_setsid = as_bool(name, v)?,
+            "stderr_to_null" => p.stderr_to_null = as_bool(name, v)?,
+            "disable_no_new_privs" => p.disable_no_new_privs = as_bool(name, v)?,
+            "niceness" => p.nice_level = Some(as_i64(name, v)?),
+            "mount_proc" => self.mount_proc = as_bool(name, v)?,
+            "disable_rl" => self.disable_rl = as_bool(name, v)?,
+
+            "persona_addr_compat_layout"
+            | "persona_mmap_page_zero"
+            | "persona_read_implies_exec"
+            | "persona_addr_limit_3gb"
+            | "persona_addr_no_randomize" => {
+                if as_bool(name, v)? {
+                    p.personality
+                        .push(name.trim_start_matches("persona_").to_owned());
+                }
+            }
+
+            "clone_newnet" => p.namespaces.net = as_bool(name, v)?,
+            "clone_newuser" => p.namespaces.user = as_bool(name, v)?,
+            "clone_newns" => p.namespaces.mount = as_bool(name, v)?,
+            "clone_newpid" => p.namespaces.pid = as_bool(name, v)?,
+            "clone_newipc" => p.namespaces.ipc = as_bool(name, v)?,
+            "clone_newuts" => p.namespaces.uts = as_bool(name, v)?,
+            "clone_newcgroup" => p.namespaces.cgroup = as_bool(name, v)?,
+
+            "uidmap" | "gidmap" => self.id_map(name, v)?,
+            "mount" => self.mount(v)?,
+            "exec_bin" => self.exec_bin(v)?,
+
+            "rlimit_as" | "rlimit_core" | "rlimit_cpu" | "rlimit_fsize" | "rlimit_nofile"
+            | "rlimit_nproc" | "rlimit_stack" => {
+                let val = as_u64(name, v)?;
+                let short = name.trim_start_matches("rlimit_");
+                if let Some(r) = self.rlimits.iter_mut().find(|r| r.0 == short) {
+                    r.1 = val;
+                }
+            }
+            "rlimit_as_type" | "rlimit_core_type" | "rlimit_cpu_type" | "rlimit_fsize_type"
+            | "rlimit_nofile_type" | "rlimit_nproc_type" | "rlimit_stack_type" => {
+                let t = as_enum(name, v)?;
+                match t.as_str() {
+                    "VALUE" | "SOFT" | "HARD" | "INF" => {}
+                    _ => return Err(key_err(name, &format!("unknown RLimit type {}", t))),
+                }
+                let short = name.trim_start_matches("rlimit_").trim_end_matches("_type");
+                if let Some(r) = self.rlimits.iter_mut().find(|r| r.0 == short) {
+                    r.2 = t;
+                }
+            }
+
+            "cgroup_mem_max" => p.cgroup.mem_max = as_u64(name, v)?,
+            "cgroup_mem_mount" => p.cgroup.mem_mount = Some(as_str(name, v)?),
+            "cgroup_mem_parent" => p.cgroup.mem_parent = Some(as_str(name, v)?),
+            "cgroup_pids_max" => p.cgroup.pids_max = as_u64(name, v)?,
+            "cgroup_pids_mount" => p.cgroup.pids_mount = Some(as_str(name, v)?),
+            "cgroup_pids_parent" => p.cgroup.pids_parent = Some(as_str(name, v)?),
+            "cgroup_net_cls_classid" => p.cgroup.net_cls_classid = as_u64(name, v)?,
+            "cgroup_net_cls_mount" => p.cgroup.net_cls_mount = Some(as_str(name, v)?),
+            "cgroup_net_cls_parent" => p.cgroup.net_cls_parent = Some(as_str(name, v)?),
+            "cgroup_cpu_ms_per_sec" => p.cgroup.cpu_ms_per_sec = as_u64(name, v)?,
+            "cgroup_cpu_mount" => p.cgroup.cpu_mount = Some(as_str(name, v)?),
+            "cgroup_cpu_parent" => p.cgroup.cpu_parent = Some(as_str(name, v)?),
+            "cgroupv2_mount" => p.cgroup.v2_mount = Some(as_str(name, v)?),
+            "use_cgroupv2" => {
+                if as_bool(name, v)? {
+                    p.cgroup.use_v2 = Some(true);
+                }
+            }
+            "detect_cgroupv2" => {
+                if as_bool(name, v)? && p.cgroup.use_v2 != Some(true) {
+                    p.cgroup.use_v2 = None;
+                }
+            }
+            "cgroup_mem_swap_max" => {
+                // nsjail uses -1 for no limit
+                let swap = as_i64(name, v)?;
+                if swap >= 0 {
+                    p.cgroup.mem_swap_max = Some(swap as u64);
+                }
+            }
+
+            "iface_no_lo" => p.net.lo = !as_bool(name, v)?,
+            "iface_own" => {
+                let iface = as_str(name, v)?;
+                p.net
+                    .ifaces
+                    .push(CString::new(iface).map_err(|_| key_err(name, "nul byte"))?);
+            }
+            "macvlan_iface" => self.macvlan.iface = as_str(name, v)?,
+            "macvlan_vs_ip" => self.macvlan.ip = as_str(name, v)?,
+            "macvlan_vs_nm" => self.macvlan.netmask = as_str(name, v)?,
+            "macvlan_vs_gw" => self.macvlan.gateway = as_str(name, v)?,
+            "macvlan_vs_ma" => self.macvlan.mac = as_str(name, v)?,
+            "macvlan_vs_mo" => {
+                let mode = as_str(name, v)?;
+                if mode != "private" {
+                    self.warn(format!(
+                        "macvlan_vs_mo: only private mode is supported, {:?} ignored",
+                        mode
+                    ));
+                }
+            }
+
+            "seccomp_log" => {
+                let log = as_bool(name, v)?;
+                p.seccomp.get_or_insert_with(SeccompProfile::default).log = log;
+            }
+            // the lines of seccomp_string make up one policy, as in nsjail
+            "seccomp_string" => {
+                let sc = p.seccomp.get_or_insert_with(SeccompProfile::default);
+                sc.policy.push_str(&as_str(name, v)?);
+                sc.policy.push('\n');
+            }
+            "seccomp_policy_file" => {
+                p.seccomp.get_or_insert_with(SeccompProfile::default).policy_file = as_str(name, v)?
+            }
+            "cap" => p.caps.push(CapValue::Name(as_str(name, v)?)),
+
+            "log" | "log_fd" | "log_level" | "pass_fd" | "cgroup_mem_memsw_max" | "clone_newtime"
+            | "rlimit_memlock" | "rlimit_memlock_type" | "rlimit_rtprio" | "rlimit_rtprio_type"
+            | "rlimit_msgqueue" | "rlimit_msgqueue_type" | "forward_signals" | "disable_tsc" | "use_pasta"
+            | "user_net" | "traceback" | "no_pivotroot" => self.unsupported(name),
+
+            _ => self.warn(format!("{}: unknown nsjail option, ignored", name)),
+        }
+        Ok(())
+    }
+
+    fn id_map(&mut self, name: &str, v: &ProtoValue) -> Result<()> {
+        let group = name == "gidmap";
+        let idx = if group {
+            self.profile.gid_map.len()
+        } else {
+            self.profile.uid_map.len()
+        };
+        let mut inside = String::new();
+        let mut outside = String::new();
+        let mut count = 1;
+        let mut use_newidmap = false;
+
+        for (f, fv) in as_message(name, v)?.iter() {
+            let key = format!("{}[{}].{}", name, idx, f);
+            match f.as_str() {
+                "inside_id" => inside = as_str(&key, fv)?,
+                "outside_id" => outside = as_str(&key, fv)?,
+                "count" => count = as_u64(&key, fv)?,
+                "use_newidmap" => use_newidmap = as_bool(&key, fv)?,
+                _ => self.warn(format!("{}: unknown IdMap option, ignored", key)),
+            }
+        }
+
+        let map = IdMapProfile {
+            inside: parse_id(&format!("{}[{}].inside_id", name, idx), &inside, group)?,
+            outside: parse_id(&format!("{}[{}].outside_id", name, idx), &outside, group)?,
+            count,
+            use_newidmap,
+        };
+        if group {
+            self.profile.gid_map.push(map);
+        } else {
+            self.profile.uid_map.push(map);
+        }
+        Ok(())
+    }
+
+    fn mount(&mut self, v: &ProtoValue) -> Result<()> {
+        let idx = self.profile.mounts.len();
+        let mut src: Option<String> = None;
+        let mut dst: Option<String> = None;
+        let mut prefix_src_env: Option<String> = None;
+        let mut prefix_dst_env: Option<String> = None;
+        let mut has_content = false;
+        let mut m = MountProfile {
+            src: None,
+            dst: String::new(),
+            fstype: None,
+            options: None,
+            rw: false,
+            bind: Some(false),
+            is_dir: None,
+            is_symlink: false,
+            mandatory: true,
+            nosuid: false,
+            nodev: false,
+            noexec: false,
+        };
+
+        for (f, fv) in as_message("mount", v)?.iter() {
+            let key = format!("mount[{}].{}", idx, f);
+            match f.as_str() {
+                "src" => src = Some(as_str(&key, fv)?),
+                "dst" => dst = Some(as_str(&key, fv)?),
+                "prefix_src_env" => prefix_src_env = Some(as_str(&key, fv)?),
+                "prefix_dst_env" => prefix_dst_env = Some(as_str(&key, fv)?),
+                "src_content" => has_content = true,
+                "fstype" => m.fstype = Some(as_str(&key, fv)?),
+                "options" => m.options = Some(as_str(&key, fv)?),
+                "is_bind" => m.bind = Some(as_bool(&key, fv)?),
+                "rw" => m.rw = as_bool(&key, fv)?,
+                "is_dir" => m.is_dir = Some(as_bool(&key, fv)?),
+                "mandatory" => m.mandatory = as_bool(&key, fv)?,
+                "is_symlink" => m.is_symlink = as_bool(&key, fv)?,
+                "nosuid" => m.nosuid = as_bool(&key, fv)?,
+                "nodev" => m.nodev = as_bool(&key, fv)?,
+                "noexec" => m.noexec = as_bool(&key, fv)?,
+                _ => self.warn(format!("{}: unknown MountPt option, ignored", key)),
+            }
+        }
+
+        let dst = match dst {
+            Some(d) => with_env_prefix(&format!("mount[{}].dst", idx), prefix_dst_env, d)?,
+            None => return Err(key_err(&format!("mount[{}].dst", idx), "missing")),
+        };
+        if has_content {
+            self.warn(format!(
+                "mount[{}].src_content: mounts with inline content are not supported yet, {} is not mounted",
+                idx, dst
+            ));
+            return Ok(());
+        }
+        m.src = match src {
+            Some(s) => Some(with_env_prefix(
+                &format!("mount[{}].src", idx),
+                prefix_src_env,
+                s,
+            )?),
+            None => None,
+        };
+
+        // the root and /proc are handled by config.rs::with_default_mounts
+        if dst.trim_matches('/').is_empty() {
+            if m.bind == Some(true) {
+                self.profile.chroot = m.src.take();
+            }
+            self.profile.chroot_rw = m.rw;
+            return Ok(());
+        }
+        if m.fstype.as_deref() == Some("proc") {
+            self.profile.proc_path = Some(dst.trim_start_matches('/').to_owned());
+            self.profile.proc_rw = m.rw;
+            self.proc_mounted = true;
+            return Ok(());
+        }
+
+        m.dst = dst;
+        self.profile.mounts.push(m);
+        Ok(())
+    }
+
+    fn exec_bin(&mut self, v: &ProtoValue) -> Result<()> {
+        let mut path: Option<String> = None;
+        let mut arg0: Option<String> = None;
+        let mut args: Vec<String> = vec![];
+
+        for (f, fv) in as_message("exec_bin", v)?.iter() {
+            let key = format!("exec_bin.{}", f);
+            match f.as_str() {
+                "path" => path = Some(as_str(&key, fv)?),
+                "arg0" => arg0 = Some(as_str(&key, fv)?),
+                "arg" => args.push(as_str(&key, fv)?),
+                "exec_fd" => {
+                    if as_bool(&key, fv)? {
+                        self.unsupported(&key);
+                    }
+                }
+                _ => self.warn(format!("{}: unknown Exe option, ignored", key)),
+            }
+        }
+
+        let path = path.ok_or_else(|| key_err("exec_bin.path", "missing"))?;
+        let mut argv = vec![arg0.unwrap_or_else(|| path.clone())];
+        argv.append(&mut args);
+        self.profile.exec_file = Some(path);
+        self.profile.argv = argv;
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<NsjailImport> {
+        // port selects listen mode in profiles, nsjail only listens with mode: LISTEN
+        if !self.listen {
+            self.profile.port = 0;
+        } else if self.profile.port == 0 {
+            self.warn(String::from(
+                "mode: LISTEN without a port, the jail will run once",
+            ));
+        }
+
+        if self.mount_proc && !self.proc_mounted {
+            self.profile.proc_path = Some(String::from("proc"));
+        }
+
+        if !self.macvlan.iface.is_empty() {
+            let macvlan = std::mem::replace(
+                &mut self.macvlan,
+                MacvlanProfile {
+                    iface: String::new(),
+                    ip: String::new(),
+                    netmask: String::new(),
+                    gateway: String::new(),
+                    mac: String::new(),
+                    ip6: String::new(),
+                    gateway6: String::new(),
+                },
+            );
+            self.profile.net.macvlan.push(macvlan);
+        }
+
+        if !self.disable_rl {
+            let mut rl = RlimitsProfile::default();
+            for (name, val, t) in self.rlimits.iter() {
+                let v = Some(match t.as_str() {
+                    "SOFT" => RlimitValue::Special(String::from("soft")),
+                    "HARD" => RlimitValue::Special(String::from("hard")),
+                    "INF" => RlimitValue::Special(String::from("inf")),
+                    _ => RlimitValue::Value(*val),
+                });
+                match *name {
+                    "as" => rl.as_ = v,
+                    "core" => rl.core = v,
+                    "cpu" => rl.cpu = v,
+                    "fsize" => rl.fsize = v,
+                    "nofile" => rl.nofile = v,
+                    "nproc" => rl.nproc = v,
+                    _ => rl.stack = v,
+                }
+            }
+            self.profile.rlimits = Some(rl);
+        }
+
+        if self.profile.argv.is_empty() {
+            self.warn(String::from(
+                "exec_bin: missing, the command must be given on the command line",
+            ));
+        }
+
+        Ok(NsjailImport {
+            profile: self.profile,
+            warnings: self.warnings,
+        })
+    }
+}
+
+fn with_env_prefix(key: &str, env: Option<String>, path: String) -> Result<String> {
+    match env {
+        None => Ok(path),
+        Some(var) => {
+            let prefix = std::env::var(&var)
+                .map_err(|_| key_err(key, &format!("environment variable {} is not set", var)))?;
+            Ok(format!("{}/{}", prefix.trim_end_matches('/'), path.trim_start_matches('/')))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NSJAIL_CFG: &str = r#"
+name: "bash-with-fake-geteuid"
+description: "An example/demo policy which allows to execute /bin/bash and other commands in "
+description: "a fairly restricted jail containing only some directories from the main "
+
+mode: ONCE
+hostname: "JAILED-BASH"
+cwd: "/tmp"
+
+time_limit: 100
+envar: "TERM=linux"
+envar: "HOME=/"
+
+rlimit_as: 2048
+rlimit_nofile_type: INF
+
+clone_newnet: false
+keep_caps: false
+cap: "CAP_NET_BIND_SERVICE"
+
+uidmap {
+    inside_id: "0"
+    outside_id: "1000"
+}
+
+mount {
+    src: "/lib"
+    dst: "/lib"
+    is_bind: true
+}
+
+mount {
+    dst: "/proc"
+    fstype: "proc"
+}
+
+mount {
+    dst: "/tmp"
+    fstype: "tmpfs"
+    rw: true
+    is_bind: false
+    options: "size=" '16m'
+}
+
+mount {
+    src_content: "nameserver 8.8.8.8"
+    dst: "/etc/resolv.conf"
+}
+
+seccomp_string: "ERRNO(1337) { geteuid }	"
+seccomp_string: "DEFAULT ALLOW"
+
+macvlan_iface: "eth0"
+macvlan_vs_ip: "10.0.0.5"
+
+exec_bin {
+    path: "/bin/bash"
+    arg: "-i"
+    arg0: "sh"
+}
+"#;
+
+    #[test]
+    fn test_import_nsjail_config() {
+        let imp = import(NSJAIL_CFG).unwrap();
+        let p = &imp.profile;
+
+        assert_eq!(p.hostname.as_deref(), Some("JAILED-BASH"));
+        assert_eq!(p.tlimit, 100);
+        assert_eq!(p.env, vec!["TERM=linux", "HOME=/"]);
+        assert!(!p.namespaces.net);
+        assert!(p.namespaces.user && p.namespaces.pid);
+        assert_eq!(p.uid_map[0].inside, 0);
+        assert_eq!(p.uid_map[0].outside, 1000);
+        assert_eq!(p.proc_path.as_deref(), Some("proc"));
+        assert_eq!(p.mounts.len(), 2);
+        assert_eq!(p.mounts[1].options.as_deref(), Some("size=16m"));
+        assert_eq!(p.argv, vec!["sh", "-i"]);
+        assert_eq!(p.exec_file.as_deref(), Some("/bin/bash"));
+        assert_eq!(p.net.macvlan[0].ip, "10.0.0.5");
+
+        assert_eq!(imp.warnings.len(), 1, "{:?}", imp.warnings);
+        assert!(imp.warnings[0].contains("src_content"));
+        assert_eq!(
+            p.seccomp.as_ref().unwrap().policy,
+            "ERRNO(1337) { geteuid }\t\nDEFAULT ALLOW\n"
+        );
+
+        let jconf = p.to_jail_conf().unwrap();
+        assert!(jconf.seccomp_filter.is_some());
+        assert_eq!(jconf.rl_as, 2048 * 1024 * 1024);
+        assert_eq!(jconf.rl_nofile, crate::rlimit::RLIM64_INFINITY);
+        assert_eq!(jconf.hostname, "JAILED-BASH");
+        assert_eq!(jconf.caps, vec![10]);
+    }
+
+    #[test]
+    fn test_modes() {
+        let imp = import("mode: LISTEN\nport: 31337\nmax_conns_per_ip: 2\n").unwrap();
+        assert_eq!(imp.profile.port, 31337);
+        assert_eq!(imp.profile.max_conns_per_ip, 2);
+
+        let imp = import("mode: ONCE\nport: 31337\n").unwrap();
+        assert_eq!(imp.profile.port, 0);
+        assert!(imp.profile.restart.is_none());
+
+        let imp = import("mode: RERUN\n").unwrap();
+        assert_eq!(imp.profile.restart.unwrap().policy, "always");
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let err = import("mount { dst: \"/a\" ").unwrap_err();
+        assert!(err.to_string().contains("missing closing bracket"), "{}", err);
+
+        let err = import("time_limit: \"ten\"").unwrap_err();
+        assert!(err.to_string().contains("time_limit"), "{}", err);
+
+        let err = import("mount { src: \"/a\" }").unwrap_err();
+        assert!(err.to_string().contains("mount[0].dst"), "{}", err);
+    }
+}
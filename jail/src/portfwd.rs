@@ -0,0 +1,844 @@
+use super::config::{ForwardProto, JailConf, PortForward};
+use super::error::Result;
+use super::net::parse_cidr;
+
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Read, Write};
+use std::mem;
+use std::net::{IpAddr, Ipv4Addr, Shutdown, SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use sys_util::epoll::{self, EpollEvent, EpollFlags, EpollOp};
+use sys_util::errno::Errno;
+use sys_util::fcntl;
+use sys_util::sched::setns;
+use sys_util::EventFd;
+
+const BUF_SIZE: usize = 64 * 1024; // per direction and connection
+const UDP_IDLE: Duration = Duration::from_secs(60);
+const UDP_TICK_MS: isize = 1000;
+
+const STOP_TOKEN: u64 = 0;
+const FIRST_TOKEN: u64 = 1;
+
+/**
+ * Parses "[<host_ip>:]<host_port>:<jail_port>[/tcp|/udp]", bind_host when host_ip is missing. IPv6 host addresses are
+ * written in brackets, e.g. "[::1]:8080:80"
+ */
+pub fn parse_port_forward(s: &str, bind_host: &str) -> Result<PortForward> {
+    let invalid = || {
+        format!(
+            "invalid port forward {:?}, expected [<host_ip>:]<host_port>:<jail_port>[/tcp|/udp]",
+            s
+        )
+    };
+    let port = |p: &str| match p.parse::<u16>() {
+        Ok(p) if p > 0 => Ok(p),
+        _ => Err(invalid()),
+    };
+
+    let (spec, proto) = match s.rfind('/') {
+        Some(i) => match &s[i + 1..] {
+            "tcp" => (&s[..i], ForwardProto::Tcp),
+            "udp" => (&s[..i], ForwardProto::Udp),
+            _ => return Err(invalid().into()),
+        },
+        None => (s, ForwardProto::Tcp),
+    };
+
+    let (host, jail_port) = match spec.rfind(':') {
+        Some(i) => (&spec[..i], port(&spec[i + 1..])?),
+        None => return Err(invalid().into()),
+    };
+    let (host_ip, host_port) = match host.rfind(':') {
+        Some(i) => (
+            host[..i].trim_start_matches('[').trim_end_matches(']'),
+            &host[i + 1..],
+        ),
+        None => (bind_host, host),
+    };
+    let host_ip = host_ip.parse::<IpAddr>().map_err(|_| invalid())?;
+
+    Ok(PortForward {
+        proto,
+        host: SocketAddr::new(host_ip, port(host_port)?),
+        jail_port,
+    })
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Side {
+    Client,
+    Jail,
+}
+
+enum Token {
+    Listener(usize),
+    Conn(u64, Side),
+    UdpFlow(u64),
+}
+
+enum Listener {
+    Tcp(TcpListener, u16),
+    Udp(UdpSocket, u16),
+}
+
+/// One direction of a forwarded TCP connection
+#[derive(Default)]
+struct Half {
+    buf: VecDeque<u8>,
+    eof: bool, // the sending side closed, once buf is flushed the receiving side is shut down for writing
+    shut: bool, // done
+}
+
+struct Conn {
+    client: TcpStream,
+    jail: TcpStream,
+    client_ip: IpAddr,
+    connecting: bool,
+    to_jail: Half,
+    to_client: Half,
+    registered: [Option<EpollFlags>; 2],
+}
+
+impl Conn {
+    fn stream(&self, side: Side) -> &TcpStream {
+        match side {
+            Side::Client => &self.client,
+            Side::Jail => &self.jail,
+        }
+    }
+
+    /// Epoll interest of a side: reading while its half has room, writing while the other half has data for it
+    fn interest(&self, side: Side) -> EpollFlags {
+        let (rx, tx) = match side {
+            Side::Client => (&self.to_jail, &self.to_client),
+            Side::Jail => (&self.to_client, &self.to_jail),
+        };
+        let mut flags = EpollFlags::empty();
+        if side == Side::Jail && self.connecting {
+            return EpollFlags::EPOLLOUT;
+        }
+        if !rx.eof && rx.buf.len() < BUF_SIZE && !self.connecting {
+            flags |= EpollFlags::EPOLLIN;
+        }
+        if !tx.buf.is_empty() {
+            flags |= EpollFlags::EPOLLOUT;
+        }
+        flags
+    }
+}
+
+struct UdpFlow {
+    listener: usize,
+    client: SocketAddr,
+    sock: UdpSocket,
+    last: Instant,
+}
+
+/**
+ * Proxy of the port forwards of one jail. It runs in a thread of the parent, in the NET namespace of the host, and only
+ * enters the NET namespace of the jail to create the socket of each new connection or UDP client
+ */
+pub struct Forwarder {
+    epfd: RawFd,
+    own_ns: RawFd,
+    jail_ns: Option<RawFd>, // None for the current NET namespace
+    jail_ip: Ipv4Addr,      // where the forwards connect in it
+    max_conns_per_ip: u32,
+    listeners: Vec<Listener>,
+    next_token: u64,
+    tokens: HashMap<u64, Token>,
+    conns: HashMap<u64, Conn>,
+    conns_per_ip: HashMap<IpAddr, u32>,
+    udp_flows: HashMap<u64, UdpFlow>,
+    udp_clients: HashMap<(usize, SocketAddr), u64>,
+}
+
+impl Drop for Forwarder {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.epfd);
+            libc::close(self.own_ns);
+            if let Some(fd) = self.jail_ns {
+                libc::close(fd);
+            }
+        }
+    }
+}
+
+fn ns_fd(path: &str) -> Result<RawFd> {
+    fcntl::open(path, libc::O_RDONLY | libc::O_CLOEXEC, 0)
+        .map_err(|e| format!("could not open {}: {}", path, e).into())
+}
+
+fn would_block(e: &io::Error) -> bool {
+    e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::Interrupted
+}
+
+impl Forwarder {
+    /// Binds every forward on the host, pid is the jail whose NET namespace receives the connections, None for tests
+    pub fn new(
+        forwards: &[PortForward],
+        pid: Option<libc::pid_t>,
+        max_conns_per_ip: u32,
+    ) -> Result<Forwarder> {
+        let epfd = epoll::epoll_create1(epoll::EpollCreateFlags::EPOLL_CLOEXEC)?;
+        let mut fwd = Forwarder {
+            epfd,
+            own_ns: -1,
+            jail_ns: None,
+            jail_ip: Ipv4Addr::LOCALHOST,
+            max_conns_per_ip,
+            listeners: Vec::with_capacity(forwards.len()),
+            next_token: FIRST_TOKEN,
+            tokens: HashMap::new(),
+            conns: HashMap::new(),
+            conns_per_ip: HashMap::new(),
+            udp_flows: HashMap::new(),
+            udp_clients: HashMap::new(),
+        };
+        fwd.own_ns = ns_fd("/proc/thread-self/ns/net")?;
+        if let Some(pid) = pid {
+            fwd.jail_ns = Some(ns_fd(&format!("/proc/{}/ns/net", pid))?);
+            // fail now rather than on the first connection if we may not join it
+            fwd.in_jail_ns(|| Ok(()))
+                .map_err(|e| format!("could not join the NET namespace of the child: {}", e))?;
+        }
+
+        for f in forwards.iter() {
+            let (listener, fd) = match f.proto {
+                ForwardProto::Tcp => {
+                    let l = TcpListener::bind(f.host)
+                        .map_err(|e| format!("could not listen on {}/tcp: {}", f.host, e))?;
+                    let fd = l.as_raw_fd();
+                    (Listener::Tcp(l, f.jail_port), fd)
+                }
+                ForwardProto::Udp => {
+                    let s = UdpSocket::bind(f.host)
+                        .map_err(|e| format!("could not bind {}/udp: {}", f.host, e))?;
+                    let fd = s.as_raw_fd();
+                    (Listener::Udp(s, f.jail_port), fd)
+                }
+            };
+            unsafe { libc::fcntl(fd, libc::F_SETFL, libc::O_NONBLOCK) };
+            let token = fwd.new_token(Token::Listener(fwd.listeners.len()));
+            fwd.listeners.push(listener);
+            fwd.epoll(EpollOp::EpollCtlAdd, fd, EpollFlags::EPOLLIN, token)?;
+        }
+
+        Ok(fwd)
+    }
+
+    /// The host address of each forward, in order, with the port the kernel picked for port 0
+    pub fn local_addrs(&self) -> Vec<SocketAddr> {
+        self.listeners
+            .iter()
+            .map(|l| match l {
+                Listener::Tcp(l, _) => l.local_addr().unwrap(),
+                Listener::Udp(s, _) => s.local_addr().unwrap(),
+            })
+            .collect()
+    }
+
+    fn new_token(&mut self, t: Token) -> u64 {
+        let token = self.next_token;
+        self.next_token += 1;
+        self.tokens.insert(token, t);
+        token
+    }
+
+    fn epoll(&self, op: EpollOp, fd: RawFd, flags: EpollFlags, token: u64) -> Result<()> {
+        epoll::epoll_ctl(self.epfd, op, fd, &mut EpollEvent::new(flags, token))?;
+        Ok(())
+    }
+
+    /// Runs f with the calling thread in the NET namespace of the jail
+    fn in_jail_ns<T>(&self, f: impl FnOnce() -> io::Result<T>) -> io::Result<T> {
+        let jail_ns = match self.jail_ns {
+            Some(fd) => fd,
+            None => return f(),
+        };
+        setns(jail_ns, libc::CLONE_NEWNET).map_err(|e| io::Error::from_raw_os_error(e as i32))?;
+        let res = f();
+        // the listeners are already bound, a thread left in the jail namespace still forwards, so this only fails
+        // the current connection
+        setns(self.own_ns, libc::CLONE_NEWNET)
+            .map_err(|e| io::Error::from_raw_os_error(e as i32))?;
+        res
+    }
+
+    /// Waits up to timeout_ms then handles what is ready, returns true once stop_fd is readable
+    pub fn poll(&mut self, timeout_ms: isize) -> Result<bool> {
+        let timeout_ms = if self.udp_flows.is_empty() {
+            timeout_ms
+        } else if timeout_ms < 0 {
+            UDP_TICK_MS
+        } else {
+            timeout_ms.min(UDP_TICK_MS)
+        };
+        let mut events = [EpollEvent::empty(); 64];
+        let n = match epoll::epoll_wait(self.epfd, &mut events, timeout_ms) {
+            Ok(n) => n,
+            Err(Errno::EINTR) => 0,
+            Err(e) => return Err(("port forwarding epoll_wait", e).into()),
+        };
+
+        for ev in events[..n].iter() {
+            if ev.data() == STOP_TOKEN {
+                return Ok(true);
+            }
+            match self.tokens.get(&ev.data()) {
+                Some(Token::Listener(i)) => {
+                    let i = *i;
+                    match self.listeners[i] {
+                        Listener::Tcp(..) => self.accept(i),
+                        Listener::Udp(..) => self.udp_from_client(i),
+                    }
+                }
+                Some(Token::Conn(id, side)) => {
+                    let (id, side) = (*id, *side);
+                    self.conn_event(id, side, ev);
+                }
+                Some(Token::UdpFlow(id)) => {
+                    let id = *id;
+                    self.udp_from_jail(id);
+                }
+                None => {}
+            }
+        }
+        self.expire_udp(Instant::now());
+        Ok(false)
+    }
+
+    fn accept(&mut self, i: usize) {
+        loop {
+            let (client, peer, jail_port) = match self.listeners[i] {
+                Listener::Tcp(ref l, jail_port) => match l.accept() {
+                    Ok((client, peer)) => (client, peer, jail_port),
+                    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(_) => return,
+                },
+                Listener::Udp(..) => return,
+            };
+            let count = self.conns_per_ip.get(&peer.ip()).cloned().unwrap_or(0);
+            if self.max_conns_per_ip > 0 && count >= self.max_conns_per_ip {
+                // dropping the stream closes it
+                continue;
+            }
+            let jail_ip = self.jail_ip;
+            let jail = match self.in_jail_ns(|| connect_jail(jail_ip, jail_port)) {
+                Ok(jail) => jail,
+                Err(_) => continue,
+            };
+            if client.set_nonblocking(true).is_err() {
+                continue;
+            }
+
+            let id = self.next_token;
+            let client_token = self.new_token(Token::Conn(id, Side::Client));
+            let jail_token = self.new_token(Token::Conn(id, Side::Jail));
+            debug_assert_eq!((client_token, jail_token), (id, id + 1));
+            self.conns.insert(
+                id,
+                Conn {
+                    client,
+                    jail,
+                    client_ip: peer.ip(),
+                    connecting: true,
+                    to_jail: Half::default(),
+                    to_client: Half::default(),
+                    registered: [None, None],
+                },
+            );
+            *self.conns_per_ip.entry(peer.ip()).or_insert(0) += 1;
+            self.conn_progress(id);
+        }
+    }
+
+    fn conn_event(&mut self, id: u64, side: Side, ev: &EpollEvent) {
+        let conn = match self.conns.get_mut(&id) {
+            Some(conn) => conn,
+            None => return,
+        };
+        if side == Side::Jail && conn.connecting {
+            if !ev.is_writable() && !ev.is_error() && !ev.is_read_closed() {
+                return;
+            }
+            match conn.jail.take_error() {
+                Ok(None) => conn.connecting = false,
+                // nothing listens in the jail, the client sees its connection closed
+                _ => return self.close_conn(id),
+            }
+        }
+        self.conn_progress(id);
+    }
+
+    /// Moves data both ways as far as buffers allow, then updates the epoll interest of both sockets
+    fn conn_progress(&mut self, id: u64) {
+        let mut conn = match self.conns.remove(&id) {
+            Some(conn) => conn,
+            None => return,
+        };
+
+        if !conn.connecting {
+            let ok = pump(&conn.client, &mut conn.to_jail, &conn.jail)
+                && pump(&conn.jail, &mut conn.to_client, &conn.client);
+            if !ok || (conn.to_jail.shut && conn.to_client.shut) {
+                self.conns.insert(id, conn);
+                return self.close_conn(id);
+            }
+        }
+
+        for (i, side) in [Side::Client, Side::Jail].iter().enumerate() {
+            let interest = conn.interest(*side);
+            let fd = conn.stream(*side).as_raw_fd();
+            let token = id + i as u64;
+            let res = match (conn.registered[i], interest.is_empty()) {
+                (Some(old), false) if old == interest => Ok(()),
+                (Some(_), false) => self.epoll(EpollOp::EpollCtlMod, fd, interest, token),
+                (None, false) => self.epoll(EpollOp::EpollCtlAdd, fd, interest, token),
+                (Some(_), true) => epoll::epoll_ctl(self.epfd, EpollOp::EpollCtlDel, fd, None)
+                    .map_err(|e| e.into()),
+                (None, true) => Ok(()),
+            };
+            if res.is_err() {
+                self.conns.insert(id, conn);
+                return self.close_conn(id);
+            }
+            conn.registered[i] = if interest.is_empty() {
+                None
+            } else {
+                Some(interest)
+            };
+        }
+        self.conns.insert(id, conn);
+    }
+
+    fn close_conn(&mut self, id: u64) {
+        let conn = match self.conns.remove(&id) {
+            Some(conn) => conn,
+            None => return,
+        };
+        // closing the fds removes them from the epoll set
+        self.tokens.remove(&id);
+        self.tokens.remove(&(id + 1));
+        if let Some(count) = self.conns_per_ip.get_mut(&conn.client_ip) {
+            *count -= 1;
+            if *count == 0 {
+                self.conns_per_ip.remove(&conn.client_ip);
+            }
+        }
+    }
+
+    fn udp_from_client(&mut self, i: usize) {
+        let mut buf = [0u8; 65536];
+        loop {
+            let (n, client, jail_port) = match self.listeners[i] {
+                Listener::Udp(ref s, jail_port) => match s.recv_from(&mut buf) {
+                    Ok((n, client)) => (n, client, jail_port),
+                    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(_) => return,
+                },
+                Listener::Tcp(..) => return,
+            };
+
+            let id = match self.udp_clients.get(&(i, client)) {
+                Some(id) => *id,
+                None => {
+                    let jail_ip = self.jail_ip;
+                    let sock = match self.in_jail_ns(|| udp_jail(jail_ip, jail_port)) {
+                        Ok(sock) => sock,
+                        Err(_) => continue,
+                    };
+                    let fd = sock.as_raw_fd();
+                    let id = self.new_token(Token::UdpFlow(self.next_token));
+                    if self
+                        .epoll(EpollOp::EpollCtlAdd, fd, EpollFlags::EPOLLIN, id)
+                        .is_err()
+                    {
+                        self.tokens.remove(&id);
+                        continue;
+                    }
+                    self.udp_flows.insert(
+                        id,
+                        UdpFlow {
+                            listener: i,
+                            client,
+                            sock,
+                            last: Instant::now(),
+                        },
+                    );
+                    self.udp_clients.insert((i, client), id);
+                    id
+                }
+            };
+
+            let flow = self.udp_flows.get_mut(&id).unwrap();
+            flow.last = Instant::now();
+            // a datagram the jail cannot take right away is lost, as on any full queue
+            let _ = flow.sock.send(&buf[..n]);
+        }
+    }
+
+    fn udp_from_jail(&mut self, id: u64) {
+        let mut buf = [0u8; 65536];
+        let flow = match self.udp_flows.get_mut(&id) {
+            Some(flow) => flow,
+            None => return,
+        };
+        let host = match self.listeners[flow.listener] {
+            Listener::Udp(ref s, _) => s,
+            Listener::Tcp(..) => return,
+        };
+        loop {
+            match flow.sock.recv(&mut buf) {
+                Ok(n) => {
+                    flow.last = Instant::now();
+                    let _ = host.send_to(&buf[..n], flow.client);
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(_) => return,
+            }
+        }
+    }
+
+    fn expire_udp(&mut self, now: Instant) {
+        let expired: Vec<u64> = self
+            .udp_flows
+            .iter()
+            .filter(|(_, flow)| now.duration_since(flow.last) > UDP_IDLE)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in expired {
+            let flow = self.udp_flows.remove(&id).unwrap();
+            self.udp_clients.remove(&(flow.listener, flow.client));
+            self.tokens.remove(&id);
+        }
+    }
+}
+
+/// Copies from src into half then from half into dst, returns false on a socket error
+fn pump(mut src: &TcpStream, half: &mut Half, mut dst: &TcpStream) -> bool {
+    let mut buf = [0u8; 16 * 1024];
+    while !half.eof && half.buf.len() < BUF_SIZE {
+        let room = (BUF_SIZE - half.buf.len()).min(buf.len());
+        match src.read(&mut buf[..room]) {
+            Ok(0) => half.eof = true,
+            Ok(n) => half.buf.extend(&buf[..n]),
+            Err(ref e) if would_block(e) => break,
+            Err(_) => return false,
+        }
+    }
+    while !half.buf.is_empty() {
+        let (front, _) = half.buf.as_slices();
+        match dst.write(front) {
+            Ok(n) => {
+                half.buf.drain(..n);
+            }
+            Err(ref e) if would_block(e) => break,
+            Err(_) => return false,
+        }
+    }
+    if half.eof && half.buf.is_empty() && !half.shut {
+        let _ = dst.shutdown(Shutdown::Write);
+        half.shut = true;
+    }
+    true
+}
+
+/// Non-blocking TCP connection to ip:port of the current NET namespace
+fn connect_jail(ip: Ipv4Addr, port: u16) -> io::Result<TcpStream> {
+    let fd = unsafe {
+        libc::socket(
+            libc::AF_INET,
+            libc::SOCK_STREAM | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+            0,
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // from here the TcpStream owns fd and closes it
+    let stream = unsafe { TcpStream::from_raw_fd(fd) };
+
+    let mut sin: libc::sockaddr_in = unsafe { mem::zeroed() };
+    sin.sin_family = libc::AF_INET as libc::sa_family_t;
+    sin.sin_port = port.to_be();
+    sin.sin_addr.s_addr = u32::from(ip).to_be();
+    let res = unsafe {
+        libc::connect(
+            fd,
+            &sin as *const libc::sockaddr_in as *const libc::sockaddr,
+            mem::size_of::<libc::sockaddr_in>() as libc::socklen_t,
+        )
+    };
+    if res < 0 && Errno::last() != Errno::EINPROGRESS {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(stream)
+}
+
+/// UDP socket of the current NET namespace connected to ip:port
+fn udp_jail(ip: Ipv4Addr, port: u16) -> io::Result<UdpSocket> {
+    let sock = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    sock.connect((ip, port))?;
+    sock.set_nonblocking(true)?;
+    Ok(sock)
+}
+
+fn run(mut fwd: Forwarder, stop: EventFd) {
+    loop {
+        match fwd.poll(-1) {
+            Ok(false) => {}
+            Ok(true) => break,
+            Err(e) => {
+                println!("port forwarding stopped: {}", e);
+                break;
+            }
+        }
+    }
+    drop(stop);
+}
+
+/**
+ * Where the forwards connect in the jail, 127.0.0.1 unless lo stays down, the first IPv4 address of the jail then.
+ * That address must belong to the jail itself, the one of the tap device in user-mode networking or of its first
+ * macvlan or veth interface
+ */
+fn jail_addr(jconf: &JailConf) -> Result<Ipv4Addr> {
+    if jconf.iface_lo {
+        return Ok(Ipv4Addr::LOCALHOST);
+    }
+
+    let ips: Vec<String> = if let Some(ref user_net) = jconf.user_net {
+        vec![user_net.ip.to_string()]
+    } else if let Some(ref multi_net) = jconf.multi_net {
+        multi_net
+            .iface_vs_ip
+            .iter()
+            .map(|ip| ip.to_string_lossy().into_owned())
+            .collect()
+    } else if !jconf.iface_vs.to_bytes().is_empty() {
+        vec![jconf.iface_vs_ip.to_string_lossy().into_owned()]
+    } else {
+        Vec::new()
+    };
+    ips.iter()
+        .filter_map(|ip| match parse_cidr(ip, Some(0)) {
+            Some((IpAddr::V4(ip), _)) if !ip.is_unspecified() => Some(ip),
+            _ => None,
+        })
+        .next()
+        .ok_or_else(|| {
+            "port forwarding needs lo up or an IPv4 address in the jail to connect to".into()
+        })
+}
+
+/**
+ * Starts forwarding jconf.port_forwards to the jail pid, in a thread of the parent which runs until
+ * finish_from_parent. The host addresses are bound before it returns
+ */
+pub fn start_from_parent(jconf: &mut JailConf, pid: libc::pid_t) -> Result<()> {
+    if jconf.port_forwards.is_empty() {
+        return Ok(());
+    }
+    if !jconf.clone_newnet {
+        return Err("port forwarding needs the child to have its own NET namespace".into());
+    }
+
+    let jail_ip = jail_addr(jconf)?;
+    let mut fwd = Forwarder::new(&jconf.port_forwards, Some(pid), jconf.max_conns_per_ip)?;
+    fwd.jail_ip = jail_ip;
+    let stop = EventFd::new()?;
+    let thread_stop = stop.try_clone()?;
+    fwd.epoll(
+        EpollOp::EpollCtlAdd,
+        thread_stop.as_raw_fd(),
+        EpollFlags::EPOLLIN,
+        STOP_TOKEN,
+    )?;
+    thread::Builder::new()
+        .name("portfwd".to_string())
+        .spawn(move || run(fwd, thread_stop))?;
+
+    jconf.port_forward_fd = Some(stop.as_raw_fd());
+    // closed by finish_from_parent
+    mem::forget(stop);
+
+    Ok(())
+}
+
+/// Stops the proxy of the jail, which closes its listeners and every connection it still forwards
+pub fn finish_from_parent(jconf: &JailConf) {
+    if let Some(fd) = jconf.port_forward_fd {
+        let one: u64 = 1;
+        unsafe {
+            libc::write(fd, &one as *const u64 as *const libc::c_void, 8);
+            libc::close(fd);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::MultiNetConfig;
+    use std::ffi::CString;
+
+    #[test]
+    fn test_parse_port_forward() {
+        assert_eq!(
+            parse_port_forward("8080:80", "::").unwrap(),
+            PortForward {
+                proto: ForwardProto::Tcp,
+                host: "[::]:8080".parse().unwrap(),
+                jail_port: 80,
+            }
+        );
+        assert_eq!(
+            parse_port_forward("127.0.0.1:5353:53/udp", "::").unwrap(),
+            PortForward {
+                proto: ForwardProto::Udp,
+                host: "127.0.0.1:5353".parse().unwrap(),
+                jail_port: 53,
+            }
+        );
+        assert_eq!(
+            parse_port_forward("[fd00::1]:443:8443/tcp", "0.0.0.0")
+                .unwrap()
+                .host,
+            "[fd00::1]:443".parse().unwrap()
+        );
+
+        for bad in &[
+            "80",
+            "0:80",
+            "8080:0",
+            "8080:80/sctp",
+            "host:8080:80",
+            "8080:x",
+        ] {
+            assert!(parse_port_forward(bad, "::").is_err(), "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn test_jail_addr() {
+        let mut jconf = JailConf::default();
+        assert_eq!(jail_addr(&jconf).unwrap(), Ipv4Addr::LOCALHOST);
+
+        jconf.iface_lo = false;
+        assert!(jail_addr(&jconf).is_err());
+
+        jconf.multi_net = Some(MultiNetConfig {
+            iface_vs: Vec::new(),
+            iface_vs_mode: Vec::new(),
+            iface_vs_ip: vec![
+                CString::new("fd00:7a::2/64").unwrap(),
+                CString::new("10.166.0.2").unwrap(),
+            ],
+            iface_vs_nm: Vec::new(),
+            iface_vs_gw: Vec::new(),
+            iface_vs_ma: Vec::new(),
+            iface_vs_ip6: Vec::new(),
+            iface_vs_gw6: Vec::new(),
+        });
+        assert_eq!(jail_addr(&jconf).unwrap(), Ipv4Addr::new(10, 166, 0, 2));
+    }
+
+    fn forwarder(
+        proto: ForwardProto,
+        jail_port: u16,
+        max_conns_per_ip: u32,
+    ) -> (Forwarder, SocketAddr) {
+        let fwd = Forwarder::new(
+            &[PortForward {
+                proto,
+                host: "127.0.0.1:0".parse().unwrap(),
+                jail_port,
+            }],
+            None,
+            max_conns_per_ip,
+        )
+        .unwrap();
+        let addr = fwd.local_addrs()[0];
+        (fwd, addr)
+    }
+
+    #[test]
+    fn test_tcp_forward() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let jail_port = server.local_addr().unwrap().port();
+        thread::spawn(move || {
+            for s in server.incoming() {
+                let mut s = s.unwrap();
+                thread::spawn(move || {
+                    let mut data = Vec::new();
+                    s.read_to_end(&mut data).unwrap();
+                    s.write_all(&data).unwrap();
+                });
+            }
+        });
+
+        let (mut fwd, addr) = forwarder(ForwardProto::Tcp, jail_port, 1);
+        let client = thread::spawn(move || {
+            let mut c = TcpStream::connect(addr).unwrap();
+
+            // over max_conns_per_ip, closed right away, while the first one is still open
+            let mut second = TcpStream::connect(addr).unwrap();
+            let mut buf = [0u8; 1];
+            assert_eq!(second.read(&mut buf).unwrap_or(0), 0);
+
+            let data: Vec<u8> = (0..200_000u32).map(|i| i as u8).collect();
+            let mut w = c.try_clone().unwrap();
+            let sent = data.clone();
+            let writer = thread::spawn(move || {
+                w.write_all(&sent).unwrap();
+                w.shutdown(Shutdown::Write).unwrap();
+            });
+
+            let mut back = Vec::new();
+            c.read_to_end(&mut back).unwrap();
+            writer.join().unwrap();
+            assert_eq!(back, data);
+        });
+
+        while !client.is_finished() {
+            fwd.poll(10).unwrap();
+        }
+        client.join().unwrap();
+        for _ in 0..10 {
+            fwd.poll(10).unwrap();
+        }
+        assert!(fwd.conns.is_empty());
+        assert!(fwd.conns_per_ip.is_empty());
+    }
+
+    #[test]
+    fn test_udp_forward() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let jail_port = server.local_addr().unwrap().port();
+        thread::spawn(move || {
+            let mut buf = [0u8; 64];
+            let (n, from) = server.recv_from(&mut buf).unwrap();
+            server.send_to(&buf[..n], from).unwrap();
+        });
+
+        let (mut fwd, addr) = forwarder(ForwardProto::Udp, jail_port, 0);
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client.send_to(b"ping", addr).unwrap();
+        client.set_nonblocking(true).unwrap();
+        let mut buf = [0u8; 64];
+        for _ in 0..100 {
+            fwd.poll(10).unwrap();
+            if let Ok((n, from)) = client.recv_from(&mut buf) {
+                assert_eq!((&buf[..n], from), (&b"ping"[..], addr));
+                return;
+            }
+        }
+        panic!("no reply");
+    }
+}
@@ -0,0 +1,1657 @@
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::ffi::CString;
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::Path;
+
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+use seccomp::{
+    policy, profiles, BpfProgram, SeccompAction, SeccompCmpArgLen, SeccompCmpOp, SeccompCondition,
+    SeccompFilter, SeccompRule,
+};
+
+use super::caps;
+use super::config::{
+    EgressPolicy, IDMapT, JailConf, MountT, MultiNetConfig, NetMode, NotifyMount, RestartConfig,
+    RestartPolicy, SeccompNotifyConfig, UserNetConfig,
+};
+use super::error::Result;
+use super::{cgroup, cgroupv2, firewall, net, portfwd};
+
+// personality(2) flags, see config.md, values from linux/personality.h
+const ADDR_NO_RANDOMIZE: u64 = 0x0040000;
+const MMAP_PAGE_ZERO: u64 = 0x0100000;
+const ADDR_COMPAT_LAYOUT: u64 = 0x0200000;
+const READ_IMPLIES_EXEC: u64 = 0x0400000;
+const ADDR_LIMIT_3GB: u64 = 0x8000000;
+
+const HOST_NAME_MAX: usize = 64;
+
+/**
+ * Declarative description of a jail, loaded from a TOML or JSON file.
+ *
+ * The profile owns all of its strings, the JailConf built by to_jail_conf borrows from it,
+ * so keep the profile alive as long as the jail configuration is used.
+ *
+ * See config.md for the file format.
+ */
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct JailProfile {
+    pub debug: bool,
+    pub hostname: Option<String>,
+    pub cwd: Option<String>,
+    pub chroot: Option<String>,
+    pub chroot_rw: bool,
+    pub proc_path: Option<String>, // empty string disables the /proc mount
+    pub proc_rw: bool,
+    pub daemonize: bool,
+    pub tlimit: u64,
+    pub tlimit_grace: Option<u64>,
+    pub restart: Option<RestartProfile>,
+    pub max_cpus: u16,
+    pub nice_level: Option<i64>,
+    pub personality: Vec<String>,
+    pub keep_env: bool,
+    pub keep_caps: bool,
+    pub caps: Vec<CapValue>,
+    pub silent: bool,
+    pub stderr_to_null: bool,
+    pub skip_setsid: bool,
+    pub disable_no_new_privs: bool,
+    pub mnt_ms_slave: bool,
+    pub port: u32,
+    pub bind_host: Option<String>,
+    pub max_conns_per_ip: u32,
+    pub inside_uid: Option<i32>,
+    pub inside_gid: Option<i32>,
+
+    pub exec_file: Option<String>, // defaults to argv[0]
+    pub argv: Vec<String>,
+    pub env: Vec<String>,
+
+    pub namespaces: NamespacesProfile,
+    pub mounts: Vec<MountProfile>,
+    pub uid_map: Vec<IdMapProfile>,
+    pub gid_map: Vec<IdMapProfile>,
+    pub rlimits: Option<RlimitsProfile>,
+    pub cgroup: CgroupProfile,
+    pub net: NetProfile,
+    pub seccomp: Option<SeccompProfile>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NamespacesProfile {
+    pub net: bool,
+    pub user: bool,
+    pub mount: bool,
+    pub pid: bool,
+    pub ipc: bool,
+    pub uts: bool,
+    pub cgroup: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MountProfile {
+    pub src: Option<String>,
+    pub dst: String,
+    pub fstype: Option<String>,
+    pub options: Option<String>,
+    #[serde(default)]
+    pub rw: bool,
+    pub bind: Option<bool>, // defaults to true when src is set and fstype is not
+    pub is_dir: Option<bool>, // defaults to the type of src, or true
+    #[serde(default)]
+    pub is_symlink: bool,
+    #[serde(default = "default_true")]
+    pub mandatory: bool,
+    #[serde(default)]
+    pub nosuid: bool,
+    #[serde(default)]
+    pub nodev: bool,
+    #[serde(default)]
+    pub noexec: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct IdMapProfile {
+    pub inside: libc::uid_t,
+    pub outside: libc::uid_t,
+    #[serde(default = "default_count")]
+    pub count: u64,
+    #[serde(default)]
+    pub use_newidmap: bool,
+}
+
+/// A capability is either its number or its name, e.g. "CAP_NET_BIND_SERVICE" or "net_bind_service"
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum CapValue {
+    Value(i64),
+    Name(String),
+}
+
+/// A rlimit is either a number (MB for as, core, fsize and stack) or one of "soft", "hard", "inf"
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum RlimitValue {
+    Value(u64),
+    Special(String),
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RlimitsProfile {
+    #[serde(rename = "as")]
+    pub as_: Option<RlimitValue>,
+    pub core: Option<RlimitValue>,
+    pub cpu: Option<RlimitValue>,
+    pub fsize: Option<RlimitValue>,
+    pub nofile: Option<RlimitValue>,
+    pub nproc: Option<RlimitValue>,
+    pub stack: Option<RlimitValue>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CgroupProfile {
+    pub use_v2: Option<bool>, // detected from the host when missing, see cgroup.rs
+    pub v2_mount: Option<String>,
+    pub v2_parent: Option<String>, // may be nested, e.g. "toastainer/jails"
+    pub mem_max: u64,
+    pub mem_mount: Option<String>,
+    pub mem_parent: Option<String>,
+    pub pids_max: u64,
+    pub pids_mount: Option<String>,
+    pub pids_parent: Option<String>,
+    pub net_cls_classid: u64,
+    pub net_cls_mount: Option<String>,
+    pub net_cls_parent: Option<String>,
+    pub cpu_ms_per_sec: u64,
+    pub cpu_mount: Option<String>,
+    pub cpu_parent: Option<String>,
+    // cgroup v2 only
+    pub mem_high: u64,
+    pub mem_swap_max: Option<u64>,
+    pub io_weight: u64,
+    pub io_max: Vec<String>, // "<device>[,rbps=<n>][,wbps=<n>][,riops=<n>][,wiops=<n>]"
+    pub cpuset_cpus: Option<String>,
+    pub cpuset_mems: Option<String>,
+    pub hugetlb_max: BTreeMap<String, u64>, // page size, e.g. "2MB", to bytes
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NetProfile {
+    pub lo: bool,
+    pub ifaces: Vec<CString>, // existing interfaces moved inside the jail
+    pub macvlan: Vec<MacvlanProfile>,
+    pub veth: Vec<VethProfile>, // numbered after the macvlan ones, vs_<n>
+    pub ipv6: bool,             // false disables IPv6 inside the jail
+    pub egress: Option<EgressProfile>,
+    pub user: Option<UserNetProfile>, // user-mode network for rootless jails, instead of macvlan and veth
+    pub forward: Vec<String>, // "[<host_ip>:]<host_port>:<jail_port>[/tcp|/udp]", bind_host when host_ip is missing
+}
+
+impl Default for NetProfile {
+    fn default() -> NetProfile {
+        NetProfile {
+            lo: true,
+            ifaces: vec![],
+            macvlan: vec![],
+            veth: vec![],
+            ipv6: true,
+            egress: None,
+            user: None,
+            forward: vec![],
+        }
+    }
+}
+
+/// See EgressPolicy, allow entries are written "<cidr>[,tcp|udp][,<port>[-<port>]]"
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EgressProfile {
+    pub allow: Vec<String>,
+    pub dns_only: bool,
+    pub block_link_local: bool,
+}
+
+impl Default for EgressProfile {
+    fn default() -> EgressProfile {
+        EgressProfile {
+            allow: vec![],
+            dns_only: false,
+            block_link_local: true,
+        }
+    }
+}
+
+/// See RestartConfig, policy is "always" or "on_failure"
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RestartProfile {
+    pub policy: String,
+    pub max_restarts: u32,
+    pub backoff_ms: u64,
+    pub backoff_max_ms: u64,
+}
+
+impl Default for RestartProfile {
+    fn default() -> RestartProfile {
+        let d = RestartConfig::default();
+        RestartProfile {
+            policy: String::from("always"),
+            max_restarts: d.max_restarts,
+            backoff_ms: d.backoff_ms,
+            backoff_max_ms: d.backoff_max_ms,
+        }
+    }
+}
+
+/// See UserNetConfig, ip is written with its prefix length, /24 if missing
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UserNetProfile {
+    pub ip: String,
+    pub gateway: String,
+    pub dns: String,
+    pub host_loopback: bool,
+}
+
+impl Default for UserNetProfile {
+    fn default() -> UserNetProfile {
+        let d = UserNetConfig::default();
+        UserNetProfile {
+            ip: format!("{}/{}", d.ip, d.prefix_len),
+            gateway: d.gateway.to_string(),
+            dns: d.dns.to_string(),
+            host_loopback: d.host_loopback,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MacvlanProfile {
+    pub iface: String,
+    pub ip: String,
+    #[serde(default = "default_netmask")]
+    pub netmask: String,
+    #[serde(default = "default_gateway")]
+    pub gateway: String,
+    #[serde(default)]
+    pub mac: String,
+    #[serde(default)]
+    pub ip6: String, // dual-stack, IPv6 address with its prefix length, /64 if missing
+    #[serde(default)]
+    pub gateway6: String,
+}
+
+/// Same as MacvlanProfile but the jail is connected to a host bridge through a veth pair, see NetMode::Veth
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VethProfile {
+    pub bridge: String,
+    pub ip: String,
+    #[serde(default = "default_netmask")]
+    pub netmask: String,
+    #[serde(default = "default_gateway")]
+    pub gateway: String,
+    #[serde(default)]
+    pub mac: String,
+    #[serde(default)]
+    pub ip6: String, // dual-stack, IPv6 address with its prefix length, /64 if missing
+    #[serde(default)]
+    pub gateway6: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SeccompProfile {
+    #[serde(default = "default_allow")]
+    pub default_action: String,
+    #[serde(default)]
+    pub log: bool,
+    #[serde(default)]
+    pub rules: Vec<SeccompRuleProfile>,
+    #[serde(default)]
+    pub policy: String, // a policy in the text language of seccomp::policy, instead of default_action and rules
+    #[serde(default)]
+    pub policy_file: String, // same, from a file
+    #[serde(default)]
+    pub profile: String, // a built-in profile of seccomp::profiles, e.g. "python", instead of all of the above
+    pub notify: Option<SeccompNotifyProfile>, // what the parent accepts of the syscalls handed over with user_notif
+    #[serde(default)]
+    pub learn: String, // file the parent writes the policy of the syscalls the jail made to, the rest is then ignored
+}
+
+impl SeccompProfile {
+    /// a seccomp policy file holds the content of a profile [seccomp] table
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<SeccompProfile> {
+        parse_file(path.as_ref())
+    }
+}
+
+impl SeccompProfile {
+    /// The filter of policy or policy_file, which set the default action and rules on their own
+    fn parse_policy(&self) -> Result<SeccompFilter> {
+        let key = if self.policy.is_empty() {
+            "seccomp.policy_file"
+        } else {
+            "seccomp.policy"
+        };
+        if !self.policy.is_empty() && !self.policy_file.is_empty() {
+            return Err(key_err(key, "cannot be combined with seccomp.policy_file"));
+        }
+        if !self.rules.is_empty() || self.default_action != default_allow() {
+            return Err(key_err(
+                key,
+                "the policy has its own DEFAULT and rules, seccomp.default_action and seccomp.rules must be left out",
+            ));
+        }
+
+        if self.policy.is_empty() {
+            policy::parse_policy_file(&self.policy_file)
+        } else {
+            policy::parse_policy(&self.policy)
+        }
+        .map_err(|e| key_err(key, &e.to_string()))
+    }
+
+    /// The filter of default_action and rules
+    fn parse_rules(&self) -> Result<SeccompFilter> {
+        let default_action = parse_action("seccomp.default_action", &self.default_action)?;
+
+        let mut rules: BTreeMap<i64, Vec<SeccompRule>> = BTreeMap::new();
+        for (i, r) in self.rules.iter().enumerate() {
+            let key = format!("seccomp.rules[{}]", i);
+            if r.syscall < 0 {
+                return Err(key_err(&format!("{}.syscall", key), "must be positive"));
+            }
+            let action = parse_action(&format!("{}.action", key), &r.action)?;
+
+            let mut conditions = Vec::with_capacity(r.args.len());
+            for (j, arg) in r.args.iter().enumerate() {
+                conditions.push(parse_condition(&format!("{}.args[{}]", key, j), arg)?);
+            }
+
+            rules
+                .entry(r.syscall)
+                .or_default()
+                .push(SeccompRule::new(conditions, action));
+        }
+
+        SeccompFilter::new(rules, default_action).map_err(|e| key_err("seccomp", &e.to_string()))
+    }
+}
+
+impl Default for SeccompProfile {
+    fn default() -> SeccompProfile {
+        SeccompProfile {
+            default_action: default_allow(),
+            log: false,
+            rules: vec![],
+            policy: String::new(),
+            policy_file: String::new(),
+            profile: String::new(),
+            notify: None,
+            learn: String::new(),
+        }
+    }
+}
+
+/// See SeccompNotifyConfig, connect is written as net.egress.allow
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SeccompNotifyProfile {
+    #[serde(default)]
+    pub mounts: Vec<NotifyMountProfile>,
+    #[serde(default)]
+    pub connect: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NotifyMountProfile {
+    #[serde(default)]
+    pub src: String,
+    pub dst: String,
+    #[serde(default)]
+    pub fstype: String,
+    #[serde(default)]
+    pub flags: Vec<String>, // rdonly, nosuid, nodev, noexec, remount, bind, rec, noatime, nodiratime, relatime, silent
+    #[serde(default)]
+    pub data: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SeccompRuleProfile {
+    pub syscall: i64,
+    pub action: String,
+    #[serde(default)]
+    pub args: Vec<SeccompArgProfile>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SeccompArgProfile {
+    pub index: u8,
+    pub op: String,
+    pub value: u64,
+    pub mask: Option<u64>, // only for op = "masked_eq"
+    #[serde(default = "default_qword")]
+    pub len: String,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_count() -> u64 {
+    1
+}
+
+fn default_netmask() -> String {
+    String::from("255.255.255.0")
+}
+
+fn default_gateway() -> String {
+    String::from("0.0.0.0")
+}
+
+fn default_allow() -> String {
+    String::from("allow")
+}
+
+fn default_qword() -> String {
+    String::from("qword")
+}
+
+impl JailProfile {
+    /// files ending in .json are parsed as JSON, everything else as TOML
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<JailProfile> {
+        parse_file(path.as_ref())
+    }
+
+    pub fn from_toml_str(content: &str) -> Result<JailProfile> {
+        parse_toml(content)
+    }
+
+    pub fn from_json_str(content: &str) -> Result<JailProfile> {
+        parse_json(content)
+    }
+
+    /// Validates the profile and builds the matching JailConf, default mounts included
+    pub fn to_jail_conf(&self) -> Result<JailConf<'_>> {
+        let mut jconf = JailConf::new();
+        jconf.num_cpus = unsafe { libc::sysconf(libc::_SC_NPROCESSORS_ONLN) };
+
+        jconf.debug = self.debug;
+        if let Some(ref hostname) = self.hostname {
+            if hostname.is_empty() || hostname.len() > HOST_NAME_MAX {
+                return Err(key_err("hostname", "must be between 1 and 64 bytes"));
+            }
+            jconf.hostname = hostname;
+        }
+        if let Some(ref cwd) = self.cwd {
+            if !cwd.starts_with('/') {
+                return Err(key_err("cwd", "must be an absolute path"));
+            }
+            jconf.cwd = cwd.clone();
+        }
+        if let Some(ref chroot) = self.chroot {
+            jconf.chroot = to_cstring("chroot", chroot)?;
+        }
+        jconf.is_root_rw = self.chroot_rw;
+        if let Some(ref proc_path) = self.proc_path {
+            jconf.proc_path = proc_path.trim_start_matches('/').to_owned();
+        }
+        jconf.is_proc_rw = self.proc_rw;
+        jconf.daemonize = self.daemonize;
+        jconf.tlimit = self.tlimit;
+        if let Some(grace) = self.tlimit_grace {
+            jconf.tlimit_grace = grace;
+        }
+        if let Some(ref restart) = self.restart {
+            jconf.restart = Some(RestartConfig {
+                policy: match restart.policy.as_str() {
+                    "always" => RestartPolicy::Always,
+                    "on_failure" => RestartPolicy::OnFailure,
+                    _ => {
+                        return Err(key_err(
+                            "restart.policy",
+                            &format!(
+                                "unknown policy {:?}, expected always or on_failure",
+                                restart.policy
+                            ),
+                        ))
+                    }
+                },
+                max_restarts: restart.max_restarts,
+                backoff_ms: restart.backoff_ms,
+                backoff_max_ms: restart.backoff_max_ms,
+            });
+        }
+        jconf.max_cpus = self.max_cpus;
+        if let Some(nice_level) = self.nice_level {
+            if !(-20..=19).contains(&nice_level) {
+                return Err(key_err("nice_level", "must be between -20 and 19"));
+            }
+            jconf.nice_level = nice_level;
+        }
+        for (i, persona) in self.personality.iter().enumerate() {
+            jconf.personality |= match persona.as_str() {
+                "addr_no_randomize" => ADDR_NO_RANDOMIZE,
+                "mmap_page_zero" => MMAP_PAGE_ZERO,
+                "addr_compat_layout" => ADDR_COMPAT_LAYOUT,
+                "read_implies_exec" => READ_IMPLIES_EXEC,
+                "addr_limit_3gb" => ADDR_LIMIT_3GB,
+                _ => {
+                    return Err(key_err(
+                        &format!("personality[{}]", i),
+                        &format!("unknown persona {:?}", persona),
+                    ))
+                }
+            };
+        }
+        jconf.keep_env = self.keep_env;
+        jconf.keep_caps = self.keep_caps;
+        for (i, cap) in self.caps.iter().enumerate() {
+            let parsed = match cap {
+                CapValue::Value(n) => caps::parse_cap(&n.to_string()),
+                CapValue::Name(name) => caps::parse_cap(name),
+            };
+            jconf
+                .caps
+                .push(parsed.map_err(|e| key_err(&format!("caps[{}]", i), &e.to_string()))?);
+        }
+        jconf.is_silent = self.silent;
+        jconf.stderr_to_null = self.stderr_to_null;
+        jconf.skip_setsid = self.skip_setsid;
+        jconf.disable_no_new_privs = self.disable_no_new_privs;
+        jconf.mnt_ms_slave = self.mnt_ms_slave;
+        jconf.port = self.port;
+        if let Some(ref bind_host) = self.bind_host {
+            jconf.bind_host = bind_host;
+        }
+        jconf.max_conns_per_ip = self.max_conns_per_ip;
+        if let Some(uid) = self.inside_uid {
+            jconf.user_inside_uid = uid;
+        }
+        if let Some(gid) = self.inside_gid {
+            jconf.user_inside_gid = gid;
+        }
+
+        self.apply_exec(&mut jconf)?;
+        self.apply_namespaces(&mut jconf);
+        self.apply_id_maps(&mut jconf)?;
+        self.apply_rlimits(&mut jconf)?;
+        self.apply_cgroup(&mut jconf)?;
+        self.apply_net(&mut jconf)?;
+        self.apply_seccomp(&mut jconf)?;
+
+        // the root and /proc mounts come first, see config.rs::with_default_mounts
+        jconf.with_default_mounts();
+        self.apply_mounts(&mut jconf)?;
+
+        Ok(jconf)
+    }
+
+    fn apply_exec(&self, jconf: &mut JailConf) -> Result<()> {
+        if !self.argv.is_empty() {
+            let mut argv = Vec::with_capacity(self.argv.len());
+            for (i, arg) in self.argv.iter().enumerate() {
+                argv.push(to_cstring(&format!("argv[{}]", i), arg)?);
+            }
+            let exec_file = match self.exec_file {
+                Some(ref f) => to_cstring("exec_file", f)?,
+                None => argv[0].clone(),
+            };
+            jconf.exec_file = Some(exec_file);
+            jconf.argv = Some(argv);
+        } else if self.exec_file.is_some() {
+            return Err(key_err("argv", "must not be empty when exec_file is set"));
+        }
+
+        if !self.env.is_empty() {
+            let mut env = Vec::with_capacity(self.env.len());
+            for (i, var) in self.env.iter().enumerate() {
+                let key = format!("env[{}]", i);
+                match var.find('=') {
+                    Some(pos) if pos > 0 => env.push(to_cstring(&key, var)?),
+                    _ => return Err(key_err(&key, "must be of the form NAME=value")),
+                }
+            }
+            jconf.env = Some(env);
+        }
+
+        Ok(())
+    }
+
+    fn apply_namespaces(&self, jconf: &mut JailConf) {
+        jconf.clone_newnet = self.namespaces.net;
+        jconf.clone_newuser = self.namespaces.user;
+        jconf.clone_newns = self.namespaces.mount;
+        jconf.clone_newpid = self.namespaces.pid;
+        jconf.clone_newipc = self.namespaces.ipc;
+        jconf.clone_newuts = self.namespaces.uts;
+        jconf.clone_newcgroup = self.namespaces.cgroup;
+    }
+
+    fn apply_id_maps(&self, jconf: &mut JailConf) -> Result<()> {
+        jconf.uids.extend(to_id_maps("uid_map", &self.uid_map)?);
+        jconf.gids.extend(to_id_maps("gid_map", &self.gid_map)?);
+        Ok(())
+    }
+
+    fn apply_rlimits(&self, jconf: &mut JailConf) -> Result<()> {
+        let rlimits = match self.rlimits {
+            Some(ref r) => r,
+            None => {
+                jconf.disable_rl = true;
+                return Ok(());
+            }
+        };
+        jconf.disable_rl = false;
+
+        // unset limits keep the current soft limit, as nsjail does
+        let (hard, soft, inf, val) = rlimit_args("rlimits.as", &rlimits.as_)?;
+        jconf.with_rlimit_as(hard, soft, inf, val)?;
+        let (hard, soft, inf, val) = rlimit_args("rlimits.core", &rlimits.core)?;
+        jconf.with_rlimit_core(hard, soft, inf, val)?;
+        let (hard, soft, inf, val) = rlimit_args("rlimits.cpu", &rlimits.cpu)?;
+        jconf.with_rlimit_cpu(hard, soft, inf, val)?;
+        let (hard, soft, inf, val) = rlimit_args("rlimits.fsize", &rlimits.fsize)?;
+        jconf.with_rlimit_fsize(hard, soft, inf, val)?;
+        let (hard, soft, inf, val) = rlimit_args("rlimits.nofile", &rlimits.nofile)?;
+        jconf.with_rlimit_nofile(hard, soft, inf, val)?;
+        let (hard, soft, inf, val) = rlimit_args("rlimits.nproc", &rlimits.nproc)?;
+        jconf.with_rlimit_nproc(hard, soft, inf, val)?;
+        let (hard, soft, inf, val) = rlimit_args("rlimits.stack", &rlimits.stack)?;
+        jconf.with_rlimit_stack(hard, soft, inf, val)?;
+
+        Ok(())
+    }
+
+    fn apply_cgroup<'a>(&'a self, jconf: &mut JailConf<'a>) -> Result<()> {
+        let cg = &self.cgroup;
+
+        jconf.cgroup_mem_max = cg.mem_max;
+        jconf.cgroup_pids_max = cg.pids_max;
+        jconf.cgroup_net_cls_classid = cg.net_cls_classid;
+        jconf.cgroup_cpu_ms_per_sec = cg.cpu_ms_per_sec;
+        if cg.cpu_ms_per_sec > 1000 * jconf.num_cpus.max(1) as u64 {
+            return Err(key_err(
+                "cgroup.cpu_ms_per_sec",
+                "exceeds the cpu time available on this host",
+            ));
+        }
+
+        jconf.cgroup_mem_high = cg.mem_high;
+        if let Some(swap) = cg.mem_swap_max {
+            jconf.cgroup_mem_swap_max = swap.min(i64::MAX as u64) as i64;
+        }
+        if cg.io_weight > 10000 {
+            return Err(key_err("cgroup.io_weight", "must be between 1 and 10000"));
+        }
+        jconf.cgroup_io_weight = cg.io_weight;
+        for (i, io_max) in cg.io_max.iter().enumerate() {
+            jconf.cgroup_io_max.push(
+                cgroupv2::parse_io_max(io_max)
+                    .map_err(|e| key_err(&format!("cgroup.io_max[{}]", i), &e.to_string()))?,
+            );
+        }
+        if let Some(ref cpus) = cg.cpuset_cpus {
+            jconf.cgroup_cpuset_cpus = cpus;
+        }
+        if let Some(ref mems) = cg.cpuset_mems {
+            jconf.cgroup_cpuset_mems = mems;
+        }
+        for (size, max) in cg.hugetlb_max.iter() {
+            cgroupv2::check_hugetlb_page_size(size)
+                .map_err(|e| key_err(&format!("cgroup.hugetlb_max.{}", size), &e.to_string()))?;
+            jconf.cgroup_hugetlb_max.push((size.clone(), *max));
+        }
+
+        // the detected mounts come first so that the ones of the profile override them. Without a cgroup namespace
+        // there is nothing to detect, starting the jail fails if it has limits, see cgroup::check_namespace
+        match cg.use_v2 {
+            Some(use_v2) => jconf.use_cgroupv2 = use_v2,
+            None if jconf.clone_newcgroup => cgroup::host()
+                .select_backend(jconf)
+                .map_err(|e| key_err("cgroup", &e.to_string()))?,
+            None => {}
+        }
+
+        if let Some(ref m) = cg.v2_mount {
+            jconf.cgroupv2_mount = check_abs_path("cgroup.v2_mount", m)?;
+        }
+        if let Some(ref p) = cg.v2_parent {
+            jconf.cgroupv2_parent = check_parent("cgroup.v2_parent", p)?;
+        }
+        if let Some(ref m) = cg.mem_mount {
+            jconf.cgroup_mem_mount = check_abs_path("cgroup.mem_mount", m)?;
+        }
+        if let Some(ref p) = cg.mem_parent {
+            jconf.cgroup_mem_parent = check_parent("cgroup.mem_parent", p)?;
+        }
+        if let Some(ref m) = cg.pids_mount {
+            jconf.cgroup_pids_mount = check_abs_path("cgroup.pids_mount", m)?;
+        }
+        if let Some(ref p) = cg.pids_parent {
+            jconf.cgroup_pids_parent = check_parent("cgroup.pids_parent", p)?;
+        }
+        if let Some(ref m) = cg.net_cls_mount {
+            jconf.cgroup_net_cls_mount = check_abs_path("cgroup.net_cls_mount", m)?;
+        }
+        if let Some(ref p) = cg.net_cls_parent {
+            jconf.cgroup_net_cls_parent = check_parent("cgroup.net_cls_parent", p)?;
+        }
+        if let Some(ref m) = cg.cpu_mount {
+            jconf.cgroup_cpu_mount = check_abs_path("cgroup.cpu_mount", m)?;
+        }
+        if let Some(ref p) = cg.cpu_parent {
+            jconf.cgroup_cpu_parent = check_parent("cgroup.cpu_parent", p)?;
+        }
+
+        Ok(())
+    }
+
+    fn apply_net<'a>(&'a self, jconf: &mut JailConf<'a>) -> Result<()> {
+        jconf.iface_lo = self.net.lo;
+        jconf.ifaces = self.net.ifaces.iter().map(|i| i.as_c_str()).collect();
+        jconf.iface_ipv6 = self.net.ipv6;
+
+        if let Some(ref egress) = self.net.egress {
+            let mut allow = Vec::with_capacity(egress.allow.len());
+            for (i, rule) in egress.allow.iter().enumerate() {
+                allow.push(
+                    firewall::parse_egress_rule(rule).map_err(|e| {
+                        key_err(&format!("net.egress.allow[{}]", i), &e.to_string())
+                    })?,
+                );
+            }
+            jconf.egress = Some(EgressPolicy {
+                allow,
+                dns_only: egress.dns_only,
+                block_link_local: egress.block_link_local,
+            });
+        }
+
+        for (i, forward) in self.net.forward.iter().enumerate() {
+            jconf.port_forwards.push(
+                portfwd::parse_port_forward(forward, jconf.bind_host)
+                    .map_err(|e| key_err(&format!("net.forward[{}]", i), &e.to_string()))?,
+            );
+        }
+
+        if let Some(ref user) = self.net.user {
+            if !self.net.macvlan.is_empty() || !self.net.veth.is_empty() {
+                return Err(key_err(
+                    "net.user",
+                    "cannot be combined with net.macvlan or net.veth",
+                ));
+            }
+            let (ip, prefix_len) = match net::parse_cidr(&user.ip, Some(24)) {
+                Some((IpAddr::V4(ip), prefix_len)) => (ip, prefix_len),
+                _ => {
+                    return Err(key_err(
+                        "net.user.ip",
+                        &format!("invalid IPv4 address {:?}", user.ip),
+                    ))
+                }
+            };
+            jconf.user_net = Some(UserNetConfig {
+                ip,
+                prefix_len,
+                gateway: parse_ipv4("net.user.gateway", &user.gateway)?,
+                dns: parse_ipv4("net.user.dns", &user.dns)?,
+                host_loopback: user.host_loopback,
+            });
+            return Ok(());
+        }
+
+        if self.net.macvlan.is_empty() && self.net.veth.is_empty() {
+            return Ok(());
+        }
+
+        let mut multi_net = MultiNetConfig {
+            iface_vs: vec![],
+            iface_vs_mode: vec![],
+            iface_vs_ip: vec![],
+            iface_vs_nm: vec![],
+            iface_vs_gw: vec![],
+            iface_vs_ma: vec![],
+            iface_vs_ip6: vec![],
+            iface_vs_gw6: vec![],
+        };
+        let macvlan = self.net.macvlan.iter().enumerate().map(|(i, mv)| {
+            (
+                format!("net.macvlan[{}]", i),
+                NetMode::Macvlan,
+                ("iface", &mv.iface),
+                [
+                    &mv.ip,
+                    &mv.netmask,
+                    &mv.gateway,
+                    &mv.mac,
+                    &mv.ip6,
+                    &mv.gateway6,
+                ],
+            )
+        });
+        let veth = self.net.veth.iter().enumerate().map(|(i, v)| {
+            (
+                format!("net.veth[{}]", i),
+                NetMode::Veth,
+                ("bridge", &v.bridge),
+                [&v.ip, &v.netmask, &v.gateway, &v.mac, &v.ip6, &v.gateway6],
+            )
+        });
+        for (table, mode, (iface_key, iface), [ip, netmask, gateway, mac, ip6, gateway6]) in
+            macvlan.chain(veth)
+        {
+            let key = |field: &str| format!("{}.{}", table, field);
+
+            if iface.is_empty() {
+                return Err(key_err(&key(iface_key), "must not be empty"));
+            }
+            if net::parse_cidr(ip, Some(0)).is_none() {
+                return Err(key_err(&key("ip"), &format!("invalid IP address {:?}", ip)));
+            }
+            if net::parse_netmask(netmask).is_none() {
+                return Err(key_err(
+                    &key("netmask"),
+                    &format!("invalid netmask {:?}", netmask),
+                ));
+            }
+            check_ip(&key("gateway"), gateway, false)?;
+            if !ip6.is_empty() {
+                match net::parse_cidr(ip6, Some(net::DEFAULT_IPV6_PREFIX_LEN)) {
+                    Some((IpAddr::V6(_), _)) => {}
+                    _ => {
+                        return Err(key_err(
+                            &key("ip6"),
+                            &format!("invalid IPv6 address {:?}", ip6),
+                        ))
+                    }
+                }
+            }
+            if !gateway6.is_empty() {
+                check_ip(&key("gateway6"), gateway6, true)?;
+            }
+            if !mac.is_empty() && !is_mac(mac) {
+                return Err(key_err(
+                    &key("mac"),
+                    &format!("invalid MAC address {:?}", mac),
+                ));
+            }
+
+            multi_net.iface_vs.push(to_cstring(&key(iface_key), iface)?);
+            multi_net.iface_vs_mode.push(mode);
+            multi_net.iface_vs_ip.push(to_cstring(&key("ip"), ip)?);
+            multi_net
+                .iface_vs_nm
+                .push(to_cstring(&key("netmask"), netmask)?);
+            multi_net
+                .iface_vs_gw
+                .push(to_cstring(&key("gateway"), gateway)?);
+            multi_net.iface_vs_ma.push(to_cstring(&key("mac"), mac)?);
+            multi_net.iface_vs_ip6.push(to_cstring(&key("ip6"), ip6)?);
+            multi_net
+                .iface_vs_gw6
+                .push(to_cstring(&key("gateway6"), gateway6)?);
+        }
+        jconf.multi_net = Some(multi_net);
+
+        Ok(())
+    }
+
+    fn apply_seccomp<'a>(&'a self, jconf: &mut JailConf<'a>) -> Result<()> {
+        let sc = match self.seccomp {
+            Some(ref sc) => sc,
+            None => return Ok(()),
+        };
+        jconf.seccomp_log = sc.log;
+
+        if !sc.learn.is_empty() {
+            // both need the listener of the filter, a process can only have one
+            if sc.notify.is_some() {
+                return Err(key_err("seccomp.learn", "cannot be combined with seccomp.notify"));
+            }
+            // the jail runs under the learning filter alone, see supervisor::learning_filter
+            if !sc.profile.is_empty()
+                || !sc.policy.is_empty()
+                || !sc.policy_file.is_empty()
+                || !sc.rules.is_empty()
+                || sc.default_action != default_allow()
+            {
+                return Err(key_err(
+                    "seccomp.learn",
+                    "cannot be combined with a filter, seccomp.profile, seccomp.policy, seccomp.policy_file, seccomp.rules and seccomp.default_action must be left out",
+                ));
+            }
+            jconf.seccomp_learn = &sc.learn;
+            return Ok(());
+        }
+
+        if let Some(ref notify) = sc.notify {
+            if sc.policy.is_empty() && sc.policy_file.is_empty() && sc.rules.is_empty() {
+                return Err(key_err(
+                    "seccomp.notify",
+                    "needs a seccomp.policy, seccomp.policy_file or seccomp.rules handing syscalls over with user_notif",
+                ));
+            }
+            jconf.seccomp_notify = Some(parse_notify(notify)?);
+        }
+
+        if !sc.profile.is_empty() {
+            if !sc.policy.is_empty() || !sc.policy_file.is_empty() {
+                return Err(key_err(
+                    "seccomp.profile",
+                    "cannot be combined with seccomp.policy or seccomp.policy_file",
+                ));
+            }
+            if !sc.rules.is_empty() || sc.default_action != default_allow() {
+                return Err(key_err(
+                    "seccomp.profile",
+                    "the profile has its own default action and rules, seccomp.default_action and seccomp.rules must be left out",
+                ));
+            }
+            if profiles::profile_policy(&sc.profile).is_none() {
+                return Err(key_err(
+                    "seccomp.profile",
+                    &format!(
+                        "unknown profile {:?}, expected one of {}",
+                        sc.profile,
+                        profiles::profile_names().join(", ")
+                    ),
+                ));
+            }
+            jconf.seccomp_profile = &sc.profile;
+            return Ok(());
+        }
+
+        let mut filter = if !sc.policy.is_empty() || !sc.policy_file.is_empty() {
+            sc.parse_policy()?
+        } else {
+            sc.parse_rules()?
+        };
+
+        // the child passes the listener of its filter to the parent once the filter is loaded, see
+        // supervisor::send_from_child, a jail that cannot would die, or wait for its supervisor forever
+        if sc.notify.is_some()
+            && !(filter.allows(libc::SYS_sendmsg) && filter.allows(libc::SYS_close))
+        {
+            return Err(key_err(
+                "seccomp.notify",
+                "the seccomp policy must allow sendmsg and close whatever their arguments, the child passes the listener of its filter to the parent with them",
+            ));
+        }
+
+        if sc.notify.is_some() {
+            // the supervisor kills for the filter instead, it then knows the arguments of the syscall
+            jconf.seccomp_kill_filter = Some(filter.clone());
+            filter.notify_kills();
+        }
+
+        let filter: BpfProgram = filter
+            .try_into()
+            .map_err(|e: seccomp::Error| key_err("seccomp", &e.to_string()))?;
+        jconf.seccomp_filter = Some(filter);
+
+        Ok(())
+    }
+
+    fn apply_mounts(&self, jconf: &mut JailConf) -> Result<()> {
+        for (i, m) in self.mounts.iter().enumerate() {
+            let key = |field: &str| format!("mounts[{}].{}", i, field);
+
+            let dst = m.dst.trim_start_matches('/');
+            if dst.is_empty() {
+                return Err(key_err(&key("dst"), "must not be / or empty"));
+            }
+            if m.src.is_none() && m.fstype.is_none() {
+                return Err(key_err(&key("src"), "one of src or fstype must be set"));
+            }
+
+            let bind = m.bind.unwrap_or(m.src.is_some() && m.fstype.is_none());
+            let mut flags = 0;
+            if bind {
+                if m.src.is_none() {
+                    return Err(key_err(&key("src"), "must be set for a bind mount"));
+                }
+                flags |= libc::MS_BIND | libc::MS_REC | libc::MS_PRIVATE;
+            }
+            if !m.rw {
+                flags |= libc::MS_RDONLY;
+            }
+            if m.nosuid {
+                flags |= libc::MS_NOSUID;
+            }
+            if m.nodev {
+                flags |= libc::MS_NODEV;
+            }
+            if m.noexec {
+                flags |= libc::MS_NOEXEC;
+            }
+
+            let is_dir = match m.is_dir {
+                Some(d) => d,
+                None => match (&m.src, bind) {
+                    (Some(src), true) => fs::metadata(src).map(|md| md.is_dir()).unwrap_or(true),
+                    _ => true,
+                },
+            };
+
+            let src = match m.src {
+                Some(ref s) => Some(to_cstring(&key("src"), s)?),
+                None => None,
+            };
+            let fs_type = match m.fstype {
+                Some(ref s) => Some(to_cstring(&key("fstype"), s)?),
+                None => None,
+            };
+            let options = match m.options {
+                Some(ref s) => Some(to_cstring(&key("options"), s)?),
+                None => None,
+            };
+
+            let mountpt = MountT {
+                src,
+                dst: MountT::transform_dst(jconf, dst),
+                dst_in_pivot: to_cstring(&key("dst"), &format!("/{}", dst))?,
+                fs_type,
+                options,
+                flags,
+                is_dir,
+                is_symlink: m.is_symlink,
+                is_mandatory: m.mandatory,
+                mounted: false,
+            };
+            jconf.with_mnt(mountpt);
+        }
+
+        Ok(())
+    }
+}
+
+fn parse_file<T: DeserializeOwned>(path: &Path) -> Result<T> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| format!("could not read profile {}: {}", path.display(), e))?;
+
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("json") => parse_json(&content),
+        _ => parse_toml(&content),
+    }
+}
+
+fn parse_toml<T: DeserializeOwned>(content: &str) -> Result<T> {
+    let mut de = toml::Deserializer::new(content);
+    serde_path_to_error::deserialize(&mut de)
+        .map_err(|e| format!("profile: {}: {}", error_key(e.path()), e.inner()).into())
+}
+
+fn parse_json<T: DeserializeOwned>(content: &str) -> Result<T> {
+    let mut de = serde_json::Deserializer::from_str(content);
+    serde_path_to_error::deserialize(&mut de)
+        .map_err(|e| format!("profile: {}: {}", error_key(e.path()), e.inner()).into())
+}
+
+fn key_err(key: &str, msg: &str) -> super::error::Error {
+    format!("profile: {}: {}", key, msg).into()
+}
+
+fn error_key(path: &serde_path_to_error::Path) -> String {
+    let p = path.to_string();
+    if p == "." {
+        return String::from("<root>");
+    }
+    p
+}
+
+fn to_cstring(key: &str, s: &str) -> Result<CString> {
+    CString::new(s).map_err(|_| key_err(key, "must not contain a nul byte"))
+}
+
+fn to_id_maps(key: &str, maps: &[IdMapProfile]) -> Result<Vec<IDMapT>> {
+    let mut res = Vec::with_capacity(maps.len());
+    for (i, map) in maps.iter().enumerate() {
+        if map.count == 0 {
+            return Err(key_err(
+                &format!("{}[{}].count", key, i),
+                "must be greater than 0",
+            ));
+        }
+        res.push(IDMapT {
+            inside_id: map.inside,
+            outside_id: map.outside,
+            count: map.count,
+            is_newidmap: map.use_newidmap,
+        });
+    }
+    Ok(res)
+}
+
+fn check_abs_path<'a>(key: &str, p: &'a str) -> Result<&'a str> {
+    if !p.starts_with('/') {
+        return Err(key_err(key, "must be an absolute path"));
+    }
+    Ok(p)
+}
+
+fn check_parent<'a>(key: &str, p: &'a str) -> Result<&'a str> {
+    if p.is_empty() || p.contains("..") {
+        return Err(key_err(key, "must be a non empty path without .."));
+    }
+    Ok(p)
+}
+
+fn check_ip(key: &str, ip: &str, v6_only: bool) -> Result<()> {
+    match ip.parse::<IpAddr>() {
+        Ok(IpAddr::V4(_)) if v6_only => {
+            Err(key_err(key, &format!("invalid IPv6 address {:?}", ip)))
+        }
+        Ok(_) => Ok(()),
+        Err(_) => Err(key_err(key, &format!("invalid IP address {:?}", ip))),
+    }
+}
+
+fn parse_ipv4(key: &str, ip: &str) -> Result<Ipv4Addr> {
+    ip.parse()
+        .map_err(|_| key_err(key, &format!("invalid IPv4 address {:?}", ip)))
+}
+
+fn is_mac(mac: &str) -> bool {
+    let parts: Vec<&str> = mac.split(':').collect();
+    parts.len() == 6
+        && parts
+            .iter()
+            .all(|p| p.len() == 2 && u8::from_str_radix(p, 16).is_ok())
+}
+
+/// returns the (hard, soft, infinity, value) arguments of the JailConf::with_rlimit_* builders
+fn rlimit_args(key: &str, v: &Option<RlimitValue>) -> Result<(bool, bool, bool, u64)> {
+    match v {
+        None => Ok((false, true, false, 0)),
+        Some(RlimitValue::Value(n)) => Ok((false, false, false, *n)),
+        Some(RlimitValue::Special(s)) => match s.as_str() {
+            "soft" => Ok((false, true, false, 0)),
+            "hard" => Ok((true, false, false, 0)),
+            "inf" | "infinity" => Ok((false, false, true, 0)),
+            _ => Err(key_err(
+                key,
+                &format!("expected a number, \"soft\", \"hard\" or \"inf\", got {:?}", s),
+            )),
+        },
+    }
+}
+
+/// The mounts and connects the seccomp supervisor accepts, see supervisor::decide
+fn parse_notify(notify: &SeccompNotifyProfile) -> Result<SeccompNotifyConfig> {
+    let mut mounts = Vec::with_capacity(notify.mounts.len());
+    for (i, m) in notify.mounts.iter().enumerate() {
+        let key = |field: &str| format!("seccomp.notify.mounts[{}].{}", i, field);
+        if !m.dst.starts_with('/') {
+            return Err(key_err(&key("dst"), "must be an absolute path"));
+        }
+        let mut flags = 0;
+        for (j, flag) in m.flags.iter().enumerate() {
+            flags |= match flag.as_str() {
+                "rdonly" => libc::MS_RDONLY,
+                "nosuid" => libc::MS_NOSUID,
+                "nodev" => libc::MS_NODEV,
+                "noexec" => libc::MS_NOEXEC,
+                "remount" => libc::MS_REMOUNT,
+                "bind" => libc::MS_BIND,
+                "rec" => libc::MS_REC,
+                "noatime" => libc::MS_NOATIME,
+                "nodiratime" => libc::MS_NODIRATIME,
+                "relatime" => libc::MS_RELATIME,
+                "silent" => libc::MS_SILENT,
+                _ => {
+                    return Err(key_err(
+                        &format!("seccomp.notify.mounts[{}].flags[{}]", i, j),
+                        &format!("unknown mount flag {:?}", flag),
+                    ))
+                }
+            };
+        }
+        mounts.push(NotifyMount {
+            src: m.src.clone(),
+            // the jail asks for /tmp, not /tmp/
+            dst: match m.dst.trim_end_matches('/') {
+                "" => String::from("/"),
+                dst => dst.to_string(),
+            },
+            fstype: m.fstype.clone(),
+            flags,
+            data: m.data.clone(),
+        });
+    }
+
+    let mut connect = Vec::with_capacity(notify.connect.len());
+    for (i, rule) in notify.connect.iter().enumerate() {
+        connect.push(
+            firewall::parse_egress_rule(rule)
+                .map_err(|e| key_err(&format!("seccomp.notify.connect[{}]", i), &e.to_string()))?,
+        );
+    }
+
+    Ok(SeccompNotifyConfig { mounts, connect })
+}
+
+/// "allow", "kill", "log", "trap", "user_notif", "errno:<n>" or "trace:<n>"
+fn parse_action(key: &str, action: &str) -> Result<SeccompAction> {
+    let mut spl = action.splitn(2, ':');
+    let name = spl.next().unwrap_or("");
+    let arg = spl.next();
+
+    let num = |arg: Option<&str>| -> Result<u32> {
+        match arg.map(|a| a.parse::<u32>()) {
+            Some(Ok(n)) => Ok(n),
+            _ => Err(key_err(
+                key,
+                &format!("{:?} needs a numeric argument, e.g. {}:1", action, name),
+            )),
+        }
+    };
+
+    match (name.to_ascii_lowercase().as_str(), arg) {
+        ("allow", None) => Ok(SeccompAction::Allow),
+        ("kill", None) => Ok(SeccompAction::Kill),
+        ("log", None) => Ok(SeccompAction::Log),
+        ("trap", None) => Ok(SeccompAction::Trap),
+        ("user_notif", None) => Ok(SeccompAction::UserNotif),
+        ("errno", _) => Ok(SeccompAction::Errno(num(arg)?)),
+        ("trace", _) => Ok(SeccompAction::Trace(num(arg)?)),
+        _ => Err(key_err(key, &format!("unknown seccomp action {:?}", action))),
+    }
+}
+
+fn parse_condition(key: &str, arg: &SeccompArgProfile) -> Result<SeccompCondition> {
+    let len = match arg.len.as_str() {
+        "dword" => SeccompCmpArgLen::DWORD,
+        "qword" => SeccompCmpArgLen::QWORD,
+        _ => return Err(key_err(&format!("{}.len", key), "must be dword or qword")),
+    };
+    let op = match (arg.op.as_str(), arg.mask) {
+        ("eq", None) => SeccompCmpOp::Eq,
+        ("ne", None) => SeccompCmpOp::Ne,
+        ("ge", None) => SeccompCmpOp::Ge,
+        ("gt", None) => SeccompCmpOp::Gt,
+        ("le", None) => SeccompCmpOp::Le,
+        ("lt", None) => SeccompCmpOp::Lt,
+        ("masked_eq", Some(mask)) => SeccompCmpOp::MaskedEq(mask),
+        ("masked_eq", None) => {
+            return Err(key_err(&format!("{}.mask", key), "required by masked_eq"))
+        }
+        (_, Some(_)) => {
+            return Err(key_err(&format!("{}.mask", key), "only valid with masked_eq"))
+        }
+        (op, None) => {
+            return Err(key_err(
+                &format!("{}.op", key),
+                &format!("unknown comparison {:?}", op),
+            ))
+        }
+    };
+
+    SeccompCondition::new(arg.index, len, op, arg.value)
+        .map_err(|e| key_err(&format!("{}.index", key), &e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sandbox;
+
+    const TOML_PROFILE: &str = r#"
+hostname = "sandbox"
+cwd = "/tmp"
+argv = ["/bin/sh", "-c", "echo hi"]
+env = ["PATH=/bin:/usr/bin"]
+caps = [10, "CAP_NET_RAW"]
+
+[restart]
+policy = "on_failure"
+max_restarts = 5
+backoff_ms = 500
+
+[namespaces]
+net = true
+mount = true
+pid = true
+
+[[mounts]]
+src = "/usr"
+dst = "/usr"
+
+[[mounts]]
+dst = "/tmp"
+fstype = "tmpfs"
+options = "size=16m"
+rw = true
+
+[[uid_map]]
+inside = 0
+outside = 1000
+
+[rlimits]
+nofile = 512
+as = "inf"
+
+[cgroup]
+mem_max = 67108864
+pids_max = 32
+v2_parent = "toastainer/jails"
+mem_swap_max = 0
+io_weight = 200
+io_max = ["8:0,wbps=1048576"]
+hugetlb_max = { "2MB" = 0 }
+
+[net]
+forward = ["8080:80", "127.0.0.1:5353:53/udp"]
+
+[[net.macvlan]]
+iface = "eth0"
+ip = "10.0.0.2"
+gateway = "10.0.0.1"
+
+[[net.veth]]
+bridge = "toaster0"
+ip = "10.1.0.2"
+gateway = "10.1.0.1"
+mac = "ba:ad:ba:be:45:00"
+ip6 = "fd00:7a::2"
+gateway6 = "fd00:7a::1"
+
+[net.egress]
+allow = ["10.0.0.0/8,tcp,443", "1.1.1.1"]
+
+[seccomp]
+default_action = "allow"
+
+[[seccomp.rules]]
+syscall = 165
+action = "errno:1"
+"#;
+
+    #[test]
+    fn test_toml_profile() {
+        let profile = JailProfile::from_toml_str(TOML_PROFILE).unwrap();
+        let jconf = profile.to_jail_conf().unwrap();
+
+        assert_eq!(jconf.hostname, "sandbox");
+        assert_eq!(jconf.cwd, "/tmp");
+        let restart = jconf.restart.as_ref().unwrap();
+        assert_eq!(restart.policy, RestartPolicy::OnFailure);
+        assert!(restart.restarts_after(1, 4) && !restart.restarts_after(1, 5));
+        assert!(!restart.restarts_after(0, 0));
+        assert_eq!(restart.delay(1).as_millis(), 500);
+        assert_eq!(restart.delay(3).as_millis(), 2000);
+        assert_eq!(restart.delay(100).as_millis(), 60_000);
+        assert!(jconf.clone_newnet && jconf.clone_newns && jconf.clone_newpid);
+        assert!(!jconf.clone_newuser);
+        assert_eq!(jconf.argv.as_ref().unwrap().len(), 3);
+        assert_eq!(jconf.exec_file.as_ref().unwrap().to_str().unwrap(), "/bin/sh");
+        assert_eq!(jconf.uids.len(), 1);
+        assert_eq!(jconf.uids[0].outside_id, 1000);
+        assert!(!jconf.disable_rl);
+        assert_eq!(jconf.rl_nofile, 512);
+        assert_eq!(jconf.rl_as, crate::rlimit::RLIM64_INFINITY);
+        assert_eq!(jconf.cgroup_pids_max, 32);
+        assert_eq!(jconf.cgroupv2_parent, "toastainer/jails");
+        assert_eq!(jconf.cgroup_mem_swap_max, 0);
+        assert_eq!(jconf.cgroup_io_weight, 200);
+        assert_eq!(jconf.cgroup_io_max[0].wbps, 1048576);
+        assert_eq!(jconf.cgroup_hugetlb_max, vec![(String::from("2MB"), 0)]);
+        let multi_net = jconf.multi_net.as_ref().unwrap();
+        assert_eq!(multi_net.iface_vs.len(), 2);
+        assert_eq!(
+            multi_net.iface_vs_mode,
+            vec![NetMode::Macvlan, NetMode::Veth]
+        );
+        assert_eq!(multi_net.iface_vs[1].to_str().unwrap(), "toaster0");
+        assert!(multi_net.iface_vs_ip6[0].as_bytes().is_empty());
+        assert_eq!(multi_net.iface_vs_ip6[1].to_str().unwrap(), "fd00:7a::2");
+        assert!(jconf.iface_ipv6);
+        let egress = jconf.egress.as_ref().unwrap();
+        assert_eq!(egress.allow.len(), 2);
+        assert_eq!(egress.allow[0].ports, Some((443, 443)));
+        assert!(egress.block_link_local && !egress.dns_only);
+        assert_eq!(jconf.port_forwards.len(), 2);
+        assert_eq!(jconf.port_forwards[0].host, "[::]:8080".parse().unwrap());
+        assert_eq!(
+            jconf.port_forwards[1].proto,
+            crate::config::ForwardProto::Udp
+        );
+        assert!(jconf.seccomp_filter.is_some());
+        assert_eq!(jconf.caps, vec![10, 13]);
+        // root + /proc + the two profile mounts
+        assert_eq!(jconf.mountpts.len(), 4);
+        assert_eq!(jconf.mountpts[2].flags & libc::MS_RDONLY, libc::MS_RDONLY);
+        assert_eq!(jconf.mountpts[3].flags & libc::MS_RDONLY, 0);
+        assert_eq!(jconf.mountpts[3].dst_in_pivot.to_str().unwrap(), "/tmp");
+    }
+
+    #[test]
+    fn test_json_profile() {
+        let profile = JailProfile::from_json_str(
+            r#"{"argv": ["/bin/true"], "namespaces": {"user": true}, "rlimits": {"nproc": "soft"}}"#,
+        )
+        .unwrap();
+        let jconf = profile.to_jail_conf().unwrap();
+        assert!(jconf.clone_newuser);
+        assert!(!jconf.disable_rl);
+
+        let profile =
+            JailProfile::from_json_str(r#"{"net": {"user": {"host_loopback": true}}}"#).unwrap();
+        let jconf = profile.to_jail_conf().unwrap();
+        assert_eq!(
+            jconf.user_net,
+            Some(UserNetConfig {
+                host_loopback: true,
+                ..Default::default()
+            })
+        );
+    }
+
+    #[test]
+    fn test_errors_name_the_key() {
+        let err = JailProfile::from_toml_str("[namespaces]\nnett = true\n").unwrap_err();
+        assert!(err.to_string().contains("namespaces.nett"), "{}", err);
+
+        let err = JailProfile::from_json_str(r#"{"rlimits": {"nofile": -1}}"#).unwrap_err();
+        assert!(err.to_string().contains("rlimits.nofile"), "{}", err);
+
+        let profile = JailProfile::from_toml_str(
+            "[[net.macvlan]]\niface = \"eth0\"\nip = \"10.0.0.300\"\n",
+        )
+        .unwrap();
+        let err = profile.to_jail_conf().unwrap_err();
+        assert!(err.to_string().contains("net.macvlan[0].ip"), "{}", err);
+
+        let profile =
+            JailProfile::from_toml_str("[[net.veth]]\nbridge = \"\"\nip = \"10.0.0.2\"\n").unwrap();
+        let err = profile.to_jail_conf().unwrap_err();
+        assert!(err.to_string().contains("net.veth[0].bridge"), "{}", err);
+
+        let profile = JailProfile::from_toml_str(
+            "[[net.macvlan]]\niface = \"eth0\"\nip = \"fd00::2/64\"\nip6 = \"10.0.0.2\"\n",
+        )
+        .unwrap();
+        let err = profile.to_jail_conf().unwrap_err();
+        assert!(err.to_string().contains("net.macvlan[0].ip6"), "{}", err);
+
+        let profile =
+            JailProfile::from_toml_str("[net.egress]\nallow = [\"10.0.0.0/8,tcp,https\"]\n")
+                .unwrap();
+        let err = profile.to_jail_conf().unwrap_err();
+        assert!(err.to_string().contains("net.egress.allow[0]"), "{}", err);
+
+        let profile = JailProfile::from_toml_str("[net.user]\ngateway = \"fd00::1\"\n").unwrap();
+        let err = profile.to_jail_conf().unwrap_err();
+        assert!(err.to_string().contains("net.user.gateway"), "{}", err);
+
+        let profile = JailProfile::from_toml_str("[net]\nforward = [\"80\"]\n").unwrap();
+        let err = profile.to_jail_conf().unwrap_err();
+        assert!(err.to_string().contains("net.forward[0]"), "{}", err);
+
+        let profile =
+            JailProfile::from_toml_str("[cgroup]\nio_max = [\"8:0,rbps=fast\"]\n").unwrap();
+        let err = profile.to_jail_conf().unwrap_err();
+        assert!(err.to_string().contains("cgroup.io_max[0]"), "{}", err);
+
+        let profile = JailProfile::from_toml_str("[restart]\npolicy = \"never\"\n").unwrap();
+        let err = profile.to_jail_conf().unwrap_err();
+        assert!(err.to_string().contains("restart.policy"), "{}", err);
+
+        let profile = JailProfile::from_toml_str("env = [\"NOVALUE\"]\n").unwrap();
+        let err = profile.to_jail_conf().unwrap_err();
+        assert!(err.to_string().contains("env[0]"), "{}", err);
+
+        let profile = JailProfile::from_toml_str("caps = [\"CAP_NOPE\"]\n").unwrap();
+        let err = profile.to_jail_conf().unwrap_err();
+        assert!(err.to_string().contains("caps[0]"), "{}", err);
+
+        let profile =
+            JailProfile::from_toml_str("[[seccomp.rules]]\nsyscall = 1\naction = \"deny\"\n")
+                .unwrap();
+        let err = profile.to_jail_conf().unwrap_err();
+        assert!(err.to_string().contains("seccomp.rules[0].action"), "{}", err);
+
+        let profile = JailProfile::from_toml_str(
+            "[seccomp]\npolicy = \"ALLOW { read }\\nKILL { nope }\"\n",
+        )
+        .unwrap();
+        let err = profile.to_jail_conf().unwrap_err();
+        assert!(
+            err.to_string().contains("seccomp.policy: seccomp policy line 2"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_seccomp_policy() {
+        let profile = JailProfile::from_toml_str(
+            "[seccomp]\npolicy = \"ERRNO(1) { ptrace, mount } DEFAULT ALLOW\"\n",
+        )
+        .unwrap();
+        assert!(profile.to_jail_conf().unwrap().seccomp_filter.is_some());
+
+        let profile = JailProfile::from_toml_str(
+            "[seccomp]\npolicy = \"DEFAULT ALLOW\"\n[[seccomp.rules]]\nsyscall = 1\naction = \"kill\"\n",
+        )
+        .unwrap();
+        let err = profile.to_jail_conf().unwrap_err();
+        assert!(err.to_string().contains("must be left out"), "{}", err);
+    }
+
+    #[test]
+    fn test_seccomp_notify() {
+        let profile = JailProfile::from_toml_str(
+            r#"
+[seccomp]
+policy = "USER_NOTIF { mount, umount2, connect } DEFAULT ALLOW"
+
+[seccomp.notify]
+mounts = [{ src = "tmpfs", dst = "/tmp/", fstype = "tmpfs", flags = ["nosuid", "nodev"], data = "size=64m" }]
+connect = ["10.0.0.0/8,tcp,443"]
+"#,
+        )
+        .unwrap();
+        let jconf = profile.to_jail_conf().unwrap();
+        assert!(jconf.seccomp_filter.is_some());
+        let notify = jconf.seccomp_notify.unwrap();
+        assert_eq!(notify.mounts[0].dst, "/tmp");
+        assert_eq!(notify.mounts[0].flags, libc::MS_NOSUID | libc::MS_NODEV);
+        assert_eq!(notify.connect[0].ports, Some((443, 443)));
+
+        let profile =
+            JailProfile::from_toml_str("[seccomp.notify]\nconnect = [\"10.0.0.1\"]\n").unwrap();
+        let err = profile.to_jail_conf().unwrap_err();
+        assert!(err.to_string().contains("seccomp.notify: needs"), "{}", err);
+
+        let profile = JailProfile::from_toml_str(
+            "[seccomp]\npolicy = \"USER_NOTIF { mount } DEFAULT ALLOW\"\n[seccomp.notify]\nmounts = [{ dst = \"/mnt\", flags = [\"suid\"] }]\n",
+        )
+        .unwrap();
+        let err = profile.to_jail_conf().unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("seccomp.notify.mounts[0].flags[0]"),
+            "{}",
+            err
+        );
+
+        // the child could not pass the listener of its filter to the parent
+        let profile = JailProfile::from_toml_str(
+            "[seccomp]\npolicy = \"USER_NOTIF { mount, close } DEFAULT ALLOW\"\n[seccomp.notify]\nmounts = [{ dst = \"/mnt\" }]\n",
+        )
+        .unwrap();
+        let err = profile.to_jail_conf().unwrap_err();
+        assert!(
+            err.to_string().contains("must allow sendmsg and close"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_seccomp_learn() {
+        let profile =
+            JailProfile::from_toml_str("[seccomp]\nlearn = \"/tmp/python.policy\"\n").unwrap();
+        let jconf = profile.to_jail_conf().unwrap();
+        assert_eq!(jconf.seccomp_learn, "/tmp/python.policy");
+        assert!(jconf.seccomp_filter.is_none());
+
+        // the learning filter would silently replace the profile
+        let profile = JailProfile::from_toml_str(
+            "[seccomp]\nprofile = \"python\"\nlearn = \"/tmp/python.policy\"\n",
+        )
+        .unwrap();
+        let err = profile.to_jail_conf().unwrap_err();
+        assert!(err.to_string().contains("seccomp.learn: cannot"), "{}", err);
+
+        let profile = JailProfile::from_toml_str(
+            "[seccomp]\nlearn = \"/tmp/python.policy\"\n[seccomp.notify]\nconnect = [\"10.0.0.1\"]\n",
+        )
+        .unwrap();
+        let err = profile.to_jail_conf().unwrap_err();
+        assert!(err.to_string().contains("seccomp.learn: cannot"), "{}", err);
+    }
+
+    #[test]
+    fn test_seccomp_profile() {
+        let profile = JailProfile::from_toml_str("[seccomp]\nprofile = \"python\"\n").unwrap();
+        let mut jconf = profile.to_jail_conf().unwrap();
+        assert_eq!(jconf.seccomp_profile, "python");
+        assert!(jconf.seccomp_filter.is_none());
+        sandbox::prepare_policy(&mut jconf).unwrap();
+        assert!(jconf.seccomp_filter.is_some());
+
+        let profile = JailProfile::from_toml_str("[seccomp]\nprofile = \"ruby\"\n").unwrap();
+        let err = profile.to_jail_conf().unwrap_err();
+        assert!(
+            err.to_string().contains("seccomp.profile: unknown profile"),
+            "{}",
+            err
+        );
+
+        let profile = JailProfile::from_toml_str(
+            "[seccomp]\nprofile = \"default\"\npolicy = \"DEFAULT ALLOW\"\n",
+        )
+        .unwrap();
+        assert!(profile.to_jail_conf().is_err());
+    }
+}
@@ -2,8 +2,10 @@ use std::ffi::{CStr, CString};
 
 use std::str;
 
-use super::config::JailConf;
+use super::config::{ForwardProto, JailConf, PortForward};
 use super::error::Result;
+use super::sandbox::SeccompViolation;
+use super::usage::{LiveMetrics, ResourceUsage};
 
 pub fn extract_u16(mess: &[u8], offset: usize) -> u16 {
     (mess[offset + 1] as u16) | ((mess[offset] as u16) << 8)
@@ -41,6 +43,11 @@ pub fn put_u32(mess: &mut [u8], offset: usize, v: u32) {
     mess[offset + 3] = v as u8;
 }
 
+pub fn put_u64(mess: &mut [u8], offset: usize, v: u64) {
+    put_u32(mess, offset, (v >> 32) as u32);
+    put_u32(mess, offset + 4, v as u32);
+}
+
 pub fn put_u32_vec_capacity(mess: &mut Vec<u8>, v: u32) {
     mess.push((v >> 24) as u8);
     mess.push((v >> 16) as u8);
@@ -99,6 +106,154 @@ pub fn parse_kill_mess(mess: &[u8]) -> Result<(u32, u64)> {
     Ok((exe_id, timeout_sec))
 }
 
+/// message type of the usage report, sent to gtvs right before the exit message of the same exe_id
+pub const USAGE_MESS_TYPE: u8 = 6;
+
+/**
+ * Usage report of exe_id: len u16 (excluded), type u8, exe_id u32, then u64 wall, user and system time in microseconds,
+ * memory peak, io read and written, net received and sent in bytes, and an u8 set to 1 if the OOM killer fired
+ */
+pub fn create_usage_mess(exe_id: u32, usage: &ResourceUsage) -> Vec<u8> {
+    let mut mess = vec![0; 2 + 1 + 4 + 8 * 8 + 1];
+    let len = mess.len() - 2;
+    put_u16(&mut mess, 0, len as u16);
+    mess[2] = USAGE_MESS_TYPE;
+    put_u32(&mut mess, 3, exe_id);
+
+    let mut offset = 7;
+    for v in [
+        usage.wall_time.as_micros() as u64,
+        usage.user_time.as_micros() as u64,
+        usage.system_time.as_micros() as u64,
+        usage.mem_peak,
+        usage.io_read_bytes,
+        usage.io_write_bytes,
+        usage.net_rx_bytes,
+        usage.net_tx_bytes,
+    ]
+    .iter()
+    {
+        put_u64(&mut mess, offset, *v);
+        offset += 8;
+    }
+    mess[offset] = usage.oom_killed as u8;
+
+    mess
+}
+
+/// message type of the answer to a toaster command that could not be carried out, e.g. when no address is left
+pub const COMMAND_ERROR_MESS_TYPE: u8 = 9;
+
+/// Error answering a command about exe_id: len u16 (excluded), type u8, exe_id u32, then the error text up to the end
+pub fn create_command_error_mess(exe_id: u32, err: &str) -> Vec<u8> {
+    let mut mess = vec![0; 2 + 1 + 4];
+    mess.extend_from_slice(err.as_bytes());
+    let len = mess.len() - 2;
+    put_u16(&mut mess, 0, len as u16);
+    mess[2] = COMMAND_ERROR_MESS_TYPE;
+    put_u32(&mut mess, 3, exe_id);
+
+    mess
+}
+
+/// message type of the seccomp violation that killed a toaster, sent to gtvs right before the exit message of the same
+/// exe_id, between it and the usage report
+pub const SECCOMP_VIOLATION_MESS_TYPE: u8 = 8;
+
+/**
+ * Seccomp violation of exe_id: len u16 (excluded), type u8, exe_id u32, pid u32, AUDIT_ARCH_* u32, syscall number u32,
+ * instruction pointer u64, an u8 set to 1 if the 6 u64 arguments that follow are known, 0 if they are not and zeroed,
+ * then the syscall name, len u8 then bytes, empty if unknown
+ */
+pub fn create_seccomp_violation_mess(exe_id: u32, violation: &SeccompViolation) -> Vec<u8> {
+    let name = violation.name.unwrap_or_default().as_bytes();
+    let mut mess = vec![0; 2 + 1 + 4 * 4 + 8 + 1 + 6 * 8 + 1 + name.len()];
+    let len = mess.len() - 2;
+    put_u16(&mut mess, 0, len as u16);
+    mess[2] = SECCOMP_VIOLATION_MESS_TYPE;
+    put_u32(&mut mess, 3, exe_id);
+    put_u32(&mut mess, 7, violation.pid as u32);
+    put_u32(&mut mess, 11, violation.arch);
+    put_u32(&mut mess, 15, violation.nr as u32);
+    put_u64(&mut mess, 19, violation.instruction_pointer);
+
+    let mut offset = 27;
+    if let Some(args) = violation.args {
+        mess[offset] = 1;
+        for (i, arg) in args.iter().enumerate() {
+            put_u64(&mut mess, offset + 1 + i * 8, *arg);
+        }
+    }
+    offset += 1 + 6 * 8;
+    mess[offset] = name.len() as u8;
+    mess[offset + 1..].copy_from_slice(name);
+
+    mess
+}
+
+/// message type of the live metrics of running toasters, an answer to the metrics commands
+pub const METRICS_MESS_TYPE: u8 = 7;
+
+const METRICS_ENTRY_LEN: usize = 4 + 4 * 8;
+
+/// Toasters per metrics message, for it to stay below the 8192 bytes of a gtvs message
+pub const METRICS_PER_MESS: usize = 200;
+
+/// message type of the answer to the metrics command of a toaster that is not running
+pub const METRICS_NOT_FOUND_MESS_TYPE: u8 = 10;
+
+/// Answer to the metrics command of exe_id when it is not running: len u16 (excluded), type u8, exe_id u32
+pub fn create_metrics_not_found_mess(exe_id: u32) -> Vec<u8> {
+    let mut mess = vec![0; 2 + 1 + 4];
+    let len = mess.len() - 2;
+    put_u16(&mut mess, 0, len as u16);
+    mess[2] = METRICS_NOT_FOUND_MESS_TYPE;
+    put_u32(&mut mess, 3, exe_id);
+    mess
+}
+
+/**
+ * Commands about one toaster: type u8, exe_id u32. The metrics command for one toaster, the pause and the resume
+ * commands. The metrics command for every toaster is only its type
+ */
+pub fn parse_exe_id_mess(mess: &[u8]) -> Result<u32> {
+    if mess.len() < 5 {
+        return Err("incomplete message".into());
+    }
+    Ok(extract_u32(mess, 1))
+}
+
+/**
+ * Metrics of at most METRICS_PER_MESS toasters: len u16 (excluded), type u8, u16 number of toasters, then for each of
+ * them exe_id u32, and u64 cpu time in microseconds, memory in bytes, number of threads and uptime in milliseconds
+ */
+pub fn create_metrics_mess(metrics: &[(u32, LiveMetrics)]) -> Vec<u8> {
+    let mut mess = vec![0; 2 + 1 + 2 + metrics.len() * METRICS_ENTRY_LEN];
+    let len = mess.len() - 2;
+    put_u16(&mut mess, 0, len as u16);
+    mess[2] = METRICS_MESS_TYPE;
+    put_u16(&mut mess, 3, metrics.len() as u16);
+
+    let mut offset = 5;
+    for (exe_id, m) in metrics.iter() {
+        put_u32(&mut mess, offset, *exe_id);
+        offset += 4;
+        for v in [
+            m.cpu_time.as_micros() as u64,
+            m.mem_current,
+            m.pids_current,
+            m.uptime.as_millis() as u64,
+        ]
+        .iter()
+        {
+            put_u64(&mut mess, offset, *v);
+            offset += 8;
+        }
+    }
+
+    mess
+}
+
 pub fn create_pooled_wake_up_mess(jconf: &mut JailConf) -> Vec<u8> {
     let mut mess = Vec::with_capacity(1024);
     let mut offset = 0;
@@ -248,9 +403,11 @@ pub fn parse_pooled_wake_up(
 /// if command name provided, first argv must also be the command name
 /// mount_slave must be set to true if you intend to mount overlay after a toaster is created and in pool
 /// otherwise set it to false even if you mount overlay at execution time
+/// the ip may be followed by port forwards: their count on 1 byte, then for each a flags byte (1 for udp), the host
+/// port and the jail port on 2 bytes each. Older messages end with the ip and have none
 pub fn parse_toaster_command<'a>(
     mess: &'a [u8],
-) -> (
+) -> Result<(
     u32,
     u16,
     CString,
@@ -267,7 +424,8 @@ pub fn parse_toaster_command<'a>(
     Option<Vec<CString>>,
     Option<Vec<CString>>,
     CString,
-) {
+    Vec<PortForward>,
+)> {
     let mut offset: usize = 1;
 
     let pool = extract_u16(mess, offset);
@@ -380,8 +538,35 @@ pub fn parse_toaster_command<'a>(
     let ip = unsafe {
         CString::from_vec_unchecked(mess[offset + 1..offset + 1 + mess[offset] as usize].to_owned())
     };
+    offset = offset + 1 + mess[offset] as usize;
+
+    let mut port_forwards = Vec::new();
+    if offset < mess.len() {
+        let count = mess[offset] as usize;
+        offset = offset + 1;
+
+        for i in 0..count {
+            if offset + 5 > mess.len() {
+                return Err(format!("port forward {} of {} is truncated", i, count).into());
+            }
+            let proto = if mess[offset] & 1 > 0 {
+                ForwardProto::Udp
+            } else {
+                ForwardProto::Tcp
+            };
+            let host_port = extract_u16(mess, offset + 1);
+            let jail_port = extract_u16(mess, offset + 3);
+            offset = offset + 5;
+
+            port_forwards.push(PortForward {
+                proto,
+                host: (std::net::Ipv6Addr::UNSPECIFIED, host_port).into(),
+                jail_port,
+            });
+        }
+    }
 
-    (
+    Ok((
         exe_id,
         pool,
         uid,
@@ -398,5 +583,123 @@ pub fn parse_toaster_command<'a>(
         command_args,
         env,
         ip,
-    )
+        port_forwards,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_parse_toaster_command_port_forwards() {
+        // type, pool, exe_id, then uid, btrfs_file_system, overlay_dir, lower_dirs, cwd and log_path all empty, flags,
+        // no command name, no env and no ip
+        let mut mess = vec![0, 0, 1, 0, 0, 0, 7];
+        mess.extend_from_slice(&[0; 6 * 2 + 1 + 2 + 2 + 1]);
+        mess.extend_from_slice(&[2, 1, 0x1f, 0x90, 0, 80, 0, 0x01]);
+
+        let err = parse_toaster_command(&mess).err().unwrap();
+        assert!(err.to_string().contains("port forward 1 of 2"), "{}", err);
+
+        mess.extend_from_slice(&[0xbb, 0x01, 0xbb]);
+        let forwards = parse_toaster_command(&mess).unwrap().16;
+        assert_eq!(forwards.len(), 2);
+        assert_eq!(forwards[0].proto, ForwardProto::Udp);
+        assert_eq!(forwards[0].host.port(), 8080);
+        assert_eq!(forwards[0].jail_port, 80);
+        assert_eq!(forwards[1].jail_port, 443);
+    }
+
+    #[test]
+    fn test_create_usage_mess() {
+        let usage = ResourceUsage {
+            wall_time: Duration::from_millis(1500),
+            mem_peak: 1 << 33,
+            net_tx_bytes: 42,
+            oom_killed: true,
+            ..Default::default()
+        };
+        let mess = create_usage_mess(7, &usage);
+
+        assert_eq!(extract_u16(&mess, 0) as usize, mess.len() - 2);
+        assert_eq!(mess[2], USAGE_MESS_TYPE);
+        assert_eq!(extract_u32(&mess, 3), 7);
+        assert_eq!(extract_u64(&mess, 7), 1_500_000);
+        assert_eq!(extract_u64(&mess, 7 + 3 * 8), 1 << 33);
+        assert_eq!(extract_u64(&mess, 7 + 7 * 8), 42);
+        assert_eq!(mess[mess.len() - 1], 1);
+    }
+
+    #[test]
+    fn test_create_command_error_mess() {
+        let mess = create_command_error_mess(7, "no address left");
+        assert_eq!(extract_u16(&mess, 0) as usize, mess.len() - 2);
+        assert_eq!(mess[2], COMMAND_ERROR_MESS_TYPE);
+        assert_eq!(extract_u32(&mess, 3), 7);
+        assert_eq!(
+            string_from_bytes(&mess, 7, mess.len() - 7),
+            "no address left"
+        );
+    }
+
+    #[test]
+    fn test_create_metrics_mess() {
+        let m = LiveMetrics {
+            cpu_time: Duration::from_millis(250),
+            mem_current: 4096,
+            pids_current: 3,
+            uptime: Duration::from_secs(2),
+        };
+        let mess = create_metrics_mess(&[(1, LiveMetrics::default()), (9, m)]);
+        assert_eq!(extract_u16(&mess, 0) as usize, mess.len() - 2);
+        assert_eq!(mess[2], METRICS_MESS_TYPE);
+        assert_eq!(extract_u16(&mess, 3), 2);
+
+        let second = 5 + METRICS_ENTRY_LEN;
+        assert_eq!(extract_u32(&mess, second), 9);
+        assert_eq!(extract_u64(&mess, second + 4), 250_000);
+        assert_eq!(extract_u64(&mess, second + 4 + 16), 3);
+        assert_eq!(extract_u64(&mess, second + 4 + 24), 2000);
+
+        assert_eq!(parse_exe_id_mess(&[4, 0, 0, 0, 9]).unwrap(), 9);
+
+        let mess = create_metrics_not_found_mess(9);
+        assert_eq!(extract_u16(&mess, 0) as usize, mess.len() - 2);
+        assert_eq!(mess[2], METRICS_NOT_FOUND_MESS_TYPE);
+        assert_eq!(extract_u32(&mess, 3), 9);
+        assert!(parse_exe_id_mess(&[4]).is_err());
+    }
+
+    #[test]
+    fn test_create_seccomp_violation_mess() {
+        let mut violation = SeccompViolation {
+            pid: 1234,
+            nr: 41,
+            name: Some("socket"),
+            arch: 0xc000003e,
+            args: None,
+            instruction_pointer: 0x7f64_b65d_3829,
+        };
+        let mess = create_seccomp_violation_mess(7, &violation);
+        assert_eq!(extract_u16(&mess, 0) as usize, mess.len() - 2);
+        assert_eq!(mess[2], SECCOMP_VIOLATION_MESS_TYPE);
+        assert_eq!(extract_u32(&mess, 3), 7);
+        assert_eq!(extract_u32(&mess, 7), 1234);
+        assert_eq!(extract_u32(&mess, 11), 0xc000003e);
+        assert_eq!(extract_u32(&mess, 15), 41);
+        assert_eq!(extract_u64(&mess, 19), 0x7f64_b65d_3829);
+        assert_eq!(mess[27], 0);
+        assert_eq!(mess[76], 6);
+        assert_eq!(string_from_bytes(&mess, 77, 6), "socket");
+
+        violation.name = None;
+        violation.args = Some([1, 2, 3, 4, 5, 6]);
+        let mess = create_seccomp_violation_mess(7, &violation);
+        assert_eq!(mess[27], 1);
+        assert_eq!(extract_u64(&mess, 28 + 5 * 8), 6);
+        assert_eq!(mess[76], 0);
+        assert_eq!(mess.len(), 77);
+    }
 }
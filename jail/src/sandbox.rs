@@ -1,20 +1,100 @@
 // wrapper arround seccomp package
 use super::config::JailConf;
-use super::utils::read_from_fd_ignore_err;
-use seccomp::SeccompFilter;
+use super::error::Result;
+use super::supervisor;
+use seccomp::notify::Notification;
+use seccomp::syscalls::syscall_name;
+use seccomp::{profiles, BpfProgram, SeccompFilter};
 
-use sys_util::fcntl;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::os::unix::fs::OpenOptionsExt;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use std::ffi::CString;
-use std::mem::MaybeUninit; // This new feature may soon be stable, see if it should replace maybeunit: https://doc.rust-lang.org/std/boxed/struct.Box.html#method.new_uninit
-use std::str::from_utf8;
+// AUDIT_ARCH_* of the architectures in seccomp violations, see /usr/include/linux/audit.h
+const AUDIT_ARCH_X86_64: u32 = 0xc000_003e;
+const AUDIT_ARCH_I386: u32 = 0x4000_0003;
+const AUDIT_ARCH_AARCH64: u32 = 0xc000_00b7;
+const AUDIT_ARCH_ARM: u32 = 0x4000_0028;
+
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: u32 = AUDIT_ARCH_X86_64;
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: u32 = AUDIT_ARCH_AARCH64;
+
+// type of the audit records of seccomp, AUDIT_SECCOMP
+const AUDIT_SECCOMP: &str = "type=1326";
+
+// the built-in profiles compiled so far, by name, see prepare_policy
+static PROFILES: OnceLock<Mutex<HashMap<String, BpfProgram>>> = OnceLock::new();
+
+// the kernel log, opened by the first read_violation, None if it could not be
+static KMSG: OnceLock<Mutex<Option<Kmsg>>> = OnceLock::new();
+
+// how many of the last violations read from the kernel log are kept for read_violation
+const KEPT_VIOLATIONS: usize = 64;
+
+/**
+ * Sets jconf.seccomp_filter to the built-in profile jconf.seccomp_profile, in the parent so that a broken profile is an
+ * error of run_child and not a child dying before exec. Each profile is only compiled the first time a jail uses it
+ */
+pub fn prepare_policy(jconf: &mut JailConf) -> Result<()> {
+    // the learning filter would replace them, see supervisor::learning_filter
+    if !jconf.seccomp_learn.is_empty()
+        && (jconf.seccomp_filter.is_some() || !jconf.seccomp_profile.is_empty())
+    {
+        return Err(
+            "seccomp_learn cannot be combined with seccomp_filter or seccomp_profile".into(),
+        );
+    }
+    if jconf.seccomp_filter.is_some() || jconf.seccomp_profile.is_empty() {
+        return Ok(());
+    }
+    let mut compiled = PROFILES
+        .get_or_init(|| Mutex::new(HashMap::new()))
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+    let filter = match compiled.get(jconf.seccomp_profile) {
+        Some(filter) => filter.clone(),
+        None => {
+            let filter =
+                profiles::compile_profile(jconf.seccomp_profile).map_err(|e| e.to_string())?;
+            compiled.insert(jconf.seccomp_profile.to_string(), filter.clone());
+            filter
+        }
+    };
+    jconf.seccomp_filter = Some(filter);
+    Ok(())
+}
 
 pub fn apply_policy(jconf: &mut JailConf) -> bool {
     let filter = jconf.seccomp_filter.take(); // we consume/move the seccomp filter out of jconf, so after this you can no longer use it but you should not need to
 
+    // a jail learning its policy runs under the learning filter only
+    let filter = if !jconf.seccomp_learn.is_empty() {
+        match supervisor::learning_filter(jconf) {
+            Ok(filter) => Some(filter),
+            Err(_) => return false,
+        }
+    } else {
+        filter
+    };
+
     if let Some(filter) = filter {
-        match SeccompFilter::apply(filter) {
-            Ok(_) => {}
+        let supervised = jconf.seccomp_notify.is_some() || !jconf.seccomp_learn.is_empty();
+        let listener = if jconf.seccomp_log {
+            SeccompFilter::apply_logged(filter, supervised)
+        } else if supervised {
+            SeccompFilter::apply_with_listener(filter).map(Some)
+        } else {
+            SeccompFilter::apply(filter).map(|_| None)
+        };
+        match listener {
+            Ok(Some(listener)) => return supervisor::send_from_child(jconf, listener).is_ok(),
+            Ok(None) => {}
             Err(_) => return false,
         }
     }
@@ -22,176 +102,295 @@ pub fn apply_policy(jconf: &mut JailConf) -> bool {
     true
 }
 
-pub fn which_seccomp_violation(pid: libc::pid_t, si: &libc::siginfo_t) -> String {
-    let pid_syscall_fd = match fcntl::open_no_mode(
-        &format!("/proc/{}/syscall", pid),
-        libc::O_RDONLY | libc::O_CLOEXEC,
-    ) {
-        Ok(v) => v,
-        Err(_) => {
-            return format!("Seccomp Violation: pid={} SiCode: {}, SiErrno: {}, SiSigno: {} <> could not open pid_syscall_fd", pid, si.si_code, si.si_errno, si.si_signo);
+/**
+ * A syscall refused by the seccomp filter of a jail, which killed the process that made it. The audit record of the
+ * kernel leaves the arguments of the syscall out, and the parent only gets the SIGCHLD of the death, not the siginfo
+ * of a SIGSYS. They are known when the jail has a seccomp supervisor, which the kills are handed over to, see
+ * supervisor::kill
+ */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SeccompViolation {
+    pub pid: i32, // of the process that made the syscall, as seen from the host
+    pub nr: i64,
+    pub name: Option<&'static str>, // None for a syscall unknown to seccomp::syscalls or of another architecture
+    pub arch: u32,                  // AUDIT_ARCH_* of the syscall, e.g. 0xc000003e for x86_64
+    pub args: Option<[u64; 6]>,     // None when read from the audit log, which does not record them
+    pub instruction_pointer: u64,
+}
+
+impl SeccompViolation {
+    /// The violation of a syscall handed over to the seccomp supervisor, pid is the process of the thread that made it
+    pub fn from_notification(pid: i32, n: &Notification) -> SeccompViolation {
+        SeccompViolation {
+            pid,
+            nr: n.nr,
+            name: if n.arch == AUDIT_ARCH {
+                syscall_name(n.nr)
+            } else {
+                None
+            },
+            arch: n.arch,
+            args: Some(n.args),
+            instruction_pointer: n.instruction_pointer,
         }
-    };
+    }
+}
 
-    let mut buf = [0; 4096];
-    let rdsize = read_from_fd_ignore_err(pid_syscall_fd, &mut buf[..4095]).expect("");
-
-    if rdsize < 1 {
-        unsafe { libc::close(pid_syscall_fd) };
-        return format!("Seccomp Violation: pid={} SiCode: {}, SiErrno: {}, SiSigno: {} <> could not open pid_syscall_fd", pid, si.si_code, si.si_errno, si.si_signo);
-    }
-
-    buf[4095] = '\0' as u8;
-
-    let mut arg1 = MaybeUninit::<libc::uintptr_t>::uninit();
-    let mut arg2 = MaybeUninit::<libc::uintptr_t>::uninit();
-    let mut arg3 = MaybeUninit::<libc::uintptr_t>::uninit();
-    let mut arg4 = MaybeUninit::<libc::uintptr_t>::uninit();
-    let mut arg5 = MaybeUninit::<libc::uintptr_t>::uninit();
-    let mut arg6 = MaybeUninit::<libc::uintptr_t>::uninit();
-    let mut sp = MaybeUninit::<libc::uintptr_t>::uninit();
-    let mut pc = MaybeUninit::<libc::uintptr_t>::uninit();
-    let mut sc = MaybeUninit::<libc::ptrdiff_t>::uninit();
-
-    let ret = unsafe {
-        libc::sscanf(
-            buf.as_ptr() as *const libc::c_char,
-            CString::new("%td %tx %tx %tx %tx %tx %tx %tx %tx")
-                .unwrap()
-                .as_ptr() as *const libc::c_char,
-            sc.as_mut_ptr(),
-            arg1.as_mut_ptr(),
-            arg2.as_mut_ptr(),
-            arg3.as_mut_ptr(),
-            arg4.as_mut_ptr(),
-            arg5.as_mut_ptr(),
-            arg6.as_mut_ptr(),
-            sp.as_mut_ptr(),
-            pc.as_mut_ptr(),
-        )
-    };
+impl fmt::Display for SeccompViolation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.name {
+            Some(name) => write!(f, "syscall {} ({})", name, self.nr)?,
+            None => write!(f, "syscall {}", self.nr)?,
+        }
+        write!(
+            f,
+            " on {} by pid {} at {:#x}",
+            arch_name(self.arch),
+            self.pid,
+            self.instruction_pointer
+        )?;
+        if let Some(args) = self.args {
+            let args: Vec<String> = args.iter().map(|a| format!("{:#x}", a)).collect();
+            write!(f, ", args [{}]", args.join(", "))?;
+        }
+        Ok(())
+    }
+}
 
-    unsafe { libc::close(pid_syscall_fd) };
-
-    if ret == 9 {
-        let arg1 = unsafe { arg1.assume_init() };
-        let arg2 = unsafe { arg2.assume_init() };
-        let arg3 = unsafe { arg3.assume_init() };
-        let arg4 = unsafe { arg4.assume_init() };
-        let arg5 = unsafe { arg5.assume_init() };
-        let arg6 = unsafe { arg6.assume_init() };
-        let sp = unsafe { sp.assume_init() };
-        let pc = unsafe { pc.assume_init() };
-        let sc = unsafe { sc.assume_init() };
-
-        return format!("Seccomp Violation: pid={} SiCode: {}, SiErrno: {}, SiSigno: {} <> Syscall number: {}, Arguments: [{}, {}, {}, {}, {}, {}], SP: {}, PC: {},", pid, si.si_code, si.si_errno, si.si_signo, sc, arg1, arg2, arg3, arg4, arg5, arg6, sp, pc);
-    } else if ret == 3 {
-        let arg1 = unsafe { arg1.assume_init() };
-        let arg2 = unsafe { arg2.assume_init() };
-
-        return format!(
-            "Seccomp Violation: pid={} SiCode: {}, SiErrno: {}, SiSigno: {} <> SP: {}, PC: {},",
-            pid, si.si_code, si.si_errno, si.si_signo, arg1, arg2
-        );
+fn arch_name(arch: u32) -> String {
+    match arch {
+        AUDIT_ARCH_X86_64 => "x86_64".to_string(),
+        AUDIT_ARCH_I386 => "i386".to_string(),
+        AUDIT_ARCH_AARCH64 => "aarch64".to_string(),
+        AUDIT_ARCH_ARM => "arm".to_string(),
+        _ => format!("arch {:#x}", arch),
     }
+}
 
-    let buf_str = from_utf8(&buf).ok();
-    if let Some(buf_str) = buf_str {
-        return format!(
-            "Seccomp Violation: pid={} SiCode: {}, SiErrno: {}, SiSigno: {} <> Syscall string: {}",
-            pid, si.si_code, si.si_errno, si.si_signo, buf_str
-        );
-    } else {
-        return format!("Seccomp Violation: pid={} SiCode: {}, SiErrno: {}, SiSigno: {} <> Syscall string: could not decode buf from /proc/pid/syscall as utf-8", pid, si.si_code, si.si_errno, si.si_signo);
+/**
+ * Looks up, in the kernel log, the last seccomp violation of pid logged since `since`. The kernel only writes the audit
+ * records of the processes killed by seccomp there when auditd does not take them, and at most 10 every 5 seconds, so
+ * None does not mean that there was none. /proc/<pid>/syscall cannot be used instead, it holds nothing once pid is dead
+ *
+ * /dev/kmsg is kept open: the first call reads the whole ring buffer of the kernel, the next ones only the records
+ * written since the previous call, so that the scheduler loop does not go through the whole log for each jail killed
+ * by seccomp. Only the last KEPT_VIOLATIONS violations are kept in between
+ */
+pub fn read_violation(pid: i32, since: SystemTime) -> Option<SeccompViolation> {
+    let mut kmsg = KMSG
+        .get_or_init(|| Mutex::new(Kmsg::open()))
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+    let kmsg = kmsg.as_mut()?;
+    kmsg.read_new();
+    kmsg.violations
+        .iter()
+        .rev()
+        .find(|(time, violation)| violation.pid == pid && *time >= since)
+        .map(|(_, violation)| violation.clone())
+}
+
+/// The seccomp violation of pid as a string, si is the siginfo of its death
+#[deprecated(note = "use read_violation, the violation it returns has the syscall name and number")]
+pub fn which_seccomp_violation(pid: libc::pid_t, si: &libc::siginfo_t) -> String {
+    match read_violation(pid, UNIX_EPOCH) {
+        Some(violation) => format!("Seccomp Violation: {}", violation),
+        None => format!(
+            "Seccomp Violation: pid={} SiCode: {}, SiErrno: {}, SiSigno: {} <> not found in the kernel log",
+            pid, si.si_code, si.si_errno, si.si_signo
+        ),
     }
 }
 
+/// The seccomp violation of pid as a string
+#[deprecated(note = "use read_violation, the violation it returns has the syscall name and number")]
 pub fn which_seccomp_violation_pid_only(pid: libc::pid_t) -> String {
-    let pid_syscall_fd = match fcntl::open_no_mode(
-        &format!("/proc/{}/syscall", pid),
-        libc::O_RDONLY | libc::O_CLOEXEC,
-    ) {
-        Ok(v) => v,
-        Err(_) => {
-            return format!(
-                "Seccomp Violation: pid={} <> could not open pid_syscall_fd",
-                pid
-            );
+    match read_violation(pid, UNIX_EPOCH) {
+        Some(violation) => format!("Seccomp Violation: {}", violation),
+        None => format!(
+            "Seccomp Violation: pid={} <> not found in the kernel log",
+            pid
+        ),
+    }
+}
+
+/// The kernel log and the last seccomp violations read from it
+struct Kmsg {
+    file: File,
+    violations: VecDeque<(SystemTime, SeccompViolation)>,
+}
+
+impl Kmsg {
+    fn open() -> Option<Kmsg> {
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NONBLOCK | libc::O_CLOEXEC)
+            .open("/dev/kmsg")
+            .ok()?;
+        Some(Kmsg {
+            file,
+            violations: VecDeque::with_capacity(KEPT_VIOLATIONS),
+        })
+    }
+
+    /// Reads the records written since the previous call, from the oldest one still in the ring buffer the first time
+    fn read_new(&mut self) {
+        // each read returns one record
+        let mut buf = [0u8; 8192];
+        loop {
+            let n = match self.file.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => n,
+                // the record was overwritten while we were reading, the next read returns the next one
+                Err(ref e) if e.raw_os_error() == Some(libc::EPIPE) => continue,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(_) => break, // EAGAIN once every record has been read
+            };
+            if let Some(found) = parse_audit_record(&String::from_utf8_lossy(&buf[..n])) {
+                if self.violations.len() == KEPT_VIOLATIONS {
+                    self.violations.pop_front();
+                }
+                self.violations.push_back(found);
+            }
         }
-    };
+    }
+}
 
-    let mut buf = [0; 4096];
-    let rdsize = read_from_fd_ignore_err(pid_syscall_fd, &mut buf[..4095]).unwrap();
+/**
+ * The time and the violation of a record of /dev/kmsg holding an audit record of a process killed by seccomp, e.g.
+ * `6,1234,9204710458,-;audit: type=1326 audit(1792326375.422:8): auid=4294967295 uid=0 gid=0 ses=4294967295
+ * subj=kernel pid=23967 comm="t" exe="/tmp/t" sig=31 arch=c000003e syscall=110 compat=0 ip=0x7f64b65d3829
+ * code=0x80000000`. The strings the jail controls, comm and exe, are hex encoded by the kernel when they hold a space
+ */
+fn parse_audit_record(record: &str) -> Option<(SystemTime, SeccompViolation)> {
+    let (_, message) = record.split_once(';')?;
+    let mut fields = message.split_whitespace();
+    if fields.next()? != "audit:" || fields.next()? != AUDIT_SECCOMP {
+        return None;
+    }
 
-    if rdsize < 1 {
-        unsafe { libc::close(pid_syscall_fd) };
-        return format!(
-            "Seccomp Violation: pid={}  <> could not open pid_syscall_fd",
-            pid
-        );
+    // audit(<seconds>.<milliseconds>:<serial>):
+    let stamp = fields.next()?.strip_prefix("audit(")?;
+    let stamp = &stamp[..stamp.find(':')?];
+    let (secs, millis) = stamp.split_once('.')?;
+    let (secs, millis) = (secs.parse::<u64>().ok()?, millis.parse::<u64>().ok()?);
+    let time = UNIX_EPOCH + Duration::from_secs(secs) + Duration::from_millis(millis);
+
+    let (mut pid, mut sig, mut arch, mut nr, mut ip) = (None, None, None, None, None);
+    for field in fields {
+        let (key, value) = field.split_once('=').unwrap_or((field, ""));
+        match key {
+            "pid" => pid = value.parse::<i32>().ok(),
+            "sig" => sig = value.parse::<i32>().ok(),
+            "arch" => arch = u32::from_str_radix(value, 16).ok(),
+            "syscall" => nr = value.parse::<i64>().ok(),
+            "ip" => ip = u64::from_str_radix(value.trim_start_matches("0x"), 16).ok(),
+            _ => {}
+        }
+    }
+    // the records of the syscalls that were logged or failed, not killed, have sig=0
+    if sig? != libc::SIGSYS {
+        return None;
     }
 
-    buf[4095] = '\0' as u8;
-
-    let mut arg1 = MaybeUninit::<libc::uintptr_t>::uninit();
-    let mut arg2 = MaybeUninit::<libc::uintptr_t>::uninit();
-    let mut arg3 = MaybeUninit::<libc::uintptr_t>::uninit();
-    let mut arg4 = MaybeUninit::<libc::uintptr_t>::uninit();
-    let mut arg5 = MaybeUninit::<libc::uintptr_t>::uninit();
-    let mut arg6 = MaybeUninit::<libc::uintptr_t>::uninit();
-    let mut sp = MaybeUninit::<libc::uintptr_t>::uninit();
-    let mut pc = MaybeUninit::<libc::uintptr_t>::uninit();
-    let mut sc = MaybeUninit::<libc::ptrdiff_t>::uninit();
-
-    let ret = unsafe {
-        libc::sscanf(
-            buf.as_ptr() as *const libc::c_char,
-            CString::new("%td %tx %tx %tx %tx %tx %tx %tx %tx")
-                .unwrap()
-                .as_ptr() as *const libc::c_char,
-            sc.as_mut_ptr(),
-            arg1.as_mut_ptr(),
-            arg2.as_mut_ptr(),
-            arg3.as_mut_ptr(),
-            arg4.as_mut_ptr(),
-            arg5.as_mut_ptr(),
-            arg6.as_mut_ptr(),
-            sp.as_mut_ptr(),
-            pc.as_mut_ptr(),
-        )
-    };
+    let (arch, nr) = (arch?, nr?);
+    Some((
+        time,
+        SeccompViolation {
+            pid: pid?,
+            nr,
+            name: if arch == AUDIT_ARCH {
+                syscall_name(nr)
+            } else {
+                None
+            },
+            arch,
+            args: None,
+            instruction_pointer: ip?,
+        },
+    ))
+}
 
-    unsafe { libc::close(pid_syscall_fd) };
-
-    if ret == 9 {
-        let arg1 = unsafe { arg1.assume_init() };
-        let arg2 = unsafe { arg2.assume_init() };
-        let arg3 = unsafe { arg3.assume_init() };
-        let arg4 = unsafe { arg4.assume_init() };
-        let arg5 = unsafe { arg5.assume_init() };
-        let arg6 = unsafe { arg6.assume_init() };
-        let sp = unsafe { sp.assume_init() };
-        let pc = unsafe { pc.assume_init() };
-        let sc = unsafe { sc.assume_init() };
-
-        return format!("Seccomp Violation: pid={}  <> Syscall number: {}, Arguments: [{}, {}, {}, {}, {}, {}], SP: {}, PC: {},", pid,  sc, arg1, arg2, arg3, arg4, arg5, arg6, sp, pc);
-    } else if ret == 3 {
-        let arg1 = unsafe { arg1.assume_init() };
-        let arg2 = unsafe { arg2.assume_init() };
-
-        return format!(
-            "Seccomp Violation: pid={}  <> SP: {}, PC: {},",
-            pid, arg1, arg2
-        );
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use seccomp::policy::compile_policy;
+    use seccomp::sock_filter;
+
+    use std::os::unix::process::{CommandExt, ExitStatusExt};
+    use std::process::Command;
+
+    // struct sock_fprog of linux/filter.h
+    #[repr(C)]
+    struct SockFprog {
+        len: u16,
+        filter: *const sock_filter,
     }
 
-    let buf_str = from_utf8(&buf).ok();
-    if let Some(buf_str) = buf_str {
-        return format!(
-            "Seccomp Violation: pid={} <> Syscall string: {}",
-            pid, buf_str
+    #[test]
+    fn test_parse_audit_record() {
+        let record = "6,1234,9204710458,-;audit: type=1326 audit(1792326375.422:8): auid=4294967295 uid=0 gid=0 \
+                      ses=4294967295 subj=kernel pid=23967 comm=\"t\" exe=\"/tmp/t\" sig=31 arch=c000003e syscall=110 \
+                      compat=0 ip=0x7f64b65d3829 code=0x80000000\n";
+        let (time, violation) = parse_audit_record(record).unwrap();
+        assert_eq!(time, UNIX_EPOCH + Duration::from_millis(1792326375422));
+        assert_eq!(violation.pid, 23967);
+        assert_eq!(violation.nr, 110);
+        assert_eq!(violation.arch, 0xc000003e);
+        assert_eq!(violation.instruction_pointer, 0x7f64b65d3829);
+        #[cfg(target_arch = "x86_64")]
+        assert_eq!(
+            violation.to_string(),
+            "syscall getppid (110) on x86_64 by pid 23967 at 0x7f64b65d3829"
         );
-    } else {
-        return format!("Seccomp Violation: pid={}  <> Syscall string: could not decode buf from /proc/pid/syscall as utf-8", pid);
+
+        // only logged, the process was not killed
+        assert!(parse_audit_record(&record.replace("sig=31", "sig=0")).is_none());
+        assert!(parse_audit_record(
+            "6,1235,9204710459,-;audit: type=1300 audit(1792326375.422:9): arch=c000003e"
+        )
+        .is_none());
+        assert!(parse_audit_record("6,1236,9204710460,-;eth0: link up").is_none());
+    }
+
+    #[test]
+    fn test_read_violation() {
+        let since = SystemTime::now() - Duration::from_secs(1);
+        let filter = compile_policy("KILL { uname } DEFAULT ALLOW").unwrap();
+
+        let mut command = Command::new("uname");
+        // nothing is allocated after the fork, the test harness is multi-threaded
+        unsafe {
+            command.pre_exec(move || {
+                let prog = SockFprog {
+                    len: filter.len() as u16,
+                    filter: filter.as_ptr(),
+                };
+                if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0
+                    || libc::prctl(
+                        libc::PR_SET_SECCOMP,
+                        libc::SECCOMP_MODE_FILTER,
+                        &prog as *const SockFprog,
+                    ) != 0
+                {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        let mut child = command.spawn().unwrap();
+        let pid = child.id() as i32;
+        let status = child.wait().unwrap();
+        assert_eq!(status.signal(), Some(libc::SIGSYS));
+
+        // auditd may have taken the record, or the kernel not be allowed to log it
+        match read_violation(pid, since) {
+            Some(violation) => {
+                assert_eq!(violation.nr, libc::SYS_uname);
+                assert_eq!(violation.name, Some("uname"));
+                assert_eq!(violation.arch, AUDIT_ARCH);
+            }
+            None => println!("no audit record of the seccomp violation of uname in /dev/kmsg"),
+        }
     }
 }
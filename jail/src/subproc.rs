@@ -5,12 +5,18 @@ use super::protobuf::create_pooled_wake_up_mess;
 use super::utils::{
     read_from_fd_ignore_err, to_exec_array, to_exec_array_cstring, write_message_to_fd, write_to_fd,
 };
-use super::{cgroupv1, cgroupv2, net, sandbox, user};
+use super::usage::{self, ResourceUsage};
+use super::wait::{self, ExitReport};
+use super::{
+    cgroup, cgroupv1, cgroupv2, firewall, limits, net, portfwd, sandbox, supervisor, user, usernet,
+};
 
 use std::ffi::{CStr, CString};
 use std::mem::MaybeUninit;
+use std::net::SocketAddr;
 use std::ptr;
-use std::time::SystemTime;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 
 use sys_util::{errno::Errno, execv, fcntl, socket, unistd, SyscallReturnCode};
 
@@ -25,43 +31,94 @@ const CLONE_PIDFD: i32 = 0x00001000;
  *
  * We force jconf.clone_newpid to be true so that we are sure that waiting for first child to finish is enough to be sure
  * that all other child (in this pid namespace) will also be terminated
+ *
+ * With jconf.restart the jail is started again as long as its RestartConfig allows it, each exit is printed and the
+ * report of the last run is returned. A start that fails counts as a run that failed, so it is retried with the same
+ * backoff, and its error is returned once the policy gives up
  */
 pub fn run_monitor_child(
     jconf: &mut JailConf,
     callback: extern "C" fn(*mut libc::c_void) -> libc::c_int,
-) -> Result<i32> {
+) -> Result<ExitReport> {
     jconf.clone_newpid();
 
-    let child_pid = run_child(jconf, callback)?;
-
-    // it is also while waiting that we must check jconf.tlimit, e.g. container max execution time if one was specified
-
-    // we need to wait for all child and child's child to terminate
+    let restart = match jconf.restart {
+        Some(ref restart) => restart.clone(),
+        None => return run_monitor_child_once(jconf, callback),
+    };
 
-    // let exit_status = wait::reap_proc(true); -> apparemment ne marche pas ; ne wait pas correctement -> cf check dans nsjail il y a une boucle for en plus de celles qu'on fait wui tourne tant qu'il y a des process
-    let mut wait_status: i32 = 0;
+    let mut restarts = 0;
+    let mut in_a_row = 0;
+    loop {
+        // a run leaves the fds of its network helpers and its pids in the JailConf, the next one starts from scratch
+        let mut run_conf = jconf.clone();
+        let run = run_monitor_child_once(&mut run_conf, callback);
+        let (what, status, wall_time) = match run {
+            Ok(ref report) => (report.to_string(), report.status, report.wall_time),
+            Err(ref e) => (format!("jail failed to start: {}", e), 1, Duration::from_secs(0)),
+        };
+        if !restart.restarts_after(status, restarts) {
+            println!("{}, not restarted", what);
+            return run;
+        }
 
-    if unsafe { libc::waitpid(child_pid, &mut wait_status as *mut libc::c_int, 0) } != child_pid {
-        return Err(format!(
-            "Error waiting the child process to finish: {}",
-            Errno::last()
-        )
-        .into());
+        if wall_time > Duration::from_millis(restart.backoff_max_ms) {
+            in_a_row = 0;
+        }
+        restarts += 1;
+        in_a_row += 1;
+        let delay = restart.delay(in_a_row);
+        println!(
+            "{}, restart {} in {:.3}s",
+            what,
+            restarts,
+            delay.as_secs_f64()
+        );
+        thread::sleep(delay);
     }
+}
 
-    let mut status = 0;
-    if unsafe { libc::WIFEXITED(wait_status) } {
-        status = unsafe { libc::WEXITSTATUS(wait_status) };
-    }
+fn run_monitor_child_once(
+    jconf: &mut JailConf,
+    callback: extern "C" fn(*mut libc::c_void) -> libc::c_int,
+) -> Result<ExitReport> {
+    let child_pid = match run_child(jconf, callback) {
+        Ok(p) => p,
+        Err(Error::ParsePid((p, pidfd, e))) => {
+            // the child was cloned before its setup failed, it must not outlive the run
+            unsafe {
+                libc::kill(p, libc::SIGKILL);
+                libc::waitpid(p, ptr::null_mut(), 0);
+            }
+            let _ = clean_after_child(jconf, p);
+            return Err(Error::ParsePid((p, pidfd, e)));
+        }
+        Err(e) => return Err(e),
+    };
+
+    // the child is the init of its pid namespace, once it is reaped all other processes in the namespace are gone too,
+    // it is also while waiting that jconf.tlimit, the container max execution time, is enforced, and that the syscalls
+    // of its seccomp supervisor are answered
+    let mut report = wait::wait_supervised(child_pid, jconf)?;
 
-    clean_after_child(jconf, child_pid)?;
+    report.add_usage(&clean_after_child(jconf, child_pid)?);
 
-    // Ok(exit_status)
-    Ok(status)
+    Ok(report)
 }
 
 /// clean_after_child does not call wait syscall, do not forget to wait child_pid to avoid for it to become a zombie process
-pub fn clean_after_child(jconf: &JailConf, child_pid: i32) -> Result<()> {
+///
+/// Returns what the jail used, measured from its cgroups and NET namespace before they go away, see usage.rs
+pub fn clean_after_child(jconf: &JailConf, child_pid: i32) -> Result<ResourceUsage> {
+    let usage = usage::collect(jconf, child_pid.to_string().as_str());
+
+    firewall::finish_from_parent(jconf);
+    usernet::finish_from_parent(jconf);
+    supervisor::finish_from_parent(jconf);
+    portfwd::finish_from_parent(jconf);
+    usage::finish_from_parent(jconf);
+    limits::finish_from_parent(jconf);
+
     if jconf.clone_newcgroup {
         // only namespace not to clean itself when no more process in it ?
         // so we may not need to keep him open with an opened fd for other container to run in the same namespace ? TODO: test this
@@ -80,7 +137,7 @@ pub fn clean_after_child(jconf: &JailConf, child_pid: i32) -> Result<()> {
     //     }
     // }
 
-    Ok(())
+    Ok(usage)
 }
 
 /// do not forget to wait for the child pid after this call returns and then to run clean_after_child
@@ -90,16 +147,22 @@ pub fn run_child(
 ) -> Result<libc::pid_t> {
     if jconf.join_sleeping_thread {
         run_child_wake_up_pooled(jconf)?;
-        return Ok(jconf.child_pid.unwrap());
+        let p = jconf.child_pid.unwrap();
+        portfwd::start_from_parent(jconf, p)
+            .map_err(|e| Error::ParsePid((p, None, e.to_string())))?;
+        return Ok(p);
     }
 
     let (p, _) = run_child_instantiate(jconf, false, callback)?;
 
-    run_child_listen_fd(jconf, jconf.passed_admin_parent_fd)
-        .map_err(|e| Error::ParsePid((p, None, e.to_string())))?;
+    let parent_fd = jconf.passed_admin_parent_fd;
+    run_child_listen_fd(jconf, parent_fd).map_err(|e| Error::ParsePid((p, None, e.to_string())))?;
 
+    // a pooled child only runs once woken up, its forwards start then
     if !jconf.create_pooled_thread {
         unsafe { libc::close(jconf.passed_admin_parent_fd) };
+        portfwd::start_from_parent(jconf, p)
+            .map_err(|e| Error::ParsePid((p, None, e.to_string())))?;
     }
 
     jconf.child_pid = Some(p);
@@ -114,16 +177,23 @@ pub fn run_child_pidfd(
 ) -> Result<(libc::pid_t, libc::pid_t)> {
     if jconf.join_sleeping_thread {
         run_child_wake_up_pooled(jconf)?;
-        return Ok((jconf.child_pid.unwrap(), jconf.child_pidfd.unwrap()));
+        let (p, pidfd) = (jconf.child_pid.unwrap(), jconf.child_pidfd.unwrap());
+        portfwd::start_from_parent(jconf, p)
+            .map_err(|e| Error::ParsePid((p, Some(pidfd), e.to_string())))?;
+        return Ok((p, pidfd));
     }
 
     let (p, pidfd) = run_child_instantiate(jconf, true, callback)?;
 
-    run_child_listen_fd(jconf, jconf.passed_admin_parent_fd)
+    let parent_fd = jconf.passed_admin_parent_fd;
+    run_child_listen_fd(jconf, parent_fd)
         .map_err(|e| Error::ParsePid((p, pidfd, e.to_string())))?;
 
+    // a pooled child only runs once woken up, its forwards start then
     if !jconf.create_pooled_thread {
         unsafe { libc::close(jconf.passed_admin_parent_fd) };
+        portfwd::start_from_parent(jconf, p)
+            .map_err(|e| Error::ParsePid((p, pidfd, e.to_string())))?;
     }
 
     jconf.child_pid = Some(p);
@@ -137,6 +207,9 @@ fn run_child_instantiate(
     gen_pidfd: bool,
     callback: extern "C" fn(*mut libc::c_void) -> libc::c_int,
 ) -> Result<(libc::pid_t, Option<libc::pid_t>)> {
+    cgroup::check_namespace(jconf)?;
+    sandbox::prepare_policy(jconf)?;
+
     let (child_fd, parent_fd) =
         socket::socketpair(libc::AF_UNIX, libc::SOCK_STREAM | libc::SOCK_CLOEXEC, 0)?;
 
@@ -173,6 +246,7 @@ fn run_child_instantiate(
         unsafe { libc::close(parent_fd) };
         return Err(("clone failed", Errno::last()).into());
     }
+    jconf.child_start = Some(Instant::now());
 
     match init_parent(jconf, p, parent_fd) {
         Ok(_) => {}
@@ -213,12 +287,74 @@ fn run_child_setup_flags(jconf: &JailConf) -> libc::c_int {
     flags
 }
 
-fn run_child_listen_fd(jconf: &JailConf, parent_fd: i32) -> Result<()> {
+/**
+ * Reads the answer of the child: 'W' for a pooled child, 'E' on error and EOF once executed. A child with a user-mode
+ * network first sends 'T' along with its tap device, and one with a seccomp supervisor 'N' along with its listener,
+ * which is then answered until the child is executed: the filter of a jail learning its policy hands over its close
+ * and execve
+ */
+fn run_child_listen_fd(jconf: &mut JailConf, parent_fd: i32) -> Result<()> {
     let mut buf = [0; 1];
-    if read_from_fd_ignore_err(parent_fd, &mut buf)? == 1 {
+    let mut supervised = true;
+    let n = loop {
+        if let (Some(listener), true) = (jconf.seccomp_notify_fd, supervised) {
+            let mut pfds = [
+                libc::pollfd {
+                    fd: parent_fd,
+                    events: libc::POLLIN,
+                    revents: 0,
+                },
+                libc::pollfd {
+                    fd: listener,
+                    events: libc::POLLIN,
+                    revents: 0,
+                },
+            ];
+            if unsafe { libc::poll(pfds.as_mut_ptr(), 2, -1) } < 0 {
+                match Errno::last() {
+                    Errno::EINTR => continue,
+                    e => return Err(format!("could not poll the child: {}", e).into()),
+                }
+            }
+            if pfds[1].revents & libc::POLLIN != 0 {
+                supervisor::handle(jconf)?;
+            } else if pfds[1].revents != 0 {
+                supervised = false;
+            }
+            if pfds[0].revents == 0 {
+                continue;
+            }
+        }
+        let (n, fd) = match socket::recvmsg_fd(parent_fd, &mut buf) {
+            Ok(v) => v,
+            Err(Errno::EINTR) => continue,
+            Err(e) => return Err(format!("could not read from the child: {}", e).into()),
+        };
+        match fd {
+            Some(fd) if n == 1 && buf[0] == usernet::TAP_MESSAGE => {
+                usernet::start_from_parent(jconf, fd)?
+            }
+            Some(fd) if n == 1 && buf[0] == supervisor::NOTIFY_MESSAGE => {
+                supervisor::start_from_parent(jconf, fd)?
+            }
+            Some(fd) => unsafe {
+                libc::close(fd);
+            },
+            None => break n,
+        }
+    };
+    if n == 1 {
         match buf[0] as char {
             'W' => {}
             'E' => {
+                // the child will not run, nothing else stops its network stack and supervisor, nor is there a
+                // policy to learn from it
+                usernet::finish_from_parent(jconf);
+                jconf.user_net_fd = None;
+                jconf.seccomp_recorder = None;
+                supervisor::finish_from_parent(jconf);
+                jconf.seccomp_notify_fd = None;
+
                 if jconf.debug {
                     let mut buf = [0; 4096];
                     let len = read_from_fd_ignore_err(parent_fd, &mut buf)?;
@@ -257,8 +393,10 @@ fn run_child_wake_up_pooled(jconf: &mut JailConf) -> Result<()> {
     // let now2 = std::time::Instant::now();
     // println!("write_message_to_fd: {:?}", now2.duration_since(now));
 
-    run_child_listen_fd(jconf, jconf.passed_admin_parent_fd)?;
+    let parent_fd = jconf.passed_admin_parent_fd;
+    run_child_listen_fd(jconf, parent_fd)?;
     unsafe { libc::close(jconf.passed_admin_parent_fd) };
+    jconf.child_start = Some(Instant::now());
 
     Ok(())
 }
@@ -316,6 +454,9 @@ pub fn init_parent(jconf: &mut JailConf, pid: libc::pid_t, pipefd: libc::c_int)
         net::init_ns_from_parent(jconf, pid)?;
     }
 
+    firewall::init_ns_from_parent(jconf, pid)?;
+    usage::init_ns_from_parent(jconf, pid)?;
+
     let pid_string = pid.to_string();
 
     if jconf.clone_newcgroup {
@@ -325,6 +466,7 @@ pub fn init_parent(jconf: &mut JailConf, pid: libc::pid_t, pipefd: libc::c_int)
             cgroupv1::init_ns_from_parent(jconf, &pid_string)?;
         }
     }
+    limits::watch_from_parent(jconf, &pid_string)?;
 
     if jconf.clone_newuser {
         user::init_ns_from_parent(jconf, &pid_string, jconf.borrow_env())?;
@@ -477,12 +619,25 @@ pub fn subproc_new_proc_exec(jconf: &mut JailConf) -> Result<()> {
  * mode PTRACE_MODE_ATTACH_FSCREDS check; see ptrace(2).
 */
 pub fn add_proc(jconf: &mut JailConf, pid: libc::pid_t) -> Result<()> {
+    add_proc_remote(jconf, pid, None)
+}
+
+/// add_proc for a jail serving the client remote, see listen.rs
+pub fn add_proc_remote(
+    jconf: &mut JailConf,
+    pid: libc::pid_t,
+    remote: Option<SocketAddr>,
+) -> Result<()> {
     let proc_syscall = format!("/proc/{}/syscall", pid);
     let proc_syscall = CString::new(proc_syscall).unwrap();
     let pid_t = PIDT {
         pid: pid,
         start: SystemTime::now(),
-        remote_txt: "[STANDALONE MODE]",
+        remote_txt: match remote {
+            Some(addr) => addr.to_string(),
+            None => String::from("[STANDALONE MODE]"),
+        },
+        remote_addr: remote,
         pid_syscall_fd: unsafe {
             libc::open(proc_syscall.as_ptr(), libc::O_RDONLY | libc::O_CLOEXEC)
         },
@@ -501,6 +656,14 @@ pub fn add_proc(jconf: &mut JailConf, pid: libc::pid_t) -> Result<()> {
     Ok(())
 }
 
+/// Forgets a reaped pid of add_proc
+pub fn remove_proc(jconf: &mut JailConf, pid: libc::pid_t) {
+    if let Some(i) = jconf.pids.iter().position(|p| p.pid == pid) {
+        let p = jconf.pids.swap_remove(i);
+        unsafe { libc::close(p.pid_syscall_fd) };
+    }
+}
+
 /*
  * See clone_proc documentation (this is an experiment for now)
  *
@@ -0,0 +1,793 @@
+// Seccomp supervisor of a jail. The seccomp filter of the child is loaded with a listener, see sandbox::apply_policy,
+// which the child passes to the parent before it is executed. The parent keeps it in JailConf::seccomp_notify_fd and
+// polls it along with the rest of what it waits for, the child answer in subproc::run_child_listen_fd, then the jail
+// in wait::wait_supervised, or the epoll of the scheduler. Each time it is readable, handle decides the next syscall
+// the filter handed over with USER_NOTIF, see config::SeccompNotifyConfig. The syscalls it accepts are run by the
+// parent itself, with the arguments it read, so the jail cannot change them between the check and the syscall.
+// The syscalls the policy kills for are handed over too, so that kill records their arguments before it kills.
+//
+// When the jail learns its policy, see JailConf::seccomp_learn, the filter hands every syscall over instead and the
+// supervisor lets them through, recording them in seccomp::learn::Recorder. The policy is written once the jail exits
+
+use super::config::{EgressProto, EgressRule, JailConf, NotifyMount, SeccompNotifyConfig};
+use super::error::Result;
+use super::sandbox::SeccompViolation;
+
+use seccomp::learn::Recorder;
+use seccomp::notify::{Listener, Notification, Response};
+use seccomp::{policy, BpfProgram, SeccompAction};
+use sys_util::errno::Errno;
+use sys_util::{socket, uio::IoVec};
+
+use std::ffi::CString;
+use std::fs::{self, File};
+use std::io;
+use std::mem::{self, ManuallyDrop};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::path::Path;
+use std::ptr;
+
+/// Byte the child sends to the parent along with the listener of its filter, see subproc::run_child_listen_fd
+pub const NOTIFY_MESSAGE: u8 = b'N';
+
+// MS_MGC_VAL, the magic number old programs still put in the upper 16 bits of the mount flags
+const MS_MGC_MSK: libc::c_ulong = 0xffff_0000;
+const MS_MGC_VAL: libc::c_ulong = 0xc0ed_0000;
+
+const UMOUNT_NOFOLLOW: libc::c_int = 8;
+
+// openat2, linux 5.6, which the libc crate does not have yet
+const SYS_OPENAT2: libc::c_long = 437;
+const RESOLVE_NO_SYMLINKS: u64 = 0x04;
+const RESOLVE_BENEATH: u64 = 0x08;
+const RESOLVE_IN_ROOT: u64 = 0x10;
+
+#[repr(C)]
+struct OpenHow {
+    flags: u64,
+    mode: u64,
+    resolve: u64,
+}
+
+/// The filter of a jail learning its policy: every syscall is handed over to the supervisor, but the sendmsg of
+/// send_from_child passing the listener to it
+pub fn learning_filter(jconf: &JailConf) -> Result<BpfProgram> {
+    let filter = policy::compile_policy(&format!(
+        "ALLOW {{ sendmsg(fd) {{ fd == {} }} }} DEFAULT USER_NOTIF",
+        jconf.passed_admin_child_fd
+    ))
+    .map_err(|e| e.to_string())?;
+    Ok(filter)
+}
+
+/// Sends the listener of the filter of the child to the parent, then closes it. Runs in the child, after its filter is
+/// loaded, so the filter must allow sendmsg and close
+pub fn send_from_child(jconf: &JailConf, listener: RawFd) -> Result<()> {
+    let res = socket::sendmsg(
+        jconf.passed_admin_child_fd,
+        &[IoVec::from_slice(&[NOTIFY_MESSAGE])],
+        listener,
+        0,
+    );
+    unsafe { libc::close(listener) };
+    res.map_err(|e| format!("could not send the seccomp listener to the parent: {}", e))?;
+
+    Ok(())
+}
+
+/**
+ * Keeps the listener received from the child in jconf, which owns it from now on. The parent then calls handle each
+ * time it is readable, until it hangs up once every process of the jail is gone, or finish_from_parent
+ */
+pub fn start_from_parent(jconf: &mut JailConf, listener: RawFd) -> Result<()> {
+    if !jconf.seccomp_learn.is_empty() {
+        jconf.seccomp_recorder = Some(Recorder::default());
+    } else if jconf.seccomp_notify.is_none() {
+        // closing the listener fails the syscalls it would have received with ENOSYS
+        unsafe { libc::close(listener) };
+        return Err("received a seccomp listener but no supervisor is configured".into());
+    }
+    jconf.seccomp_notify_fd = Some(listener);
+
+    Ok(())
+}
+
+/// Receives the next syscall handed over to the supervisor of the jail and answers it, call it once
+/// jconf.seccomp_notify_fd is readable
+pub fn handle(jconf: &mut JailConf) -> Result<()> {
+    let listener = match jconf.seccomp_notify_fd {
+        // closed by finish_from_parent
+        Some(fd) => ManuallyDrop::new(unsafe { Listener::from_raw_fd(fd) }),
+        None => return Ok(()),
+    };
+    let res = match (
+        jconf.seccomp_recorder.as_mut(),
+        jconf.seccomp_notify.as_ref(),
+    ) {
+        (Some(recorder), _) => listener.handle_next(|_, n| learn(recorder, n)),
+        (None, Some(cfg)) => {
+            let (kill_filter, violation) = (
+                jconf.seccomp_kill_filter.as_ref(),
+                &mut jconf.seccomp_violation,
+            );
+            listener.handle_next(|l, n| match kill_filter {
+                Some(filter) if filter.action(n.nr, &n.args) == SeccompAction::Kill => {
+                    kill(violation, n)
+                }
+                _ => decide(cfg, l, n),
+            })
+        }
+        (None, None) => Ok(()),
+    };
+    res.map_err(|e| format!("seccomp supervisor: {}", e).into())
+}
+
+/**
+ * Closes the listener of the jail, the syscalls still waiting for the supervisor fail with ENOSYS, then writes the
+ * policy of a jail learning its policy
+ */
+pub fn finish_from_parent(jconf: &JailConf) {
+    if let Some(fd) = jconf.seccomp_notify_fd {
+        unsafe { libc::close(fd) };
+
+        if let Some(ref recorder) = jconf.seccomp_recorder {
+            let path = Path::new(jconf.seccomp_learn);
+            if let Err(e) = write_policy(recorder, path) {
+                println!(
+                    "seccomp supervisor: could not write the learnt policy to {}: {}",
+                    path.display(),
+                    e
+                );
+            }
+        }
+    }
+}
+
+fn decide(cfg: &SeccompNotifyConfig, listener: &Listener, n: &Notification) -> Response {
+    match n.nr {
+        libc::SYS_mount => mount(cfg, listener, n),
+        libc::SYS_umount2 => umount(cfg, listener, n),
+        libc::SYS_connect => connect(cfg, listener, n),
+        _ => Response::Errno(libc::EPERM),
+    }
+}
+
+/**
+ * Kills the process of a syscall the filter of the jail kills for, which SeccompFilter::notify_kills handed over to the
+ * supervisor, and records it as the violation of the jail along with its arguments. The whole process is killed, with
+ * SIGKILL, where the filter would only have killed the thread with SIGSYS
+ */
+fn kill(violation: &mut Option<SeccompViolation>, n: &Notification) -> Response {
+    let pid = tgid(n.pid).unwrap_or(n.pid as i32);
+    *violation = Some(SeccompViolation::from_notification(pid, n));
+    unsafe { libc::kill(pid, libc::SIGKILL) };
+    // never seen by the process, the answer is refused once it is gone
+    Response::Errno(libc::ENOSYS)
+}
+
+/// The process of the thread tid, both in the PID namespace of the supervisor
+fn tgid(tid: u32) -> Option<i32> {
+    let status = fs::read_to_string(format!("/proc/{}/status", tid)).ok()?;
+    status
+        .lines()
+        .find(|l| l.starts_with("Tgid:"))
+        .and_then(|l| l["Tgid:".len()..].trim().parse().ok())
+}
+
+fn learn(recorder: &mut Recorder, n: &Notification) -> Response {
+    recorder.record(n.nr, &n.args);
+    // the syscalls are not checked, their arguments can be let through as they are
+    Response::Continue
+}
+
+/// Replaces the file at path with the policy of recorder, at once so that it is never read half written
+fn write_policy(recorder: &Recorder, path: &Path) -> io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    fs::write(&tmp, recorder.policy())?;
+    fs::rename(&tmp, path)
+}
+
+/// A string argument of the syscall, None for a null pointer
+fn read_arg(
+    listener: &Listener,
+    n: &Notification,
+    arg: usize,
+) -> std::result::Result<Option<String>, Response> {
+    if n.args[arg] == 0 {
+        return Ok(None);
+    }
+    match listener.read_string(n, n.args[arg]) {
+        Ok(s) => Ok(Some(s)),
+        Err(seccomp::Error::Notify(errno)) => Err(Response::Errno(errno)),
+        Err(_) => Err(Response::Errno(libc::EFAULT)),
+    }
+}
+
+/// Whether rule accepts mount(src, dst, fstype, flags, data) with dst its own dst, see mount_rule
+fn mount_allowed(
+    rule: &NotifyMount,
+    src: &str,
+    fstype: &str,
+    flags: libc::c_ulong,
+    data: &str,
+) -> bool {
+    let flags = if flags & MS_MGC_MSK == MS_MGC_VAL {
+        flags & !MS_MGC_MSK
+    } else {
+        flags
+    };
+    (rule.src.is_empty() || rule.src == src)
+        && rule.fstype == fstype
+        && flags & !rule.flags == 0
+        && rule.data == data
+}
+
+/// Opens path with O_PATH from dirfd, without following symlinks, resolve is the RESOLVE_* flags of openat2
+fn openat2(dirfd: RawFd, path: &str, resolve: u64) -> io::Result<File> {
+    let path = CString::new(path).map_err(|_| io::Error::from_raw_os_error(libc::EINVAL))?;
+    let how = OpenHow {
+        flags: (libc::O_PATH | libc::O_CLOEXEC) as u64,
+        mode: 0,
+        resolve: resolve | RESOLVE_NO_SYMLINKS,
+    };
+    let fd = unsafe {
+        libc::syscall(
+            SYS_OPENAT2,
+            dirfd,
+            path.as_ptr(),
+            &how as *const OpenHow,
+            mem::size_of::<OpenHow>(),
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { File::from_raw_fd(fd as RawFd) })
+}
+
+/**
+ * Opens the path a syscall of the jail passed, an absolute one from root, a relative one from cwd, the root and cwd of
+ * the process that made it. Symlinks are refused and ".." never leaves root, nor cwd for a relative path, so that the
+ * file is the one a rule names or none at all
+ */
+fn resolve(root: &File, cwd: &File, path: &str) -> io::Result<File> {
+    if path.starts_with('/') {
+        openat2(root.as_raw_fd(), path, RESOLVE_IN_ROOT)
+    } else {
+        openat2(cwd.as_raw_fd(), path, RESOLVE_BENEATH)
+    }
+}
+
+fn same_file(a: &File, b: &File) -> bool {
+    match (a.metadata(), b.metadata()) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false,
+    }
+}
+
+/// The root and cwd of the process pid
+fn root_and_cwd(pid: u32) -> io::Result<(File, File)> {
+    let open = |path: String| {
+        fs::OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_PATH | libc::O_DIRECTORY)
+            .open(path)
+    };
+    Ok((
+        open(format!("/proc/{}/root", pid))?,
+        open(format!("/proc/{}/cwd", pid))?,
+    ))
+}
+
+fn errno(e: io::Error) -> Response {
+    Response::Errno(e.raw_os_error().unwrap_or(libc::EIO))
+}
+
+fn mount(cfg: &SeccompNotifyConfig, listener: &Listener, n: &Notification) -> Response {
+    let args = (|| {
+        Ok((
+            read_arg(listener, n, 0)?.unwrap_or_default(),
+            read_arg(listener, n, 1)?.unwrap_or_default(),
+            read_arg(listener, n, 2)?.unwrap_or_default(),
+            read_arg(listener, n, 4)?.unwrap_or_default(),
+        ))
+    })();
+    let (src, dst, fstype, data) = match args {
+        Ok(args) => args,
+        Err(response) => return response,
+    };
+    let flags = n.args[3] as libc::c_ulong;
+
+    let (root, cwd) = match root_and_cwd(n.pid) {
+        Ok(dirs) => dirs,
+        Err(_) => return Response::Errno(libc::ESRCH),
+    };
+    let target = match resolve(&root, &cwd, &dst) {
+        Ok(target) => target,
+        Err(e) => return errno(e),
+    };
+    let allowed = cfg.mounts.iter().any(|r| {
+        mount_allowed(r, &src, &fstype, flags, &data)
+            && openat2(root.as_raw_fd(), &r.dst, RESOLVE_IN_ROOT)
+                .map(|f| same_file(&f, &target))
+                .unwrap_or(false)
+    });
+    if !allowed {
+        return Response::Errno(libc::EPERM);
+    }
+
+    let cstr = |s: &str| CString::new(s).unwrap_or_default();
+    let (src, fstype, data) = (cstr(&src), cstr(&fstype), cstr(&data));
+    let opt = |s: &CString| {
+        if s.as_bytes().is_empty() {
+            ptr::null()
+        } else {
+            s.as_ptr()
+        }
+    };
+    let (src, fstype, data) = (opt(&src), opt(&fstype), opt(&data));
+    // mounted on the file resolved, the jail cannot swap it for another meanwhile
+    in_jail(n.pid, target.as_raw_fd(), || unsafe {
+        libc::mount(
+            src,
+            b".\0".as_ptr() as *const libc::c_char,
+            fstype,
+            flags,
+            data as *const libc::c_void,
+        )
+    })
+}
+
+fn umount(cfg: &SeccompNotifyConfig, listener: &Listener, n: &Notification) -> Response {
+    let dst = match read_arg(listener, n, 0) {
+        Ok(Some(dst)) => dst,
+        Ok(None) => return Response::Errno(libc::EFAULT),
+        Err(response) => return response,
+    };
+
+    let (root, cwd) = match root_and_cwd(n.pid) {
+        Ok(dirs) => dirs,
+        Err(_) => return Response::Errno(libc::ESRCH),
+    };
+    let target = match resolve(&root, &cwd, &dst) {
+        Ok(target) => target,
+        Err(e) => return errno(e),
+    };
+    let rule = cfg.mounts.iter().find(|r| {
+        openat2(root.as_raw_fd(), &r.dst, RESOLVE_IN_ROOT)
+            .map(|f| same_file(&f, &target))
+            .unwrap_or(false)
+    });
+    // an fd of the mounted file system would keep it busy
+    drop(target);
+    // the mount point is unmounted from its parent directory, the root of the jail has none
+    let (parent, name) = match rule.map(|r| Path::new(&r.dst)) {
+        Some(dst) => match (dst.parent(), dst.file_name()) {
+            (Some(parent), Some(name)) => (parent.to_string_lossy(), name.to_string_lossy()),
+            _ => return Response::Errno(libc::EPERM),
+        },
+        None => return Response::Errno(libc::EPERM),
+    };
+    let parent = match openat2(root.as_raw_fd(), &parent, RESOLVE_IN_ROOT) {
+        Ok(parent) => parent,
+        Err(e) => return errno(e),
+    };
+
+    let name = CString::new(name.as_bytes()).unwrap_or_default();
+    let flags = n.args[1] as libc::c_int | UMOUNT_NOFOLLOW;
+    in_jail(n.pid, parent.as_raw_fd(), || unsafe {
+        libc::umount2(name.as_ptr(), flags)
+    })
+}
+
+fn same_ns(pid: u32, ns: &str) -> bool {
+    let ino = |path: String| fs::metadata(path).map(|m| m.ino()).ok();
+    ino(format!("/proc/self/ns/{}", ns)) == ino(format!("/proc/{}/ns/{}", pid, ns))
+}
+
+/**
+ * Runs f in a child process inside the user namespace, mount namespace and root of the process pid, with dir as its
+ * cwd, and returns what f returns. The user namespace is joined even by a root parent, so that f only has the
+ * privileges of the jail. setns of a mount namespace is refused to a multi-threaded process
+ */
+fn in_jail<F: FnOnce() -> libc::c_int>(pid: u32, dir: RawFd, f: F) -> Response {
+    let open = |path: String| {
+        let p = CString::new(path).unwrap_or_default();
+        unsafe { libc::open(p.as_ptr(), libc::O_RDONLY | libc::O_CLOEXEC) }
+    };
+    let userns = if !same_ns(pid, "user") {
+        open(format!("/proc/{}/ns/user", pid))
+    } else {
+        -1
+    };
+    let mntns = open(format!("/proc/{}/ns/mnt", pid));
+    let root = open(format!("/proc/{}/root", pid));
+    let close = || unsafe {
+        for fd in [userns, mntns, root].iter().filter(|fd| **fd >= 0) {
+            libc::close(*fd);
+        }
+    };
+    if mntns < 0 || root < 0 {
+        close();
+        return Response::Errno(libc::ESRCH);
+    }
+
+    let child = unsafe { libc::fork() };
+    if child == 0 {
+        // only async-signal-safe calls from here on
+        unsafe {
+            let dot = b".\0".as_ptr() as *const libc::c_char;
+            if (userns >= 0 && libc::setns(userns, libc::CLONE_NEWUSER) != 0)
+                || libc::setns(mntns, libc::CLONE_NEWNS) != 0
+                || libc::fchdir(root) != 0
+                || libc::chroot(dot) != 0
+                || libc::fchdir(dir) != 0
+                || f() != 0
+            {
+                libc::_exit(*libc::__errno_location());
+            }
+            libc::_exit(0);
+        }
+    }
+    close();
+    if child < 0 {
+        return Response::Errno(Errno::last() as i32);
+    }
+
+    let mut status = 0;
+    while unsafe { libc::waitpid(child, &mut status, 0) } < 0 {
+        if Errno::last() != Errno::EINTR {
+            return Response::Errno(libc::EIO);
+        }
+    }
+    match (libc::WIFEXITED(status), libc::WEXITSTATUS(status)) {
+        (true, 0) => Response::Return(0),
+        (true, errno) => Response::Errno(errno),
+        _ => Response::Errno(libc::EIO),
+    }
+}
+
+/// The IPv4 or IPv6 address of a struct sockaddr, None for any other family
+fn parse_sockaddr(buf: &[u8]) -> Option<SocketAddr> {
+    if buf.len() < 2 {
+        return None;
+    }
+    let family = u16::from_ne_bytes([buf[0], buf[1]]) as libc::c_int;
+    let port = |buf: &[u8]| u16::from_be_bytes([buf[2], buf[3]]);
+    match family {
+        libc::AF_INET if buf.len() >= 8 => {
+            let ip = Ipv4Addr::new(buf[4], buf[5], buf[6], buf[7]);
+            Some(SocketAddr::new(IpAddr::V4(ip), port(buf)))
+        }
+        libc::AF_INET6 if buf.len() >= 24 => {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(&buf[8..24]);
+            let ip = Ipv6Addr::from(octets);
+            // a dual-stack socket reaches IPv4 addresses through mapped ones
+            let ip = match ip.to_ipv4_mapped() {
+                Some(v4) => IpAddr::V4(v4),
+                None => IpAddr::V6(ip),
+            };
+            Some(SocketAddr::new(ip, port(buf)))
+        }
+        _ => None,
+    }
+}
+
+fn in_net(ip: IpAddr, net: IpAddr, prefix_len: u8) -> bool {
+    match (ip, net) {
+        (IpAddr::V4(ip), IpAddr::V4(net)) => {
+            let mask = u32::MAX
+                .checked_shl(32 - u32::from(prefix_len))
+                .unwrap_or(0);
+            u32::from(ip) & mask == u32::from(net) & mask
+        }
+        (IpAddr::V6(ip), IpAddr::V6(net)) => {
+            let mask = u128::MAX
+                .checked_shl(128 - u32::from(prefix_len))
+                .unwrap_or(0);
+            u128::from(ip) & mask == u128::from(net) & mask
+        }
+        _ => false,
+    }
+}
+
+/// Whether one of rules accepts a connect to addr, proto is None for sockets neither TCP nor UDP
+fn connect_allowed(rules: &[EgressRule], addr: &SocketAddr, proto: Option<EgressProto>) -> bool {
+    rules.iter().any(|r| {
+        let proto_ok = match (r.proto, r.ports) {
+            (Some(p), _) => proto == Some(p),
+            (None, Some(_)) => proto.is_some(),
+            (None, None) => true,
+        };
+        let port_ok = match r.ports {
+            Some((from, to)) => addr.port() >= from && addr.port() <= to,
+            None => true,
+        };
+        proto_ok && port_ok && in_net(addr.ip(), r.net, r.prefix_len)
+    })
+}
+
+fn connect(cfg: &SeccompNotifyConfig, listener: &Listener, n: &Notification) -> Response {
+    let mut buf = [0u8; mem::size_of::<libc::sockaddr_storage>()];
+    let len = n.args[2] as usize;
+    if len > buf.len() {
+        return Response::Errno(libc::EINVAL);
+    }
+    match listener.read_memory(n, n.args[1], &mut buf[..len]) {
+        Ok(read) if read == len => {}
+        _ => return Response::Errno(libc::EFAULT),
+    }
+
+    // AF_UNSPEC dissolves the association of a socket, anything else must be an allowed destination
+    let unspec = len >= 2 && u16::from_ne_bytes([buf[0], buf[1]]) as libc::c_int == libc::AF_UNSPEC;
+    let addr = parse_sockaddr(&buf[..len]);
+    if !unspec && addr.is_none() {
+        return Response::Errno(libc::EPERM);
+    }
+
+    let sock = match listener.get_fd(n, n.args[0] as RawFd) {
+        Ok(sock) => sock,
+        Err(_) => return Response::Errno(libc::EBADF),
+    };
+    if let Some(addr) = addr {
+        let mut ty: libc::c_int = 0;
+        let mut ty_len = mem::size_of::<libc::c_int>() as libc::socklen_t;
+        unsafe {
+            libc::getsockopt(
+                sock,
+                libc::SOL_SOCKET,
+                libc::SO_TYPE,
+                &mut ty as *mut libc::c_int as *mut libc::c_void,
+                &mut ty_len,
+            )
+        };
+        let proto = match ty {
+            libc::SOCK_STREAM => Some(EgressProto::Tcp),
+            libc::SOCK_DGRAM => Some(EgressProto::Udp),
+            _ => None,
+        };
+        if !connect_allowed(&cfg.connect, &addr, proto) {
+            unsafe { libc::close(sock) };
+            return Response::Errno(libc::EPERM);
+        }
+    }
+
+    // the copy shares the socket of the jail, a non-blocking one gets EINPROGRESS as it would have
+    let ret = unsafe {
+        libc::connect(
+            sock,
+            buf.as_ptr() as *const libc::sockaddr,
+            len as libc::socklen_t,
+        )
+    };
+    let errno = Errno::last() as i32;
+    unsafe { libc::close(sock) };
+    if ret == 0 {
+        Response::Return(0)
+    } else {
+        Response::Errno(errno)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::firewall::parse_egress_rule;
+    use seccomp::policy::compile_policy;
+    use seccomp::SeccompFilter;
+
+    use std::convert::TryInto;
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::mpsc;
+    use std::thread;
+
+    #[test]
+    fn test_mount_allowed() {
+        let rule = NotifyMount {
+            src: String::new(),
+            dst: "/tmp".to_string(),
+            fstype: "tmpfs".to_string(),
+            flags: libc::MS_NOSUID | libc::MS_NODEV,
+            data: "size=64m".to_string(),
+        };
+        assert!(mount_allowed(
+            &rule,
+            "tmpfs",
+            "tmpfs",
+            libc::MS_NOSUID,
+            "size=64m"
+        ));
+        assert!(mount_allowed(
+            &rule, "none", "tmpfs", MS_MGC_VAL, "size=64m"
+        ));
+        assert!(!mount_allowed(&rule, "tmpfs", "proc", 0, "size=64m"));
+        assert!(!mount_allowed(
+            &rule,
+            "tmpfs",
+            "tmpfs",
+            libc::MS_BIND,
+            "size=64m"
+        ));
+        assert!(!mount_allowed(&rule, "tmpfs", "tmpfs", 0, "size=1g"));
+    }
+
+    #[test]
+    fn test_resolve() {
+        let dir = std::env::temp_dir().join(format!("supervisor-resolve-{}", std::process::id()));
+        fs::create_dir_all(dir.join("root/tmp")).unwrap();
+        fs::create_dir_all(dir.join("tmp")).unwrap();
+        std::os::unix::fs::symlink("/etc", dir.join("root/etc")).unwrap();
+        let open = |path: &Path| {
+            fs::OpenOptions::new()
+                .read(true)
+                .custom_flags(libc::O_PATH | libc::O_DIRECTORY)
+                .open(path)
+                .unwrap()
+        };
+        let (root, cwd) = (open(&dir.join("root")), open(&dir.join("root/tmp")));
+        let tmp = open(&dir.join("root/tmp"));
+
+        assert!(same_file(&resolve(&root, &cwd, "/tmp/").unwrap(), &tmp));
+        assert!(same_file(&resolve(&root, &cwd, ".").unwrap(), &tmp));
+        // ".." stops at the root of the jail instead of reaching dir/tmp
+        assert!(same_file(&resolve(&root, &cwd, "/../tmp").unwrap(), &tmp));
+        let err = |path: &str| resolve(&root, &cwd, path).unwrap_err().raw_os_error();
+        assert_eq!(err("/etc"), Some(libc::ELOOP));
+        assert_eq!(err("../../tmp"), Some(libc::EXDEV));
+        assert_eq!(err("/nonexistent"), Some(libc::ENOENT));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_connect_allowed() {
+        let rules = vec![
+            parse_egress_rule("10.0.0.0/8,tcp,443").unwrap(),
+            parse_egress_rule("fd00::/8,53").unwrap(),
+        ];
+        let addr = |s: &str| s.parse::<SocketAddr>().unwrap();
+        let tcp = Some(EgressProto::Tcp);
+        assert!(connect_allowed(&rules, &addr("10.1.2.3:443"), tcp));
+        assert!(!connect_allowed(
+            &rules,
+            &addr("10.1.2.3:443"),
+            Some(EgressProto::Udp)
+        ));
+        assert!(!connect_allowed(&rules, &addr("11.1.2.3:443"), tcp));
+        assert!(!connect_allowed(&rules, &addr("10.1.2.3:80"), tcp));
+        assert!(connect_allowed(
+            &rules,
+            &addr("[fd12::1]:53"),
+            Some(EgressProto::Udp)
+        ));
+        assert!(!connect_allowed(&rules, &addr("[fd12::1]:53"), None));
+
+        // 10.1.2.3 through an IPv4-mapped IPv6 address
+        let mut buf = [0u8; 28];
+        buf[..2].copy_from_slice(&(libc::AF_INET6 as u16).to_ne_bytes());
+        buf[2..4].copy_from_slice(&443u16.to_be_bytes());
+        buf[18..24].copy_from_slice(&[0xff, 0xff, 10, 1, 2, 3]);
+        assert_eq!(parse_sockaddr(&buf), Some(addr("10.1.2.3:443")));
+        buf[..2].copy_from_slice(&(libc::AF_UNIX as u16).to_ne_bytes());
+        assert_eq!(parse_sockaddr(&buf), None);
+    }
+
+    #[test]
+    fn test_supervise_connect() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let allowed = server.local_addr().unwrap();
+        let cfg = SeccompNotifyConfig {
+            mounts: Vec::new(),
+            connect: vec![parse_egress_rule(&format!("127.0.0.1,tcp,{}", allowed.port())).unwrap()],
+        };
+
+        let (tx, rx) = mpsc::channel();
+        // the filter only applies to the thread that loads it, the test thread supervises it
+        let supervised = thread::spawn(move || {
+            let filter = compile_policy("USER_NOTIF { connect } DEFAULT ALLOW").unwrap();
+            tx.send(SeccompFilter::apply_with_listener(filter).unwrap())
+                .unwrap();
+            let denied = TcpStream::connect("127.0.0.1:1").map_err(|e| e.raw_os_error());
+            let stream = TcpStream::connect(allowed).map(|s| s.peer_addr().unwrap());
+            (denied.unwrap_err(), stream.unwrap())
+        });
+
+        let listener = unsafe { Listener::from_raw_fd(rx.recv().unwrap()) };
+        for _ in 0..2 {
+            listener.handle_next(|l, n| decide(&cfg, l, n)).unwrap();
+        }
+        assert_eq!(supervised.join().unwrap(), (Some(libc::EPERM), allowed));
+        assert!(server.accept().is_ok());
+    }
+
+    #[test]
+    fn test_supervise_kill() {
+        let mut filter = policy::parse_policy("KILL { getppid } DEFAULT ALLOW").unwrap();
+        let mut fds = [0; 2];
+        assert_eq!(
+            unsafe { libc::socketpair(libc::AF_UNIX, libc::SOCK_STREAM, 0, fds.as_mut_ptr()) },
+            0
+        );
+        let mut jconf = JailConf {
+            passed_admin_child_fd: fds[1],
+            seccomp_notify: Some(SeccompNotifyConfig {
+                mounts: Vec::new(),
+                connect: Vec::new(),
+            }),
+            seccomp_kill_filter: Some(filter.clone()),
+            ..Default::default()
+        };
+        filter.notify_kills();
+        let filter: BpfProgram = filter.try_into().unwrap();
+
+        // the supervisor kills the whole process, not a thread of the tests
+        let pid = unsafe { libc::fork() };
+        if pid == 0 {
+            let listener = SeccompFilter::apply_with_listener(filter).unwrap();
+            send_from_child(&jconf, listener).unwrap();
+            unsafe {
+                libc::syscall(libc::SYS_getppid, 1, 2, 3);
+                libc::_exit(0);
+            }
+        }
+
+        let (_, listener) = socket::recvmsg_fd(fds[0], &mut [0]).unwrap();
+        start_from_parent(&mut jconf, listener.unwrap()).unwrap();
+        handle(&mut jconf).unwrap();
+        let mut status = 0;
+        assert_eq!(unsafe { libc::waitpid(pid, &mut status, 0) }, pid);
+        assert!(libc::WIFSIGNALED(status) && libc::WTERMSIG(status) == libc::SIGKILL);
+        finish_from_parent(&jconf);
+        unsafe {
+            libc::close(fds[0]);
+            libc::close(fds[1]);
+        }
+
+        let violation = jconf.seccomp_violation.unwrap();
+        assert_eq!((violation.pid, violation.nr), (pid, libc::SYS_getppid));
+        assert_eq!(violation.name, Some("getppid"));
+        assert_eq!(violation.args.unwrap()[..3], [1, 2, 3]);
+    }
+
+    #[test]
+    fn test_supervise_learn() {
+        let path =
+            std::env::temp_dir().join(format!("seccomp-learn-{}.policy", std::process::id()));
+        let mut jconf = JailConf {
+            passed_admin_child_fd: 42,
+            seccomp_learn: path.to_str().unwrap(),
+            ..Default::default()
+        };
+        assert!(learning_filter(&jconf).is_ok());
+
+        let (tx, rx) = mpsc::channel();
+        let supervised = thread::spawn(move || {
+            let filter = compile_policy("USER_NOTIF { getppid, socket } DEFAULT ALLOW").unwrap();
+            tx.send(SeccompFilter::apply_with_listener(filter).unwrap())
+                .unwrap();
+            unsafe {
+                libc::getppid();
+                libc::close(libc::socket(libc::AF_INET, libc::SOCK_STREAM, 0));
+            }
+        });
+
+        start_from_parent(&mut jconf, rx.recv().unwrap()).unwrap();
+        for _ in 0..2 {
+            handle(&mut jconf).unwrap();
+        }
+        supervised.join().unwrap();
+
+        // written once, when the jail is done
+        assert!(!path.exists());
+        finish_from_parent(&jconf);
+        let policy = fs::read_to_string(&path).unwrap();
+        assert!(policy.contains("getppid,"), "{}", policy);
+        assert!(policy.contains("(domain == 0x2 && type == 0x1 && protocol == 0x0)"));
+        assert!(seccomp::policy::parse_policy_file(&path).is_ok());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,440 @@
+// Resource usage of a jail once it exited, for billing. clean_after_child collects it from the cgroups of the jail and
+// from its NET namespace before they are removed, the monitor completes it with the rusage of wait4
+
+use super::cgroupv2::{self, parse_flat_keyed};
+use super::config::JailConf;
+use super::error::Result;
+use super::limits;
+
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+use sys_util::fcntl;
+use sys_util::sched::setns;
+
+/**
+ * What a jail used. A value the host could not measure is 0: a cgroup v1 jail only has the controllers of its limits
+ * and no blkio one, and the io.stat of cgroup v2 needs the io controller, enabled only with an io limit
+ */
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ResourceUsage {
+    pub wall_time: Duration,
+    pub user_time: Duration,
+    pub system_time: Duration,
+    pub mem_peak: u64, // bytes
+    pub io_read_bytes: u64,
+    pub io_write_bytes: u64,
+    pub net_rx_bytes: u64, // received by the interfaces of the jail, loopback excluded
+    pub net_tx_bytes: u64,
+    pub oom_killed: bool,
+    pub pids_max_hits: u64, // forks that failed because of pids.max
+}
+
+impl ResourceUsage {
+    /// Usage from the rusage of wait4, i.e. of the jail init and the processes it reaped, ru_maxrss is the peak of the
+    /// biggest one of them only
+    pub fn from_rusage(ru: &libc::rusage) -> ResourceUsage {
+        ResourceUsage {
+            user_time: timeval_to_duration(&ru.ru_utime),
+            system_time: timeval_to_duration(&ru.ru_stime),
+            mem_peak: ru.ru_maxrss.max(0) as u64 * 1024,
+            ..Default::default()
+        }
+    }
+
+    /// Takes every value other measured, for the cgroup values to replace the rusage ones
+    pub fn update(&mut self, other: &ResourceUsage) {
+        for (dst, src) in [
+            (&mut self.wall_time, other.wall_time),
+            (&mut self.user_time, other.user_time),
+            (&mut self.system_time, other.system_time),
+        ]
+        .iter_mut()
+        {
+            if *src != Duration::from_secs(0) {
+                **dst = *src;
+            }
+        }
+        for (dst, src) in [
+            (&mut self.mem_peak, other.mem_peak),
+            (&mut self.io_read_bytes, other.io_read_bytes),
+            (&mut self.io_write_bytes, other.io_write_bytes),
+            (&mut self.net_rx_bytes, other.net_rx_bytes),
+            (&mut self.net_tx_bytes, other.net_tx_bytes),
+            (&mut self.pids_max_hits, other.pids_max_hits),
+        ]
+        .iter_mut()
+        {
+            if *src != 0 {
+                **dst = *src;
+            }
+        }
+        self.oom_killed |= other.oom_killed;
+    }
+}
+
+impl fmt::Display for ResourceUsage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "wall {:.3}s, user {:.3}s, system {:.3}s, memory peak {}B, io {}B read {}B written, net {}B received {}B sent",
+            self.wall_time.as_secs_f64(),
+            self.user_time.as_secs_f64(),
+            self.system_time.as_secs_f64(),
+            self.mem_peak,
+            self.io_read_bytes,
+            self.io_write_bytes,
+            self.net_rx_bytes,
+            self.net_tx_bytes
+        )?;
+        if self.pids_max_hits > 0 {
+            write!(f, ", {} forks refused by pids.max", self.pids_max_hits)?;
+        }
+        if self.oom_killed {
+            write!(f, ", OOM killed")?;
+        }
+        Ok(())
+    }
+}
+
+fn timeval_to_duration(tv: &libc::timeval) -> Duration {
+    Duration::from_secs(tv.tv_sec.max(0) as u64) + Duration::from_micros(tv.tv_usec.max(0) as u64)
+}
+
+/// Keeps the NET namespace of the child open in jconf.net_ns_fd, so that it outlives the jail until clean_after_child
+pub fn init_ns_from_parent(jconf: &mut JailConf, pid: libc::pid_t) -> Result<()> {
+    if !jconf.clone_newnet {
+        return Ok(());
+    }
+    let fd = fcntl::open(
+        &format!("/proc/{}/ns/net", pid),
+        libc::O_RDONLY | libc::O_CLOEXEC,
+        0,
+    )
+    .map_err(|e| ("could not open the NET namespace of the child", e))?;
+    jconf.net_ns_fd = Some(fd);
+    Ok(())
+}
+
+/// What the jail pid used, to call before its cgroups are removed. Files that cannot be read leave their values to 0
+pub fn collect(jconf: &JailConf, pid: &str) -> ResourceUsage {
+    let mut usage = ResourceUsage::default();
+    if let Some(start) = jconf.child_start {
+        usage.wall_time = start.elapsed();
+    }
+
+    let hits = limits::read_hits(jconf, pid);
+    usage.oom_killed = hits.oom_kills > 0;
+    usage.pids_max_hits = hits.pids_max;
+
+    if jconf.clone_newcgroup {
+        if jconf.use_cgroupv2 {
+            if cgroupv2::has_cgroup(jconf) {
+                collect_v2(&jconf.get_cgroup_v2_path(pid), &mut usage);
+            }
+        } else {
+            collect_v1(jconf, pid, &mut usage);
+        }
+    }
+
+    if let Some(fd) = jconf.net_ns_fd {
+        if let Some((rx, tx)) = read_net_dev(fd) {
+            usage.net_rx_bytes = rx;
+            usage.net_tx_bytes = tx;
+        }
+    }
+
+    usage
+}
+
+fn read_cgroup_file(dir: &Path, file: &str) -> Option<String> {
+    fs::read_to_string(dir.join(file)).ok()
+}
+
+fn collect_v2(dir: &Path, usage: &mut ResourceUsage) {
+    // cpu.stat is there even without the cpu controller
+    if let Some(stat) = read_cgroup_file(dir, "cpu.stat") {
+        usage.user_time = Duration::from_micros(parse_flat_keyed(&stat, "user_usec").unwrap_or(0));
+        usage.system_time =
+            Duration::from_micros(parse_flat_keyed(&stat, "system_usec").unwrap_or(0));
+    }
+    // memory.peak needs linux 5.19
+    if let Some(peak) = read_cgroup_file(dir, "memory.peak") {
+        usage.mem_peak = peak.trim().parse().unwrap_or(0);
+    }
+    if let Some(stat) = read_cgroup_file(dir, "io.stat") {
+        let (read, written) = parse_io_stat(&stat);
+        usage.io_read_bytes = read;
+        usage.io_write_bytes = written;
+    }
+}
+
+fn collect_v1(jconf: &JailConf, pid: &str, usage: &mut ResourceUsage) {
+    if jconf.cgroup_mem_max != 0 {
+        let dir = jconf.get_mem_cgroup_v1_path(pid);
+        if let Some(peak) = read_cgroup_file(&dir, "memory.max_usage_in_bytes") {
+            usage.mem_peak = peak.trim().parse().unwrap_or(0);
+        }
+    }
+
+    // only when cpuacct is mounted along with cpu, as in /sys/fs/cgroup/cpu,cpuacct
+    if jconf.cgroup_cpu_ms_per_sec != 0 {
+        let dir = jconf.get_cpu_cgroup_v1_path(pid);
+        if let Some(stat) = read_cgroup_file(&dir, "cpuacct.stat") {
+            let hz = unsafe { libc::sysconf(libc::_SC_CLK_TCK) }.max(1) as u64;
+            let ticks = |key| parse_flat_keyed(&stat, key).unwrap_or(0);
+            usage.user_time = Duration::from_micros(ticks("user") * 1_000_000 / hz);
+            usage.system_time = Duration::from_micros(ticks("system") * 1_000_000 / hz);
+        }
+    }
+}
+
+/// What a running jail is using right now, see live_metrics
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LiveMetrics {
+    pub cpu_time: Duration, // user and system
+    pub mem_current: u64,   // bytes
+    pub pids_current: u64,  // threads, like the pids.current of the pids cgroup
+    pub uptime: Duration,
+}
+
+/**
+ * Current usage of the running jail pid. The cgroups of the jail give the values of all its processes, the processes
+ * that /proc shows under pid are summed up for the values without cgroup: their cpu time, including the one of the
+ * children they reaped, and their resident memory
+ */
+pub fn live_metrics(jconf: &JailConf, pid: libc::pid_t) -> LiveMetrics {
+    let mut metrics = LiveMetrics::default();
+    if let Some(start) = jconf.child_start {
+        metrics.uptime = start.elapsed();
+    }
+
+    let hz = unsafe { libc::sysconf(libc::_SC_CLK_TCK) }.max(1) as u64;
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) }.max(1) as u64;
+    let (tree, threads) = proc_tree(pid);
+    let (mut ticks, mut rss_pages) = (0, 0);
+    for p in tree.iter() {
+        if let Some((t, rss)) = fs::read_to_string(format!("/proc/{}/stat", p))
+            .ok()
+            .and_then(|stat| parse_proc_stat(&stat))
+        {
+            ticks += t;
+            rss_pages += rss;
+        }
+    }
+    metrics.cpu_time = Duration::from_micros(ticks * 1_000_000 / hz);
+    metrics.mem_current = rss_pages * page_size;
+    metrics.pids_current = threads;
+
+    if !jconf.clone_newcgroup {
+        return metrics;
+    }
+    let pid = pid.to_string();
+    let read_u64 = |dir: &Path, file: &str| {
+        read_cgroup_file(dir, file).and_then(|c| c.trim().parse::<u64>().ok())
+    };
+    if jconf.use_cgroupv2 {
+        if !cgroupv2::has_cgroup(jconf) {
+            return metrics;
+        }
+        let dir = jconf.get_cgroup_v2_path(&pid);
+        if let Some(usec) =
+            read_cgroup_file(&dir, "cpu.stat").and_then(|s| parse_flat_keyed(&s, "usage_usec"))
+        {
+            metrics.cpu_time = Duration::from_micros(usec);
+        }
+        if let Some(mem) = read_u64(&dir, "memory.current") {
+            metrics.mem_current = mem;
+        }
+        if let Some(pids) = read_u64(&dir, "pids.current") {
+            metrics.pids_current = pids;
+        }
+    } else {
+        if jconf.cgroup_cpu_ms_per_sec != 0 {
+            if let Some(ns) = read_u64(&jconf.get_cpu_cgroup_v1_path(&pid), "cpuacct.usage") {
+                metrics.cpu_time = Duration::from_nanos(ns);
+            }
+        }
+        if jconf.cgroup_mem_max != 0 {
+            if let Some(mem) =
+                read_u64(&jconf.get_mem_cgroup_v1_path(&pid), "memory.usage_in_bytes")
+            {
+                metrics.mem_current = mem;
+            }
+        }
+        if jconf.cgroup_pids_max != 0 {
+            if let Some(pids) = read_u64(&jconf.get_pids_cgroup_v1_path(&pid), "pids.current") {
+                metrics.pids_current = pids;
+            }
+        }
+    }
+    metrics
+}
+
+/// pid and all its descendants, from the /proc/<pid>/task/<tid>/children of linux 4.2, and the number of their threads
+fn proc_tree(pid: libc::pid_t) -> (Vec<libc::pid_t>, u64) {
+    let mut tree = vec![pid];
+    let mut threads = 0;
+    let mut i = 0;
+    while i < tree.len() {
+        let tasks = match fs::read_dir(format!("/proc/{}/task", tree[i])) {
+            Ok(tasks) => tasks,
+            Err(_) => {
+                i += 1;
+                continue;
+            }
+        };
+        for task in tasks.flatten() {
+            threads += 1;
+            if let Ok(children) = fs::read_to_string(task.path().join("children")) {
+                tree.extend(
+                    children
+                        .split_whitespace()
+                        .filter_map(|c| c.parse::<libc::pid_t>().ok()),
+                );
+            }
+        }
+        i += 1;
+    }
+    (tree, threads)
+}
+
+/// utime + stime + cutime + cstime in clock ticks and the rss in pages of a /proc/<pid>/stat
+pub fn parse_proc_stat(content: &str) -> Option<(u64, u64)> {
+    // the command name, second field, may contain spaces and parentheses, the third field follows the last ')'
+    let fields: Vec<&str> = content[content.rfind(')')? + 1..]
+        .split_whitespace()
+        .collect();
+    let field = |n: usize| fields.get(n - 3).and_then(|f| f.parse::<i64>().ok());
+    let ticks = field(14)? + field(15)? + field(16)? + field(17)?;
+    Some((ticks.max(0) as u64, field(24)?.max(0) as u64))
+}
+
+/// Bytes read and written over all the devices of a cgroup v2 io.stat, made of "<maj:min> rbytes=<n> wbytes=<n> ..." lines
+pub fn parse_io_stat(content: &str) -> (u64, u64) {
+    let (mut read, mut written) = (0, 0);
+    for field in content.lines().flat_map(|l| l.split_whitespace().skip(1)) {
+        let mut kv = field.splitn(2, '=');
+        let (key, val) = match (kv.next(), kv.next().and_then(|v| v.parse::<u64>().ok())) {
+            (Some(key), Some(val)) => (key, val),
+            _ => continue,
+        };
+        match key {
+            "rbytes" => read += val,
+            "wbytes" => written += val,
+            _ => {}
+        }
+    }
+    (read, written)
+}
+
+/// Bytes received and sent by every interface of a /proc/net/dev but the loopback
+pub fn parse_net_dev(content: &str) -> (u64, u64) {
+    let (mut rx, mut tx) = (0, 0);
+    // two header lines, then "<iface>: <8 receive counters> <8 transmit counters>", bytes first
+    for line in content.lines().skip(2) {
+        let mut parts = line.splitn(2, ':');
+        let (iface, counters) = match (parts.next(), parts.next()) {
+            (Some(iface), Some(counters)) => (iface.trim(), counters),
+            _ => continue,
+        };
+        if iface == "lo" {
+            continue;
+        }
+        let counters: Vec<u64> = counters
+            .split_whitespace()
+            .map(|c| c.parse().unwrap_or(0))
+            .collect();
+        if counters.len() >= 9 {
+            rx += counters[0];
+            tx += counters[8];
+        }
+    }
+    (rx, tx)
+}
+
+/**
+ * Interface counters of the NET namespace ns_fd, read from /proc/thread-self/net/dev by a thread that joins it. The
+ * thread exits with the namespace instead of going back to the one of the monitor, which could fail
+ */
+fn read_net_dev(ns_fd: libc::c_int) -> Option<(u64, u64)> {
+    let reader = thread::Builder::new().spawn(move || {
+        setns(ns_fd, libc::CLONE_NEWNET).ok()?;
+        fs::read_to_string("/proc/thread-self/net/dev").ok()
+    });
+    let content = reader.ok()?.join().ok()??;
+    Some(parse_net_dev(&content))
+}
+
+/// Closes jconf.net_ns_fd, the NET namespace of the jail is then freed
+pub fn finish_from_parent(jconf: &JailConf) {
+    if let Some(fd) = jconf.net_ns_fd {
+        unsafe { libc::close(fd) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_counters() {
+        let net_dev = "\
+Inter-|   Receive                                                |  Transmit
+ face |bytes    packets errs drop fifo frame compressed multicast|bytes    packets errs drop fifo colls carrier compressed
+    lo:    1000      10    0    0    0     0          0         0     1000      10    0    0    0     0       0          0
+  eth0:    4096      12    0    0    0     0          0         0      512       4    0    0    0     0       0          0
+ vs_1:      100       1    0    0    0     0          0         0       50       1    0    0    0     0       0          0
+";
+        assert_eq!(parse_net_dev(net_dev), (4196, 562));
+
+        let io_stat = "8:0 rbytes=4096 wbytes=8192 rios=1 wios=2 dbytes=0 dios=0\n\
+                       259:0 rbytes=100 wbytes=0 rios=1 wios=0 dbytes=0 dios=0\n";
+        assert_eq!(parse_io_stat(io_stat), (4196, 8192));
+        assert_eq!(parse_io_stat(""), (0, 0));
+    }
+
+    #[test]
+    fn test_proc_stat() {
+        let stat =
+            "4242 (my (weird) cmd) S 1 4242 4242 0 -1 4194560 500 0 0 0 120 30 7 3 20 0 1 0 \
+                    100 10485760 512 18446744073709551615 1 1 0 0 0 0 0 0 0 0 0 0 17 0 0 0 0 0 0";
+        assert_eq!(parse_proc_stat(stat), Some((160, 512)));
+        assert_eq!(parse_proc_stat("4242 (cmd"), None);
+
+        let jconf = JailConf::default();
+        let me = unsafe { libc::getpid() };
+        let metrics = live_metrics(&jconf, me);
+        assert!(metrics.pids_current >= 1 && metrics.mem_current > 0);
+    }
+
+    #[test]
+    fn test_update() {
+        let mut ru: libc::rusage = unsafe { std::mem::zeroed() };
+        ru.ru_utime.tv_sec = 1;
+        ru.ru_stime.tv_usec = 500;
+        ru.ru_maxrss = 2048;
+
+        let mut usage = ResourceUsage::from_rusage(&ru);
+        assert_eq!(usage.user_time, Duration::from_secs(1));
+        assert_eq!(usage.mem_peak, 2048 * 1024);
+
+        // the cgroup counts every process of the jail, not only the biggest one
+        usage.update(&ResourceUsage {
+            wall_time: Duration::from_secs(3),
+            mem_peak: 1 << 26,
+            net_rx_bytes: 10,
+            oom_killed: true,
+            ..Default::default()
+        });
+        assert_eq!(usage.wall_time, Duration::from_secs(3));
+        assert_eq!(usage.user_time, Duration::from_secs(1));
+        assert_eq!(usage.system_time, Duration::from_micros(500));
+        assert_eq!(usage.mem_peak, 1 << 26);
+        assert!(usage.oom_killed);
+        assert!(usage.to_string().ends_with("OOM killed"));
+    }
+}
@@ -41,8 +41,6 @@ const SECBIT_NO_SETUID_FIXUP: libc::c_int = 1 << SECURE_NO_SETUID_FIXUP;
 // const SECBIT_NO_SETUID_FIXUP_LOCKED: libc::c_int = 1 << SECURE_NO_SETUID_FIXUP_LOCKED;
 
 pub fn set_res_gid(gid: libc::gid_t) -> Result<()> {
-    // let sysno = cpp_bindings::get_set_res_gid_syscall_number();
-
     if unsafe {
         libc::syscall(
             __NR_setresgid as i64,
@@ -59,8 +57,6 @@ pub fn set_res_gid(gid: libc::gid_t) -> Result<()> {
 }
 
 pub fn set_res_uid(uid: libc::uid_t) -> Result<()> {
-    // let sysno = cpp_bindings::get_set_res_uid_syscall_number();
-
     if unsafe { libc::syscall(__NR_setresuid as i64, uid, uid, uid) } == -1 {
         return Err(format!("set_res_uid: {}", Errno::last()).into());
     }
@@ -0,0 +1,205 @@
+// User-mode networking, for jails started by an unprivileged user who cannot create macvlan or veth interfaces on the
+// host. The child creates a tap device in its own NET namespace, where it has CAP_NET_ADMIN through its user namespace,
+// and passes the tap fd to the parent. A thread of the parent then runs a small TCP/IP stack on it, see stack.rs, and
+// replays what the jail sends with ordinary sockets, so the jail has the network access of the user that started it
+
+pub mod packet;
+pub mod stack;
+
+use super::config::{JailConf, UserNetConfig};
+use super::error::Result;
+
+use std::fs;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::thread;
+
+use stack::{Stack, STOP_TOKEN, TAP_TOKEN};
+use sys_util::rtnetlink::RtNetlink;
+use sys_util::EventFd;
+use sys_util::{fcntl, socket, uio::IoVec};
+
+pub const TAP: &str = "tap0";
+const TUN_PATH: &str = "/dev/net/tun";
+const RESOLV_CONF: &str = "/etc/resolv.conf";
+
+/// Byte the child sends to the parent along with the tap fd, see subproc::run_child_listen_fd
+pub const TAP_MESSAGE: u8 = b'T';
+
+// from linux/if_tun.h
+const TUNSETIFF: libc::c_ulong = 0x4004_54ca;
+const IFF_TAP: libc::c_short = 0x0002;
+const IFF_NO_PI: libc::c_short = 0x1000;
+
+#[repr(C)]
+struct IfReq {
+    name: [u8; libc::IFNAMSIZ],
+    flags: libc::c_short,
+    _pad: [u8; 22],
+}
+
+fn open_tap(name: &str) -> Result<RawFd> {
+    let fd = fcntl::open(
+        TUN_PATH,
+        libc::O_RDWR | libc::O_NONBLOCK | libc::O_CLOEXEC,
+        0,
+    )
+    .map_err(|e| format!("could not open {}: {}", TUN_PATH, e))?;
+
+    let mut req = IfReq {
+        name: [0; libc::IFNAMSIZ],
+        flags: IFF_TAP | IFF_NO_PI,
+        _pad: [0; 22],
+    };
+    req.name[..name.len()].copy_from_slice(name.as_bytes());
+    if unsafe { libc::ioctl(fd, TUNSETIFF as _, &mut req) } < 0 {
+        let err = io::Error::last_os_error();
+        unsafe { libc::close(fd) };
+        return Err(format!("could not create the tap device {}: {}", name, err).into());
+    }
+
+    Ok(fd)
+}
+
+/**
+ * Creates and configures the tap device of the jail then sends it to the parent. Runs in the child, in its NET
+ * namespace and before it is executed
+ */
+pub fn init_ns_from_child(jconf: &JailConf, nl: &mut RtNetlink) -> Result<()> {
+    let cfg = match jconf.user_net {
+        Some(ref cfg) => cfg,
+        None => return Ok(()),
+    };
+
+    // the tap fd is non-blocking for the stack of the parent, the flag belongs to the open file and is sent with it
+    let fd = open_tap(TAP)?;
+    nl.set_link_up(TAP)?;
+    nl.add_address(TAP, IpAddr::V4(cfg.ip), cfg.prefix_len)?;
+    nl.add_default_route(TAP, IpAddr::V4(cfg.gateway))?;
+
+    let res = socket::sendmsg(
+        jconf.passed_admin_child_fd,
+        &[IoVec::from_slice(&[TAP_MESSAGE])],
+        fd,
+        0,
+    );
+    unsafe { libc::close(fd) };
+    res.map_err(|e| format!("could not send the tap device to the parent: {}", e))?;
+
+    Ok(())
+}
+
+/// The first IPv4 nameserver of resolv.conf
+fn parse_nameserver(resolv_conf: &str) -> Option<Ipv4Addr> {
+    resolv_conf.lines().find_map(|line| {
+        let mut words = line.split_whitespace();
+        match (words.next(), words.next()) {
+            (Some("nameserver"), Some(addr)) => addr.parse().ok(),
+            _ => None,
+        }
+    })
+}
+
+fn run(mut stack: Stack, tap: RawFd, stop: EventFd) {
+    let mut buf = [0u8; 65536];
+    'outer: loop {
+        let ready = match stack.poll(-1) {
+            Ok(ready) => ready,
+            Err(e) => {
+                println!("user-mode network stopped: {}", e);
+                break;
+            }
+        };
+        if ready.contains(&STOP_TOKEN) {
+            break;
+        }
+        if ready.contains(&TAP_TOKEN) {
+            loop {
+                let n =
+                    unsafe { libc::read(tap, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+                if n <= 0 {
+                    if n < 0 && io::Error::last_os_error().kind() == io::ErrorKind::WouldBlock {
+                        break;
+                    }
+                    // the device is gone with the NET namespace of the jail
+                    break 'outer;
+                }
+                stack.handle_frame(&buf[..n as usize]);
+            }
+        }
+        for frame in stack.take_frames() {
+            // like on a wire, a frame the jail is too slow to take is lost and TCP sends it again
+            unsafe { libc::write(tap, frame.as_ptr() as *const libc::c_void, frame.len()) };
+        }
+    }
+
+    unsafe { libc::close(tap) };
+    drop(stop);
+}
+
+/**
+ * Starts the stack of the jail on the tap fd received from the child, in a thread of the parent which owns tap from
+ * now on. It runs until finish_from_parent
+ */
+pub fn start_from_parent(jconf: &mut JailConf, tap: RawFd) -> Result<()> {
+    let cfg: UserNetConfig = match jconf.user_net {
+        Some(ref cfg) => cfg.clone(),
+        None => {
+            unsafe { libc::close(tap) };
+            return Err("received a tap device but no user-mode network is configured".into());
+        }
+    };
+
+    let started = (|| -> Result<EventFd> {
+        let nameserver = fs::read_to_string(RESOLV_CONF)
+            .ok()
+            .and_then(|s| parse_nameserver(&s));
+
+        let stop = EventFd::new()?;
+        let thread_stop = stop.try_clone()?;
+        let mut stack = Stack::new(cfg, nameserver)?;
+        stack.watch(tap, TAP_TOKEN)?;
+        stack.watch(thread_stop.as_raw_fd(), STOP_TOKEN)?;
+        thread::Builder::new()
+            .name("usernet".to_string())
+            .spawn(move || run(stack, tap, thread_stop))?;
+        Ok(stop)
+    })();
+
+    match started {
+        Ok(stop) => {
+            jconf.user_net_fd = Some(stop.as_raw_fd());
+            // closed by finish_from_parent
+            std::mem::forget(stop);
+            Ok(())
+        }
+        Err(e) => {
+            unsafe { libc::close(tap) };
+            Err(format!("could not start the user-mode network: {}", e).into())
+        }
+    }
+}
+
+/// Stops the stack of the jail, which closes its tap device and every connection it still forwards
+pub fn finish_from_parent(jconf: &JailConf) {
+    if let Some(fd) = jconf.user_net_fd {
+        let one: u64 = 1;
+        unsafe {
+            libc::write(fd, &one as *const u64 as *const libc::c_void, 8);
+            libc::close(fd);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_nameserver() {
+        let conf = "# generated\nsearch lan\nnameserver fe80::1\nnameserver 127.0.0.53\nnameserver 1.1.1.1\n";
+        assert_eq!(parse_nameserver(conf), Some(Ipv4Addr::new(127, 0, 0, 53)));
+        assert_eq!(parse_nameserver("search lan\n"), None);
+    }
+}
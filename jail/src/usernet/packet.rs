@@ -0,0 +1,425 @@
+// Ethernet, ARP, IPv4, ICMP, UDP and TCP headers, only what the user-mode network stack reads and writes.
+// Options other than the TCP MSS are ignored, IPv4 fragments are not supported.
+
+use std::net::Ipv4Addr;
+
+pub const ETH_HDR_LEN: usize = 14;
+pub const IPV4_HDR_LEN: usize = 20;
+pub const UDP_HDR_LEN: usize = 8;
+pub const TCP_HDR_LEN: usize = 20;
+pub const ARP_LEN: usize = 28;
+
+pub const ETH_P_IP: u16 = 0x0800;
+pub const ETH_P_ARP: u16 = 0x0806;
+
+pub const IPPROTO_ICMP: u8 = 1;
+pub const IPPROTO_TCP: u8 = 6;
+pub const IPPROTO_UDP: u8 = 17;
+
+pub const ARP_REQUEST: u16 = 1;
+pub const ARP_REPLY: u16 = 2;
+
+pub const ICMP_ECHO_REPLY: u8 = 0;
+pub const ICMP_ECHO_REQUEST: u8 = 8;
+
+pub const TCP_FIN: u8 = 0x01;
+pub const TCP_SYN: u8 = 0x02;
+pub const TCP_RST: u8 = 0x04;
+pub const TCP_PSH: u8 = 0x08;
+pub const TCP_ACK: u8 = 0x10;
+
+const TCP_OPT_END: u8 = 0;
+const TCP_OPT_NOP: u8 = 1;
+const TCP_OPT_MSS: u8 = 2;
+
+pub type Mac = [u8; 6];
+
+fn be16(buf: &[u8], off: usize) -> u16 {
+    u16::from_be_bytes([buf[off], buf[off + 1]])
+}
+
+fn be32(buf: &[u8], off: usize) -> u32 {
+    u32::from_be_bytes([buf[off], buf[off + 1], buf[off + 2], buf[off + 3]])
+}
+
+fn ipv4(buf: &[u8], off: usize) -> Ipv4Addr {
+    Ipv4Addr::new(buf[off], buf[off + 1], buf[off + 2], buf[off + 3])
+}
+
+/// One's complement sum of data, folded to 16 bits but not inverted
+fn sum(mut acc: u32, data: &[u8]) -> u32 {
+    let mut chunks = data.chunks_exact(2);
+    for c in chunks.by_ref() {
+        acc += u32::from(u16::from_be_bytes([c[0], c[1]]));
+    }
+    if let [last] = chunks.remainder() {
+        acc += u32::from(*last) << 8;
+    }
+    while acc > 0xffff {
+        acc = (acc & 0xffff) + (acc >> 16);
+    }
+    acc
+}
+
+/// The internet checksum of RFC 1071, a buffer that embeds its checksum sums to 0
+pub fn checksum(data: &[u8]) -> u16 {
+    !(sum(0, data) as u16)
+}
+
+/// Checksum of a TCP or UDP segment, including the IPv4 pseudo header
+pub fn l4_checksum(src: Ipv4Addr, dst: Ipv4Addr, proto: u8, segment: &[u8]) -> u16 {
+    let mut pseudo = [0u8; 12];
+    pseudo[0..4].copy_from_slice(&src.octets());
+    pseudo[4..8].copy_from_slice(&dst.octets());
+    pseudo[9] = proto;
+    pseudo[10..12].copy_from_slice(&(segment.len() as u16).to_be_bytes());
+    !(sum(sum(0, &pseudo), segment) as u16)
+}
+
+pub struct EthFrame<'a> {
+    pub dst: Mac,
+    pub src: Mac,
+    pub ethertype: u16,
+    pub payload: &'a [u8],
+}
+
+impl<'a> EthFrame<'a> {
+    pub fn parse(buf: &'a [u8]) -> Option<EthFrame<'a>> {
+        if buf.len() < ETH_HDR_LEN {
+            return None;
+        }
+        let mut dst = [0; 6];
+        let mut src = [0; 6];
+        dst.copy_from_slice(&buf[0..6]);
+        src.copy_from_slice(&buf[6..12]);
+        Some(EthFrame {
+            dst,
+            src,
+            ethertype: be16(buf, 12),
+            payload: &buf[ETH_HDR_LEN..],
+        })
+    }
+}
+
+pub fn eth_frame(dst: Mac, src: Mac, ethertype: u16, payload: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(ETH_HDR_LEN + payload.len());
+    buf.extend_from_slice(&dst);
+    buf.extend_from_slice(&src);
+    buf.extend_from_slice(&ethertype.to_be_bytes());
+    buf.extend_from_slice(payload);
+    buf
+}
+
+/// An IPv4 over Ethernet ARP packet
+pub struct Arp {
+    pub op: u16,
+    pub sha: Mac,
+    pub spa: Ipv4Addr,
+    pub tha: Mac,
+    pub tpa: Ipv4Addr,
+}
+
+impl Arp {
+    pub fn parse(buf: &[u8]) -> Option<Arp> {
+        if buf.len() < ARP_LEN
+            || be16(buf, 0) != 1
+            || be16(buf, 2) != ETH_P_IP
+            || buf[4] != 6
+            || buf[5] != 4
+        {
+            return None;
+        }
+        let mut sha = [0; 6];
+        let mut tha = [0; 6];
+        sha.copy_from_slice(&buf[8..14]);
+        tha.copy_from_slice(&buf[18..24]);
+        Some(Arp {
+            op: be16(buf, 6),
+            sha,
+            spa: ipv4(buf, 14),
+            tha,
+            tpa: ipv4(buf, 24),
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(ARP_LEN);
+        buf.extend_from_slice(&1u16.to_be_bytes());
+        buf.extend_from_slice(&ETH_P_IP.to_be_bytes());
+        buf.extend_from_slice(&[6, 4]);
+        buf.extend_from_slice(&self.op.to_be_bytes());
+        buf.extend_from_slice(&self.sha);
+        buf.extend_from_slice(&self.spa.octets());
+        buf.extend_from_slice(&self.tha);
+        buf.extend_from_slice(&self.tpa.octets());
+        buf
+    }
+}
+
+pub struct Ipv4Packet<'a> {
+    pub src: Ipv4Addr,
+    pub dst: Ipv4Addr,
+    pub proto: u8,
+    pub payload: &'a [u8],
+}
+
+impl<'a> Ipv4Packet<'a> {
+    /// None for anything malformed, with a bad header checksum or fragmented
+    pub fn parse(buf: &'a [u8]) -> Option<Ipv4Packet<'a>> {
+        if buf.len() < IPV4_HDR_LEN || buf[0] >> 4 != 4 {
+            return None;
+        }
+        let ihl = usize::from(buf[0] & 0xf) * 4;
+        let total = usize::from(be16(buf, 2));
+        if ihl < IPV4_HDR_LEN || total < ihl || total > buf.len() {
+            return None;
+        }
+        if checksum(&buf[..ihl]) != 0 {
+            return None;
+        }
+        // more fragments or a fragment offset
+        if be16(buf, 6) & 0x3fff != 0 {
+            return None;
+        }
+        Some(Ipv4Packet {
+            src: ipv4(buf, 12),
+            dst: ipv4(buf, 16),
+            proto: buf[9],
+            payload: &buf[ihl..total],
+        })
+    }
+}
+
+pub fn ipv4_packet(src: Ipv4Addr, dst: Ipv4Addr, proto: u8, payload: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(IPV4_HDR_LEN + payload.len());
+    buf.push(0x45);
+    buf.push(0);
+    buf.extend_from_slice(&((IPV4_HDR_LEN + payload.len()) as u16).to_be_bytes());
+    buf.extend_from_slice(&[0, 0, 0x40, 0]); // id, don't fragment
+    buf.push(64);
+    buf.push(proto);
+    buf.extend_from_slice(&[0, 0]);
+    buf.extend_from_slice(&src.octets());
+    buf.extend_from_slice(&dst.octets());
+    let csum = checksum(&buf[..IPV4_HDR_LEN]);
+    buf[10..12].copy_from_slice(&csum.to_be_bytes());
+    buf.extend_from_slice(payload);
+    buf
+}
+
+pub struct UdpDatagram<'a> {
+    pub src_port: u16,
+    pub dst_port: u16,
+    pub payload: &'a [u8],
+}
+
+impl<'a> UdpDatagram<'a> {
+    pub fn parse(buf: &'a [u8]) -> Option<UdpDatagram<'a>> {
+        if buf.len() < UDP_HDR_LEN {
+            return None;
+        }
+        let len = usize::from(be16(buf, 4));
+        if len < UDP_HDR_LEN || len > buf.len() {
+            return None;
+        }
+        Some(UdpDatagram {
+            src_port: be16(buf, 0),
+            dst_port: be16(buf, 2),
+            payload: &buf[UDP_HDR_LEN..len],
+        })
+    }
+}
+
+pub fn udp_datagram(
+    src: Ipv4Addr,
+    src_port: u16,
+    dst: Ipv4Addr,
+    dst_port: u16,
+    payload: &[u8],
+) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(UDP_HDR_LEN + payload.len());
+    buf.extend_from_slice(&src_port.to_be_bytes());
+    buf.extend_from_slice(&dst_port.to_be_bytes());
+    buf.extend_from_slice(&((UDP_HDR_LEN + payload.len()) as u16).to_be_bytes());
+    buf.extend_from_slice(&[0, 0]);
+    buf.extend_from_slice(payload);
+    let csum = match l4_checksum(src, dst, IPPROTO_UDP, &buf) {
+        0 => 0xffff, // 0 means no checksum for UDP
+        c => c,
+    };
+    buf[6..8].copy_from_slice(&csum.to_be_bytes());
+    buf
+}
+
+pub struct TcpSegment<'a> {
+    pub src_port: u16,
+    pub dst_port: u16,
+    pub seq: u32,
+    pub ack: u32,
+    pub flags: u8,
+    pub window: u16,
+    pub mss: Option<u16>,
+    pub payload: &'a [u8],
+}
+
+impl<'a> TcpSegment<'a> {
+    pub fn parse(buf: &'a [u8]) -> Option<TcpSegment<'a>> {
+        if buf.len() < TCP_HDR_LEN {
+            return None;
+        }
+        let off = usize::from(buf[12] >> 4) * 4;
+        if off < TCP_HDR_LEN || off > buf.len() {
+            return None;
+        }
+
+        let mut mss = None;
+        let mut opts = &buf[TCP_HDR_LEN..off];
+        while let Some(&kind) = opts.first() {
+            match kind {
+                TCP_OPT_END => break,
+                TCP_OPT_NOP => opts = &opts[1..],
+                _ => {
+                    let len = usize::from(*opts.get(1)?);
+                    if len < 2 || len > opts.len() {
+                        return None;
+                    }
+                    if kind == TCP_OPT_MSS && len == 4 {
+                        mss = Some(be16(opts, 2));
+                    }
+                    opts = &opts[len..];
+                }
+            }
+        }
+
+        Some(TcpSegment {
+            src_port: be16(buf, 0),
+            dst_port: be16(buf, 2),
+            seq: be32(buf, 4),
+            ack: be32(buf, 8),
+            flags: buf[13],
+            window: be16(buf, 14),
+            mss,
+            payload: &buf[off..],
+        })
+    }
+
+    /// Sequence space taken by the segment, SYN and FIN count for one
+    pub fn seq_len(&self) -> u32 {
+        let mut len = self.payload.len() as u32;
+        if self.flags & TCP_SYN != 0 {
+            len += 1;
+        }
+        if self.flags & TCP_FIN != 0 {
+            len += 1;
+        }
+        len
+    }
+}
+
+/// Builds a TCP segment, with an MSS option when mss is given, which is only valid on SYN segments
+#[allow(clippy::too_many_arguments)]
+pub fn tcp_segment(
+    src: Ipv4Addr,
+    src_port: u16,
+    dst: Ipv4Addr,
+    dst_port: u16,
+    seq: u32,
+    ack: u32,
+    flags: u8,
+    window: u16,
+    mss: Option<u16>,
+    payload: &[u8],
+) -> Vec<u8> {
+    let hdr_len = if mss.is_some() {
+        TCP_HDR_LEN + 4
+    } else {
+        TCP_HDR_LEN
+    };
+    let mut buf = Vec::with_capacity(hdr_len + payload.len());
+    buf.extend_from_slice(&src_port.to_be_bytes());
+    buf.extend_from_slice(&dst_port.to_be_bytes());
+    buf.extend_from_slice(&seq.to_be_bytes());
+    buf.extend_from_slice(&ack.to_be_bytes());
+    buf.push(((hdr_len / 4) as u8) << 4);
+    buf.push(flags);
+    buf.extend_from_slice(&window.to_be_bytes());
+    buf.extend_from_slice(&[0, 0, 0, 0]); // checksum, urgent pointer
+    if let Some(mss) = mss {
+        buf.extend_from_slice(&[TCP_OPT_MSS, 4]);
+        buf.extend_from_slice(&mss.to_be_bytes());
+    }
+    buf.extend_from_slice(payload);
+    let csum = l4_checksum(src, dst, IPPROTO_TCP, &buf);
+    buf[16..18].copy_from_slice(&csum.to_be_bytes());
+    buf
+}
+
+/// An ICMP echo reply to an echo request, None if request is anything else
+pub fn icmp_echo_reply(request: &[u8]) -> Option<Vec<u8>> {
+    if request.len() < 8 || request[0] != ICMP_ECHO_REQUEST || checksum(request) != 0 {
+        return None;
+    }
+    let mut reply = request.to_vec();
+    reply[0] = ICMP_ECHO_REPLY;
+    reply[2..4].copy_from_slice(&[0, 0]);
+    let csum = checksum(&reply);
+    reply[2..4].copy_from_slice(&csum.to_be_bytes());
+    Some(reply)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip_and_checksums() {
+        let src = Ipv4Addr::new(10, 0, 2, 100);
+        let dst = Ipv4Addr::new(93, 184, 216, 34);
+
+        let seg = tcp_segment(
+            src,
+            40000,
+            dst,
+            80,
+            1,
+            2,
+            TCP_SYN | TCP_ACK,
+            65535,
+            Some(1460),
+            b"hi",
+        );
+        assert_eq!(l4_checksum(src, dst, IPPROTO_TCP, &seg), 0);
+        let packet = ipv4_packet(src, dst, IPPROTO_TCP, &seg);
+        assert_eq!(checksum(&packet[..IPV4_HDR_LEN]), 0);
+
+        let ip = Ipv4Packet::parse(&packet).unwrap();
+        assert_eq!((ip.src, ip.dst, ip.proto), (src, dst, IPPROTO_TCP));
+        let tcp = TcpSegment::parse(ip.payload).unwrap();
+        assert_eq!(
+            (tcp.src_port, tcp.dst_port, tcp.seq, tcp.ack),
+            (40000, 80, 1, 2)
+        );
+        assert_eq!(tcp.mss, Some(1460));
+        assert_eq!(tcp.payload, b"hi");
+        assert_eq!(tcp.seq_len(), 3);
+
+        let udp = udp_datagram(src, 5353, dst, 53, b"odd");
+        assert_eq!(l4_checksum(src, dst, IPPROTO_UDP, &udp), 0);
+        assert_eq!(UdpDatagram::parse(&udp).unwrap().payload, b"odd");
+
+        // fragments and corrupted headers are dropped
+        let mut frag = packet.clone();
+        frag[6] |= 0x20;
+        assert!(Ipv4Packet::parse(&frag).is_none());
+        let mut bad = packet;
+        bad[8] = 1;
+        assert!(Ipv4Packet::parse(&bad).is_none());
+
+        let mut ping = vec![ICMP_ECHO_REQUEST, 0, 0, 0, 0, 1, 0, 7, b'x'];
+        let csum = checksum(&ping);
+        ping[2..4].copy_from_slice(&csum.to_be_bytes());
+        let pong = icmp_echo_reply(&ping).unwrap();
+        assert_eq!(pong[0], ICMP_ECHO_REPLY);
+        assert_eq!(checksum(&pong), 0);
+    }
+}
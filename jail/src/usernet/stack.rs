@@ -0,0 +1,1069 @@
+use super::packet::*;
+use crate::config::UserNetConfig;
+use crate::error::Result;
+
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Read, Write};
+use std::mem;
+use std::net::{Ipv4Addr, Shutdown, SocketAddrV4, TcpStream, UdpSocket};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use sys_util::epoll::{self, EpollEvent, EpollFlags, EpollOp};
+use sys_util::errno::Errno;
+
+/// MAC address the stack answers ARP requests with, for the gateway and every other address of the jail network
+pub const GATEWAY_MAC: Mac = [0x52, 0x55, 0x0a, 0x00, 0x02, 0x02];
+
+const MTU: usize = 1500;
+const MSS: u16 = (MTU - IPV4_HDR_LEN - TCP_HDR_LEN) as u16;
+const DEFAULT_MSS: u16 = 536;
+const TCP_BUF_SIZE: usize = 64 * 1024; // per direction and connection, also our largest window since we do not scale it
+const UDP_IDLE: Duration = Duration::from_secs(60);
+const RTO_MIN: Duration = Duration::from_millis(200);
+const RTO_MAX: Duration = Duration::from_secs(10);
+const TICK_MS: isize = 100;
+
+pub const TAP_TOKEN: u64 = 0;
+pub const STOP_TOKEN: u64 = 1;
+const FIRST_FLOW_TOKEN: u64 = 2;
+
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+struct FlowKey {
+    guest_port: u16,
+    dst: Ipv4Addr, // as seen by the jail, e.g. the gateway address
+    dst_port: u16,
+}
+
+struct UdpFlow {
+    sock: UdpSocket,
+    last: Instant,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum TcpState {
+    Connecting, // SYN received from the jail, waiting for the host connect to complete
+    SynAckSent,
+    Established,
+}
+
+/**
+ * A TCP connection of the jail terminated by the stack and proxied through a host socket. Sequence numbers follow
+ * RFC 793 names: snd_* for what we send to the jail, whose unacknowledged and unsent bytes are in to_guest starting at
+ * snd_una, rcv_nxt for what we expect from it
+ */
+struct TcpConn {
+    token: u64,
+    host: TcpStream,
+    registered: Option<EpollFlags>,
+    state: TcpState,
+
+    rcv_nxt: u32,
+    to_host: VecDeque<u8>,
+    guest_fin: bool,
+    host_shut: bool,
+    adv_wnd: usize,
+
+    iss: u32,
+    snd_una: u32,
+    snd_nxt: u32,
+    snd_wnd: usize,
+    mss: usize,
+    to_guest: VecDeque<u8>,
+    host_eof: bool,
+    fin_acked: bool,
+    rto: Duration,
+    deadline: Option<Instant>,
+}
+
+impl TcpConn {
+    fn window(&self) -> usize {
+        (TCP_BUF_SIZE - self.to_host.len()).min(usize::from(u16::MAX))
+    }
+
+    fn fin_seq(&self) -> u32 {
+        self.snd_una.wrapping_add(self.to_guest.len() as u32)
+    }
+
+    fn in_flight(&self) -> bool {
+        self.snd_nxt != self.snd_una
+    }
+
+    fn done(&self) -> bool {
+        self.guest_fin && self.host_shut && self.fin_acked
+    }
+
+    fn interest(&self) -> EpollFlags {
+        let mut flags = EpollFlags::empty();
+        if self.state == TcpState::Connecting || !self.to_host.is_empty() {
+            flags |= EpollFlags::EPOLLOUT;
+        }
+        if self.state != TcpState::Connecting
+            && !self.host_eof
+            && self.to_guest.len() < TCP_BUF_SIZE
+        {
+            flags |= EpollFlags::EPOLLIN;
+        }
+        flags
+    }
+}
+
+enum Flow {
+    Udp(FlowKey),
+    Tcp(FlowKey),
+}
+
+/**
+ * Userspace network stack behind the tap device of a jail, in the spirit of slirp: the jail sees a router at
+ * UserNetConfig.gateway, and every TCP connection or UDP flow it opens to the outside is replayed by the monitor with
+ * its own unprivileged sockets. The jail is only reachable through connections it opened.
+ *
+ * Frames from the jail go in through handle_frame, frames for the jail are queued until take_frames
+ */
+pub struct Stack {
+    cfg: UserNetConfig,
+    nameserver: Option<Ipv4Addr>,
+    epfd: RawFd,
+    guest_mac: Option<Mac>,
+    out: Vec<Vec<u8>>,
+    next_token: u64,
+    tokens: HashMap<u64, Flow>,
+    udp: HashMap<FlowKey, UdpFlow>,
+    tcp: HashMap<FlowKey, TcpConn>,
+}
+
+impl Drop for Stack {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.epfd) };
+    }
+}
+
+impl Stack {
+    pub fn new(cfg: UserNetConfig, nameserver: Option<Ipv4Addr>) -> Result<Stack> {
+        let epfd = epoll::epoll_create1(epoll::EpollCreateFlags::EPOLL_CLOEXEC)?;
+        Ok(Stack {
+            cfg,
+            nameserver,
+            epfd,
+            guest_mac: None,
+            out: Vec::new(),
+            next_token: FIRST_FLOW_TOKEN,
+            tokens: HashMap::new(),
+            udp: HashMap::new(),
+            tcp: HashMap::new(),
+        })
+    }
+
+    /// Adds fd to the epoll set of the stack, token must be TAP_TOKEN or STOP_TOKEN
+    pub fn watch(&mut self, fd: RawFd, token: u64) -> Result<()> {
+        epoll::epoll_ctl(
+            self.epfd,
+            EpollOp::EpollCtlAdd,
+            fd,
+            &mut EpollEvent::new(EpollFlags::EPOLLIN, token),
+        )?;
+        Ok(())
+    }
+
+    /// Frames for the jail, in order
+    pub fn take_frames(&mut self) -> Vec<Vec<u8>> {
+        mem::take(&mut self.out)
+    }
+
+    /**
+     * Waits up to timeout_ms for host sockets and the fds added with watch, handles host sockets and timers and
+     * returns the tokens of the watched fds that are ready
+     */
+    pub fn poll(&mut self, timeout_ms: isize) -> Result<Vec<u64>> {
+        let timeout_ms = if self.tcp.values().any(|c| c.deadline.is_some()) {
+            timeout_ms.min(TICK_MS)
+        } else {
+            timeout_ms
+        };
+        let mut events = [EpollEvent::empty(); 64];
+        let n = match epoll::epoll_wait(self.epfd, &mut events, timeout_ms) {
+            Ok(n) => n,
+            Err(Errno::EINTR) => 0,
+            Err(e) => return Err(("usernet epoll_wait", e).into()),
+        };
+
+        let mut ready = Vec::new();
+        for ev in events[..n].iter() {
+            match ev.data() {
+                TAP_TOKEN | STOP_TOKEN => ready.push(ev.data()),
+                token => self.host_event(token, ev),
+            }
+        }
+        self.timers(Instant::now());
+        Ok(ready)
+    }
+
+    pub fn handle_frame(&mut self, frame: &[u8]) {
+        let eth = match EthFrame::parse(frame) {
+            Some(eth) => eth,
+            None => return,
+        };
+        // the kernel of the jail only ever sends from the MAC address of the tap device
+        if eth.src[0] & 1 == 0 {
+            self.guest_mac = Some(eth.src);
+        }
+        match eth.ethertype {
+            ETH_P_ARP => self.handle_arp(eth.payload),
+            ETH_P_IP => {
+                if let Some(ip) = Ipv4Packet::parse(eth.payload) {
+                    if ip.src == self.cfg.ip {
+                        self.handle_ipv4(&ip);
+                    }
+                }
+            }
+            _ => {} // IPv6 is not supported, the jail only gets a link-local address
+        }
+    }
+
+    fn in_network(&self, addr: Ipv4Addr) -> bool {
+        let mask = u32::MAX
+            .checked_shl(32 - u32::from(self.cfg.prefix_len))
+            .unwrap_or(0);
+        u32::from(addr) & mask == u32::from(self.cfg.ip) & mask
+    }
+
+    /// Where the host socket of a flow to dst connects, None if the jail may not reach dst
+    fn host_addr(&self, dst: Ipv4Addr, port: u16) -> Option<SocketAddrV4> {
+        if dst == self.cfg.gateway {
+            if self.cfg.host_loopback {
+                return Some(SocketAddrV4::new(Ipv4Addr::LOCALHOST, port));
+            }
+            return None;
+        }
+        if dst == self.cfg.dns {
+            return self.nameserver.map(|ns| SocketAddrV4::new(ns, port));
+        }
+        if self.in_network(dst)
+            || dst.is_loopback()
+            || dst.is_unspecified()
+            || dst.is_broadcast()
+            || dst.is_multicast()
+        {
+            return None;
+        }
+        Some(SocketAddrV4::new(dst, port))
+    }
+
+    fn send_ipv4(&mut self, src: Ipv4Addr, proto: u8, payload: &[u8]) {
+        if let Some(mac) = self.guest_mac {
+            let packet = ipv4_packet(src, self.cfg.ip, proto, payload);
+            self.out
+                .push(eth_frame(mac, GATEWAY_MAC, ETH_P_IP, &packet));
+        }
+    }
+
+    fn handle_arp(&mut self, buf: &[u8]) {
+        let arp = match Arp::parse(buf) {
+            Some(arp) => arp,
+            None => return,
+        };
+        if arp.op != ARP_REQUEST || arp.tpa == self.cfg.ip || !self.in_network(arp.tpa) {
+            return;
+        }
+        let reply = Arp {
+            op: ARP_REPLY,
+            sha: GATEWAY_MAC,
+            spa: arp.tpa,
+            tha: arp.sha,
+            tpa: arp.spa,
+        };
+        self.out.push(eth_frame(
+            arp.sha,
+            GATEWAY_MAC,
+            ETH_P_ARP,
+            &reply.to_bytes(),
+        ));
+    }
+
+    fn handle_ipv4(&mut self, ip: &Ipv4Packet) {
+        match ip.proto {
+            IPPROTO_ICMP if ip.dst == self.cfg.gateway || ip.dst == self.cfg.dns => {
+                if let Some(reply) = icmp_echo_reply(ip.payload) {
+                    self.send_ipv4(ip.dst, IPPROTO_ICMP, &reply);
+                }
+            }
+            IPPROTO_UDP => {
+                if let Some(udp) = UdpDatagram::parse(ip.payload) {
+                    self.guest_udp(ip.dst, &udp);
+                }
+            }
+            IPPROTO_TCP => {
+                if l4_checksum(ip.src, ip.dst, IPPROTO_TCP, ip.payload) != 0 {
+                    return;
+                }
+                if let Some(seg) = TcpSegment::parse(ip.payload) {
+                    self.guest_tcp(ip.dst, &seg);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn new_token(&mut self) -> u64 {
+        let token = self.next_token;
+        self.next_token += 1;
+        token
+    }
+
+    fn guest_udp(&mut self, dst: Ipv4Addr, udp: &UdpDatagram) {
+        let key = FlowKey {
+            guest_port: udp.src_port,
+            dst,
+            dst_port: udp.dst_port,
+        };
+        if !self.udp.contains_key(&key) {
+            let addr = match self.host_addr(dst, udp.dst_port) {
+                Some(addr) => addr,
+                None => return,
+            };
+            let sock = match udp_socket(addr) {
+                Ok(sock) => sock,
+                Err(_) => return,
+            };
+            let token = self.new_token();
+            if epoll::epoll_ctl(
+                self.epfd,
+                EpollOp::EpollCtlAdd,
+                sock.as_raw_fd(),
+                &mut EpollEvent::new(EpollFlags::EPOLLIN, token),
+            )
+            .is_err()
+            {
+                return;
+            }
+            self.tokens.insert(token, Flow::Udp(key));
+            self.udp.insert(
+                key,
+                UdpFlow {
+                    sock,
+                    last: Instant::now(),
+                },
+            );
+        }
+
+        let flow = self.udp.get_mut(&key).unwrap();
+        flow.last = Instant::now();
+        // like a router with a full queue, a datagram the host cannot take right away is lost
+        let _ = flow.sock.send(udp.payload);
+    }
+
+    fn host_udp(&mut self, key: FlowKey) {
+        let mut buf = [0u8; MTU - IPV4_HDR_LEN - UDP_HDR_LEN];
+        loop {
+            let n = match self.udp.get_mut(&key) {
+                Some(flow) => match flow.sock.recv(&mut buf) {
+                    Ok(n) => {
+                        flow.last = Instant::now();
+                        n
+                    }
+                    Err(_) => return,
+                },
+                None => return,
+            };
+            let datagram = udp_datagram(
+                key.dst,
+                key.dst_port,
+                self.cfg.ip,
+                key.guest_port,
+                &buf[..n],
+            );
+            self.send_ipv4(key.dst, IPPROTO_UDP, &datagram);
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn send_tcp(
+        &mut self,
+        key: FlowKey,
+        seq: u32,
+        ack: u32,
+        flags: u8,
+        window: usize,
+        mss: Option<u16>,
+        payload: &[u8],
+    ) {
+        let seg = tcp_segment(
+            key.dst,
+            key.dst_port,
+            self.cfg.ip,
+            key.guest_port,
+            seq,
+            ack,
+            flags,
+            window.min(usize::from(u16::MAX)) as u16,
+            mss,
+            payload,
+        );
+        self.send_ipv4(key.dst, IPPROTO_TCP, &seg);
+    }
+
+    /// Answers a segment no connection can take, see the reset generation of RFC 793
+    fn send_rst(&mut self, key: FlowKey, seg: &TcpSegment) {
+        if seg.flags & TCP_RST != 0 {
+            return;
+        }
+        if seg.flags & TCP_ACK != 0 {
+            self.send_tcp(key, seg.ack, 0, TCP_RST, 0, None, &[]);
+        } else {
+            let ack = seg.seq.wrapping_add(seg.seq_len());
+            self.send_tcp(key, 0, ack, TCP_RST | TCP_ACK, 0, None, &[]);
+        }
+    }
+
+    fn guest_tcp(&mut self, dst: Ipv4Addr, seg: &TcpSegment) {
+        let key = FlowKey {
+            guest_port: seg.src_port,
+            dst,
+            dst_port: seg.dst_port,
+        };
+
+        if !self.tcp.contains_key(&key) {
+            if seg.flags & (TCP_SYN | TCP_ACK | TCP_RST) != TCP_SYN {
+                self.send_rst(key, seg);
+                return;
+            }
+            if self.tcp_connect(key, seg).is_none() {
+                self.send_rst(key, seg);
+            }
+            return;
+        }
+
+        if seg.flags & TCP_RST != 0 {
+            self.remove_tcp(key);
+            return;
+        }
+
+        let mut conn = self.tcp.remove(&key).unwrap();
+        if seg.flags & TCP_SYN != 0 {
+            // the jail did not get our SYN-ACK yet
+            if conn.state == TcpState::SynAckSent {
+                self.send_tcp(
+                    key,
+                    conn.iss,
+                    conn.rcv_nxt,
+                    TCP_SYN | TCP_ACK,
+                    conn.window(),
+                    Some(MSS),
+                    &[],
+                );
+            }
+            self.tcp.insert(key, conn);
+            return;
+        }
+
+        if seg.flags & TCP_ACK != 0 {
+            self.guest_ack(&mut conn, seg);
+        }
+
+        let seg_len = seg.payload.len() as u32 + if seg.flags & TCP_FIN != 0 { 1 } else { 0 };
+        if seg_len > 0 && conn.state == TcpState::Established && !conn.guest_fin {
+            // bytes before rcv_nxt were already taken, the jail retransmitted them
+            let skip = conn.rcv_nxt.wrapping_sub(seg.seq) as usize;
+            if skip <= seg.payload.len() {
+                let payload = &seg.payload[skip..];
+                let take = payload.len().min(TCP_BUF_SIZE - conn.to_host.len());
+                conn.to_host.extend(&payload[..take]);
+                conn.rcv_nxt = conn.rcv_nxt.wrapping_add(take as u32);
+                if take == payload.len() && seg.flags & TCP_FIN != 0 {
+                    conn.rcv_nxt = conn.rcv_nxt.wrapping_add(1);
+                    conn.guest_fin = true;
+                }
+            }
+        }
+        if seg_len > 0 {
+            conn.adv_wnd = conn.window();
+            self.send_tcp(
+                key,
+                conn.snd_nxt,
+                conn.rcv_nxt,
+                TCP_ACK,
+                conn.adv_wnd,
+                None,
+                &[],
+            );
+        }
+
+        self.tcp_progress(key, conn);
+    }
+
+    fn guest_ack(&mut self, conn: &mut TcpConn, seg: &TcpSegment) {
+        conn.snd_wnd = usize::from(seg.window);
+        let mut acked = seg.ack.wrapping_sub(conn.snd_una) as usize;
+        if acked == 0 || acked > conn.snd_nxt.wrapping_sub(conn.snd_una) as usize {
+            return;
+        }
+        conn.snd_una = seg.ack;
+        if conn.state == TcpState::SynAckSent {
+            conn.state = TcpState::Established;
+            acked -= 1;
+        }
+        let data = acked.min(conn.to_guest.len());
+        conn.to_guest.drain(..data);
+        if acked > data {
+            conn.fin_acked = true;
+        }
+        conn.rto = RTO_MIN;
+        conn.deadline = if conn.in_flight() {
+            Some(Instant::now() + conn.rto)
+        } else {
+            None
+        };
+    }
+
+    /// Returns None if the host socket could not be created, the jail then gets a reset
+    fn tcp_connect(&mut self, key: FlowKey, seg: &TcpSegment) -> Option<()> {
+        let addr = self.host_addr(key.dst, key.dst_port)?;
+        let host = tcp_connect_nonblocking(addr).ok()?;
+        let token = self.new_token();
+        let iss = initial_seq();
+        let conn = TcpConn {
+            token,
+            host,
+            registered: None,
+            state: TcpState::Connecting,
+            rcv_nxt: seg.seq.wrapping_add(1),
+            to_host: VecDeque::new(),
+            guest_fin: false,
+            host_shut: false,
+            adv_wnd: TCP_BUF_SIZE,
+            iss,
+            snd_una: iss,
+            snd_nxt: iss,
+            snd_wnd: usize::from(seg.window),
+            mss: usize::from(seg.mss.unwrap_or(DEFAULT_MSS).min(MSS)),
+            to_guest: VecDeque::new(),
+            host_eof: false,
+            fin_acked: false,
+            rto: RTO_MIN,
+            deadline: None,
+        };
+        self.tokens.insert(token, Flow::Tcp(key));
+        self.tcp_progress(key, conn);
+        Some(())
+    }
+
+    fn remove_tcp(&mut self, key: FlowKey) {
+        if let Some(conn) = self.tcp.remove(&key) {
+            self.tokens.remove(&conn.token);
+            if conn.registered.is_some() {
+                let _ =
+                    epoll::epoll_ctl(self.epfd, EpollOp::EpollCtlDel, conn.host.as_raw_fd(), None);
+            }
+        }
+    }
+
+    fn reset_tcp(&mut self, key: FlowKey, conn: TcpConn) {
+        self.send_tcp(
+            key,
+            conn.snd_nxt,
+            conn.rcv_nxt,
+            TCP_RST | TCP_ACK,
+            0,
+            None,
+            &[],
+        );
+        self.tcp.insert(key, conn);
+        self.remove_tcp(key);
+    }
+
+    /// Moves data both ways as far as buffers and windows allow, then updates the epoll interest of the host socket
+    fn tcp_progress(&mut self, key: FlowKey, mut conn: TcpConn) {
+        if conn.state != TcpState::Connecting {
+            // jail to host
+            while !conn.to_host.is_empty() {
+                let (front, _) = conn.to_host.as_slices();
+                match conn.host.write(front) {
+                    Ok(n) => {
+                        conn.to_host.drain(..n);
+                    }
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    Err(_) => return self.reset_tcp(key, conn),
+                }
+            }
+            if conn.guest_fin && conn.to_host.is_empty() && !conn.host_shut {
+                let _ = conn.host.shutdown(Shutdown::Write);
+                conn.host_shut = true;
+            }
+            // window update once the jail may send a full segment again
+            if conn.adv_wnd < conn.mss && conn.window() >= conn.mss && !conn.guest_fin {
+                conn.adv_wnd = conn.window();
+                self.send_tcp(
+                    key,
+                    conn.snd_nxt,
+                    conn.rcv_nxt,
+                    TCP_ACK,
+                    conn.adv_wnd,
+                    None,
+                    &[],
+                );
+            }
+
+            // host to jail
+            let mut buf = [0u8; 16 * 1024];
+            while !conn.host_eof && conn.to_guest.len() < TCP_BUF_SIZE {
+                let room = (TCP_BUF_SIZE - conn.to_guest.len()).min(buf.len());
+                match conn.host.read(&mut buf[..room]) {
+                    Ok(0) => conn.host_eof = true,
+                    Ok(n) => conn.to_guest.extend(&buf[..n]),
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    Err(_) => return self.reset_tcp(key, conn),
+                }
+            }
+            if conn.state == TcpState::Established {
+                let wnd = conn.snd_wnd;
+                self.tcp_send(key, &mut conn, wnd);
+            }
+        }
+
+        if conn.done() {
+            self.tcp.insert(key, conn);
+            return self.remove_tcp(key);
+        }
+
+        let interest = conn.interest();
+        let fd = conn.host.as_raw_fd();
+        let res = match (conn.registered, interest.is_empty()) {
+            (Some(old), false) if old == interest => Ok(()),
+            (Some(_), false) => epoll::epoll_ctl(
+                self.epfd,
+                EpollOp::EpollCtlMod,
+                fd,
+                &mut EpollEvent::new(interest, conn.token),
+            ),
+            (None, false) => epoll::epoll_ctl(
+                self.epfd,
+                EpollOp::EpollCtlAdd,
+                fd,
+                &mut EpollEvent::new(interest, conn.token),
+            ),
+            // nothing to wait for, a fully shut down socket would otherwise keep reporting EPOLLHUP
+            (Some(_), true) => epoll::epoll_ctl(self.epfd, EpollOp::EpollCtlDel, fd, None),
+            (None, true) => Ok(()),
+        };
+        if res.is_err() {
+            return self.reset_tcp(key, conn);
+        }
+        conn.registered = if interest.is_empty() {
+            None
+        } else {
+            Some(interest)
+        };
+        self.tcp.insert(key, conn);
+    }
+
+    /// Sends what the window of the jail allows from snd_nxt, then our FIN once the host closed and all was sent
+    fn tcp_send(&mut self, key: FlowKey, conn: &mut TcpConn, wnd: usize) {
+        let mut offset = conn.snd_nxt.wrapping_sub(conn.snd_una) as usize;
+        let sent_before = conn.snd_nxt;
+        while offset < conn.to_guest.len() && offset < wnd {
+            let len = conn.mss.min(conn.to_guest.len() - offset).min(wnd - offset);
+            let payload: Vec<u8> = conn.to_guest.range(offset..offset + len).cloned().collect();
+            let seq = conn.snd_una.wrapping_add(offset as u32);
+            self.send_tcp(
+                key,
+                seq,
+                conn.rcv_nxt,
+                TCP_ACK | TCP_PSH,
+                conn.window(),
+                None,
+                &payload,
+            );
+            offset += len;
+            conn.snd_nxt = conn.snd_una.wrapping_add(offset as u32);
+        }
+        if conn.host_eof
+            && offset == conn.to_guest.len()
+            && !conn.fin_acked
+            && conn.snd_nxt == conn.fin_seq()
+        {
+            self.send_tcp(
+                key,
+                conn.snd_nxt,
+                conn.rcv_nxt,
+                TCP_FIN | TCP_ACK,
+                conn.window(),
+                None,
+                &[],
+            );
+            conn.snd_nxt = conn.snd_nxt.wrapping_add(1);
+        }
+        if conn.snd_nxt != sent_before && conn.deadline.is_none() {
+            conn.deadline = Some(Instant::now() + conn.rto);
+        }
+        // zero window, probe it with one byte on the next timeout
+        if conn.snd_wnd == 0
+            && !conn.in_flight()
+            && !conn.to_guest.is_empty()
+            && conn.deadline.is_none()
+        {
+            conn.deadline = Some(Instant::now() + conn.rto);
+        }
+    }
+
+    fn host_event(&mut self, token: u64, ev: &EpollEvent) {
+        let key = match self.tokens.get(&token) {
+            Some(Flow::Udp(key)) => {
+                let key = *key;
+                return self.host_udp(key);
+            }
+            Some(Flow::Tcp(key)) => *key,
+            None => return,
+        };
+        let mut conn = match self.tcp.remove(&key) {
+            Some(conn) => conn,
+            None => return,
+        };
+
+        if conn.state == TcpState::Connecting {
+            if !ev.is_writable() && !ev.is_error() && !ev.is_read_closed() {
+                return self.tcp_progress(key, conn);
+            }
+            match conn.host.take_error() {
+                Ok(None) => {
+                    conn.state = TcpState::SynAckSent;
+                    self.send_tcp(
+                        key,
+                        conn.iss,
+                        conn.rcv_nxt,
+                        TCP_SYN | TCP_ACK,
+                        conn.window(),
+                        Some(MSS),
+                        &[],
+                    );
+                    conn.snd_nxt = conn.iss.wrapping_add(1);
+                    conn.deadline = Some(Instant::now() + conn.rto);
+                }
+                // refused, unreachable or timed out, the jail gets the reset a closed port would give
+                _ => return self.reset_tcp(key, conn),
+            }
+        }
+        self.tcp_progress(key, conn);
+    }
+
+    fn timers(&mut self, now: Instant) {
+        let expired: Vec<FlowKey> = self
+            .udp
+            .iter()
+            .filter(|(_, flow)| now.duration_since(flow.last) > UDP_IDLE)
+            .map(|(key, _)| *key)
+            .collect();
+        for key in expired {
+            if let Some(flow) = self.udp.remove(&key) {
+                let _ =
+                    epoll::epoll_ctl(self.epfd, EpollOp::EpollCtlDel, flow.sock.as_raw_fd(), None);
+                self.tokens
+                    .retain(|_, f| !matches!(f, Flow::Udp(k) if *k == key));
+            }
+        }
+
+        let due: Vec<FlowKey> = self
+            .tcp
+            .iter()
+            .filter(|(_, conn)| matches!(conn.deadline, Some(d) if d <= now))
+            .map(|(key, _)| *key)
+            .collect();
+        for key in due {
+            let mut conn = self.tcp.remove(&key).unwrap();
+            conn.deadline = None;
+            conn.rto = (conn.rto * 2).min(RTO_MAX);
+            // go back N, everything not acknowledged is sent again
+            conn.snd_nxt = conn.snd_una;
+            match conn.state {
+                TcpState::SynAckSent => {
+                    self.send_tcp(
+                        key,
+                        conn.iss,
+                        conn.rcv_nxt,
+                        TCP_SYN | TCP_ACK,
+                        conn.window(),
+                        Some(MSS),
+                        &[],
+                    );
+                    conn.snd_nxt = conn.iss.wrapping_add(1);
+                    conn.deadline = Some(now + conn.rto);
+                }
+                TcpState::Established => {
+                    let wnd = conn.snd_wnd.max(1);
+                    self.tcp_send(key, &mut conn, wnd);
+                }
+                TcpState::Connecting => {}
+            }
+            self.tcp.insert(key, conn);
+        }
+    }
+}
+
+fn initial_seq() -> u32 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        // RFC 793 clock, one tick every 4 microseconds
+        Ok(d) => (d.as_micros() / 4) as u32,
+        Err(_) => 0,
+    }
+}
+
+fn udp_socket(addr: SocketAddrV4) -> io::Result<UdpSocket> {
+    let sock = UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0))?;
+    sock.connect(addr)?;
+    sock.set_nonblocking(true)?;
+    Ok(sock)
+}
+
+fn tcp_connect_nonblocking(addr: SocketAddrV4) -> io::Result<TcpStream> {
+    let fd = unsafe {
+        libc::socket(
+            libc::AF_INET,
+            libc::SOCK_STREAM | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+            0,
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // from here the TcpStream owns fd and closes it
+    let stream = unsafe { TcpStream::from_raw_fd(fd) };
+
+    let mut sin: libc::sockaddr_in = unsafe { mem::zeroed() };
+    sin.sin_family = libc::AF_INET as libc::sa_family_t;
+    sin.sin_port = addr.port().to_be();
+    sin.sin_addr.s_addr = u32::from(*addr.ip()).to_be();
+    let res = unsafe {
+        libc::connect(
+            fd,
+            &sin as *const libc::sockaddr_in as *const libc::sockaddr,
+            mem::size_of::<libc::sockaddr_in>() as libc::socklen_t,
+        )
+    };
+    if res < 0 && Errno::last() != Errno::EINPROGRESS {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(stream)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{TcpListener, UdpSocket};
+    use std::thread;
+
+    const GUEST_MAC: Mac = [0x02, 0, 0, 0, 0, 0x64];
+
+    fn stack(host_loopback: bool) -> Stack {
+        Stack::new(
+            UserNetConfig {
+                host_loopback,
+                ..Default::default()
+            },
+            None,
+        )
+        .unwrap()
+    }
+
+    fn guest_ip() -> Ipv4Addr {
+        UserNetConfig::default().ip
+    }
+
+    fn gateway() -> Ipv4Addr {
+        UserNetConfig::default().gateway
+    }
+
+    fn ip_frame(proto: u8, payload: &[u8]) -> Vec<u8> {
+        eth_frame(
+            GATEWAY_MAC,
+            GUEST_MAC,
+            ETH_P_IP,
+            &ipv4_packet(guest_ip(), gateway(), proto, payload),
+        )
+    }
+
+    fn tcp_frame(dst_port: u16, seq: u32, ack: u32, flags: u8, payload: &[u8]) -> Vec<u8> {
+        ip_frame(
+            IPPROTO_TCP,
+            &tcp_segment(
+                guest_ip(),
+                40000,
+                gateway(),
+                dst_port,
+                seq,
+                ack,
+                flags,
+                65535,
+                Some(1460),
+                payload,
+            ),
+        )
+    }
+
+    fn ip_payload(frame: &[u8]) -> Vec<u8> {
+        let eth = EthFrame::parse(frame).unwrap();
+        assert_eq!(eth.dst, GUEST_MAC);
+        Ipv4Packet::parse(eth.payload).unwrap().payload.to_vec()
+    }
+
+    /// Polls until a frame for the jail is queued, then returns the IPv4 payload of all the queued frames
+    fn wait_frames(stack: &mut Stack) -> Vec<Vec<u8>> {
+        for _ in 0..100 {
+            stack.poll(20).unwrap();
+            let frames = stack.take_frames();
+            if !frames.is_empty() {
+                return frames.iter().map(|f| ip_payload(f)).collect();
+            }
+        }
+        panic!("no frame for the jail");
+    }
+
+    #[test]
+    fn test_arp_and_ping() {
+        let mut stack = stack(false);
+        let request = Arp {
+            op: ARP_REQUEST,
+            sha: GUEST_MAC,
+            spa: guest_ip(),
+            tha: [0; 6],
+            tpa: gateway(),
+        };
+        stack.handle_frame(&eth_frame(
+            [0xff; 6],
+            GUEST_MAC,
+            ETH_P_ARP,
+            &request.to_bytes(),
+        ));
+        let frames = stack.take_frames();
+        assert_eq!(frames.len(), 1);
+        let reply = Arp::parse(EthFrame::parse(&frames[0]).unwrap().payload).unwrap();
+        assert_eq!(
+            (reply.op, reply.sha, reply.spa),
+            (ARP_REPLY, GATEWAY_MAC, gateway())
+        );
+
+        // nobody answers for the address of the jail itself
+        let request = Arp {
+            tpa: guest_ip(),
+            ..request
+        };
+        stack.handle_frame(&eth_frame(
+            [0xff; 6],
+            GUEST_MAC,
+            ETH_P_ARP,
+            &request.to_bytes(),
+        ));
+        assert!(stack.take_frames().is_empty());
+
+        let mut ping = vec![ICMP_ECHO_REQUEST, 0, 0, 0, 0, 1, 0, 1];
+        let csum = checksum(&ping);
+        ping[2..4].copy_from_slice(&csum.to_be_bytes());
+        stack.handle_frame(&ip_frame(IPPROTO_ICMP, &ping));
+        let frames = stack.take_frames();
+        assert_eq!(frames.len(), 1);
+        let ip = Ipv4Packet::parse(EthFrame::parse(&frames[0]).unwrap().payload).unwrap();
+        assert_eq!(
+            (ip.src, ip.dst, ip.payload[0]),
+            (gateway(), guest_ip(), ICMP_ECHO_REPLY)
+        );
+    }
+
+    #[test]
+    fn test_udp_through_host_loopback() {
+        let echo = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let port = echo.local_addr().unwrap().port();
+        thread::spawn(move || {
+            let mut buf = [0u8; 64];
+            let (n, from) = echo.recv_from(&mut buf).unwrap();
+            echo.send_to(&buf[..n], from).unwrap();
+        });
+
+        let mut stack = stack(true);
+        stack.handle_frame(&ip_frame(
+            IPPROTO_UDP,
+            &udp_datagram(guest_ip(), 5000, gateway(), port, b"ping"),
+        ));
+        let frames = wait_frames(&mut stack);
+        let udp = UdpDatagram::parse(&frames[0]).unwrap();
+        assert_eq!(
+            (udp.src_port, udp.dst_port, udp.payload),
+            (port, 5000, &b"ping"[..])
+        );
+    }
+
+    #[test]
+    fn test_tcp_through_host_loopback() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let (mut s, _) = listener.accept().unwrap();
+            let mut buf = [0u8; 5];
+            s.read_exact(&mut buf).unwrap();
+            assert_eq!(&buf, b"hello");
+            s.write_all(b"world").unwrap();
+            // then close, the jail gets our FIN
+        });
+
+        // the gateway does not lead to the host loopback unless asked for
+        let mut closed = stack(false);
+        closed.handle_frame(&tcp_frame(port, 1000, 0, TCP_SYN, &[]));
+        let frames = closed.take_frames();
+        assert_eq!(frames.len(), 1);
+        let rst = ip_payload(&frames[0]);
+        assert_eq!(TcpSegment::parse(&rst).unwrap().flags, TCP_RST | TCP_ACK);
+
+        let mut stack = stack(true);
+        stack.handle_frame(&tcp_frame(port, 1000, 0, TCP_SYN, &[]));
+        let frames = wait_frames(&mut stack);
+        let syn_ack = TcpSegment::parse(&frames[0]).unwrap();
+        assert_eq!(syn_ack.flags, TCP_SYN | TCP_ACK);
+        assert_eq!(syn_ack.ack, 1001);
+        assert_eq!(syn_ack.mss, Some(MSS));
+        let iss = syn_ack.seq;
+
+        stack.handle_frame(&tcp_frame(port, 1001, iss.wrapping_add(1), TCP_ACK, &[]));
+        stack.handle_frame(&tcp_frame(
+            port,
+            1001,
+            iss.wrapping_add(1),
+            TCP_ACK | TCP_PSH,
+            b"hello",
+        ));
+        let frames = stack.take_frames();
+        let ack = ip_payload(&frames[0]);
+        let ack = TcpSegment::parse(&ack).unwrap();
+        assert_eq!((ack.flags, ack.ack), (TCP_ACK, 1006));
+
+        // the host answers then closes
+        let mut data = Vec::new();
+        let mut fin = None;
+        while fin.is_none() {
+            for frame in wait_frames(&mut stack) {
+                let seg = TcpSegment::parse(&frame).unwrap();
+                data.extend_from_slice(seg.payload);
+                if seg.flags & TCP_FIN != 0 {
+                    fin = Some(seg.seq);
+                }
+            }
+        }
+        assert_eq!(data, b"world");
+        assert_eq!(fin, Some(iss.wrapping_add(6)));
+        server.join().unwrap();
+
+        // the jail acknowledges everything and closes too, the connection is then gone
+        stack.handle_frame(&tcp_frame(
+            port,
+            1006,
+            iss.wrapping_add(7),
+            TCP_ACK | TCP_FIN,
+            &[],
+        ));
+        let frames = stack.take_frames();
+        assert_eq!(
+            TcpSegment::parse(&ip_payload(&frames[0])).unwrap().ack,
+            1007
+        );
+        assert!(stack.tcp.is_empty());
+        assert!(stack.tokens.is_empty());
+    }
+}
@@ -1,8 +1,539 @@
+use super::config::JailConf;
+use super::error::Result;
+use super::sandbox::{self, SeccompViolation};
+use super::supervisor;
+use super::usage::ResourceUsage;
+
+use std::fmt;
+use std::mem;
+use std::ptr;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use sys_util::errno::Errno;
+
 /**
  * Wait for all children, so it is not for use in the global rust programs that handles multiple container
  * You may use it for tests with only one container
  * This function blocks until all children are done
 */
 pub fn reap_proc(print_seccomp_violation: bool) -> i32 {
-    cpp_bindings::reap_proc(print_seccomp_violation)
+    let mut rv = 0;
+
+    loop {
+        let mut si: libc::siginfo_t = unsafe { mem::zeroed() };
+        // WNOWAIT keeps the child a zombie so that /proc/<pid>/syscall can still be read below
+        if unsafe {
+            libc::waitid(
+                libc::P_ALL,
+                0,
+                &mut si,
+                libc::WNOHANG | libc::WNOWAIT | libc::WEXITED,
+            )
+        } == -1
+        {
+            break;
+        }
+        let pid = unsafe { si.si_pid() };
+        if pid == 0 {
+            break;
+        }
+
+        if print_seccomp_violation
+            && si.si_code == libc::CLD_KILLED
+            && unsafe { si.si_status() } == libc::SIGSYS
+        {
+            seccomp_violation(pid);
+        }
+
+        let mut status = 0;
+        if unsafe { libc::wait4(pid, &mut status, 0, ptr::null_mut()) } == pid {
+            if libc::WIFEXITED(status) {
+                rv = libc::WEXITSTATUS(status);
+            } else if libc::WIFSIGNALED(status) {
+                rv = 128 + libc::WTERMSIG(status);
+            }
+        }
+    }
+
+    rv
+}
+
+fn seccomp_violation(pid: libc::pid_t) {
+    println!(
+        "pid={} commited a syscall/seccomp violation and exited with SIGSYS",
+        pid
+    );
+    // the last one of pid, reap_proc is only meant for tests, pids are not reused in between
+    if let Some(violation) = sandbox::read_violation(pid, UNIX_EPOCH) {
+        println!("pid={}, {}", pid, violation);
+    }
+}
+
+/// Why the monitor stopped waiting for a jail
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TermReason {
+    /// the jail init exited, or was killed by someone else than the monitor
+    Exited,
+    /// the jail ran past JailConf.tlimit and the monitor killed it
+    TimeLimit,
+    /// the OOM killer of the memory cgroup of the jail fired and the jail init did not exit with 0
+    Oom,
+    /// forks were refused by pids.max and the jail init did not exit with 0
+    PidsLimit,
+    /// the jail init was killed by SIGXCPU, RLIMIT_CPU
+    CpuRlimit,
+    /// the jail init was killed by SIGSYS, a seccomp violation
+    Seccomp,
+    /// the jail was killed on request, e.g. by a kill message of gtvs to the scheduler
+    Killed,
+}
+
+impl TermReason {
+    /// Code of the reason in the exit message of the scheduler
+    pub fn code(self) -> u8 {
+        match self {
+            TermReason::Exited => 0,
+            TermReason::TimeLimit => 1,
+            TermReason::Oom => 2,
+            TermReason::PidsLimit => 3,
+            TermReason::CpuRlimit => 4,
+            TermReason::Seccomp => 5,
+            TermReason::Killed => 6,
+        }
+    }
+}
+
+/// What the monitor knows about a jail once it has been reaped
+#[derive(Clone, Debug)]
+pub struct ExitReport {
+    pub pid: i32,
+    /// exit code, or 128 + signal number when the jail init was killed by a signal, like nsjail
+    pub status: i32,
+    pub signal: Option<i32>,
+    pub reason: TermReason,
+    /// the time limit that was enforced, in seconds, 0 if none
+    pub tlimit: u64,
+    pub wall_time: Duration,
+    /// from the rusage of wait4 when reaped by wait_with_tlimit, run_monitor_child adds the cgroup values
+    pub usage: ResourceUsage,
+    /// the syscall that killed the jail init when reason is Seccomp, if it was found, see add_seccomp_violation
+    pub seccomp_violation: Option<SeccompViolation>,
+}
+
+impl fmt::Display for ExitReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.reason {
+            TermReason::TimeLimit => write!(
+                f,
+                "jail {} killed for exceeding its time limit of {}s",
+                self.pid, self.tlimit
+            )?,
+            TermReason::Oom => write!(
+                f,
+                "jail {} ended by the OOM killer with status {}",
+                self.pid, self.status
+            )?,
+            TermReason::PidsLimit => write!(
+                f,
+                "jail {} hit its pids limit and exited with status {}",
+                self.pid, self.status
+            )?,
+            TermReason::CpuRlimit => {
+                write!(f, "jail {} killed for exceeding RLIMIT_CPU", self.pid)?
+            }
+            TermReason::Seccomp => {
+                write!(
+                    f,
+                    "jail {} killed by SIGSYS for a seccomp violation",
+                    self.pid
+                )?;
+                if let Some(ref violation) = self.seccomp_violation {
+                    write!(f, ", {}", violation)?;
+                }
+            }
+            TermReason::Killed => write!(f, "jail {} killed on request", self.pid)?,
+            TermReason::Exited => match self.signal {
+                Some(sig) => write!(f, "jail {} killed by signal {}", self.pid, sig)?,
+                None => write!(f, "jail {} exited with status {}", self.pid, self.status)?,
+            },
+        }
+        write!(f, " (ran for {:.3}s)", self.wall_time.as_secs_f64())
+    }
+}
+
+impl ExitReport {
+    /**
+     * Report of a jail reaped with the `wait_status` of waitpid, timed_out when the monitor killed it for tlimit. The
+     * limits of its cgroups are only known with add_usage
+     */
+    pub fn from_wait_status(
+        pid: i32,
+        wait_status: i32,
+        timed_out: bool,
+        tlimit: u64,
+        wall_time: Duration,
+    ) -> ExitReport {
+        let (status, signal) = if libc::WIFSIGNALED(wait_status) {
+            let sig = libc::WTERMSIG(wait_status);
+            (128 + sig, Some(sig))
+        } else {
+            (libc::WEXITSTATUS(wait_status), None)
+        };
+
+        ExitReport {
+            pid,
+            status,
+            signal,
+            reason: match signal {
+                _ if timed_out => TermReason::TimeLimit,
+                Some(libc::SIGSYS) => TermReason::Seccomp,
+                Some(libc::SIGXCPU) => TermReason::CpuRlimit,
+                _ => TermReason::Exited,
+            },
+            tlimit,
+            wall_time,
+            usage: ResourceUsage {
+                wall_time,
+                ..Default::default()
+            },
+            seccomp_violation: None,
+        }
+    }
+
+    /**
+     * Looks up the syscall that killed the jail init in the audit records of the kernel, when it was killed by SIGSYS.
+     * Must be called right after the jail is reaped, before its pid can be reused
+     */
+    pub fn add_seccomp_violation(&mut self) {
+        if self.reason != TermReason::Seccomp || self.seccomp_violation.is_some() {
+            return;
+        }
+        // the audit records are stamped to the millisecond, ahead of the start of the jail to be safe
+        let since = SystemTime::now() - self.wall_time - Duration::from_secs(1);
+        self.seccomp_violation = sandbox::read_violation(self.pid, since);
+    }
+
+    /**
+     * Takes the violation the seccomp supervisor of jconf killed the jail init for, see supervisor::kill, whose SIGKILL
+     * is then a seccomp violation rather than a kill by someone else
+     */
+    pub fn add_supervised_violation(&mut self, jconf: &JailConf) {
+        match jconf.seccomp_violation {
+            Some(ref v)
+                if v.pid == self.pid
+                    && self.reason == TermReason::Exited
+                    && self.signal == Some(libc::SIGKILL) =>
+            {
+                self.reason = TermReason::Seccomp;
+                self.seccomp_violation = Some(v.clone());
+            }
+            _ => (),
+        }
+    }
+
+    /// The jail was killed on request, which explains its end unless something else killed it first
+    pub fn set_killed(&mut self) {
+        if self.reason == TermReason::Exited {
+            self.reason = TermReason::Killed;
+        }
+    }
+
+    /// Adds what clean_after_child measured, an OOM kill or a pids.max hit explain a jail that did not exit with 0
+    pub fn add_usage(&mut self, usage: &ResourceUsage) {
+        self.usage.update(usage);
+        if self.reason != TermReason::Exited || self.status == 0 {
+            return;
+        }
+        if self.usage.oom_killed {
+            self.reason = TermReason::Oom;
+        } else if self.usage.pids_max_hits > 0 {
+            self.reason = TermReason::PidsLimit;
+        }
+    }
+}
+
+/**
+ * Blocking wait of the jail init `pid` that enforces a wall-clock limit of `tlimit` seconds (0 means none)
+ *
+ * Once the limit is reached the jail gets SIGTERM, and SIGKILL `grace` seconds later if it is still running.
+ * pid must be the init of its own pid namespace so that killing it tears down the whole namespace;
+ * the signals are also sent to its process group since the init only receives the SIGTERM if it handles it
+ *
+ * A pidfd is used to sleep until the jail exits or a deadline is reached, with a fallback on polling waitpid
+ * on kernels that do not have pidfd_open
+ */
+pub fn wait_with_tlimit(pid: i32, tlimit: u64, grace: u64) -> Result<ExitReport> {
+    wait_jail(pid, tlimit, grace, None)
+}
+
+/**
+ * wait_with_tlimit with the time limit of jconf, which also answers the syscalls the seccomp filter of the jail hands
+ * over to its supervisor meanwhile, see supervisor::handle
+ */
+pub fn wait_supervised(pid: i32, jconf: &mut JailConf) -> Result<ExitReport> {
+    wait_jail(pid, jconf.tlimit, jconf.tlimit_grace, Some(jconf))
+}
+
+fn wait_jail(
+    pid: i32,
+    tlimit: u64,
+    grace: u64,
+    mut jconf: Option<&mut JailConf>,
+) -> Result<ExitReport> {
+    let start = Instant::now();
+    let supervised = jconf.as_ref().and_then(|j| j.seccomp_notify_fd).is_some();
+    let pidfd = if tlimit > 0 || supervised {
+        unsafe { libc::syscall(libc::SYS_pidfd_open, pid, 0) as i32 }
+    } else {
+        -1
+    };
+
+    let res = wait_loop(pid, pidfd, start, tlimit, grace, jconf.as_deref_mut());
+    if pidfd >= 0 {
+        unsafe { libc::close(pidfd) };
+    }
+    let (wait_status, timed_out, ru) = res?;
+
+    let mut report =
+        ExitReport::from_wait_status(pid, wait_status, timed_out, tlimit, start.elapsed());
+    report.usage.update(&ResourceUsage::from_rusage(&ru));
+    if let Some(jconf) = jconf {
+        report.add_supervised_violation(jconf);
+    }
+    report.add_seccomp_violation();
+    Ok(report)
+}
+
+const POLL_FALLBACK_MS: u64 = 50;
+
+fn wait_loop(
+    pid: i32,
+    pidfd: i32,
+    start: Instant,
+    tlimit: u64,
+    grace: u64,
+    mut jconf: Option<&mut JailConf>,
+) -> Result<(i32, bool, libc::rusage)> {
+    let mut wait_status: i32 = 0;
+    let mut ru: libc::rusage = unsafe { mem::zeroed() };
+    let mut timed_out = false;
+    let mut killed = false;
+    let mut deadline = if tlimit > 0 {
+        Some(start + Duration::from_secs(tlimit))
+    } else {
+        None
+    };
+    // -1 once every process of the jail is gone, or when it has no seccomp supervisor
+    let mut listener = jconf
+        .as_ref()
+        .and_then(|j| j.seccomp_notify_fd)
+        .unwrap_or(-1);
+
+    loop {
+        let flags = if deadline.is_some() || listener >= 0 {
+            libc::WNOHANG
+        } else {
+            0
+        };
+        let r = unsafe { libc::wait4(pid, &mut wait_status as *mut libc::c_int, flags, &mut ru) };
+        if r == pid {
+            return Ok((wait_status, timed_out, ru));
+        }
+        if r < 0 {
+            let err = Errno::last();
+            if err == Errno::EINTR {
+                continue;
+            }
+            return Err(format!("Error waiting the child process to finish: {}", err).into());
+        }
+
+        let now = Instant::now();
+        if deadline.is_some_and(|d| now >= d) {
+            if !timed_out && grace > 0 {
+                timed_out = true;
+                signal_jail(pid, libc::SIGTERM);
+                deadline = Some(now + Duration::from_secs(grace));
+            } else if !killed {
+                timed_out = true;
+                killed = true;
+                signal_jail(pid, libc::SIGKILL);
+                deadline = None;
+            }
+            continue;
+        }
+
+        // rounded up so that we do not spin during the last millisecond, without a deadline until the jail exits or
+        // a syscall waits for the supervisor
+        let mut timeout = match deadline {
+            Some(d) => ((d - now).as_millis() + 1).min(libc::c_int::MAX as u128) as libc::c_int,
+            None => -1,
+        };
+        if pidfd < 0 && (timeout < 0 || timeout > POLL_FALLBACK_MS as libc::c_int) {
+            timeout = POLL_FALLBACK_MS as libc::c_int;
+        }
+        // poll skips the negative fds
+        let mut pfds = [
+            libc::pollfd {
+                fd: pidfd,
+                events: libc::POLLIN,
+                revents: 0,
+            },
+            libc::pollfd {
+                fd: listener,
+                events: libc::POLLIN,
+                revents: 0,
+            },
+        ];
+        if unsafe { libc::poll(pfds.as_mut_ptr(), 2, timeout) } < 0 {
+            if Errno::last() == Errno::EINTR {
+                continue;
+            }
+            return Err(format!("Error polling the child pidfd: {}", Errno::last()).into());
+        }
+
+        if pfds[1].revents & libc::POLLIN != 0 {
+            if let Some(jconf) = jconf.as_mut() {
+                if let Err(e) = supervisor::handle(jconf) {
+                    // the syscalls waiting for it fail with ENOSYS rather than forever
+                    println!("WARNING: stopping the seccomp supervisor of {}: {}", pid, e);
+                    supervisor::finish_from_parent(jconf);
+                    jconf.seccomp_notify_fd = None;
+                    listener = -1;
+                }
+            }
+        } else if pfds[1].revents != 0 {
+            listener = -1;
+        }
+    }
+}
+
+pub(crate) fn signal_jail(pid: i32, sig: libc::c_int) {
+    // errors are ignored: the jail may have exited in between, or not be a process group leader with skip_setsid
+    unsafe {
+        libc::kill(pid, sig);
+        libc::kill(-pid, sig);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the child only calls async-signal-safe functions, so forking from the test harness threads is fine
+    fn fork_child(ignore_sigterm: bool, secs: u32) -> i32 {
+        let pid = unsafe { libc::fork() };
+        assert!(pid >= 0);
+        if pid == 0 {
+            unsafe {
+                if ignore_sigterm {
+                    libc::signal(libc::SIGTERM, libc::SIG_IGN);
+                }
+                libc::sleep(secs);
+                libc::_exit(7);
+            }
+        }
+        pid
+    }
+
+    #[test]
+    fn term_reasons() {
+        let d = Duration::from_secs(1);
+        // wait statuses: the exit code in the second byte, the signal in the first one
+        let mut report = ExitReport::from_wait_status(1, libc::SIGSYS, false, 0, d);
+        assert_eq!(report.reason, TermReason::Seccomp);
+        report.seccomp_violation = Some(SeccompViolation {
+            pid: 1,
+            nr: 41,
+            name: Some("socket"),
+            arch: 0xc000003e,
+            args: None,
+            instruction_pointer: 0x7f00,
+        });
+        assert!(report
+            .to_string()
+            .contains("seccomp violation, syscall socket (41) on x86_64 by pid 1 at 0x7f00"));
+        let report = ExitReport::from_wait_status(1, libc::SIGXCPU, false, 0, d);
+        assert_eq!(report.reason, TermReason::CpuRlimit);
+        let report = ExitReport::from_wait_status(1, libc::SIGKILL, true, 5, d);
+        assert_eq!(report.reason, TermReason::TimeLimit);
+        let mut report = ExitReport::from_wait_status(1, libc::SIGTERM, false, 0, d);
+        report.set_killed();
+        assert_eq!(report.reason, TermReason::Killed);
+        assert_eq!(report.reason.code(), 6);
+        assert!(report.to_string().contains("killed on request"));
+        let mut report = ExitReport::from_wait_status(1, libc::SIGSYS, false, 0, d);
+        report.set_killed();
+        assert_eq!(report.reason, TermReason::Seccomp);
+
+        // killed with SIGKILL by the seccomp supervisor, which knows the arguments
+        let jconf = JailConf {
+            seccomp_violation: Some(SeccompViolation {
+                pid: 1,
+                nr: 41,
+                name: Some("socket"),
+                arch: 0xc000003e,
+                args: Some([2, 1, 0, 0, 0, 0]),
+                instruction_pointer: 0x7f00,
+            }),
+            ..Default::default()
+        };
+        let mut report = ExitReport::from_wait_status(1, libc::SIGKILL, false, 0, d);
+        report.add_supervised_violation(&jconf);
+        assert_eq!(report.reason, TermReason::Seccomp);
+        assert!(report
+            .to_string()
+            .contains("at 0x7f00, args [0x2, 0x1, 0x0, 0x0, 0x0, 0x0]"));
+        let mut report = ExitReport::from_wait_status(2, libc::SIGKILL, false, 0, d);
+        report.add_supervised_violation(&jconf);
+        assert_eq!(report.reason, TermReason::Exited);
+
+        let oom = ResourceUsage {
+            oom_killed: true,
+            ..Default::default()
+        };
+        let mut report = ExitReport::from_wait_status(1, libc::SIGKILL, false, 0, d);
+        report.add_usage(&oom);
+        assert_eq!(report.reason, TermReason::Oom);
+        assert_eq!(report.reason.code(), 2);
+
+        // a jail that survived the loss of one of its processes
+        let mut report = ExitReport::from_wait_status(1, 0, false, 0, d);
+        report.add_usage(&oom);
+        assert_eq!(report.reason, TermReason::Exited);
+
+        let mut report = ExitReport::from_wait_status(1, 1 << 8, false, 0, d);
+        report.add_usage(&ResourceUsage {
+            pids_max_hits: 3,
+            ..Default::default()
+        });
+        assert_eq!(report.reason, TermReason::PidsLimit);
+        assert_eq!(report.status, 1);
+    }
+
+    #[test]
+    fn exits_before_tlimit() {
+        let pid = fork_child(false, 0);
+        let report = wait_with_tlimit(pid, 5, 1).unwrap();
+        assert_eq!(report.reason, TermReason::Exited);
+        assert_eq!(report.status, 7);
+        assert_eq!(report.signal, None);
+    }
+
+    #[test]
+    fn tlimit_sends_sigterm_then_sigkill() {
+        let pid = fork_child(false, 30);
+        let report = wait_with_tlimit(pid, 1, 5).unwrap();
+        assert_eq!(report.reason, TermReason::TimeLimit);
+        assert_eq!(report.signal, Some(libc::SIGTERM));
+        assert_eq!(report.status, 128 + libc::SIGTERM);
+        assert!(report
+            .to_string()
+            .contains("exceeding its time limit of 1s"));
+
+        let pid = fork_child(true, 30);
+        let report = wait_with_tlimit(pid, 1, 1).unwrap();
+        assert_eq!(report.reason, TermReason::TimeLimit);
+        assert_eq!(report.signal, Some(libc::SIGKILL));
+        assert!(report.wall_time >= Duration::from_secs(2));
+    }
 }
@@ -0,0 +1,278 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// Error returned by AddrPool, an address the controlling process asked for is either not in the pool or already taken
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    InvalidCidr(String),
+    InvalidAddr(String),
+    OutOfRange(IpAddr),
+    InUse(IpAddr),
+    Exhausted,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::InvalidCidr(ref s) => write!(f, "invalid CIDR {:?}", s),
+            Error::InvalidAddr(ref s) => write!(f, "invalid address {:?}", s),
+            Error::OutOfRange(ref addr) => write!(f, "{} is not in the toaster network", addr),
+            Error::InUse(ref addr) => write!(f, "{} is already given to another toaster", addr),
+            Error::Exhausted => write!(f, "no address left in the toaster network"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/**
+ * Hands out the addresses of an IPv4 or IPv6 network to toasters and takes them back once they are reaped.
+ *
+ * Addresses are stored as offsets from the network address, so the same code covers both families. The network
+ * address is never given, nor the IPv4 broadcast, and the gateway must be reserved by the caller. Allocation walks
+ * from the last given address, so a released address is not handed out again right away, which leaves time for
+ * neighbour caches and connections of the previous toaster to expire.
+ */
+pub struct AddrPool {
+    network: IpAddr,
+    prefix_len: u8,
+    first: u128, // first and last usable offsets
+    last: u128,
+    next: u128,
+    in_use: HashSet<u128>,
+}
+
+impl AddrPool {
+    /// network may have host bits set, e.g. the gateway, they are ignored
+    pub fn new(network: IpAddr, prefix_len: u8) -> Result<AddrPool> {
+        let width = match network {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        // at least two usable addresses, the gateway and one toaster, and the number of addresses must fit in a u128
+        if prefix_len > width - 2 || (width == 128 && prefix_len == 0) {
+            return Err(Error::InvalidCidr(format!("{}/{}", network, prefix_len)));
+        }
+
+        let host_bits = u32::from(width - prefix_len);
+        let size = 1u128 << host_bits;
+        let network = from_u128(network, to_u128(network) & !(size - 1));
+        let last = match network {
+            IpAddr::V4(_) => size - 2, // broadcast
+            IpAddr::V6(_) => size - 1,
+        };
+
+        Ok(AddrPool {
+            network,
+            prefix_len,
+            first: 1, // the network address, the subnet-router anycast address in IPv6
+            last,
+            next: 1,
+            in_use: HashSet::new(),
+        })
+    }
+
+    /// Parses a network written as <address>/<prefix length>, e.g. "10.166.0.0/16" or "fd00:7a::/64"
+    pub fn parse(cidr: &str) -> Result<AddrPool> {
+        let invalid = || Error::InvalidCidr(cidr.to_owned());
+        let mut parts = cidr.splitn(2, '/');
+        let addr = parts
+            .next()
+            .and_then(|a| a.parse::<IpAddr>().ok())
+            .ok_or_else(invalid)?;
+        let prefix_len = parts
+            .next()
+            .and_then(|p| p.parse::<u8>().ok())
+            .ok_or_else(invalid)?;
+        AddrPool::new(addr, prefix_len)
+    }
+
+    /// Lowest usable address, by convention the gateway of the network
+    pub fn first(&self) -> IpAddr {
+        from_u128(self.network, to_u128(self.network) + self.first)
+    }
+
+    pub fn prefix_len(&self) -> u8 {
+        self.prefix_len
+    }
+
+    pub fn netmask(&self) -> IpAddr {
+        match self.network {
+            IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::from(
+                (u64::from(u32::MAX) << (32 - self.prefix_len)) as u32,
+            )),
+            IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::from(
+                u128::MAX
+                    .checked_shl(128 - u32::from(self.prefix_len))
+                    .unwrap_or(0),
+            )),
+        }
+    }
+
+    /// Number of addresses that can still be handed out
+    pub fn available(&self) -> u128 {
+        self.last - self.first + 1 - self.in_use.len() as u128
+    }
+
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        self.offset(addr).is_some()
+    }
+
+    /// Marks an address chosen by someone else as taken, e.g. the gateway or an address sent by the controlling process
+    pub fn reserve(&mut self, addr: IpAddr) -> Result<()> {
+        let offset = self.offset(addr).ok_or(Error::OutOfRange(addr))?;
+        if !self.in_use.insert(offset) {
+            return Err(Error::InUse(addr));
+        }
+        Ok(())
+    }
+
+    pub fn allocate(&mut self) -> Result<IpAddr> {
+        if self.available() == 0 {
+            return Err(Error::Exhausted);
+        }
+
+        let mut offset = self.next;
+        while self.in_use.contains(&offset) {
+            offset = if offset == self.last {
+                self.first
+            } else {
+                offset + 1
+            };
+        }
+
+        self.in_use.insert(offset);
+        self.next = if offset == self.last {
+            self.first
+        } else {
+            offset + 1
+        };
+
+        Ok(from_u128(self.network, to_u128(self.network) + offset))
+    }
+
+    /// Gives an address back to the pool, returns false if it was not taken
+    pub fn release(&mut self, addr: IpAddr) -> bool {
+        match self.offset(addr) {
+            Some(offset) => self.in_use.remove(&offset),
+            None => false,
+        }
+    }
+
+    fn offset(&self, addr: IpAddr) -> Option<u128> {
+        if addr.is_ipv4() != self.network.is_ipv4() {
+            return None;
+        }
+        let offset = to_u128(addr).checked_sub(to_u128(self.network))?;
+        if offset < self.first || offset > self.last {
+            return None;
+        }
+        Some(offset)
+    }
+}
+
+/// Every toaster gets an address from v4, and one from v6 as well when the toaster network is dual-stack
+pub struct AddrPools {
+    pub v4: AddrPool,
+    pub v6: Option<AddrPool>,
+}
+
+impl AddrPools {
+    /// Gives an address back to the pool of its family
+    pub fn release(&mut self, addr: IpAddr) -> bool {
+        match (addr, self.v6.as_mut()) {
+            (IpAddr::V4(_), _) => self.v4.release(addr),
+            (IpAddr::V6(_), Some(v6)) => v6.release(addr),
+            (IpAddr::V6(_), None) => false,
+        }
+    }
+}
+
+fn to_u128(addr: IpAddr) -> u128 {
+    match addr {
+        IpAddr::V4(a) => u128::from(u32::from(a)),
+        IpAddr::V6(a) => u128::from(a),
+    }
+}
+
+fn from_u128(family: IpAddr, v: u128) -> IpAddr {
+    match family {
+        IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::from(v as u32)),
+        IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::from(v)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_allocate_reserve_release_v4() {
+        let mut pool = AddrPool::parse("10.166.3.4/30").unwrap();
+        assert_eq!(pool.netmask(), ip("255.255.255.252"));
+        assert_eq!(pool.available(), 2);
+
+        pool.reserve(ip("10.166.3.5")).unwrap();
+        assert_eq!(
+            pool.reserve(ip("10.166.3.5")),
+            Err(Error::InUse(ip("10.166.3.5")))
+        );
+        // network and broadcast addresses are never given
+        assert_eq!(
+            pool.reserve(ip("10.166.3.4")),
+            Err(Error::OutOfRange(ip("10.166.3.4")))
+        );
+        assert_eq!(
+            pool.reserve(ip("10.166.3.7")),
+            Err(Error::OutOfRange(ip("10.166.3.7")))
+        );
+        assert_eq!(
+            pool.reserve(ip("fd00::1")),
+            Err(Error::OutOfRange(ip("fd00::1")))
+        );
+
+        assert_eq!(pool.allocate(), Ok(ip("10.166.3.6")));
+        assert_eq!(pool.allocate(), Err(Error::Exhausted));
+
+        assert!(pool.release(ip("10.166.3.6")));
+        assert!(!pool.release(ip("10.166.3.6")));
+        assert_eq!(pool.allocate(), Ok(ip("10.166.3.6")));
+    }
+
+    #[test]
+    fn test_released_addresses_are_reused_last() {
+        let mut pool = AddrPool::new(ip("10.166.0.1"), 16).unwrap();
+        assert_eq!(pool.netmask(), ip("255.255.0.0"));
+        pool.reserve(ip("10.166.0.1")).unwrap();
+
+        assert_eq!(pool.allocate(), Ok(ip("10.166.0.2")));
+        assert_eq!(pool.allocate(), Ok(ip("10.166.0.3")));
+        assert!(pool.release(ip("10.166.0.2")));
+        assert_eq!(pool.allocate(), Ok(ip("10.166.0.4")));
+        assert_eq!(pool.available(), 65534 - 3);
+    }
+
+    #[test]
+    fn test_v6() {
+        let mut pool = AddrPool::parse("fd00:7a::/64").unwrap();
+        assert_eq!(pool.netmask(), ip("ffff:ffff:ffff:ffff::"));
+        assert_eq!(pool.available(), (1 << 64) - 1);
+        assert_eq!(pool.first(), ip("fd00:7a::1"));
+        pool.reserve(ip("fd00:7a::1")).unwrap();
+        assert_eq!(pool.allocate(), Ok(ip("fd00:7a::2")));
+        assert!(pool.contains(ip("fd00:7a::ffff:ffff:ffff:ffff")));
+        assert!(!pool.contains(ip("fd00:7b::2")));
+
+        assert!(AddrPool::parse("fd00::/127").is_err());
+        assert!(AddrPool::parse("::/0").is_err());
+        assert!(AddrPool::parse("10.0.0.0/31").is_err());
+        assert!(AddrPool::parse("10.0.0.0").is_err());
+    }
+}
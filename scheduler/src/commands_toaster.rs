@@ -1,14 +1,20 @@
 use std::ffi::{CStr, CString};
+use std::fmt;
+use std::net::IpAddr;
 use std::os::unix::io::AsRawFd;
 use std::os::unix::io::RawFd;
 use std::os::unix::net::{UnixListener, UnixStream};
 
+use super::addr_pool::{self, AddrPool, AddrPools};
 use super::config::{create_toaster_jconf, create_toaster_pool_jconf, set_jconf_as_join};
 use super::gtvs_message::{GtvsMessageReader, GtvsMessageWriter};
 use super::hash_table::{HashTable, Item};
 use super::net::connect_unix_blocking;
 use super::pool::{Item as PoolItem, NamespacePool};
-use super::waiter::Waiter;
+use super::waiter::{Waiter, LIMIT_EVENT_TAG, SECCOMP_NOTIFY_TAG};
+use jail::config::PortForward;
+use jail::protobuf::create_command_error_mess;
+use jail::protobuf::extract_u32;
 use jail::protobuf::parse_toaster_command;
 use jail::protobuf::put_u32;
 
@@ -32,7 +38,10 @@ pub fn execute_toaster<'a>(
     efd: i32,
     toaster_pool: &mut NamespacePool<'a>,
     hash_table: &mut HashTable<'a>,
+    addr_pools: &mut AddrPools,
+    nm: &'a CStr,
     gw: &'a CStr,
+    gw6: &'a CStr,
     non_root_owner: libc::uid_t,
     non_root_group: libc::gid_t,
     read_endpoint_fd: i32,
@@ -56,7 +65,19 @@ pub fn execute_toaster<'a>(
         command_args,
         env,
         ip,
-    ) = get_toaster_message(gtvs_mess_buffer_reader);
+        port_forwards,
+    ) = match get_toaster_message(gtvs_mess_buffer_reader) {
+        Ok(v) => v,
+        Err(e) => {
+            let mess = gtvs_mess_buffer_reader.get_message();
+            let exe_id = if mess.len() >= 7 {
+                extract_u32(mess, 3)
+            } else {
+                0
+            };
+            return reject_toaster(gtvs_mess_buffer_writer, exe_id, e);
+        }
+    };
 
     if let Some(cmd_name) = command_name {
         immediate_execution(
@@ -65,7 +86,10 @@ pub fn execute_toaster<'a>(
             efd,
             toaster_pool,
             hash_table,
+            addr_pools,
+            nm,
             gw,
+            gw6,
             non_root_owner,
             non_root_group,
             read_endpoint_fd,
@@ -88,6 +112,7 @@ pub fn execute_toaster<'a>(
             command_args,
             env,
             ip,
+            port_forwards,
         );
 
         return;
@@ -98,7 +123,10 @@ pub fn execute_toaster<'a>(
         num_cpus,
         non_root_owner,
         non_root_group,
+        addr_pools,
+        nm,
         gw,
+        gw6,
         gtvs_mess_buffer_writer,
         toaster_pool,
         pool,
@@ -118,7 +146,10 @@ fn create_pool_toaster<'a>(
     num_cpus: i64,
     non_root_owner: libc::uid_t,
     non_root_group: libc::gid_t,
+    addr_pools: &mut AddrPools,
+    nm: &'a CStr,
     gw: &'a CStr,
+    gw6: &'a CStr,
     gtvs_mess_buffer_writer: &mut GtvsMessageWriter,
     toaster_pool: &mut NamespacePool<'a>,
     // -
@@ -138,7 +169,7 @@ fn create_pool_toaster<'a>(
         panic!("pool cannot be null when no command name is provided");
     }
 
-    let mut pool_item = create_pool_item(
+    let mut pool_item = match create_pool_item(
         local_cloud_provider,
         btrfs_file_system,
         uid,
@@ -148,10 +179,16 @@ fn create_pool_toaster<'a>(
         overlay_dir,
         non_root_owner,
         non_root_group,
+        addr_pools,
+        nm,
         gw,
+        gw6,
         admin,
         false,
-    );
+    ) {
+        Ok(item) => item,
+        Err(e) => return reject_toaster(gtvs_mess_buffer_writer, exe_id, e),
+    };
 
     if let Some(log_path) = log_path {
         let logfd =
@@ -172,7 +209,7 @@ fn create_pool_toaster<'a>(
     mess[1] = 17 as u8;
     mess[2] = 100;
     put_u32(&mut mess, 3, exe_id);
-    gtvs_mess_buffer_writer.write_mess(mess);
+    gtvs_mess_buffer_writer.write_mess(&mess);
 }
 
 fn immediate_execution<'a>(
@@ -181,7 +218,10 @@ fn immediate_execution<'a>(
     efd: i32,
     toaster_pool: &mut NamespacePool<'a>,
     hash_table: &mut HashTable<'a>,
+    addr_pools: &mut AddrPools,
+    nm: &'a CStr,
     gw: &'a CStr,
+    gw6: &'a CStr,
     non_root_owner: libc::uid_t,
     non_root_group: libc::gid_t,
     read_endpoint_fd: i32,
@@ -207,6 +247,7 @@ fn immediate_execution<'a>(
     command_args: Option<Vec<CString>>,
     env: Option<Vec<CString>>,
     ip: CString,
+    port_forwards: Vec<PortForward>,
 ) {
     let mut item = if pool > 0 && toaster_pool.len((pool - 1) as usize) > 0 {
         let mut item = toaster_pool.pop((pool - 1) as usize);
@@ -216,7 +257,7 @@ fn immediate_execution<'a>(
         }
         item
     } else {
-        create_pool_item(
+        match create_pool_item(
             local_cloud_provider,
             btrfs_file_system,
             uid,
@@ -226,10 +267,16 @@ fn immediate_execution<'a>(
             overlay_dir,
             non_root_owner,
             non_root_group,
+            addr_pools,
+            nm,
             gw,
+            gw6,
             admin,
             true,
-        )
+        ) {
+            Ok(item) => item,
+            Err(e) => return reject_toaster(gtvs_mess_buffer_writer, exe_id, e),
+        }
     };
 
     mount_overlayfs(lower_dirs, &mut item);
@@ -272,6 +319,7 @@ fn immediate_execution<'a>(
     item.jconf.exec_file = Some(command_name);
     item.jconf.argv = command_args;
     item.jconf.env = env;
+    item.jconf.port_forwards = port_forwards;
 
     let (child_pid, child_pidfd) = match subproc::run_child_pidfd(&mut item.jconf, subproc::child) {
         Ok(v) => v,
@@ -280,8 +328,11 @@ fn immediate_execution<'a>(
                 p,
                 exe_id,
                 gtvs_mess_buffer_writer,
+                addr_pools,
                 &item.jconf,
                 Some(item.ovdir),
+                item.ip,
+                item.ip6,
             );
             return;
         }
@@ -290,7 +341,34 @@ fn immediate_execution<'a>(
         }
     };
 
-    epoll_register_running_child(child_pid as u64, efd, child_pidfd);
+    let registered = epoll_register_running_child(child_pid as u64, efd, child_pidfd)
+        .and_then(|_| {
+            epoll_register_limit_events(child_pid as u64, efd, &item.jconf.limit_event_fds)
+        })
+        .and_then(|_| match item.jconf.seccomp_notify_fd {
+            Some(fd) => epoll_register_seccomp_notify(child_pid as u64, efd, fd),
+            None => Ok(()),
+        });
+    if let Err(e) = registered {
+        // a toaster that cannot be watched is killed and reaped now, the other toasters keep running
+        reject_toaster(
+            gtvs_mess_buffer_writer,
+            exe_id,
+            format!("could not register the toaster into epoll: {}", e),
+        );
+        kill_unwatched(child_pid, child_pidfd);
+        waiter.wait_pid_from_err(
+            child_pid,
+            exe_id,
+            gtvs_mess_buffer_writer,
+            addr_pools,
+            &item.jconf,
+            Some(item.ovdir),
+            item.ip,
+            item.ip6,
+        );
+        return;
+    }
 
     hash_table.insert(Item {
         pid: child_pid,
@@ -298,12 +376,39 @@ fn immediate_execution<'a>(
         jconf: item.jconf,
         ovdir: Some(item.ovdir),
         toaster_listener: execution_listener,
+        ip: item.ip,
+        ip6: item.ip6,
+        killed: false,
+        paused: false,
+        kill_after_left: None,
     });
+    hash_table.insert_exe_id(exe_id as i32, child_pid as u32);
+}
+
+/// SIGKILLs a toaster and waits for it to be a zombie, for wait_pid_from_err to reap it
+fn kill_unwatched(child_pid: i32, child_pidfd: RawFd) {
+    unsafe {
+        if libc::kill(child_pid, libc::SIGKILL) != 0 {
+            println!("Could not SIGKILL {}: {}", child_pid, Errno::last());
+        }
+        // closing the pidfd takes it out of the epoll if it was registered
+        libc::close(child_pidfd);
+
+        let mut info: libc::siginfo_t = std::mem::zeroed();
+        while libc::waitid(
+            libc::P_PID,
+            child_pid as libc::id_t,
+            &mut info,
+            libc::WEXITED | libc::WNOWAIT,
+        ) != 0
+            && Errno::last() == Errno::EINTR
+        {}
+    }
 }
 
 fn get_toaster_message<'a>(
     gtvs_mess_buffer_reader: &'a GtvsMessageReader,
-) -> (
+) -> jail::error::Result<(
     u32,
     u16,
     CString,
@@ -320,7 +425,8 @@ fn get_toaster_message<'a>(
     Option<Vec<CString>>,
     Option<Vec<CString>>,
     CString,
-) {
+    Vec<PortForward>,
+)> {
     let mess = gtvs_mess_buffer_reader.get_message();
     parse_toaster_command(mess)
 }
@@ -335,10 +441,28 @@ fn create_pool_item<'a>(
     overlay_dir: &[u8],
     non_root_owner: libc::uid_t,
     non_root_group: libc::gid_t,
+    addr_pools: &mut AddrPools,
+    nm: &'a CStr,
     gw: &'a CStr,
+    gw6: &'a CStr,
     admin: bool,
     immediate_execution: bool,
-) -> PoolItem<'a> {
+) -> addr_pool::Result<PoolItem<'a>> {
+    let ip = toaster_ip(&mut addr_pools.v4, ip)?;
+    let ip6 = match addr_pools
+        .v6
+        .as_mut()
+        .map(|pool| pool.allocate())
+        .transpose()
+    {
+        Ok(ip6) => ip6,
+        Err(e) => {
+            addr_pools.v4.release(ip);
+            return Err(e);
+        }
+    };
+    let ip_cstr = CString::new(ip.to_string()).unwrap();
+
     let ovdir = OverlayDir::new(
         btrfs_file_system,
         overlay_dir,
@@ -349,12 +473,13 @@ fn create_pool_item<'a>(
     )
     .expect("could not create overlaydir");
 
-    let jconf = if immediate_execution {
+    let mut jconf = if immediate_execution {
         create_toaster_jconf(
             local_cloud_provider,
             ovdir.mount_point.clone(),
             num_cpus,
-            ip,
+            ip_cstr,
+            nm,
             gw,
             cwd,
             false,
@@ -365,7 +490,8 @@ fn create_pool_item<'a>(
             local_cloud_provider,
             ovdir.mount_point.clone(),
             num_cpus,
-            ip,
+            ip_cstr,
+            nm,
             gw,
             cwd,
             true,
@@ -373,10 +499,46 @@ fn create_pool_item<'a>(
         )
     };
 
-    PoolItem {
+    if let (Some(ip6), Some(pool)) = (ip6, addr_pools.v6.as_ref()) {
+        jconf.with_net_config_v6(
+            CString::new(format!("{}/{}", ip6, pool.prefix_len())).unwrap(),
+            gw6,
+        );
+    }
+
+    Ok(PoolItem {
         jconf: jconf,
         ovdir: ovdir,
+        ip,
+        ip6,
+    })
+}
+
+/// The controlling process may still send the ip of a toaster, it is then only checked against the pool,
+/// an empty one lets the pool pick
+fn toaster_ip(addr_pool: &mut AddrPool, ip: CString) -> addr_pool::Result<IpAddr> {
+    if ip.as_bytes().is_empty() {
+        return addr_pool.allocate();
     }
+
+    let ip = ip
+        .to_str()
+        .ok()
+        .and_then(|s| s.parse::<IpAddr>().ok())
+        .ok_or_else(|| addr_pool::Error::InvalidAddr(ip.to_string_lossy().into_owned()))?;
+    addr_pool.reserve(ip)?;
+    Ok(ip)
+}
+
+/// Answers a toaster command that is invalid or could not get an address with an error, the other toasters keep
+/// running
+fn reject_toaster(
+    gtvs_mess_buffer_writer: &mut GtvsMessageWriter,
+    exe_id: u32,
+    e: impl fmt::Display,
+) {
+    println!("WARNING: could not start toaster exe {}: {}", exe_id, e);
+    gtvs_mess_buffer_writer.write_mess(&create_command_error_mess(exe_id, &e.to_string()));
 }
 
 fn mount_overlayfs(lower_dirs: Option<&[u8]>, item: &mut PoolItem) {
@@ -438,8 +600,31 @@ fn handle_toaster_execution_listener(
     lst
 }
 
-pub fn epoll_register_running_child(child_pid: u64, epfd: RawFd, child_pidfd: RawFd) {
+pub fn epoll_register_running_child(
+    child_pid: u64,
+    epfd: RawFd,
+    child_pidfd: RawFd,
+) -> Result<(), Errno> {
     let mut event = EpollEvent::new(EpollFlags::EPOLLIN | EpollFlags::EPOLLONESHOT, child_pid);
     epoll_ctl(epfd, EpollOp::EpollCtlAdd, child_pidfd, &mut event)
-        .expect("could not register child_pidfd into epoll");
+}
+
+/// The fds readable when the child hits a cgroup limit, tagged with LIMIT_EVENT_TAG to be told apart from its pidfd
+pub fn epoll_register_limit_events(
+    child_pid: u64,
+    epfd: RawFd,
+    fds: &[RawFd],
+) -> Result<(), Errno> {
+    for fd in fds.iter() {
+        let mut event = EpollEvent::new(EpollFlags::EPOLLIN, LIMIT_EVENT_TAG | child_pid);
+        epoll_ctl(epfd, EpollOp::EpollCtlAdd, *fd, &mut event)?;
+    }
+    Ok(())
+}
+
+/// The listener of the seccomp filter of the child, readable when one of its syscalls waits for the supervisor, see
+/// Waiter::seccomp_notify
+pub fn epoll_register_seccomp_notify(child_pid: u64, epfd: RawFd, fd: RawFd) -> Result<(), Errno> {
+    let mut event = EpollEvent::new(EpollFlags::EPOLLIN, SECCOMP_NOTIFY_TAG | child_pid);
+    epoll_ctl(epfd, EpollOp::EpollCtlAdd, fd, &mut event)
 }
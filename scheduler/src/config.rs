@@ -1,3 +1,4 @@
+use jail::cgroup;
 use jail::config::{IDMapT, JailConf, MountT};
 
 use seccomp::{allow_syscall, BpfProgram, SeccompAction, SeccompFilter};
@@ -6,7 +7,6 @@ use std::convert::TryInto;
 use std::ffi::{CStr, CString};
 
 const TOAST1: &[u8] = "toast1\0".as_bytes();
-const NMCIDR16: &[u8] = "255.255.0.0\0".as_bytes();
 const EMPTY: &[u8] = "\0".as_bytes();
 
 pub fn create_toaster_pool_jconf<'a>(
@@ -14,6 +14,7 @@ pub fn create_toaster_pool_jconf<'a>(
     root_dir: CString,
     num_cpus: i64,
     ip: CString,
+    nm: &'a CStr,
     gw: &'a CStr,
     cwd: String,
     mount_slave: bool,
@@ -23,7 +24,7 @@ pub fn create_toaster_pool_jconf<'a>(
         root_dir,
         unsafe { CStr::from_bytes_with_nul_unchecked(TOAST1) },
         ip,
-        unsafe { CStr::from_bytes_with_nul_unchecked(NMCIDR16) },
+        nm,
         gw,
         unsafe { CStr::from_bytes_with_nul_unchecked(EMPTY) },
     );
@@ -33,6 +34,7 @@ pub fn create_toaster_pool_jconf<'a>(
     jconf.mnt_ms_slave = mount_slave;
 
     jconf.clone_newcgroup = true;
+    enable_freezer(&mut jconf);
     jconf.clone_newipc = true;
     jconf.clone_newnet = true;
     jconf.clone_newns = true;
@@ -60,7 +62,8 @@ pub fn create_toaster_pool_jconf<'a>(
 
     jconf.iface_lo = true;
 
-    // jconf.seccomp_filter = Some(toastate_default_filter());
+    // opt-in, the toasters have not been checked against it yet, see seccomp::profiles
+    // jconf.seccomp_profile = "default";
     jconf.uids.push(IDMapT {
         inside_id: 1,
         outside_id: 1,
@@ -128,11 +131,25 @@ pub fn set_jconf_as_join(jconf: &mut JailConf) {
     jconf.join_sleeping_thread = true;
 }
 
+/// For gtvs to pause the toaster, see Waiter::pause_pid, when the host has a freezer the jail can be pointed at
+fn enable_freezer(jconf: &mut JailConf) {
+    let host = cgroup::host();
+    if !host.has_freezer() {
+        return;
+    }
+    jconf.cgroup_freezer = true;
+    if let Err(e) = host.select_backend(jconf) {
+        println!("WARNING: toasters cannot be paused: {}", e);
+        jconf.cgroup_freezer = false;
+    }
+}
+
 pub fn create_toaster_jconf<'a>(
     local_cloud_provider: &str,
     root_dir: CString,
     num_cpus: i64,
     ip: CString,
+    nm: &'a CStr,
     gw: &'a CStr,
     cwd: String,
     mount_slave: bool,
@@ -142,7 +159,7 @@ pub fn create_toaster_jconf<'a>(
         root_dir,
         unsafe { CStr::from_bytes_with_nul_unchecked(TOAST1) },
         ip,
-        unsafe { CStr::from_bytes_with_nul_unchecked(NMCIDR16) },
+        nm,
         gw,
         unsafe { CStr::from_bytes_with_nul_unchecked(EMPTY) },
     );
@@ -152,6 +169,7 @@ pub fn create_toaster_jconf<'a>(
     jconf.mnt_ms_slave = mount_slave;
 
     jconf.clone_newcgroup = true;
+    enable_freezer(&mut jconf);
     jconf.clone_newipc = true;
     jconf.clone_newnet = true;
     jconf.clone_newns = true;
@@ -178,7 +196,8 @@ pub fn create_toaster_jconf<'a>(
 
     jconf.iface_lo = true;
 
-    // jconf.seccomp_filter = Some(toastate_default_filter());
+    // opt-in, the toasters have not been checked against it yet, see seccomp::profiles
+    // jconf.seccomp_profile = "default";
     jconf.uids.push(IDMapT {
         inside_id: 1,
         outside_id: 1,
@@ -241,39 +260,6 @@ pub fn create_toaster_jconf<'a>(
     jconf
 }
 
-// pub fn toastate_default_filter() -> BpfProgram {
-//     SeccompFilter::new(
-//         vec![
-//             allow_syscall(libc::SYS_rt_sigprocmask),
-//             allow_syscall(libc::SYS_rt_sigaction),
-//             allow_syscall(libc::SYS_execve),
-//             #[cfg(target_arch = "x86_64")]
-//             allow_syscall(libc::SYS_mmap),
-//             #[cfg(target_arch = "aarch64")]
-//             // See this issue for why we are doing it this way on arch64:
-//             // https://github.com/rust-lang/libc/issues/1348.
-//             allow_syscall(SYS_mmap),
-//             #[cfg(target_arch = "x86_64")]
-//             allow_syscall(libc::SYS_arch_prctl),
-//             allow_syscall(libc::SYS_set_tid_address),
-//             #[cfg(target_arch = "x86_64")]
-//             allow_syscall(libc::SYS_readlink),
-//             #[cfg(target_arch = "x86_64")]
-//             allow_syscall(libc::SYS_open),
-//             allow_syscall(libc::SYS_read),
-//             allow_syscall(libc::SYS_close),
-//             allow_syscall(libc::SYS_brk),
-//             allow_syscall(libc::SYS_sched_getaffinity),
-//         ]
-//         .into_iter()
-//         .collect(),
-//         SeccompAction::Trap,
-//     )
-//     .unwrap()
-//     .try_into()
-//     .unwrap()
-// }
-
 /*
 
 pub fn create_image_jconf<'a>(
@@ -283,13 +269,14 @@ pub fn create_image_jconf<'a>(
     num_cpus: i64,
     admin: bool,
     ip: CString,
+    nm: &'a CStr,
     gw: &'a CStr,
 ) -> Result<JailConf<'a>> {
     let mut jconf = JailConf::new_from_root(
         root_dir,
         unsafe { CStr::from_bytes_with_nul_unchecked(TOAST1) },
         ip,
-        unsafe { CStr::from_bytes_with_nul_unchecked(NMCIDR16) },
+        nm,
         gw,
         unsafe { CStr::from_bytes_with_nul_unchecked(EMPTY) },
     );
@@ -26,7 +26,7 @@ pub struct GtvsMessageWriter {
     writer_event: sys_util::epoll::EpollEvent,
     writer_fd: i32,
     conn_write: UnixStream,
-    buffer: Vec<[u8; 19]>,
+    buffer: Vec<Vec<u8>>,
     write_index: usize,
 }
 
@@ -47,11 +47,11 @@ impl GtvsMessageWriter {
     }
 
     /// write return true if data remains to be written, false if it wrote all provided mess and all data remaining in its internal buffer
-    pub fn write_mess(&mut self, mess: [u8; 19]) -> bool {
+    pub fn write_mess(&mut self, mess: &[u8]) -> bool {
         if mess.len() > GTVSMESSAGEMAXSIZE {
             panic!("gtvs write mess mess.len() > GTVSMESSAGEMAXSIZE")
         }
-        self.buffer.insert(0, mess);
+        self.buffer.insert(0, mess.to_vec());
         self.try_write()
     }
 
@@ -1,3 +1,4 @@
+use std::net::IpAddr;
 use std::os::unix::net::{UnixListener, UnixStream};
 
 use disk::overlay_fs::OverlayDir;
@@ -54,6 +55,11 @@ pub struct Item<'a> {
     pub jconf: JailConf<'a>,
     pub ovdir: Option<OverlayDir>,
     pub toaster_listener: Option<UnixListener>,
+    pub ip: IpAddr,
+    pub ip6: Option<IpAddr>,
+    pub killed: bool, // by a kill message of gtvs, see Waiter::kill_pid
+    pub paused: bool, // frozen by a pause message of gtvs, see Waiter::pause_pid
+    pub kill_after_left: Option<u64>, // seconds left before the SIGKILL of a killed toaster, out of kill_after while paused
 }
 
 impl<'a> HashTable<'a> {
@@ -121,6 +127,17 @@ impl<'a> HashTable<'a> {
         ret
     }
 
+    /// Takes the deadline of pid out of kill_after, returns the seconds that were left before it
+    pub fn take_kill_after(&mut self, pid: u64) -> Option<u64> {
+        let now = timestamp_second(0);
+
+        self.kill_after
+            .iter_mut()
+            .find(|tab| matches!(tab, Some(tab2) if tab2[0] == pid))
+            .and_then(|tab| tab.take())
+            .map(|tab2| tab2[1].saturating_sub(now))
+    }
+
     pub fn lookup_exe_id(&self, exe_id: u32) -> Option<u32> {
         for tab in self.by_exe_id[(exe_id & self.modulo as u32) as usize].iter() {
             if tab[0] == exe_id as u32 {
@@ -177,7 +194,24 @@ impl<'a> HashTable<'a> {
         None
     }
 
-    pub fn pop(&mut self, key: i32) -> Option<Item> {
+    /// Pid of the running toaster of exe_id, still found once a kill message took exe_id out of the index of
+    /// lookup_exe_id, the toaster may run until the SIGKILL that follows the SIGTERM
+    pub fn find_exe_id(&self, exe_id: u32) -> Option<i32> {
+        self.iter().find(|item| item.exe_id == exe_id).map(|item| item.pid)
+    }
+
+    /// Every running toaster
+    pub fn iter(&self) -> impl Iterator<Item = &Item<'a>> {
+        self.store.iter().flatten()
+    }
+
+    pub fn borrow_mut(&mut self, key: i32) -> Option<&mut Item<'a>> {
+        self.store[(key & self.modulo) as usize]
+            .iter_mut()
+            .find(|tab| tab.pid == key)
+    }
+
+    pub fn pop(&mut self, key: i32) -> Option<Item<'a>> {
         let vector = &mut self.store[(key & self.modulo) as usize];
         let mut k: usize = 0;
         let mut found = false;
@@ -217,3 +251,23 @@ impl<'a> HashTable<'a> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_take_kill_after() {
+        let mut h = HashTable::new();
+        assert!(h.put_in_kill_after(7, timestamp_second(30)).is_empty());
+        assert!(h.put_in_kill_after(8, timestamp_second(0)).is_empty());
+
+        let left = h.take_kill_after(7).unwrap();
+        assert!((29..=30).contains(&left), "{}", left);
+        assert_eq!(h.take_kill_after(7), None);
+
+        // only the deadlines still in kill_after expire
+        assert_eq!(h.extract_kill_after(), vec![8]);
+        assert!(h.extract_kill_after().is_empty());
+    }
+}
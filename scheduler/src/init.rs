@@ -2,7 +2,18 @@ use std::env;
 
 use jail::init_package;
 
-pub fn init_miscellaneous() -> (String, String, String, i64, u32, u32) {
+/// args: <id> <socket dir> <cloud provider> <non root uid> <non root gid> [toaster network CIDR, default <gateway>/16]
+/// [toaster IPv6 network CIDR, toasters are dual-stack when given]
+pub fn init_miscellaneous() -> (
+    String,
+    String,
+    String,
+    i64,
+    u32,
+    u32,
+    Option<String>,
+    Option<String>,
+) {
     let args: Vec<String> = env::args().collect();
 
     let socket_path_incoming = format!("{}/t_0_{}.sock", &args[2], &args[1]);
@@ -14,6 +25,9 @@ pub fn init_miscellaneous() -> (String, String, String, i64, u32, u32) {
 
     let non_root_gid: u32 = (&args[5]).parse().unwrap();
 
+    let toaster_cidr = args.get(6).cloned();
+    let toaster_cidr6 = args.get(7).cloned();
+
     init_package(non_root_uid, non_root_gid);
 
     (
@@ -23,5 +37,7 @@ pub fn init_miscellaneous() -> (String, String, String, i64, u32, u32) {
         unsafe { libc::sysconf(libc::_SC_NPROCESSORS_ONLN) },
         non_root_uid,
         non_root_gid,
+        toaster_cidr,
+        toaster_cidr6,
     )
 }
@@ -1,8 +1,10 @@
+pub mod addr_pool;
 pub mod commands_toaster;
 pub mod config;
 mod gtvs_message;
 mod hash_table;
 mod init;
+mod metrics;
 pub mod net;
 pub mod pool;
 pub mod stateloop;
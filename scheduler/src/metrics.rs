@@ -0,0 +1,112 @@
+use jail::protobuf::{create_metrics_mess, create_metrics_not_found_mess, METRICS_PER_MESS};
+use jail::usage::{live_metrics, LiveMetrics};
+
+use super::gtvs_message::GtvsMessageWriter;
+use super::hash_table::{HashTable, Item};
+
+/// Live metrics of the running toasters for the dashboard of gtvs
+///
+/// Answers the metrics command of exe_id with one message, a not found one if it is not running anymore, and the
+/// metrics command of every toaster (exe_id None) with as many messages as needed, the last one not full
+pub fn send_metrics(
+    exe_id: Option<u32>,
+    hash_table: &HashTable,
+    gtvs_mess_buffer: &mut GtvsMessageWriter,
+) {
+    let metrics_of = |item: &Item| (item.exe_id, live_metrics(&item.jconf, item.pid));
+
+    let metrics: Vec<(u32, LiveMetrics)> = match exe_id {
+        Some(exe_id) => match hash_table
+            .find_exe_id(exe_id)
+            .and_then(|pid| hash_table.lookup(pid))
+        {
+            Some(item) => vec![metrics_of(item)],
+            None => {
+                gtvs_mess_buffer.write_mess(&create_metrics_not_found_mess(exe_id));
+                return;
+            }
+        },
+        None => hash_table.iter().map(metrics_of).collect(),
+    };
+
+    let mut chunks = metrics.chunks(METRICS_PER_MESS);
+    loop {
+        match chunks.next() {
+            Some(chunk) => {
+                gtvs_mess_buffer.write_mess(&create_metrics_mess(chunk));
+                if chunk.len() < METRICS_PER_MESS {
+                    return;
+                }
+            }
+            None => {
+                // an empty message says there is nothing more
+                gtvs_mess_buffer.write_mess(&create_metrics_mess(&[]));
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jail::config::JailConf;
+    use jail::protobuf::{
+        extract_u16, extract_u32, extract_u64, METRICS_MESS_TYPE, METRICS_NOT_FOUND_MESS_TYPE,
+    };
+    use std::io::Read;
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::os::unix::net::UnixStream;
+    use std::process::Command;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_send_metrics() {
+        let mut child = Command::new("sleep").arg("100").spawn().unwrap();
+
+        let mut jconf = JailConf::default();
+        jconf.child_start = Some(Instant::now());
+        let mut hash_table = HashTable::new();
+        hash_table.insert(Item {
+            pid: child.id() as i32,
+            exe_id: 42,
+            jconf,
+            ovdir: None,
+            toaster_listener: None,
+            ip: IpAddr::V4(Ipv4Addr::new(10, 166, 0, 2)),
+            ip6: None,
+            killed: false,
+            paused: false,
+            kill_after_left: None,
+        });
+
+        // for the sleep to have exec'd and be up for a while
+        thread::sleep(Duration::from_millis(100));
+
+        let (conn_write, mut conn_read) = UnixStream::pair().unwrap();
+        let mut gtvs_mess_buffer = GtvsMessageWriter::new(conn_write, -1);
+        send_metrics(Some(42), &hash_table, &mut gtvs_mess_buffer);
+        send_metrics(Some(7), &hash_table, &mut gtvs_mess_buffer);
+        drop(gtvs_mess_buffer);
+        child.kill().unwrap();
+        child.wait().unwrap();
+
+        let mut written = vec![];
+        conn_read.read_to_end(&mut written).unwrap();
+        let len = 2 + extract_u16(&written, 0) as usize;
+        let (mess, not_found) = written.split_at(len);
+
+        assert_eq!(mess[2], METRICS_MESS_TYPE);
+        assert_eq!(extract_u16(mess, 3), 1);
+        assert_eq!(extract_u32(mess, 5), 42);
+        // cpu time, memory, threads and uptime in milliseconds of the sleep
+        assert!(extract_u64(mess, 17) > 0);
+        assert_eq!(extract_u64(mess, 25), 1);
+        assert!(extract_u64(mess, 33) >= 100);
+
+        assert_eq!(not_found.len(), 2 + 1 + 4);
+        assert_eq!(not_found[2], METRICS_NOT_FOUND_MESS_TYPE);
+        assert_eq!(extract_u32(not_found, 3), 7);
+    }
+}
@@ -1,3 +1,5 @@
+use std::net::IpAddr;
+
 use jail::config::JailConf;
 
 use disk::overlay_fs::OverlayDir;
@@ -11,6 +13,8 @@ pub struct NamespacePool<'a> {
 pub struct Item<'a> {
     pub jconf: JailConf<'a>,
     pub ovdir: OverlayDir,
+    pub ip: IpAddr, // taken from the AddrPools, given back when the toaster is reaped
+    pub ip6: Option<IpAddr>,
 }
 
 impl<'a> NamespacePool<'a> {
@@ -1,4 +1,5 @@
 use std::ffi::CString;
+use std::net::{IpAddr, Ipv4Addr};
 /// Light & Simple Uni - loop scheduler
 /// To scale, launch 1 uni-loop scheduler per processor core, since golang handles code download and comunication anyway so it can round robin the load across all such schedulers
 /// This way we avoid the burden (time) of Rust Futures that we should implement at the same time we get completely rid of golang for virtualization by implementing the session
@@ -22,15 +23,17 @@ use std::os::unix::io::AsRawFd;
 
 use sys_util::epoll::EpollEvent;
 
+use super::addr_pool::{AddrPool, AddrPools};
 use super::commands_toaster::execute_toaster;
 
 use super::gtvs_message::{GtvsMessageReader, GtvsMessageWriter};
 use super::hash_table::HashTable;
 use super::init::init_miscellaneous;
+use super::metrics::send_metrics;
 use super::net::{init_net_epoll, poll_fd_events};
 use super::pool::NamespacePool;
-use super::waiter::Waiter;
-use jail::protobuf::parse_kill_mess;
+use super::waiter::{Waiter, LIMIT_EVENT_TAG, SECCOMP_NOTIFY_TAG};
+use jail::protobuf::{parse_exe_id_mess, parse_kill_mess};
 
 #[derive(Debug, PartialEq)]
 enum State {
@@ -50,6 +53,8 @@ pub fn start() {
         num_cpus,
         non_root_uid,
         non_root_gid,
+        toaster_cidr,
+        toaster_cidr6,
     ) = init_miscellaneous();
 
     let (
@@ -63,14 +68,32 @@ pub fn start() {
         pool_size,
     ) = init_net_epoll(&socket_path_incoming, &socket_path_outgoing);
 
-    let gateway = CString::new(format!(
-        "{}.{}.{}.{}",
-        (gateway >> 24) as u8,
-        (gateway >> 16) as u8,
-        (gateway >> 8) as u8,
-        gateway as u8
-    ))
-    .unwrap();
+    // toasters get their address from these pools, the network is the /16 of the gateway unless configured,
+    // with an optional IPv6 network whose first address is the gateway
+    let gateway = IpAddr::V4(Ipv4Addr::from(gateway));
+    let mut addr_pools = AddrPools {
+        v4: match toaster_cidr {
+            Some(ref cidr) => AddrPool::parse(cidr),
+            None => AddrPool::new(gateway, 16),
+        }
+        .expect("invalid toaster network"),
+        v6: toaster_cidr6.map(|cidr| AddrPool::parse(&cidr).expect("invalid toaster ipv6 network")),
+    };
+    addr_pools
+        .v4
+        .reserve(gateway)
+        .expect("the gateway must be in the toaster network");
+
+    let netmask = CString::new(addr_pools.v4.netmask().to_string()).unwrap();
+    let gateway = CString::new(gateway.to_string()).unwrap();
+    let gateway6 = match addr_pools.v6 {
+        Some(ref mut v6) => {
+            let gw6 = v6.first();
+            v6.reserve(gw6).expect("could not reserve the ipv6 gateway");
+            CString::new(gw6.to_string()).unwrap()
+        }
+        None => CString::default(),
+    };
 
     let endpoint_read_fd_u64 = endpoint_read_fd as u64;
     let endpoint_write_fd_u64 = endpoint_write.as_raw_fd() as u64;
@@ -156,12 +179,24 @@ pub fn start() {
                                 gtvs_mess_buffer_writer.try_write();
                             }
                         }
+                        x if x & LIMIT_EVENT_TAG != 0 => {
+                            waiter.limit_event(x as u32 as i32, &mut pid_hash_table);
+                        }
+                        x if x & SECCOMP_NOTIFY_TAG != 0 => {
+                            waiter.seccomp_notify(
+                                x as u32 as i32,
+                                &events[i],
+                                efd,
+                                &mut pid_hash_table,
+                            );
+                        }
                         pid => {
                             // println!("pid triggered: {} {}", pid, i);
                             waiter.wait_pid(
                                 pid as i32,
                                 &mut gtvs_mess_buffer_writer,
                                 &mut pid_hash_table,
+                                &mut addr_pools,
                             );
                         }
                     };
@@ -194,7 +229,7 @@ pub fn start() {
                     //     efd,
                     //     &mut namespace_pool,
                     //     &mut pid_hash_table,
-                    //     &mut ip_allocator,
+                    //     &mut addr_pools,
                     //     &mut waiter,
                     //     &gateway,
                     // ),
@@ -206,7 +241,10 @@ pub fn start() {
                         efd,
                         &mut namespace_pool,
                         &mut pid_hash_table,
+                        &mut addr_pools,
+                        &netmask,
                         &gateway,
+                        &gateway6,
                         non_root_uid,
                         non_root_gid,
                         endpoint_read_fd,
@@ -217,10 +255,33 @@ pub fn start() {
                         let mess = gtvs_mess_buffer_reader.get_message();
                         let (exe_id, timeout_sec) =
                             parse_kill_mess(mess).expect("invalid kill message");
-                        if let Some(pid) = pid_hash_table.pop_exe_id(exe_id) {
-                            waiter.kill_pid(pid as i32, timeout_sec, &mut pid_hash_table);
-                        }
+                        waiter.kill_exe_id(exe_id, timeout_sec, &mut pid_hash_table);
                     }
+                    4 => match parse_exe_id_mess(gtvs_mess_buffer_reader.get_message()) {
+                        Ok(exe_id) => send_metrics(
+                            Some(exe_id),
+                            &pid_hash_table,
+                            &mut gtvs_mess_buffer_writer,
+                        ),
+                        Err(e) => println!("WARNING: dropping invalid metrics message: {}", e),
+                    },
+                    5 => send_metrics(None, &pid_hash_table, &mut gtvs_mess_buffer_writer),
+                    6 => match parse_exe_id_mess(gtvs_mess_buffer_reader.get_message()) {
+                        Ok(exe_id) => {
+                            if let Some(pid) = pid_hash_table.find_exe_id(exe_id) {
+                                waiter.pause_pid(pid, &mut pid_hash_table);
+                            }
+                        }
+                        Err(e) => println!("WARNING: dropping invalid pause message: {}", e),
+                    },
+                    7 => match parse_exe_id_mess(gtvs_mess_buffer_reader.get_message()) {
+                        Ok(exe_id) => {
+                            if let Some(pid) = pid_hash_table.find_exe_id(exe_id) {
+                                waiter.resume_pid(pid, &mut pid_hash_table);
+                            }
+                        }
+                        Err(e) => println!("WARNING: dropping invalid resume message: {}", e),
+                    },
                     _ => panic!("invalid mess first byte"),
                 }
 
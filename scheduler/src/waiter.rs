@@ -1,29 +1,63 @@
 use std::ffi::CStr;
+use std::mem;
+use std::net::IpAddr;
 
+use sys_util::epoll::{epoll_ctl, EpollEvent, EpollOp};
 use sys_util::errno::Errno;
 
 use jail::config::JailConf;
-use jail::sandbox::which_seccomp_violation_pid_only;
+use jail::freezer;
+use jail::limits;
 use jail::subproc;
+use jail::supervisor;
+use jail::usage::ResourceUsage;
+use jail::wait::{ExitReport, TermReason};
 
 use disk::overlay_fs::OverlayDir;
 
+use super::addr_pool::AddrPools;
 use super::gtvs_message::GtvsMessageWriter;
 use super::hash_table::HashTable;
-use jail::protobuf::{parse_uint32_cstr, put_u32};
 use super::time_utils::{timestamp_micro, timestamp_second};
+use jail::protobuf::{
+    create_seccomp_violation_mess, create_usage_mess, parse_uint32_cstr, put_u16, put_u32,
+};
+
+/// Set in the epoll data of the limit event fds of a toaster, whose pid is in the lower 32 bits
+pub const LIMIT_EVENT_TAG: u64 = 1 << 32;
+
+/// Set in the epoll data of the seccomp listener of a toaster, whose pid is in the lower 32 bits
+pub const SECCOMP_NOTIFY_TAG: u64 = 1 << 33;
+
+// len u16, type u8, exe id, exit status, signal and overlay uid u32, termination reason u8, see TermReason::code
+const EXIT_MESS_LEN: usize = 20;
 
 pub struct Waiter {
     wait_status: i32,
+    rusage: libc::rusage,
 }
 
 impl Waiter {
     pub fn new() -> Self {
-        Waiter { wait_status: 0 }
+        Waiter {
+            wait_status: 0,
+            rusage: unsafe { mem::zeroed() },
+        }
+    }
+
+    /// Kills the toaster of exe_id for a kill message of gtvs, once: exe_id leaves the index of the toasters to kill
+    pub fn kill_exe_id(&self, exe_id: u32, timeout_sec: u64, pid_hash_table: &mut HashTable) {
+        if let Some(pid) = pid_hash_table.pop_exe_id(exe_id) {
+            self.kill_pid(pid as i32, timeout_sec, pid_hash_table);
+        }
     }
 
     pub fn kill_pid(&self, pid: i32, timeout_sec: u64, pid_hash_table: &mut HashTable) {
-        if let Some(_) = pid_hash_table.borrow(pid) {
+        // a frozen toaster could not handle SIGTERM, nor even die of SIGKILL with the cgroup v1 freezer
+        self.resume_pid(pid, pid_hash_table);
+
+        if let Some(item) = pid_hash_table.borrow_mut(pid) {
+            item.killed = true;
             if timeout_sec == 0 {
                 if unsafe { libc::kill(pid, libc::SIGKILL) } != 0 {
                     println!("Could not SIGKILL {}: {}", pid, Errno::last());
@@ -33,19 +67,62 @@ impl Waiter {
                     println!("Could not SIGTERM {}: {}", pid, Errno::last());
                 }
 
-                let to_kill =
-                    pid_hash_table.put_in_kill_after(pid as u64, timestamp_second(timeout_sec));
-                if to_kill.len() > 0 {
-                    for p in to_kill {
-                        if unsafe { libc::kill(p as i32, libc::SIGKILL) } != 0 {
-                            println!("Could not SIGKILL {}: {}", p, Errno::last());
-                        }
-                    }
+                self.kill_after(pid, timeout_sec, pid_hash_table);
+            }
+        }
+    }
+
+    fn kill_after(&self, pid: i32, timeout_sec: u64, pid_hash_table: &mut HashTable) {
+        let to_kill = pid_hash_table.put_in_kill_after(pid as u64, timestamp_second(timeout_sec));
+        if to_kill.len() > 0 {
+            for p in to_kill {
+                if unsafe { libc::kill(p as i32, libc::SIGKILL) } != 0 {
+                    println!("Could not SIGKILL {}: {}", p, Errno::last());
                 }
             }
         }
     }
 
+    /**
+     * Freezes every process of the toaster pid, to make room on the host without killing it. The time it stays paused
+     * does not count toward the kill_after deadline of a killed toaster, its deadline is only set back by resume_pid
+     */
+    pub fn pause_pid(&self, pid: i32, pid_hash_table: &mut HashTable) {
+        match pid_hash_table.borrow_mut(pid) {
+            Some(item) if !item.paused => {
+                if let Err(e) = freezer::freeze(&item.jconf, &pid.to_string()) {
+                    println!("Could not pause {}: {}", pid, e);
+                    return;
+                }
+                item.paused = true;
+            }
+            _ => return,
+        }
+
+        let left = pid_hash_table.take_kill_after(pid as u64);
+        if let Some(item) = pid_hash_table.borrow_mut(pid) {
+            item.kill_after_left = left;
+        }
+    }
+
+    pub fn resume_pid(&self, pid: i32, pid_hash_table: &mut HashTable) {
+        let left = match pid_hash_table.borrow_mut(pid) {
+            Some(item) if item.paused => {
+                if let Err(e) = freezer::thaw(&item.jconf, &pid.to_string()) {
+                    println!("Could not resume {}: {}", pid, e);
+                    return;
+                }
+                item.paused = false;
+                item.kill_after_left.take()
+            }
+            _ => return,
+        };
+
+        if let Some(left) = left {
+            self.kill_after(pid, left, pid_hash_table);
+        }
+    }
+
     pub fn kill_timed_out(&self, pid_hash_table: &mut HashTable) {
         let to_kill = pid_hash_table.extract_kill_after();
         if to_kill.len() > 0 {
@@ -57,20 +134,69 @@ impl Waiter {
         }
     }
 
+    /// One of the limit event fds of pid is readable, the toaster ran into its memory or pids limit
+    pub fn limit_event(&self, pid: i32, pid_hash_table: &mut HashTable) {
+        if let Some(item) = pid_hash_table.borrow_mut(pid) {
+            for fd in item.jconf.limit_event_fds.iter() {
+                limits::drain(*fd);
+            }
+            // the counters at exit are in the usage report and the termination reason
+            let hits = limits::read_hits(&item.jconf, &pid.to_string());
+            println!("toaster exe {} (pid {}): {}", item.exe_id, pid, hits);
+        }
+    }
+
+    /**
+     * The seccomp listener of pid is readable, a syscall of the toaster waits for its supervisor, or it hung up once
+     * every process of the toaster is gone and it leaves the epoll, clean_after_child closes it
+     */
+    pub fn seccomp_notify(
+        &self,
+        pid: i32,
+        event: &EpollEvent,
+        efd: i32,
+        pid_hash_table: &mut HashTable,
+    ) {
+        if let Some(item) = pid_hash_table.borrow_mut(pid) {
+            let fd = match item.jconf.seccomp_notify_fd {
+                Some(fd) => fd,
+                None => return,
+            };
+            if !event.is_readable() {
+                if let Err(e) = epoll_ctl(efd, EpollOp::EpollCtlDel, fd, None) {
+                    println!(
+                        "WARNING: could not remove the seccomp listener of {} from epoll: {}",
+                        pid, e
+                    );
+                }
+            } else if let Err(e) = supervisor::handle(&mut item.jconf) {
+                // closing it takes it out of the epoll, and fails the syscalls waiting for it with ENOSYS
+                println!(
+                    "WARNING: stopping the seccomp supervisor of toaster exe {} (pid {}): {}",
+                    item.exe_id, pid, e
+                );
+                supervisor::finish_from_parent(&item.jconf);
+                item.jconf.seccomp_notify_fd = None;
+            }
+        }
+    }
+
     pub fn wait_pid<'a>(
         &mut self,
         pid_src: i32,
         gtvs_mess_buffer: &'a mut GtvsMessageWriter,
         pid_hash_table: &'a mut HashTable,
+        addr_pools: &mut AddrPools,
     ) {
         // println!("wait_pid: {}", pid_src);
-        // println!("{}", which_seccomp_violation_pid_only(pid)); // TODO: send refused syscall to gtvs if one, test only in the case of an error
 
+        self.rusage = unsafe { mem::zeroed() };
         let mut pid = unsafe {
-            libc::waitpid(
+            libc::wait4(
                 pid_src,
                 &mut self.wait_status as *mut libc::c_int,
                 libc::WNOHANG,
+                &mut self.rusage,
             )
         };
         if pid < 0 {
@@ -79,14 +205,29 @@ impl Waiter {
             pid = pid_src;
         }
         if let Some(item) = pid_hash_table.pop(pid) {
+            // already gone from the index if it was killed by gtvs
+            pid_hash_table.pop_exe_id(item.exe_id);
+
             // println!(
             //     "found item corresponding to pid in hash table, toaster exe {} ",
             //     item.exe_id
             // ); // timestamp_micro()-item.timestamp_micro
 
-            subproc::clean_after_child(&item.jconf, pid).expect("could not clean_after_child");
+            let usage =
+                subproc::clean_after_child(&item.jconf, pid).expect("could not clean_after_child");
+            let reason = self.write_report(
+                item.exe_id,
+                pid,
+                &item.jconf,
+                item.killed,
+                &usage,
+                gtvs_mess_buffer,
+            );
+
+            // the NET namespace, and so every use of the address, is gone with the child
+            release_ips(addr_pools, item.ip, item.ip6);
 
-            let mut mess: [u8; 19] = [0; 19];
+            let mut mess = [0u8; EXIT_MESS_LEN];
 
             if let Some(ovdir) = item.ovdir {
                 put_u32(&mut mess, 15, parse_uint32_cstr(ovdir.uid.as_c_str()));
@@ -99,9 +240,9 @@ impl Waiter {
                 mess[2] = 1; // mess type
             }
 
-            mess[0] = (17 >> 8) as u8; // len with len uint16 excluded
-            mess[1] = 17 as u8;
+            put_u16(&mut mess, 0, EXIT_MESS_LEN as u16 - 2); // len with len uint16 excluded
             put_u32(&mut mess, 3, item.exe_id); // exe id
+            mess[19] = reason.code();
             if unsafe { libc::WIFEXITED(self.wait_status) } {
                 let status = unsafe { libc::WEXITSTATUS(self.wait_status) };
                 put_u32(&mut mess, 7, status as u32);
@@ -111,7 +252,7 @@ impl Waiter {
                 put_u32(&mut mess, 11, kill_by_signal as u32);
             }
 
-            gtvs_mess_buffer.write_mess(mess);
+            gtvs_mess_buffer.write_mess(&mess);
         } else {
             println!("WARNING: could not find pid item: {}", pid);
         }
@@ -122,26 +263,33 @@ impl Waiter {
         pid: i32,
         exe_id: u32,
         gtvs_mess_buffer: &'a mut GtvsMessageWriter,
+        addr_pools: &mut AddrPools,
         jconf: &JailConf,
         ovdir: Option<OverlayDir>,
+        ip: IpAddr,
+        ip6: Option<IpAddr>,
     ) {
         // println!("wait_pid_from_err: {} {}", pid, exe_id);
-        // println!("{}", which_seccomp_violation_pid_only(pid)); // TODO: send refused syscall to gtvs if one, test only in the case of an error
 
+        self.rusage = unsafe { mem::zeroed() };
         let pid = unsafe {
-            libc::waitpid(
+            libc::wait4(
                 pid as i32,
                 &mut self.wait_status as *mut libc::c_int,
                 libc::WNOHANG,
+                &mut self.rusage,
             )
         };
         if pid < 0 {
             panic!("Error waiting: {}", Errno::last());
         }
 
-        subproc::clean_after_child(jconf, pid).expect("could not clean_after_child");
+        let usage = subproc::clean_after_child(jconf, pid).expect("could not clean_after_child");
+        let reason = self.write_report(exe_id, pid, jconf, false, &usage, gtvs_mess_buffer);
 
-        let mut mess: [u8; 19] = [0; 19];
+        release_ips(addr_pools, ip, ip6);
+
+        let mut mess = [0u8; EXIT_MESS_LEN];
 
         if let Some(ovdir) = ovdir {
             put_u32(&mut mess, 15, parse_uint32_cstr(ovdir.uid.as_c_str()));
@@ -154,9 +302,9 @@ impl Waiter {
             mess[2] = 4; // mess type
         }
 
-        mess[0] = (17 >> 8) as u8; // len with len uint16 excluded
-        mess[1] = 17 as u8;
+        put_u16(&mut mess, 0, EXIT_MESS_LEN as u16 - 2); // len with len uint16 excluded
         put_u32(&mut mess, 3, exe_id);
+        mess[19] = reason.code();
         if unsafe { libc::WIFEXITED(self.wait_status) } {
             let mut status = unsafe { libc::WEXITSTATUS(self.wait_status) };
             if status == 0 {
@@ -169,6 +317,128 @@ impl Waiter {
             put_u32(&mut mess, 11, kill_by_signal as u32);
         }
 
-        gtvs_mess_buffer.write_mess(mess);
+        gtvs_mess_buffer.write_mess(&mess);
+    }
+
+    /**
+     * Sends the usage of exe_id to gtvs, the cgroup values of usage complete the rusage of the last wait4, then the
+     * syscall that killed it if it was killed by seccomp and the syscall could be found, and returns why the toaster
+     * ended, killed when by a kill message of gtvs
+     */
+    fn write_report(
+        &self,
+        exe_id: u32,
+        pid: i32,
+        jconf: &JailConf,
+        killed: bool,
+        usage: &ResourceUsage,
+        gtvs_mess_buffer: &mut GtvsMessageWriter,
+    ) -> TermReason {
+        let mut report =
+            ExitReport::from_wait_status(pid, self.wait_status, false, 0, usage.wall_time);
+        report.add_supervised_violation(jconf);
+        if killed {
+            report.set_killed();
+        }
+        report.add_usage(&ResourceUsage::from_rusage(&self.rusage));
+        report.add_usage(usage);
+        gtvs_mess_buffer.write_mess(&create_usage_mess(exe_id, &report.usage));
+
+        report.add_seccomp_violation();
+        if let Some(ref violation) = report.seccomp_violation {
+            println!("WARNING: toaster exe {}: {}", exe_id, report);
+            gtvs_mess_buffer.write_mess(&create_seccomp_violation_mess(exe_id, violation));
+        }
+        report.reason
+    }
+}
+
+fn release_ips(addr_pools: &mut AddrPools, ip: IpAddr, ip6: Option<IpAddr>) {
+    for ip in Some(ip).into_iter().chain(ip6) {
+        if !addr_pools.release(ip) {
+            println!("WARNING: toaster ip {} was not taken from the pool", ip);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::addr_pool::AddrPool;
+    use super::super::hash_table::Item;
+    use super::*;
+    use jail::protobuf::{extract_u32, parse_kill_mess, put_u64};
+    use std::io::Read;
+    use std::os::unix::net::UnixStream;
+    use std::process::Command;
+
+    #[test]
+    fn test_kill_exe_id() {
+        let pid = Command::new("sleep").arg("100").spawn().unwrap().id() as i32;
+
+        let mut addr_pools = AddrPools {
+            v4: AddrPool::parse("10.166.0.0/16").unwrap(),
+            v6: None,
+        };
+        let ip = addr_pools.v4.allocate().unwrap();
+        let mut pid_hash_table = HashTable::new();
+        pid_hash_table.insert(Item {
+            pid,
+            exe_id: 42,
+            jconf: JailConf::default(),
+            ovdir: None,
+            toaster_listener: None,
+            ip,
+            ip6: None,
+            killed: false,
+            paused: false,
+            kill_after_left: None,
+        });
+        pid_hash_table.insert_exe_id(42, pid as u32);
+
+        // kill message of gtvs: type u8, exe_id u32, seconds between the SIGTERM and the SIGKILL u64
+        let mut mess = [0u8; 13];
+        mess[0] = 3;
+        put_u32(&mut mess, 1, 42);
+        put_u64(&mut mess, 5, 0);
+        let (exe_id, timeout_sec) = parse_kill_mess(&mess).unwrap();
+
+        let mut waiter = Waiter::new();
+        waiter.kill_exe_id(exe_id, timeout_sec, &mut pid_hash_table);
+        assert_eq!(pid_hash_table.lookup_exe_id(42), None);
+        assert!(pid_hash_table.borrow(pid).unwrap().killed);
+
+        let mut info: libc::siginfo_t = unsafe { mem::zeroed() };
+        assert_eq!(
+            unsafe {
+                libc::waitid(
+                    libc::P_PID,
+                    pid as libc::id_t,
+                    &mut info,
+                    libc::WEXITED | libc::WNOWAIT,
+                )
+            },
+            0
+        );
+
+        let (conn_write, mut conn_read) = UnixStream::pair().unwrap();
+        let mut gtvs_mess_buffer = GtvsMessageWriter::new(conn_write, -1);
+        waiter.wait_pid(
+            pid,
+            &mut gtvs_mess_buffer,
+            &mut pid_hash_table,
+            &mut addr_pools,
+        );
+        assert!(pid_hash_table.lookup(pid).is_none());
+        assert!(addr_pools.v4.reserve(ip).is_ok());
+
+        drop(gtvs_mess_buffer);
+        let mut written = vec![];
+        conn_read.read_to_end(&mut written).unwrap();
+        // the usage report comes first
+        let exit_mess = &written[written.len() - EXIT_MESS_LEN..];
+        assert_eq!(exit_mess[2], 1);
+        assert_eq!(extract_u32(exit_mess, 3), 42);
+        assert_eq!(extract_u32(exit_mess, 11), libc::SIGKILL as u32);
+        assert_eq!(exit_mess[19], TermReason::Killed.code());
     }
 }
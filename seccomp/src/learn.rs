@@ -0,0 +1,266 @@
+//! Learning of seccomp policies from the syscalls a workload makes.
+//!
+//! A [`Recorder`] is given every syscall of a run, typically by a supervisor answering the
+//! notifications of a filter whose default action is [`SeccompAction::UserNotif`] with
+//! [`Response::Continue`], see [`notify`]. It then writes the smallest policy, in the language of
+//! the [`policy`] module, that allows them: every syscall seen and, for the flag-like arguments of
+//! a few syscalls such as the domain of `socket` or the flags of `clone`, the values seen.
+//!
+//! ```
+//! extern crate seccomp;
+//!
+//! use seccomp::learn::Recorder;
+//! use seccomp::policy::parse_policy;
+//! use seccomp::syscalls::syscall_number;
+//!
+//! let mut recorder = Recorder::default();
+//! recorder.record(syscall_number("read").unwrap(), &[0, 0x1000, 64, 0, 0, 0]);
+//! recorder.record(syscall_number("socket").unwrap(), &[1, 0x80001, 0, 0, 0, 0]);
+//! assert!(parse_policy(&recorder.policy()).is_ok());
+//! ```
+//!
+//! A run only shows the paths the workload took, a policy learnt from it is a starting point to
+//! be reviewed and loosened where needed, not a guarantee.
+//!
+//! [`Recorder`]: struct.Recorder.html
+//! [`SeccompAction::UserNotif`]: ../enum.SeccompAction.html#variant.UserNotif
+//! [`Response::Continue`]: ../notify/enum.Response.html#variant.Continue
+//! [`notify`]: ../notify/index.html
+//! [`policy`]: ../policy/index.html
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use super::syscalls::{syscall_name, syscall_number};
+
+// Most combinations of argument values kept for a syscall, past it the syscall is allowed
+// whatever its arguments.
+const MAX_SHAPES: usize = 16;
+
+// Syscalls whose arguments are checked, with the index and name of the flag-like ones. Pointers,
+// sizes and file descriptors change from one run to the next and are never checked.
+const SHAPED: &[(&str, &[(usize, &str)])] = &[
+    ("socket", &[(0, "domain"), (1, "type"), (2, "protocol")]),
+    ("socketpair", &[(0, "domain"), (1, "type"), (2, "protocol")]),
+    ("setsockopt", &[(1, "level"), (2, "optname")]),
+    ("getsockopt", &[(1, "level"), (2, "optname")]),
+    ("clone", &[(0, "flags")]),
+    ("unshare", &[(0, "flags")]),
+    ("setns", &[(1, "nstype")]),
+    ("personality", &[(0, "persona")]),
+    ("prctl", &[(0, "option")]),
+    ("arch_prctl", &[(0, "code")]),
+    ("ioctl", &[(1, "request")]),
+    ("fcntl", &[(1, "cmd")]),
+];
+
+/// The syscalls made during a run, with the values of their flag-like arguments.
+#[derive(Clone, Debug, Default)]
+pub struct Recorder {
+    // The combinations of values of the checked arguments seen for each syscall, None once there
+    // were more than MAX_SHAPES or when the syscall has no argument checked.
+    syscalls: BTreeMap<i64, Option<BTreeSet<Vec<u64>>>>,
+}
+
+impl Recorder {
+    /// Records a syscall. Returns whether it was not seen yet with these arguments, which is when
+    /// [`policy`] changes.
+    ///
+    /// # Arguments
+    ///
+    /// * `nr` - Number of the syscall.
+    /// * `args` - Its arguments.
+    ///
+    /// [`policy`]: #method.policy
+    pub fn record(&mut self, nr: i64, args: &[u64; 6]) -> bool {
+        let shaped = shaped_args(nr);
+        let new = !self.syscalls.contains_key(&nr);
+        let shapes = self
+            .syscalls
+            .entry(nr)
+            .or_insert_with(|| shaped.map(|_| BTreeSet::new()));
+
+        let shape: Vec<u64> = match (shapes.as_mut(), shaped) {
+            (Some(_), Some(shaped)) => shaped.iter().map(|(i, _)| args[*i]).collect(),
+            _ => return new,
+        };
+        let set = shapes.as_mut().unwrap();
+        if set.contains(&shape) {
+            return new;
+        }
+        if set.len() == MAX_SHAPES {
+            *shapes = None;
+        } else {
+            set.insert(shape);
+        }
+        true
+    }
+
+    /// Whether no syscall was recorded.
+    pub fn is_empty(&self) -> bool {
+        self.syscalls.is_empty()
+    }
+
+    /// The policy allowing the syscalls recorded, and killing the process on any other. It can be
+    /// compiled by [`parse_policy`].
+    ///
+    /// [`parse_policy`]: ../policy/fn.parse_policy.html
+    pub fn policy(&self) -> String {
+        let mut plain = Vec::new();
+        let mut conditional = Vec::new();
+        for (nr, shapes) in self.syscalls.iter() {
+            let name = match syscall_name(*nr) {
+                Some(name) => name.to_string(),
+                None => format!("SYSCALL[{}]", nr),
+            };
+            match (shapes, shaped_args(*nr)) {
+                (Some(shapes), Some(shaped)) => conditional.push((name, shaped, shapes)),
+                _ => plain.push(name),
+            }
+        }
+
+        let mut policy = String::from(
+            "// Learnt from a run, every syscall it made with the values of their flag-like\n\
+             // arguments. Review it before use, the paths the run did not take are missing.\n\n",
+        );
+        if !plain.is_empty() {
+            policy.push_str("ALLOW {\n");
+            let mut line = String::new();
+            for name in plain {
+                if !line.is_empty() && line.len() + name.len() > 90 {
+                    policy.push_str(line.trim_end());
+                    policy.push('\n');
+                    line.clear();
+                }
+                if line.is_empty() {
+                    line.push_str("   ");
+                }
+                let _ = write!(line, " {},", name);
+            }
+            policy.push_str(&line);
+            policy.push_str("\n}\n");
+        }
+        if !conditional.is_empty() {
+            policy.push_str("ALLOW {\n");
+            for (name, shaped, shapes) in conditional {
+                let names: Vec<&str> = (0..=shaped.last().unwrap().0)
+                    .map(|i| {
+                        shaped
+                            .iter()
+                            .find(|(index, _)| *index == i)
+                            .map_or("_", |(_, name)| *name)
+                    })
+                    .collect();
+                let _ = writeln!(policy, "    {}({}) {{", name, names.join(", "));
+                for (i, shape) in shapes.iter().enumerate() {
+                    let values: Vec<String> = shaped
+                        .iter()
+                        .zip(shape)
+                        .map(|((_, arg), value)| format!("{} == {:#x}", arg, value))
+                        .collect();
+                    let _ = writeln!(
+                        policy,
+                        "        {}({})",
+                        if i == 0 { "" } else { "|| " },
+                        values.join(" && ")
+                    );
+                }
+                policy.push_str("    },\n");
+            }
+            policy.push_str("}\n");
+        }
+        policy.push_str("\nDEFAULT KILL\n");
+        policy
+    }
+}
+
+// The checked arguments of the syscall nr, if it has any.
+fn shaped_args(nr: i64) -> Option<&'static [(usize, &'static str)]> {
+    SHAPED
+        .iter()
+        .find(|(name, _)| syscall_number(name) == Some(nr))
+        .map(|(_, args)| *args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notify::{Listener, Response};
+    use crate::policy::{compile_policy, parse_policy};
+    use crate::SeccompFilter;
+
+    use std::os::unix::io::FromRawFd;
+    use std::sync::mpsc;
+    use std::thread;
+
+    fn nr(name: &str) -> i64 {
+        syscall_number(name).unwrap()
+    }
+
+    #[test]
+    fn test_record() {
+        let mut recorder = Recorder::default();
+        assert!(recorder.is_empty());
+        assert!(recorder.record(nr("read"), &[3, 0x1000, 64, 0, 0, 0]));
+        assert!(!recorder.record(nr("read"), &[4, 0x2000, 16, 0, 0, 0]));
+        assert!(recorder.record(nr("socket"), &[1, 0x80001, 0, 0x1000, 0, 0]));
+        assert!(!recorder.record(nr("socket"), &[1, 0x80001, 0, 0x2000, 0, 0]));
+        assert!(recorder.record(nr("socket"), &[2, 1, 6, 0, 0, 0]));
+        assert!(recorder.record(nr("ioctl"), &[1, 0x5401, 0x1000, 0, 0, 0]));
+        assert!(recorder.record(1000, &[0; 6]));
+
+        let policy = recorder.policy();
+        assert!(policy.contains("ALLOW {\n    read, SYSCALL[1000],\n}\n"));
+        assert!(policy.contains(
+            "    socket(domain, type, protocol) {\n        \
+             (domain == 0x1 && type == 0x80001 && protocol == 0x0)\n        \
+             || (domain == 0x2 && type == 0x1 && protocol == 0x6)\n    },\n"
+        ));
+        assert!(policy.contains("    ioctl(_, request) {\n        (request == 0x5401)\n    },\n"));
+        assert!(policy.ends_with("DEFAULT KILL\n"));
+        assert!(parse_policy(&policy).is_ok());
+    }
+
+    #[test]
+    fn test_record_too_many_shapes() {
+        let mut recorder = Recorder::default();
+        for request in 0..MAX_SHAPES as u64 {
+            assert!(recorder.record(nr("ioctl"), &[1, request, 0, 0, 0, 0]));
+        }
+        // one more and any request is allowed
+        assert!(recorder.record(nr("ioctl"), &[1, 0x5401, 0, 0, 0, 0]));
+        assert!(!recorder.record(nr("ioctl"), &[1, 0x5402, 0, 0, 0, 0]));
+        assert!(recorder.policy().contains("ALLOW {\n    ioctl,\n}\n"));
+    }
+
+    #[test]
+    fn test_learn_from_run() {
+        let (tx, rx) = mpsc::channel();
+        // the filter only applies to the thread that loads it, the test thread records it
+        let supervised = thread::spawn(move || {
+            let filter = compile_policy("USER_NOTIF { getppid, socket } DEFAULT ALLOW").unwrap();
+            tx.send(SeccompFilter::apply_with_listener(filter).unwrap())
+                .unwrap();
+            unsafe {
+                libc::getppid();
+                libc::close(libc::socket(libc::AF_UNIX, libc::SOCK_DGRAM, 0));
+            }
+        });
+
+        let listener = unsafe { Listener::from_raw_fd(rx.recv().unwrap()) };
+        let mut recorder = Recorder::default();
+        for _ in 0..2 {
+            listener
+                .handle_next(|_, n| {
+                    recorder.record(n.nr, &n.args);
+                    Response::Continue
+                })
+                .unwrap();
+        }
+        supervised.join().unwrap();
+
+        let policy = recorder.policy();
+        assert!(policy.contains("getppid,"));
+        assert!(policy.contains("(domain == 0x1 && type == 0x2 && protocol == 0x0)"));
+    }
+}
@@ -230,9 +230,17 @@
 //! [`SeccompAction`]: enum.SeccompAction.html
 //! [`SeccompFilter`]: struct.SeccompFilter.html
 //! [`action`]: struct.SeccompRule.html#action
+
+pub mod learn;
+pub mod notify;
+pub mod policy;
+pub mod profiles;
+pub mod syscalls;
+
 use std::collections::BTreeMap;
 use std::convert::TryInto;
 use std::fmt::{Display, Formatter};
+use std::os::unix::io::RawFd;
 
 /// Maximum number of instructions that a BPF program can have.
 const BPF_MAX_LEN: usize = 4096;
@@ -269,8 +277,15 @@ const SECCOMP_RET_KILL: u32 = 0x0000_0000;
 const SECCOMP_RET_LOG: u32 = 0x7ffc_0000;
 const SECCOMP_RET_TRACE: u32 = 0x7ff0_0000;
 const SECCOMP_RET_TRAP: u32 = 0x0003_0000;
+const SECCOMP_RET_USER_NOTIF: u32 = 0x7fc0_0000;
 const SECCOMP_RET_MASK: u32 = 0x0000_ffff;
 
+// Operation and flag of the seccomp syscall.
+// See /usr/include/linux/seccomp.h .
+const SECCOMP_SET_MODE_FILTER: libc::c_uint = 1;
+const SECCOMP_FILTER_FLAG_LOG: libc::c_uint = 1 << 1;
+const SECCOMP_FILTER_FLAG_NEW_LISTENER: libc::c_uint = 1 << 3;
+
 // Architecture identifier.
 // See /usr/include/linux/audit.h .
 
@@ -319,6 +334,8 @@ pub enum Error {
     InvalidArgumentNumber,
     /// Failed to load seccomp rules into the kernel.
     Load(i32),
+    /// Failed to receive or to answer a user notification.
+    Notify(i32),
 }
 
 impl Display for Error {
@@ -337,6 +354,11 @@ impl Display for Error {
                 "Failed to load seccomp rules into the kernel with error {}.",
                 err
             ),
+            Notify(err) => write!(
+                f,
+                "Failed to handle a seccomp user notification with error {}.",
+                err
+            ),
         }
     }
 }
@@ -344,7 +366,7 @@ impl Display for Error {
 type Result<T> = std::result::Result<T, Error>;
 
 /// Comparison to perform when matching a condition.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum SeccompCmpOp {
     /// Argument value is equal to the specified value.
     Eq,
@@ -363,7 +385,7 @@ pub enum SeccompCmpOp {
 }
 
 /// Seccomp argument value length.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum SeccompCmpArgLen {
     /// Argument value length is 4 bytes.
     DWORD,
@@ -372,7 +394,7 @@ pub enum SeccompCmpArgLen {
 }
 
 /// Condition that syscall must match in order to satisfy a rule.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct SeccompCondition {
     /// Index of the argument that is to be compared.
     arg_number: u8,
@@ -399,6 +421,11 @@ pub enum SeccompAction {
     Trace(u32),
     /// Sends `SIGSYS` to the calling process.
     Trap,
+    /// Suspends the syscall until the supervisor reading the listener of the filter answers it,
+    /// see [`notify`].
+    ///
+    /// [`notify`]: notify/index.html
+    UserNotif,
 }
 
 /// Rule that `seccomp` attempts to match for a syscall.
@@ -406,7 +433,7 @@ pub enum SeccompAction {
 /// If all conditions match then rule gets matched.
 /// The action of the first rule that matches will be applied to the calling process.
 /// If no rule matches the default action is applied.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct SeccompRule {
     /// Conditions of rule that need to match in order for the rule to get matched.
     conditions: Vec<SeccompCondition>,
@@ -710,6 +737,33 @@ impl SeccompCondition {
         bpf
     }
 
+    /// Returns whether the arguments of a syscall match the condition, as its BPF statements do.
+    ///
+    /// # Arguments
+    ///
+    /// * `args` - Arguments of the syscall.
+    fn matches(&self, args: &[u64; 6]) -> bool {
+        // a DWORD condition only compares the lower halves
+        let width = |v: u64| match self.arg_len {
+            SeccompCmpArgLen::DWORD => v & u64::from(u32::MAX),
+            SeccompCmpArgLen::QWORD => v,
+        };
+        let arg = match args.get(usize::from(self.arg_number)) {
+            Some(arg) => width(*arg),
+            None => return false,
+        };
+        let value = width(self.value);
+        match self.operator {
+            SeccompCmpOp::Eq => arg == value,
+            SeccompCmpOp::Ge => arg >= value,
+            SeccompCmpOp::Gt => arg > value,
+            SeccompCmpOp::Le => arg <= value,
+            SeccompCmpOp::Lt => arg < value,
+            SeccompCmpOp::MaskedEq(mask) => arg & width(mask) == value & width(mask),
+            SeccompCmpOp::Ne => arg != value,
+        }
+    }
+
     /// Translates the [`SeccompCondition`] into BPF statements.
     ///
     /// # Arguments
@@ -751,6 +805,7 @@ impl From<SeccompAction> for u32 {
             SeccompAction::Log => SECCOMP_RET_LOG,
             SeccompAction::Trace(x) => SECCOMP_RET_TRACE | (x & SECCOMP_RET_MASK),
             SeccompAction::Trap => SECCOMP_RET_TRAP,
+            SeccompAction::UserNotif => SECCOMP_RET_USER_NOTIF,
         }
     }
 }
@@ -903,6 +958,76 @@ impl SeccompFilter {
         Ok(())
     }
 
+    /// Returns whether the filter allows the syscall whatever its arguments, that is the first rule it can match, or
+    /// the default action when it matches none, allows or logs it.
+    ///
+    /// # Arguments
+    ///
+    /// * `syscall_number` - Syscall identifier.
+    pub fn allows(&self, syscall_number: i64) -> bool {
+        // A filter without rules compiles to an empty program, which is never loaded.
+        if self.rules.is_empty() {
+            return true;
+        }
+
+        let allowing =
+            |action: &SeccompAction| matches!(*action, SeccompAction::Allow | SeccompAction::Log);
+        if let Some(chain) = self.rules.get(&syscall_number) {
+            for rule in chain {
+                if !allowing(&rule.action) {
+                    return false;
+                }
+                if rule.conditions.is_empty() {
+                    return true;
+                }
+            }
+        }
+        allowing(&self.default_action)
+    }
+
+    /// Returns the action the filter takes for a syscall made with these arguments, that of the
+    /// first rule of the syscall whose conditions all match, or the default action when none does.
+    ///
+    /// # Arguments
+    ///
+    /// * `syscall_number` - Syscall identifier.
+    /// * `args` - Arguments of the syscall.
+    pub fn action(&self, syscall_number: i64, args: &[u64; 6]) -> SeccompAction {
+        // A filter without rules compiles to an empty program, which is never loaded.
+        if self.rules.is_empty() {
+            return SeccompAction::Allow;
+        }
+
+        self.rules
+            .get(&syscall_number)
+            .and_then(|chain| {
+                chain
+                    .iter()
+                    .find(|rule| rule.conditions.iter().all(|c| c.matches(args)))
+            })
+            .map_or(&self.default_action, |rule| &rule.action)
+            .clone()
+    }
+
+    /// Hands the syscalls the filter kills the process for over to the supervisor reading its
+    /// listener instead, which sees their arguments, see [`notify`]. The supervisor has to kill the
+    /// process itself, it tells these syscalls apart with [`action`] on the filter as it was.
+    ///
+    /// [`notify`]: notify/index.html
+    /// [`action`]: struct.SeccompFilter.html#method.action
+    pub fn notify_kills(&mut self) {
+        let actions = self
+            .rules
+            .values_mut()
+            .flat_map(|chain| chain.iter_mut().map(|rule| &mut rule.action))
+            .chain(Some(&mut self.default_action));
+        for action in actions {
+            if *action == SeccompAction::Kill {
+                *action = SeccompAction::UserNotif;
+            }
+        }
+    }
+
     /// Builds the array of filter instructions and sends them to the kernel.
     ///
     /// # Arguments
@@ -946,6 +1071,79 @@ impl SeccompFilter {
         Ok(())
     }
 
+    /// Same as [`apply`] but also creates the listener of the filter, the file descriptor its
+    /// [`SeccompAction::UserNotif`] syscalls are received from, see [`notify`].
+    ///
+    /// # Arguments
+    ///
+    /// * `filters` - BPF program containing the seccomp rules.
+    ///
+    /// [`apply`]: #method.apply
+    /// [`SeccompAction::UserNotif`]: enum.SeccompAction.html#variant.UserNotif
+    /// [`notify`]: notify/index.html
+    pub fn apply_with_listener(filters: BpfProgram) -> Result<RawFd> {
+        Self::apply_with_flags(filters, SECCOMP_FILTER_FLAG_NEW_LISTENER)
+    }
+
+    /// Same as [`apply`] but the kernel also logs the syscalls the filter does not allow to the
+    /// audit log, the ones it kills, traps or fails included. When `listener` is true, the
+    /// listener of the filter is created and returned, as with [`apply_with_listener`].
+    ///
+    /// # Arguments
+    ///
+    /// * `filters` - BPF program containing the seccomp rules.
+    /// * `listener` - Whether to create the listener of the filter.
+    ///
+    /// [`apply`]: #method.apply
+    /// [`apply_with_listener`]: #method.apply_with_listener
+    pub fn apply_logged(filters: BpfProgram, listener: bool) -> Result<Option<RawFd>> {
+        if !listener {
+            // If the program is empty, skip this step, as apply does.
+            if filters.is_empty() {
+                return Ok(None);
+            }
+            return Self::apply_with_flags(filters, SECCOMP_FILTER_FLAG_LOG).map(|_| None);
+        }
+        Self::apply_with_flags(
+            filters,
+            SECCOMP_FILTER_FLAG_LOG | SECCOMP_FILTER_FLAG_NEW_LISTENER,
+        )
+        .map(Some)
+    }
+
+    /// Loads the filter with the seccomp syscall and `flags`, returns what it returns, the
+    /// listener of the filter with `SECCOMP_FILTER_FLAG_NEW_LISTENER`.
+    fn apply_with_flags(filters: BpfProgram, flags: libc::c_uint) -> Result<RawFd> {
+        let mut bpf_filter = Vec::new();
+        bpf_filter.extend(VALIDATE_ARCHITECTURE());
+        bpf_filter.extend(filters);
+
+        unsafe {
+            {
+                let rc = libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0);
+                if rc != 0 {
+                    return Err(Error::Load(*libc::__errno_location()));
+                }
+            }
+
+            let bpf_prog = sock_fprog {
+                len: bpf_filter.len() as u16,
+                filter: bpf_filter.as_ptr(),
+            };
+            // prctl cannot take flags, only the seccomp syscall can
+            let fd = libc::syscall(
+                libc::SYS_seccomp,
+                SECCOMP_SET_MODE_FILTER,
+                flags,
+                &bpf_prog as *const sock_fprog,
+            );
+            if fd < 0 {
+                return Err(Error::Load(*libc::__errno_location()));
+            }
+            Ok(fd as RawFd)
+        }
+    }
+
     /// Appends a chain of rules to an accumulator, updating the length of the filter.
     ///
     /// # Arguments
@@ -2016,6 +2214,140 @@ mod tests {
         assert_eq!(rc2, 0);
     }
 
+    #[test]
+    fn test_allows() {
+        let fd_is = |fd: u64| {
+            vec![SeccompCondition::new(0, SeccompCmpArgLen::DWORD, SeccompCmpOp::Eq, fd).unwrap()]
+        };
+        let mut rules = BTreeMap::new();
+        rules.insert(
+            libc::SYS_close,
+            vec![SeccompRule::new(fd_is(0), SeccompAction::Allow)],
+        );
+        rules.insert(
+            libc::SYS_sendmsg,
+            vec![
+                SeccompRule::new(fd_is(3), SeccompAction::Log),
+                SeccompRule::new(vec![], SeccompAction::Allow),
+            ],
+        );
+        rules.insert(
+            libc::SYS_write,
+            vec![
+                SeccompRule::new(fd_is(1), SeccompAction::Errno(1)),
+                SeccompRule::new(vec![], SeccompAction::Allow),
+            ],
+        );
+        let (nr, chain) = allow_syscall(libc::SYS_mount);
+        rules.insert(nr, chain);
+        let filter = SeccompFilter::new(rules.clone(), SeccompAction::Kill).unwrap();
+        assert!(!filter.allows(libc::SYS_close));
+        assert!(filter.allows(libc::SYS_sendmsg));
+        assert!(!filter.allows(libc::SYS_write));
+        assert!(filter.allows(libc::SYS_mount));
+        assert!(!filter.allows(libc::SYS_getpid));
+
+        let filter = SeccompFilter::new(rules, SeccompAction::Allow).unwrap();
+        assert!(filter.allows(libc::SYS_close));
+        assert!(filter.allows(libc::SYS_getpid));
+        assert!(SeccompFilter::new(BTreeMap::new(), SeccompAction::Kill)
+            .unwrap()
+            .allows(libc::SYS_close));
+    }
+
+    #[test]
+    fn test_action() {
+        let mut rules = BTreeMap::new();
+        rules.insert(
+            libc::SYS_write,
+            vec![
+                SeccompRule::new(
+                    vec![
+                        SeccompCondition::new(0, SeccompCmpArgLen::DWORD, SeccompCmpOp::Eq, 2)
+                            .unwrap(),
+                        SeccompCondition::new(2, SeccompCmpArgLen::QWORD, SeccompCmpOp::Gt, 10)
+                            .unwrap(),
+                    ],
+                    SeccompAction::Errno(1),
+                ),
+                SeccompRule::new(
+                    vec![SeccompCondition::new(
+                        1,
+                        SeccompCmpArgLen::QWORD,
+                        SeccompCmpOp::MaskedEq(0xf0),
+                        0x10,
+                    )
+                    .unwrap()],
+                    SeccompAction::Kill,
+                ),
+                SeccompRule::new(vec![], SeccompAction::Allow),
+            ],
+        );
+        let mut filter = SeccompFilter::new(rules, SeccompAction::Kill).unwrap();
+
+        // the upper half of a DWORD argument is not compared
+        let args = [(1 << 32) | 2, 0, 11, 0, 0, 0];
+        assert_eq!(
+            filter.action(libc::SYS_write, &args),
+            SeccompAction::Errno(1)
+        );
+        assert_eq!(
+            filter.action(libc::SYS_write, &[2, 0x1f, 10, 0, 0, 0]),
+            SeccompAction::Kill
+        );
+        assert_eq!(
+            filter.action(libc::SYS_write, &[2, 0x2f, 10, 0, 0, 0]),
+            SeccompAction::Allow
+        );
+        assert_eq!(
+            filter.action(libc::SYS_getpid, &[0; 6]),
+            SeccompAction::Kill
+        );
+
+        filter.notify_kills();
+        assert_eq!(
+            filter.action(libc::SYS_write, &[2, 0x1f, 10, 0, 0, 0]),
+            SeccompAction::UserNotif
+        );
+        assert_eq!(
+            filter.action(libc::SYS_write, &args),
+            SeccompAction::Errno(1)
+        );
+        assert_eq!(
+            filter.action(libc::SYS_getpid, &[0; 6]),
+            SeccompAction::UserNotif
+        );
+        assert_eq!(
+            SeccompFilter::new(BTreeMap::new(), SeccompAction::Kill)
+                .unwrap()
+                .action(libc::SYS_getpid, &[0; 6]),
+            SeccompAction::Allow
+        );
+    }
+
+    #[test]
+    fn test_apply_logged() {
+        let filter = || crate::policy::compile_policy("ERRNO(1000) { getppid } DEFAULT ALLOW");
+        let (errno, listener) = thread::spawn(move || {
+            assert_eq!(
+                SeccompFilter::apply_logged(filter().unwrap(), false).unwrap(),
+                None
+            );
+            let listener = SeccompFilter::apply_logged(filter().unwrap(), true).unwrap();
+            unsafe { libc::syscall(libc::SYS_getppid) };
+            (
+                std::io::Error::last_os_error().raw_os_error().unwrap(),
+                listener,
+            )
+        })
+        .join()
+        .unwrap();
+        assert_eq!(errno, 1000);
+        let listener = listener.unwrap();
+        assert!(listener >= 0);
+        unsafe { libc::close(listener) };
+    }
+
     #[test]
     fn test_parse_seccomp() {
         // Check `from_string()` behaviour for different scenarios.
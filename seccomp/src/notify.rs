@@ -0,0 +1,392 @@
+//! Supervision of syscalls from userspace, through `SECCOMP_RET_USER_NOTIF`.
+//!
+//! A filter loaded with [`SeccompFilter::apply_with_listener`] hands the syscalls its rules answer
+//! with [`SeccompAction::UserNotif`] over to whoever reads its listener: the calling thread is
+//! suspended until a [`Response`] is sent for the [`Notification`]. The supervised process cannot
+//! supervise itself, the listener is meant to be sent to another process, e.g. over a unix socket.
+//!
+//! The memory of the supervised process can change between the moment the supervisor reads it and
+//! the moment the kernel runs the syscall, so [`Response::Continue`] must not be used to let a
+//! syscall through after a check of its pointer arguments. The supervisor runs the syscall itself
+//! instead, with what it read, and returns the result.
+//!
+//! ```no_run
+//! extern crate seccomp;
+//!
+//! use seccomp::notify::{Listener, Response};
+//! use seccomp::policy::compile_policy;
+//! use seccomp::SeccompFilter;
+//! use std::os::unix::io::FromRawFd;
+//!
+//! let filter = compile_policy("USER_NOTIF { getppid } DEFAULT ALLOW").unwrap();
+//! let fd = SeccompFilter::apply_with_listener(filter).unwrap();
+//! // ... the calling process sends fd to its supervisor, which runs:
+//! let listener = unsafe { Listener::from_raw_fd(fd) };
+//! loop {
+//!     listener.handle_next(|_, _| Response::Return(1)).unwrap();
+//! }
+//! ```
+//!
+//! [`SeccompFilter::apply_with_listener`]: ../struct.SeccompFilter.html#method.apply_with_listener
+//! [`SeccompAction::UserNotif`]: ../enum.SeccompAction.html#variant.UserNotif
+//! [`Response`]: enum.Response.html
+//! [`Response::Continue`]: enum.Response.html#variant.Continue
+//! [`Notification`]: struct.Notification.html
+
+use std::fs::{self, File};
+use std::io;
+use std::os::unix::fs::FileExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
+
+use super::syscalls::syscall_number;
+use super::Error;
+
+type Result<T> = std::result::Result<T, Error>;
+
+// ioctls of the listener, _IOWR('!', 0, struct seccomp_notif) and so on.
+// See /usr/include/linux/seccomp.h .
+const SECCOMP_IOCTL_NOTIF_RECV: libc::c_ulong = 0xc050_2100;
+const SECCOMP_IOCTL_NOTIF_SEND: libc::c_ulong = 0xc018_2101;
+const SECCOMP_IOCTL_NOTIF_ID_VALID: libc::c_ulong = 0x4008_2102;
+
+const SECCOMP_USER_NOTIF_FLAG_CONTINUE: u32 = 1;
+
+// Longest string read by `read_string`, PATH_MAX.
+const MAX_STRING_LEN: usize = 4096;
+
+#[repr(C)]
+#[derive(Default)]
+struct seccomp_data {
+    nr: i32,
+    arch: u32,
+    instruction_pointer: u64,
+    args: [u64; 6],
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct seccomp_notif {
+    id: u64,
+    pid: u32,
+    flags: u32,
+    data: seccomp_data,
+}
+
+#[repr(C)]
+struct seccomp_notif_resp {
+    id: u64,
+    val: i64,
+    error: i32,
+    flags: u32,
+}
+
+/// A syscall waiting for the answer of the supervisor.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Notification {
+    /// Identifies the notification in the answer, and tells whether it is still pending.
+    pub id: u64,
+    /// Thread that made the syscall, in the PID namespace of the supervisor.
+    pub pid: u32,
+    /// Number of the syscall.
+    pub nr: i64,
+    /// `AUDIT_ARCH_*` value of the syscall.
+    pub arch: u32,
+    /// Address of the instruction that made the syscall.
+    pub instruction_pointer: u64,
+    /// Arguments of the syscall.
+    pub args: [u64; 6],
+}
+
+/// Answer of the supervisor to a notification.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Response {
+    /// Lets the kernel run the syscall, see the module documentation for when it is unsafe.
+    Continue,
+    /// Returns from the syscall with the value, without running it.
+    Return(i64),
+    /// Fails the syscall with the error number, without running it.
+    Errno(i32),
+}
+
+/// The listener of a filter, it is closed when dropped.
+#[derive(Debug)]
+pub struct Listener {
+    fd: RawFd,
+}
+
+fn last_error() -> Error {
+    Error::Notify(io::Error::last_os_error().raw_os_error().unwrap_or(0))
+}
+
+fn io_error(e: io::Error) -> Error {
+    Error::Notify(e.raw_os_error().unwrap_or(libc::EIO))
+}
+
+impl Listener {
+    /// Waits for the next notification. It fails with `ENOENT` if the thread that made the
+    /// syscall was killed in the meantime.
+    pub fn recv(&self) -> Result<Notification> {
+        // the kernel wants the structure zeroed
+        let mut notif = seccomp_notif::default();
+        if unsafe { libc::ioctl(self.fd, SECCOMP_IOCTL_NOTIF_RECV as _, &mut notif) } < 0 {
+            return Err(last_error());
+        }
+        Ok(Notification {
+            id: notif.id,
+            pid: notif.pid,
+            nr: i64::from(notif.data.nr),
+            arch: notif.data.arch,
+            instruction_pointer: notif.data.instruction_pointer,
+            args: notif.data.args,
+        })
+    }
+
+    /// Answers the notification `id`. It fails with `ENOENT` if the syscall is not waiting
+    /// anymore, because a signal interrupted it or the thread was killed.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The id of the notification.
+    /// * `response` - What the syscall does.
+    pub fn respond(&self, id: u64, response: Response) -> Result<()> {
+        let mut resp = seccomp_notif_resp {
+            id,
+            val: 0,
+            error: 0,
+            flags: 0,
+        };
+        match response {
+            Response::Continue => resp.flags = SECCOMP_USER_NOTIF_FLAG_CONTINUE,
+            Response::Return(val) => resp.val = val,
+            Response::Errno(errno) => resp.error = -errno,
+        }
+        if unsafe { libc::ioctl(self.fd, SECCOMP_IOCTL_NOTIF_SEND as _, &mut resp) } < 0 {
+            return Err(last_error());
+        }
+        Ok(())
+    }
+
+    /// Whether the notification `id` is still waiting for its answer. What was read from the
+    /// supervised process before is only trustworthy if it is, its pid could have been reused.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The id of the notification.
+    pub fn is_valid(&self, id: u64) -> bool {
+        let mut id = id;
+        unsafe { libc::ioctl(self.fd, SECCOMP_IOCTL_NOTIF_ID_VALID as _, &mut id) == 0 }
+    }
+
+    /// Receives the next notification and answers it with `f`. A notification whose syscall
+    /// stopped waiting is not an error.
+    ///
+    /// # Arguments
+    ///
+    /// * `f` - Decides what the syscall does.
+    pub fn handle_next<F>(&self, f: F) -> Result<()>
+    where
+        F: FnOnce(&Listener, &Notification) -> Response,
+    {
+        let notification = match self.recv() {
+            Ok(n) => n,
+            Err(Error::Notify(libc::ENOENT)) | Err(Error::Notify(libc::EINTR)) => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        let response = f(self, &notification);
+        match self.respond(notification.id, response) {
+            Err(Error::Notify(libc::ENOENT)) => Ok(()),
+            res => res,
+        }
+    }
+
+    /// Reads the memory of the process that made the syscall of `notification`, at `addr`, and
+    /// returns the number of bytes read.
+    ///
+    /// # Arguments
+    ///
+    /// * `notification` - The syscall.
+    /// * `addr` - An address in the process, usually an argument of the syscall.
+    /// * `buf` - Where the memory is copied to.
+    pub fn read_memory(
+        &self,
+        notification: &Notification,
+        addr: u64,
+        buf: &mut [u8],
+    ) -> Result<usize> {
+        let mem = File::open(format!("/proc/{}/mem", notification.pid)).map_err(io_error)?;
+        let n = mem.read_at(buf, addr).map_err(io_error)?;
+        if !self.is_valid(notification.id) {
+            return Err(Error::Notify(libc::ENOENT));
+        }
+        Ok(n)
+    }
+
+    /// Reads the nul terminated string at `addr` in the process that made the syscall of
+    /// `notification`, such as a path. It fails with `ENAMETOOLONG` past 4096 bytes, and with
+    /// `EINVAL` if it is not UTF-8.
+    ///
+    /// # Arguments
+    ///
+    /// * `notification` - The syscall.
+    /// * `addr` - Address of the string in the process.
+    pub fn read_string(&self, notification: &Notification, addr: u64) -> Result<String> {
+        let mut buf = vec![0u8; MAX_STRING_LEN];
+        // the string can end right before an unmapped page, it is read a page at most at a time
+        let mut len = 0;
+        while len < buf.len() {
+            let page_left = 4096 - ((addr as usize + len) % 4096);
+            let end = (len + page_left).min(buf.len());
+            let n = self.read_memory(notification, addr + len as u64, &mut buf[len..end])?;
+            if let Some(nul) = buf[len..len + n].iter().position(|b| *b == 0) {
+                buf.truncate(len + nul);
+                return String::from_utf8(buf).map_err(|_| Error::Notify(libc::EINVAL));
+            }
+            if n == 0 {
+                return Err(Error::Notify(libc::EFAULT));
+            }
+            len += n;
+        }
+        Err(Error::Notify(libc::ENAMETOOLONG))
+    }
+
+    /// Duplicates the file descriptor `fd` of the process that made the syscall of
+    /// `notification` into the supervisor, e.g. the socket of a `connect` to run it in its
+    /// place. The copy shares the open file of the original and is close on exec.
+    ///
+    /// # Arguments
+    ///
+    /// * `notification` - The syscall.
+    /// * `fd` - A file descriptor of the process, usually an argument of the syscall.
+    pub fn get_fd(&self, notification: &Notification, fd: RawFd) -> Result<RawFd> {
+        // a pidfd is opened on the process, notification.pid is only one of its threads
+        let status =
+            fs::read_to_string(format!("/proc/{}/status", notification.pid)).map_err(io_error)?;
+        let tgid = status
+            .lines()
+            .find_map(|line| line.strip_prefix("Tgid:"))
+            .and_then(|tgid| tgid.trim().parse::<libc::pid_t>().ok())
+            .ok_or(Error::Notify(libc::ESRCH))?;
+
+        let nr = |name| syscall_number(name).unwrap_or(-1);
+        let pidfd = unsafe { libc::syscall(nr("pidfd_open"), tgid, 0) };
+        if pidfd < 0 {
+            return Err(last_error());
+        }
+        let copy = unsafe { libc::syscall(nr("pidfd_getfd"), pidfd, fd, 0) };
+        let err = last_error();
+        unsafe { libc::close(pidfd as RawFd) };
+        if copy < 0 {
+            return Err(err);
+        }
+        if !self.is_valid(notification.id) {
+            unsafe { libc::close(copy as RawFd) };
+            return Err(Error::Notify(libc::ENOENT));
+        }
+        Ok(copy as RawFd)
+    }
+}
+
+impl AsRawFd for Listener {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+impl FromRawFd for Listener {
+    unsafe fn from_raw_fd(fd: RawFd) -> Listener {
+        Listener { fd }
+    }
+}
+
+impl IntoRawFd for Listener {
+    fn into_raw_fd(self) -> RawFd {
+        let fd = self.fd;
+        std::mem::forget(self);
+        fd
+    }
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::compile_policy;
+    use crate::SeccompFilter;
+
+    use std::ffi::CString;
+    use std::sync::mpsc;
+    use std::thread;
+
+    #[test]
+    fn test_user_notif() {
+        let (tx, rx) = mpsc::channel();
+        // the filter only applies to the thread that loads it, the test thread supervises it
+        let supervised = thread::spawn(move || {
+            let filter = compile_policy("USER_NOTIF { getppid, chdir } DEFAULT ALLOW").unwrap();
+            tx.send(SeccompFilter::apply_with_listener(filter).unwrap())
+                .unwrap();
+
+            let ppid = unsafe { libc::syscall(libc::SYS_getppid) };
+            let path = CString::new("/nonexistent/notify").unwrap();
+            let chdir = unsafe { libc::chdir(path.as_ptr()) };
+            let errno = io::Error::last_os_error().raw_os_error().unwrap();
+            let real_ppid = unsafe { libc::syscall(libc::SYS_getppid) };
+            (ppid, chdir, errno, real_ppid)
+        });
+
+        let listener = unsafe { Listener::from_raw_fd(rx.recv().unwrap()) };
+        listener
+            .handle_next(|_, n| {
+                assert_eq!(n.nr, libc::SYS_getppid);
+                Response::Return(4242)
+            })
+            .unwrap();
+        listener
+            .handle_next(|l, n| {
+                assert_eq!(n.nr, libc::SYS_chdir);
+                assert!(l.is_valid(n.id));
+                assert_eq!(l.read_string(n, n.args[0]).unwrap(), "/nonexistent/notify");
+                Response::Errno(libc::EACCES)
+            })
+            .unwrap();
+        listener.handle_next(|_, _| Response::Continue).unwrap();
+
+        let (ppid, chdir, errno, real_ppid) = supervised.join().unwrap();
+        assert_eq!(ppid, 4242);
+        assert_eq!((chdir, errno), (-1, libc::EACCES));
+        assert_eq!(real_ppid, i64::from(unsafe { libc::getppid() }));
+    }
+
+    #[test]
+    fn test_get_fd() {
+        let (tx, rx) = mpsc::channel();
+        let supervised = thread::spawn(move || {
+            let filter = compile_policy("USER_NOTIF { fsync } DEFAULT ALLOW").unwrap();
+            tx.send(SeccompFilter::apply_with_listener(filter).unwrap())
+                .unwrap();
+            let mut fds = [-1; 2];
+            assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+            unsafe { libc::fsync(fds[0]) }
+        });
+
+        let listener = unsafe { Listener::from_raw_fd(rx.recv().unwrap()) };
+        listener
+            .handle_next(|l, n| {
+                // a copy of the pipe, in the same process here
+                let copy = l.get_fd(n, n.args[0] as RawFd).unwrap();
+                let link = |fd: u64| fs::read_link(format!("/proc/self/fd/{}", fd)).unwrap();
+                assert_ne!(copy, n.args[0] as RawFd);
+                assert_eq!(link(copy as u64), link(n.args[0]));
+                assert!(link(n.args[0]).to_string_lossy().starts_with("pipe:"));
+                unsafe { libc::close(copy) };
+                Response::Return(0)
+            })
+            .unwrap();
+        assert_eq!(supervised.join().unwrap(), 0);
+    }
+}
@@ -0,0 +1,906 @@
+//! A text language for seccomp policies, modeled on [Kafel](https://github.com/google/kafel), and
+//! its compiler to a [`SeccompFilter`].
+//!
+//! Policies can then be audited and changed without touching Rust code.
+//!
+//! # Syntax
+//!
+//! ```text
+//! // C style comments, /* */ too
+//! #define STDOUT 1
+//! #define O_WRONLY 1
+//! #define O_CREAT 0x40
+//! #include "common.policy"          // only from a policy file, relative to its folder
+//!
+//! POLICY io {
+//!     ALLOW { read, close, lseek }
+//!     ALLOW {
+//!         write(fd, buf, count) { fd == STDOUT || fd == 2 },
+//!         openat(dirfd, path, flags) { (flags & O_CREAT) == 0 },
+//!     }
+//!     ERRNO(1) { openat }             // the openat calls the rule above did not allow
+//! }
+//!
+//! POLICY exit {
+//!     ALLOW { exit, exit_group, SYSCALL[60] }
+//! }
+//!
+//! USE io, exit DEFAULT KILL
+//! ```
+//!
+//! A policy is a list of action blocks, each listing syscalls with an optional condition on their
+//! arguments, and of `USE` statements that include the statements of named policies in place.
+//! Statements outside of any `POLICY` make up the policy that is compiled, followed by the
+//! `DEFAULT` action taken for the syscalls that no rule matches, `KILL` if there is none.
+//!
+//! The actions are `ALLOW`, `LOG`, `KILL` (or `DENY`), `TRAP`, `ERRNO(n)`, `TRACE(n)` and
+//! `USER_NOTIF`, see [`SeccompAction`]. The rules of a syscall are checked in the order they
+//! appear in, the first one that matches decides.
+//!
+//! Arguments are named in the parentheses following the syscall, in order, or referred to as
+//! `arg0` to `arg5`. They are compared as 64 bit values with `==`, `!=`, `<`, `<=`, `>` and `>=`,
+//! or through a mask with `(arg & mask) == value`. Conditions are combined with `&&`, `||` and
+//! parentheses. Values are numbers, decimal or hexadecimal, defines, or an `|` of them.
+//!
+//! [`SeccompFilter`]: ../struct.SeccompFilter.html
+//! [`SeccompAction`]: ../enum.SeccompAction.html
+
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryInto;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::syscalls::syscall_number;
+use super::{
+    BpfProgram, SeccompAction, SeccompCmpArgLen, SeccompCmpOp, SeccompCondition, SeccompFilter,
+    SeccompRule,
+};
+
+// Maximum depth of nested #include.
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+// Maximum number of rules a single condition expands to once put in disjunctive normal form.
+const MAX_ALTERNATIVES: usize = 64;
+
+// Operators, the longest first so that `<=` is not read as `<`.
+const PUNCTUATION: [&str; 18] = [
+    "&&", "||", "==", "!=", "<=", ">=", "{", "}", "(", ")", "[", "]", ",", ";", "&", "|", "<", ">",
+];
+
+/// Error found while compiling a policy.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PolicyError {
+    /// File the error is in, empty for a policy given as a string.
+    pub file: String,
+    /// Line of the error, starting at 1, 0 when it is not tied to a line.
+    pub line: usize,
+    /// What is wrong.
+    pub message: String,
+}
+
+impl Display for PolicyError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match (self.file.is_empty(), self.line) {
+            (true, 0) => write!(f, "seccomp policy: {}", self.message),
+            (true, line) => write!(f, "seccomp policy line {}: {}", line, self.message),
+            (false, 0) => write!(f, "{}: {}", self.file, self.message),
+            (false, line) => write!(f, "{}:{}: {}", self.file, line, self.message),
+        }
+    }
+}
+
+type Result<T> = std::result::Result<T, PolicyError>;
+
+/// Compiles the policy `text` into a filter.
+///
+/// # Arguments
+///
+/// * `text` - The policy. It cannot `#include` files.
+pub fn parse_policy(text: &str) -> Result<SeccompFilter> {
+    let mut compiler = Compiler::default();
+    compiler.parse(text, "", None, 0)?;
+    compiler.build()
+}
+
+/// Compiles the policy of the file `path` into a filter. Its `#include`s are relative to the
+/// folder of the file.
+///
+/// # Arguments
+///
+/// * `path` - The policy file.
+pub fn parse_policy_file<P: AsRef<Path>>(path: P) -> Result<SeccompFilter> {
+    let mut compiler = Compiler::default();
+    compiler.parse_file(path.as_ref(), "", 0, 0)?;
+    compiler.build()
+}
+
+/// Compiles the policy `text` into a BPF program, ready for [`SeccompFilter::apply`].
+///
+/// # Arguments
+///
+/// * `text` - The policy. It cannot `#include` files.
+///
+/// [`SeccompFilter::apply`]: ../struct.SeccompFilter.html#method.apply
+pub fn compile_policy(text: &str) -> Result<BpfProgram> {
+    parse_policy(text)?
+        .try_into()
+        .map_err(|e: super::Error| PolicyError {
+            file: String::new(),
+            line: 0,
+            message: e.to_string(),
+        })
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Tok {
+    Ident(String),
+    Num(u64),
+    Str(String),
+    Punct(&'static str),
+    // #define or #include
+    Directive(String),
+}
+
+#[derive(Clone, Debug)]
+struct Token {
+    tok: Tok,
+    line: usize,
+}
+
+fn tokenize(src: &str, file: &str) -> Result<Vec<Token>> {
+    let err = |line: usize, message: String| PolicyError {
+        file: file.to_string(),
+        line,
+        message,
+    };
+
+    let bytes = src.as_bytes();
+    let mut tokens = vec![];
+    let mut line = 1;
+    let mut i = 0;
+    'outer: while i < bytes.len() {
+        let c = bytes[i];
+        let rest = &src[i..];
+        if c == b'\n' {
+            line += 1;
+            i += 1;
+        } else if c.is_ascii_whitespace() {
+            i += 1;
+        } else if rest.starts_with("//") {
+            i += rest.find('\n').unwrap_or(rest.len());
+        } else if rest.starts_with("/*") {
+            match rest.find("*/") {
+                Some(end) => {
+                    line += rest[..end].matches('\n').count();
+                    i += end + 2;
+                }
+                None => return Err(err(line, String::from("unterminated comment"))),
+            }
+        } else if c == b'#' {
+            let len = rest[1..]
+                .find(|ch: char| !ch.is_ascii_alphabetic())
+                .unwrap_or(rest.len() - 1);
+            let directive = &rest[1..1 + len];
+            if directive != "define" && directive != "include" {
+                return Err(err(line, format!("unknown directive #{}", directive)));
+            }
+            tokens.push(Token {
+                tok: Tok::Directive(directive.to_string()),
+                line,
+            });
+            i += 1 + len;
+        } else if c == b'"' {
+            match rest[1..].find(['"', '\n']) {
+                Some(end) if rest.as_bytes()[1 + end] == b'"' => {
+                    tokens.push(Token {
+                        tok: Tok::Str(rest[1..1 + end].to_string()),
+                        line,
+                    });
+                    i += end + 2;
+                }
+                _ => return Err(err(line, String::from("unterminated string"))),
+            }
+        } else if c.is_ascii_digit() {
+            let len = rest
+                .find(|ch: char| !ch.is_ascii_alphanumeric())
+                .unwrap_or(rest.len());
+            let word = &rest[..len];
+            let value = if word.starts_with("0x") || word.starts_with("0X") {
+                u64::from_str_radix(&word[2..], 16)
+            } else {
+                word.parse::<u64>()
+            };
+            match value {
+                Ok(n) => tokens.push(Token {
+                    tok: Tok::Num(n),
+                    line,
+                }),
+                Err(_) => return Err(err(line, format!("invalid number {:?}", word))),
+            }
+            i += len;
+        } else if c.is_ascii_alphabetic() || c == b'_' {
+            let len = rest
+                .find(|ch: char| !(ch.is_ascii_alphanumeric() || ch == '_'))
+                .unwrap_or(rest.len());
+            tokens.push(Token {
+                tok: Tok::Ident(rest[..len].to_string()),
+                line,
+            });
+            i += len;
+        } else {
+            for p in PUNCTUATION.iter() {
+                if rest.starts_with(p) {
+                    tokens.push(Token {
+                        tok: Tok::Punct(p),
+                        line,
+                    });
+                    i += p.len();
+                    continue 'outer;
+                }
+            }
+            let ch = rest.chars().next().unwrap();
+            return Err(err(line, format!("unexpected character {:?}", ch)));
+        }
+    }
+    Ok(tokens)
+}
+
+// A statement of a policy, in the order it appears in.
+#[derive(Clone, Debug)]
+enum Statement {
+    Rules(SeccompAction, Vec<SyscallRule>),
+    // name of the policy, file and line of the USE
+    Use(String, String, usize),
+}
+
+// A syscall of an action block, an empty list of alternatives means any arguments.
+#[derive(Clone, Debug)]
+struct SyscallRule {
+    nr: i64,
+    alternatives: Vec<Vec<SeccompCondition>>,
+}
+
+#[derive(Default)]
+struct Compiler {
+    defines: HashMap<String, u64>,
+    policies: HashMap<String, Vec<Statement>>,
+    top: Vec<Statement>,
+    default_action: Option<SeccompAction>,
+}
+
+// Cursor over the tokens of one file.
+struct Parser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+    file: &'a str,
+}
+
+impl<'a> Parser<'a> {
+    fn line(&self) -> usize {
+        self.tokens
+            .get(self.pos)
+            .or_else(|| self.tokens.last())
+            .map(|t| t.line)
+            .unwrap_or(0)
+    }
+
+    fn err<T>(&self, message: String) -> Result<T> {
+        Err(PolicyError {
+            file: self.file.to_string(),
+            line: self.line(),
+            message,
+        })
+    }
+
+    fn peek(&self) -> Option<&'a Tok> {
+        self.tokens.get(self.pos).map(|t| &t.tok)
+    }
+
+    fn peek_at(&self, n: usize) -> Option<&'a Tok> {
+        self.tokens.get(self.pos + n).map(|t| &t.tok)
+    }
+
+    fn next(&mut self) -> Option<&'a Tok> {
+        let tok = self.peek();
+        if tok.is_some() {
+            self.pos += 1;
+        }
+        tok
+    }
+
+    fn is_punct(&self, p: &str) -> bool {
+        matches!(self.peek(), Some(Tok::Punct(q)) if *q == p)
+    }
+
+    fn is_keyword(&self, k: &str) -> bool {
+        matches!(self.peek(), Some(Tok::Ident(w)) if w == k)
+    }
+
+    fn eat_punct(&mut self, p: &str) -> bool {
+        if self.is_punct(p) {
+            self.pos += 1;
+            return true;
+        }
+        false
+    }
+
+    fn expect_punct(&mut self, p: &str) -> Result<()> {
+        if self.eat_punct(p) {
+            return Ok(());
+        }
+        self.err(format!("expected {:?}, found {}", p, self.describe()))
+    }
+
+    fn ident(&mut self) -> Result<String> {
+        match self.peek() {
+            Some(Tok::Ident(w)) => {
+                self.pos += 1;
+                Ok(w.clone())
+            }
+            _ => self.err(format!("expected a name, found {}", self.describe())),
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self.peek() {
+            None => String::from("the end of the policy"),
+            Some(Tok::Ident(w)) => format!("{:?}", w),
+            Some(Tok::Num(n)) => n.to_string(),
+            Some(Tok::Str(s)) => format!("{:?}", s),
+            Some(Tok::Punct(p)) => format!("{:?}", p),
+            Some(Tok::Directive(d)) => format!("#{}", d),
+        }
+    }
+}
+
+impl Compiler {
+    fn parse_file(&mut self, path: &Path, from: &str, line: usize, depth: usize) -> Result<()> {
+        let text = fs::read_to_string(path).map_err(|e| PolicyError {
+            file: from.to_string(),
+            line,
+            message: format!("could not read {:?}: {}", path, e),
+        })?;
+        let dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        self.parse(&text, &path.display().to_string(), Some(dir), depth)
+    }
+
+    fn parse(&mut self, text: &str, file: &str, dir: Option<PathBuf>, depth: usize) -> Result<()> {
+        let tokens = tokenize(text, file)?;
+        let mut p = Parser {
+            tokens: &tokens,
+            pos: 0,
+            file,
+        };
+
+        while let Some(tok) = p.peek() {
+            match tok {
+                Tok::Directive(d) if d == "define" => {
+                    p.pos += 1;
+                    let name = p.ident()?;
+                    let value = self.value(&mut p)?;
+                    self.defines.insert(name, value);
+                }
+                Tok::Directive(_) => {
+                    let line = p.line();
+                    p.pos += 1;
+                    let included = match p.next() {
+                        Some(Tok::Str(s)) => s,
+                        _ => return p.err(String::from("#include expects a \"file\"")),
+                    };
+                    let dir = match dir {
+                        Some(ref dir) => dir,
+                        None => {
+                            return p
+                                .err(String::from("#include is only available in policy files"))
+                        }
+                    };
+                    if depth >= MAX_INCLUDE_DEPTH {
+                        return p.err(format!("too many nested #include at {:?}", included));
+                    }
+                    self.parse_file(&dir.join(included), file, line, depth + 1)?;
+                }
+                Tok::Ident(w) if w == "POLICY" => {
+                    p.pos += 1;
+                    let name = p.ident()?;
+                    if self.policies.contains_key(&name) {
+                        return p.err(format!("policy {} is defined twice", name));
+                    }
+                    p.expect_punct("{")?;
+                    let mut statements = vec![];
+                    while !p.eat_punct("}") {
+                        if p.peek().is_none() {
+                            return p.err(format!("unterminated policy {}", name));
+                        }
+                        self.statement(&mut p, &mut statements, false)?;
+                    }
+                    self.policies.insert(name, statements);
+                }
+                _ => {
+                    let mut statements = vec![];
+                    self.statement(&mut p, &mut statements, true)?;
+                    self.top.append(&mut statements);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn statement(
+        &mut self,
+        p: &mut Parser,
+        statements: &mut Vec<Statement>,
+        top_level: bool,
+    ) -> Result<()> {
+        if p.eat_punct(";") {
+            return Ok(());
+        }
+
+        if p.is_keyword("USE") {
+            p.pos += 1;
+            loop {
+                let line = p.line();
+                statements.push(Statement::Use(p.ident()?, p.file.to_string(), line));
+                if !p.eat_punct(",") {
+                    break;
+                }
+            }
+            if !p.is_keyword("DEFAULT") {
+                return Ok(());
+            }
+        }
+
+        if p.is_keyword("DEFAULT") {
+            if !top_level {
+                return p.err(String::from("DEFAULT is only valid outside of a POLICY"));
+            }
+            p.pos += 1;
+            if self.default_action.is_some() {
+                return p.err(String::from("the DEFAULT action is set twice"));
+            }
+            self.default_action = Some(self.action(p)?);
+            return Ok(());
+        }
+
+        let action = self.action(p)?;
+        p.expect_punct("{")?;
+        let mut rules = vec![];
+        while !p.eat_punct("}") {
+            rules.push(self.syscall_rule(p)?);
+            if !p.eat_punct(",") {
+                p.expect_punct("}")?;
+                break;
+            }
+        }
+        statements.push(Statement::Rules(action, rules));
+        Ok(())
+    }
+
+    fn action(&mut self, p: &mut Parser) -> Result<SeccompAction> {
+        let name = p.ident()?;
+        let mut arg = || -> Result<u32> {
+            p.expect_punct("(")?;
+            let value = self.value(p)?;
+            p.expect_punct(")")?;
+            if value > 0xffff {
+                return p.err(format!(
+                    "{} is out of the 16 bits of an action value",
+                    value
+                ));
+            }
+            Ok(value as u32)
+        };
+        match name.as_str() {
+            "ALLOW" => Ok(SeccompAction::Allow),
+            "LOG" => Ok(SeccompAction::Log),
+            "KILL" | "DENY" => Ok(SeccompAction::Kill),
+            "TRAP" => Ok(SeccompAction::Trap),
+            "ERRNO" => Ok(SeccompAction::Errno(arg()?)),
+            "TRACE" => Ok(SeccompAction::Trace(arg()?)),
+            "USER_NOTIF" => Ok(SeccompAction::UserNotif),
+            _ => {
+                p.pos -= 1;
+                p.err(format!(
+                    "expected an action, POLICY, USE or DEFAULT, found {}",
+                    p.describe()
+                ))
+            }
+        }
+    }
+
+    fn syscall_rule(&mut self, p: &mut Parser) -> Result<SyscallRule> {
+        let name = p.ident()?;
+        let nr = if name == "SYSCALL" {
+            p.expect_punct("[")?;
+            let nr = self.value(p)?;
+            p.expect_punct("]")?;
+            nr as i64
+        } else {
+            match syscall_number(&name) {
+                Some(nr) => nr,
+                None => {
+                    p.pos -= 1;
+                    return p.err(format!("unknown syscall {}", name));
+                }
+            }
+        };
+
+        let mut args: Vec<String> = vec![];
+        if p.eat_punct("(") {
+            while !p.eat_punct(")") {
+                args.push(p.ident()?);
+                if !p.eat_punct(",") {
+                    p.expect_punct(")")?;
+                    break;
+                }
+            }
+            if args.len() > 6 {
+                return p.err(format!("{} has at most 6 arguments", name));
+            }
+        }
+
+        let mut alternatives = vec![];
+        if p.eat_punct("{") {
+            alternatives = self.disjunction(p, &args)?;
+            p.expect_punct("}")?;
+        }
+
+        Ok(SyscallRule { nr, alternatives })
+    }
+
+    // expr := conj ('||' conj)*, returned as an OR of AND of conditions
+    fn disjunction(
+        &mut self,
+        p: &mut Parser,
+        args: &[String],
+    ) -> Result<Vec<Vec<SeccompCondition>>> {
+        let mut alternatives = self.conjunction(p, args)?;
+        while p.eat_punct("||") {
+            alternatives.append(&mut self.conjunction(p, args)?);
+        }
+        if alternatives.len() > MAX_ALTERNATIVES {
+            return p.err(String::from("the condition is too complex"));
+        }
+        Ok(alternatives)
+    }
+
+    // conj := atom ('&&' atom)*, distributed over the ORs of the atoms
+    fn conjunction(
+        &mut self,
+        p: &mut Parser,
+        args: &[String],
+    ) -> Result<Vec<Vec<SeccompCondition>>> {
+        let mut alternatives = self.atom(p, args)?;
+        while p.eat_punct("&&") {
+            let right = self.atom(p, args)?;
+            if alternatives.len() * right.len() > MAX_ALTERNATIVES {
+                return p.err(String::from("the condition is too complex"));
+            }
+            let mut product = Vec::with_capacity(alternatives.len() * right.len());
+            for left in alternatives.iter() {
+                for r in right.iter() {
+                    product.push(left.iter().chain(r.iter()).cloned().collect());
+                }
+            }
+            alternatives = product;
+        }
+        Ok(alternatives)
+    }
+
+    // atom := '(' expr ')' | '(' arg '&' value ')' op value | arg ['&' value] op value
+    fn atom(&mut self, p: &mut Parser, args: &[String]) -> Result<Vec<Vec<SeccompCondition>>> {
+        let masked_in_parens = matches!(
+            (p.peek(), p.peek_at(1), p.peek_at(2)),
+            (
+                Some(Tok::Punct("(")),
+                Some(Tok::Ident(_)),
+                Some(Tok::Punct("&"))
+            )
+        );
+        if p.is_punct("(") && !masked_in_parens {
+            p.pos += 1;
+            let alternatives = self.disjunction(p, args)?;
+            p.expect_punct(")")?;
+            return Ok(alternatives);
+        }
+
+        let parens = p.eat_punct("(");
+        let name = p.ident()?;
+        let index = match args.iter().position(|a| *a == name) {
+            Some(i) => i as u8,
+            None => match name.strip_prefix("arg").map(str::parse::<u8>) {
+                Some(Ok(i)) if i <= 5 => i,
+                _ => {
+                    p.pos -= 1;
+                    return p.err(format!("unknown argument {}", name));
+                }
+            },
+        };
+        let mask = if p.eat_punct("&") {
+            Some(self.value(p)?)
+        } else {
+            None
+        };
+        if parens {
+            p.expect_punct(")")?;
+        }
+
+        let op = match p.next() {
+            Some(Tok::Punct(op)) => *op,
+            _ => {
+                p.pos -= 1;
+                return p.err(format!("expected a comparison, found {}", p.describe()));
+            }
+        };
+        let op = match (op, mask) {
+            ("==", Some(mask)) => SeccompCmpOp::MaskedEq(mask),
+            (_, Some(_)) => {
+                return p.err(String::from(
+                    "a masked argument can only be compared with ==",
+                ))
+            }
+            ("==", None) => SeccompCmpOp::Eq,
+            ("!=", None) => SeccompCmpOp::Ne,
+            ("<", None) => SeccompCmpOp::Lt,
+            ("<=", None) => SeccompCmpOp::Le,
+            (">", None) => SeccompCmpOp::Gt,
+            (">=", None) => SeccompCmpOp::Ge,
+            (op, None) => {
+                p.pos -= 1;
+                return p.err(format!("expected a comparison, found {:?}", op));
+            }
+        };
+        let value = self.value(p)?;
+
+        match SeccompCondition::new(index, SeccompCmpArgLen::QWORD, op, value) {
+            Ok(cond) => Ok(vec![vec![cond]]),
+            Err(e) => p.err(e.to_string()),
+        }
+    }
+
+    // value := (number | define) ('|' (number | define))*
+    fn value(&mut self, p: &mut Parser) -> Result<u64> {
+        let mut value = 0;
+        loop {
+            value |= match p.next() {
+                Some(Tok::Num(n)) => *n,
+                Some(Tok::Ident(name)) => match self.defines.get(name) {
+                    Some(v) => *v,
+                    None => {
+                        p.pos -= 1;
+                        return p.err(format!("unknown define {}", name));
+                    }
+                },
+                Some(_) => {
+                    p.pos -= 1;
+                    return p.err(format!("expected a value, found {}", p.describe()));
+                }
+                None => return p.err(String::from("expected a value")),
+            };
+            if !p.eat_punct("|") {
+                return Ok(value);
+            }
+        }
+    }
+
+    // Appends the rules of statements, with the USEd policies expanded, to rules.
+    fn expand(
+        &self,
+        statements: &[Statement],
+        stack: &mut Vec<String>,
+        rules: &mut BTreeMap<i64, Vec<SeccompRule>>,
+    ) -> Result<()> {
+        for statement in statements.iter() {
+            match statement {
+                Statement::Rules(action, syscalls) => {
+                    for s in syscalls.iter() {
+                        let chain = rules.entry(s.nr).or_default();
+                        if s.alternatives.is_empty() {
+                            chain.push(SeccompRule::new(vec![], action.clone()));
+                        }
+                        for conditions in s.alternatives.iter() {
+                            chain.push(SeccompRule::new(conditions.clone(), action.clone()));
+                        }
+                    }
+                }
+                Statement::Use(name, file, line) => {
+                    let err = |message: String| PolicyError {
+                        file: file.clone(),
+                        line: *line,
+                        message,
+                    };
+                    if stack.contains(name) {
+                        return Err(err(format!("policy {} uses itself", name)));
+                    }
+                    let policy = self
+                        .policies
+                        .get(name)
+                        .ok_or_else(|| err(format!("unknown policy {}", name)))?;
+                    stack.push(name.clone());
+                    self.expand(policy, stack, rules)?;
+                    stack.pop();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn build(self) -> Result<SeccompFilter> {
+        let mut rules = BTreeMap::new();
+        self.expand(&self.top, &mut vec![], &mut rules)?;
+
+        let default_action = self.default_action.unwrap_or(SeccompAction::Kill);
+        // a filter without rules compiles to no filter at all
+        if rules.is_empty() && default_action != SeccompAction::Allow {
+            return Err(PolicyError {
+                file: String::new(),
+                line: 0,
+                message: String::from("the policy has no rule, only DEFAULT ALLOW can be empty"),
+            });
+        }
+
+        SeccompFilter::new(rules, default_action).map_err(|e| PolicyError {
+            file: String::new(),
+            line: 0,
+            message: e.to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SeccompCmpArgLen as ArgLen;
+    use crate::SeccompCmpOp::*;
+    use crate::SeccompCondition as Cond;
+
+    const POLICY: &str = "
+        #define STDERR 2
+        #define O_WRONLY 1
+        #define O_RDWR 2
+        /* files can only be
+           opened read only */
+        POLICY files {
+            ALLOW {
+                openat(dirfd, path, flags) { (flags & O_WRONLY|O_RDWR) == 0 },
+                close,
+            }
+            ERRNO(13) { openat }
+        }
+
+        POLICY out {
+            ALLOW { write(fd) { fd == 1 || fd == STDERR && arg2 < 4096 } }
+            USE files
+        }
+
+        USE out DEFAULT TRAP
+        LOG { SYSCALL[1000] }
+    ";
+
+    #[test]
+    fn test_parse_policy() {
+        let filter = parse_policy(POLICY).unwrap();
+        assert_eq!(filter.default_action, SeccompAction::Trap);
+
+        let openat = &filter.rules[&libc::SYS_openat];
+        assert_eq!(openat.len(), 2);
+        assert_eq!(openat[0].action, SeccompAction::Allow);
+        assert_eq!(
+            openat[0].conditions[0],
+            Cond::new(2, ArgLen::QWORD, MaskedEq(3), 0).unwrap()
+        );
+        assert_eq!(openat[1].action, SeccompAction::Errno(13));
+        assert!(openat[1].conditions.is_empty());
+
+        // fd == 1 || (fd == 2 && arg2 < 4096)
+        let write = &filter.rules[&libc::SYS_write];
+        assert_eq!(write.len(), 2);
+        assert_eq!(write[0].conditions.len(), 1);
+        assert_eq!(
+            write[1].conditions[1],
+            Cond::new(2, ArgLen::QWORD, Lt, 4096).unwrap()
+        );
+
+        assert_eq!(filter.rules[&1000][0].action, SeccompAction::Log);
+        assert!(filter.rules.contains_key(&libc::SYS_close));
+
+        let prog = compile_policy(POLICY).unwrap();
+        assert!(!prog.is_empty());
+    }
+
+    #[test]
+    fn test_parse_policy_conditions() {
+        // && binds tighter than ||, parentheses distribute
+        let filter =
+            parse_policy("ALLOW { read(a, b) { (a == 0 || a == 1) && b != 0 } } DEFAULT KILL")
+                .unwrap();
+        let read = &filter.rules[&libc::SYS_read];
+        assert_eq!(read.len(), 2);
+        assert_eq!(
+            read[1].conditions[0],
+            Cond::new(0, ArgLen::QWORD, Eq, 1).unwrap()
+        );
+        assert_eq!(
+            read[1].conditions[1],
+            Cond::new(1, ArgLen::QWORD, Ne, 0).unwrap()
+        );
+        assert_eq!(filter.default_action, SeccompAction::Kill);
+    }
+
+    #[test]
+    fn test_apply_policy() {
+        let prog = compile_policy("ERRNO(77) { dup(fd) { fd == 1000 } } DEFAULT ALLOW").unwrap();
+
+        // in another thread not to filter the whole test process, errno is per thread too
+        let (refused, allowed) = std::thread::spawn(move || {
+            SeccompFilter::apply(prog).unwrap();
+            let dup = |fd| {
+                let ret = unsafe { libc::dup(fd) };
+                (ret, std::io::Error::last_os_error().raw_os_error())
+            };
+            (dup(1000), dup(1001))
+        })
+        .join()
+        .unwrap();
+        assert_eq!(refused, (-1, Some(77)));
+        // not filtered, it fails because there is no fd 1001
+        assert_eq!(allowed, (-1, Some(libc::EBADF)));
+    }
+
+    #[test]
+    fn test_policy_errors() {
+        let err = |text: &str| parse_policy(text).unwrap_err();
+
+        let e = err("ALLOW { read }\nALLOW { not_a_syscall }");
+        assert_eq!(e.line, 2);
+        assert!(e.message.contains("not_a_syscall"), "{}", e);
+        assert!(err("USE nothing").message.contains("unknown policy"));
+        assert!(err("POLICY a { USE b } POLICY b { USE a } USE a")
+            .message
+            .contains("uses itself"));
+        assert!(err("ALLOW { write(fd) { count == 1 } }")
+            .message
+            .contains("unknown argument"));
+        assert!(err("ALLOW { write(fd) { (fd & 1) < 1 } }")
+            .message
+            .contains("masked"));
+        assert!(err("ERRNO(70000) { read }").message.contains("16 bits"));
+        assert!(err("#include \"x.policy\"")
+            .message
+            .contains("policy files"));
+        assert!(err("DEFAULT KILL").message.contains("no rule"));
+        assert!(err("ALLOW { read ").message.contains("end of the policy"));
+
+        // DEFAULT ALLOW alone is no filter
+        assert!(compile_policy("DEFAULT ALLOW").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_parse_policy_file() {
+        let dir = std::env::temp_dir().join(format!("seccomp-policy-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("common.policy"),
+            "#define ONE 1\nPOLICY common { ALLOW { exit_group } }\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("main.policy"),
+            "#include \"common.policy\"\nUSE common\nERRNO(ONE) { getpid }\n",
+        )
+        .unwrap();
+
+        let filter = parse_policy_file(dir.join("main.policy")).unwrap();
+        assert_eq!(
+            filter.rules[&libc::SYS_getpid][0].action,
+            SeccompAction::Errno(1)
+        );
+        assert!(filter.rules.contains_key(&libc::SYS_exit_group));
+
+        fs::write(dir.join("loop.policy"), "#include \"loop.policy\"\n").unwrap();
+        let e = parse_policy_file(dir.join("loop.policy")).unwrap_err();
+        assert!(e.message.contains("nested"), "{}", e);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
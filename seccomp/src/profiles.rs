@@ -0,0 +1,274 @@
+//! Built-in seccomp profiles, written in the language of the [`policy`] module.
+//!
+//! | name         | for                                                                     |
+//! |--------------|-------------------------------------------------------------------------|
+//! | `default`    | anything, comparable to the default profile of Docker                   |
+//! | `static`     | statically linked binaries                                              |
+//! | `python`     | the CPython interpreter                                                 |
+//! | `nodejs`     | Node.js                                                                 |
+//! | `no-network` | `default` without network sockets, unix sockets are still allowed       |
+//!
+//! The syscalls a profile does not allow fail with `EPERM`, as in Docker, so that programs can
+//! fall back or report the error instead of being killed.
+//!
+//! [`policy`]: ../policy/index.html
+
+use super::policy::{compile_policy, PolicyError};
+use super::BpfProgram;
+
+// The syscall groups the profiles are made of.
+const COMMON: &str = include_str!("profiles/common.policy");
+
+#[cfg(target_arch = "x86_64")]
+const ARCH: &str = include_str!("profiles/x86_64.policy");
+#[cfg(target_arch = "aarch64")]
+const ARCH: &str = include_str!("profiles/aarch64.policy");
+
+const PROFILES: [(&str, &str); 5] = [
+    ("default", include_str!("profiles/default.policy")),
+    ("static", include_str!("profiles/static.policy")),
+    ("python", include_str!("profiles/python.policy")),
+    ("nodejs", include_str!("profiles/nodejs.policy")),
+    ("no-network", include_str!("profiles/no-network.policy")),
+];
+
+/// Names of the built-in profiles.
+pub fn profile_names() -> Vec<&'static str> {
+    PROFILES.iter().map(|(name, _)| *name).collect()
+}
+
+/// Returns the policy text of the profile `name`, the syscall groups it uses included, or `None`
+/// if there is no such profile.
+///
+/// # Arguments
+///
+/// * `name` - Name of the profile, see [`profile_names`].
+///
+/// [`profile_names`]: fn.profile_names.html
+pub fn profile_policy(name: &str) -> Option<String> {
+    PROFILES
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|(_, profile)| [COMMON, ARCH, profile].join("\n"))
+}
+
+/// Compiles the profile `name` into a BPF program, ready for [`SeccompFilter::apply`].
+///
+/// # Arguments
+///
+/// * `name` - Name of the profile, see [`profile_names`].
+///
+/// [`SeccompFilter::apply`]: ../struct.SeccompFilter.html#method.apply
+/// [`profile_names`]: fn.profile_names.html
+pub fn compile_profile(name: &str) -> Result<BpfProgram, PolicyError> {
+    let policy = profile_policy(name).ok_or_else(|| PolicyError {
+        file: String::new(),
+        line: 0,
+        message: format!(
+            "unknown seccomp profile {:?}, expected one of {}",
+            name,
+            profile_names().join(", ")
+        ),
+    })?;
+    compile_policy(&policy).map_err(|mut e| {
+        e.file = format!("<{} profile>", name);
+        e
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sock_filter, sock_fprog, VALIDATE_ARCHITECTURE};
+
+    use std::env;
+    use std::fs;
+    use std::io;
+    use std::os::unix::process::CommandExt;
+    use std::process::{Command, Output};
+
+    // Runs `program` under the profile `name`.
+    fn run(name: &str, program: &str, args: &[&str]) -> Output {
+        // Nothing is allocated after the fork, the test harness is multi-threaded.
+        let mut filter: Vec<sock_filter> = VALIDATE_ARCHITECTURE();
+        filter.extend(compile_profile(name).unwrap());
+
+        let mut command = Command::new(program);
+        command.args(args);
+        unsafe {
+            command.pre_exec(move || {
+                let prog = sock_fprog {
+                    len: filter.len() as u16,
+                    filter: filter.as_ptr(),
+                };
+                if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0
+                    || libc::prctl(
+                        libc::PR_SET_SECCOMP,
+                        libc::SECCOMP_MODE_FILTER,
+                        &prog as *const sock_fprog,
+                    ) != 0
+                {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+
+        command
+            .output()
+            .unwrap_or_else(|e| panic!("could not run {}: {}", program, e))
+    }
+
+    fn assert_ok(name: &str, program: &str, args: &[&str], stdout: &str) {
+        let output = run(name, program, args);
+        assert!(
+            output.status.success(),
+            "{} under the {} profile: {:?}\n{}",
+            program,
+            name,
+            output.status,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), stdout);
+    }
+
+    /// Whether program is installed, the test of a profile is skipped when its runtime is not
+    fn installed(program: &str) -> bool {
+        let found = Command::new(program).arg("--version").output().is_ok();
+        if !found {
+            println!("skipping the test, {} is not installed", program);
+        }
+        found
+    }
+
+    #[test]
+    fn test_compile_profiles() {
+        for name in profile_names() {
+            assert!(!compile_profile(name).unwrap().is_empty());
+        }
+        assert!(compile_profile("nope")
+            .unwrap_err()
+            .to_string()
+            .contains("unknown seccomp profile \"nope\""));
+    }
+
+    #[test]
+    fn test_default_profile() {
+        assert_ok(
+            "default",
+            "sh",
+            &["-c", "cd / && ls >/dev/null && echo $(echo ok) | cat"],
+            "ok",
+        );
+        // no new namespaces
+        assert!(!run("default", "unshare", &["-n", "true"]).status.success());
+    }
+
+    #[test]
+    fn test_static_profile() {
+        if !installed("cc") {
+            return;
+        }
+        let dir = env::temp_dir().join(format!("seccomp-static-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("main.c");
+        let binary = dir.join("main");
+        fs::write(
+            &source,
+            "#include <pthread.h>\n\
+             #include <stdio.h>\n\
+             #include <stdlib.h>\n\
+             static void *run(void *arg) { return malloc(1 << 20); }\n\
+             int main(void) {\n\
+                 pthread_t t; void *p;\n\
+                 FILE *f = fopen(\"/proc/self/status\", \"r\");\n\
+                 if (!f || pthread_create(&t, NULL, run, NULL) || pthread_join(t, &p) || !p) return 1;\n\
+                 puts(\"ok\");\n\
+                 return 0;\n\
+             }\n",
+        )
+        .unwrap();
+
+        let built = Command::new("cc")
+            .args(["-static", "-pthread", "-o"])
+            .arg(&binary)
+            .arg(&source)
+            .output()
+            .unwrap();
+        if !built.status.success() {
+            // most likely no static libc
+            println!(
+                "skipping the test, could not build a static binary: {}",
+                String::from_utf8_lossy(&built.stderr)
+            );
+            fs::remove_dir_all(&dir).unwrap();
+            return;
+        }
+        assert_ok("static", binary.to_str().unwrap(), &[], "ok");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_python_profile() {
+        if !installed("python3") {
+            return;
+        }
+        assert_ok(
+            "python",
+            "python3",
+            &[
+                "-c",
+                "import hashlib, json, subprocess, tempfile, threading\n\
+                 from concurrent.futures import ThreadPoolExecutor\n\
+                 with tempfile.TemporaryDirectory() as d:\n\
+                 \x20   open(d + '/f', 'w').write(json.dumps({'a': 1}))\n\
+                 with ThreadPoolExecutor(2) as ex:\n\
+                 \x20   list(ex.map(lambda s: hashlib.sha256(s).digest(), [b'a', b'b']))\n\
+                 print(subprocess.run(['echo', 'ok'], capture_output=True).stdout.decode().strip())",
+            ],
+            "ok",
+        );
+    }
+
+    #[test]
+    fn test_nodejs_profile() {
+        if !installed("node") {
+            return;
+        }
+        assert_ok(
+            "nodejs",
+            "node",
+            &[
+                "-e",
+                "const fs = require('fs'), os = require('os'), path = require('path');\n\
+                 const d = fs.mkdtempSync(path.join(os.tmpdir(), 'seccomp-'));\n\
+                 fs.promises.writeFile(path.join(d, 'f'), 'ok')\n\
+                 \x20 .then(() => fs.promises.readFile(path.join(d, 'f'), 'utf8'))\n\
+                 \x20 .then((s) => { fs.rmSync(d, { recursive: true }); setTimeout(() => console.log(s), 10); });",
+            ],
+            "ok",
+        );
+    }
+
+    #[test]
+    fn test_no_network_profile() {
+        if !installed("python3") {
+            return;
+        }
+        assert_ok(
+            "no-network",
+            "python3",
+            &[
+                "-c",
+                "import errno, socket\n\
+                 socket.socketpair()\n\
+                 socket.socket(socket.AF_UNIX).close()\n\
+                 try:\n\
+                 \x20   socket.socket(socket.AF_INET)\n\
+                 except OSError as e:\n\
+                 \x20   print('ok' if e.errno == errno.EAFNOSUPPORT else e)",
+            ],
+            "ok",
+        );
+    }
+}
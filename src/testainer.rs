@@ -1,5 +1,6 @@
 use std::env;
 use std::ffi::{CStr, CString};
+use std::net::{IpAddr, Ipv4Addr};
 use std::path::PathBuf;
 use std::time::Instant;
 
@@ -7,6 +8,7 @@ use cmd::exec::bash_cmd_stdout;
 use disk::btrfs::{new_subvolume_cstr, snapshot};
 use disk::overlay_fs::OverlayDir;
 use jail::subproc;
+use scheduler::addr_pool::AddrPool;
 use scheduler::config;
 use sys_util::errno::Errno;
 
@@ -82,6 +84,14 @@ fn pool_test(
 
     let gw = CString::new(uint_ip_to_string(gateway)).unwrap();
 
+    // the toaster addresses are taken from the /16 of the gateway, as the scheduler does by default
+    let gateway_addr = IpAddr::V4(Ipv4Addr::from(gateway));
+    let mut addr_pool = AddrPool::new(gateway_addr, 16).expect("invalid toaster network");
+    addr_pool
+        .reserve(gateway_addr)
+        .expect("could not reserve the gateway");
+    let nm = CString::new(addr_pool.netmask().to_string()).unwrap();
+
     let efd_epoll =
         sys_util::epoll::epoll_create1(sys_util::epoll::EpollCreateFlags::EPOLL_CLOEXEC)
             .expect("could not create epoll");
@@ -104,7 +114,8 @@ fn pool_test(
         )
         .expect("could not create overlaydir");
 
-        let ip = CString::new(uint_ip_to_string(gateway + i)).unwrap();
+        let addr = addr_pool.allocate().expect("no toaster address left");
+        let ip = CString::new(addr.to_string()).unwrap();
 
         // println!("ip: {:?}; gw: {:?}", &ip, &gw);
 
@@ -113,6 +124,7 @@ fn pool_test(
             ovdir.mount_point.clone(),
             num_cpus,
             ip,
+            &nm,
             &gw,
             String::from("/"),
             true,
@@ -156,7 +168,8 @@ fn pool_test(
             child_pid as u64,
             efd_epoll,
             child_pidfd,
-        );
+        )
+        .expect("could not register child_pidfd into epoll");
 
         let ready = scheduler::net::poll_fd_events(efd_epoll, &mut events, -1);
         if ready != 1 {
@@ -194,6 +207,7 @@ fn pool_test(
         }
 
         subproc::clean_after_child(&jconf, child_pid).expect("could not clean_after_child");
+        addr_pool.release(addr);
 
         let end = Instant::now();
         println!(
@@ -230,7 +244,8 @@ fn pool_test(
         )
         .expect("could not create overlaydir");
 
-        let ip = CString::new(uint_ip_to_string(gateway + i)).unwrap();
+        let addr = addr_pool.allocate().expect("no toaster address left");
+        let ip = CString::new(addr.to_string()).unwrap();
 
         // println!("ip: {:?}; gw: {:?}", &ip, &gw);
 
@@ -239,6 +254,7 @@ fn pool_test(
             ovdir.mount_point.clone(),
             num_cpus,
             ip,
+            &nm,
             &gw,
             String::from("/"),
             true,
@@ -272,7 +288,8 @@ fn pool_test(
             child_pid as u64,
             efd_epoll,
             child_pidfd,
-        );
+        )
+        .expect("could not register child_pidfd into epoll");
 
         let ready = scheduler::net::poll_fd_events(efd_epoll, &mut events, -1);
         if ready != 1 {
@@ -310,6 +327,7 @@ fn pool_test(
         }
 
         subproc::clean_after_child(&jconf, child_pid).expect("could not clean_after_child");
+        addr_pool.release(addr);
 
         let end = Instant::now();
         println!(
@@ -338,7 +356,8 @@ fn pool_test(
         println!("creating pool {}", i);
 
         let uid = (i + 50).to_string();
-        let ip = CString::new(uint_ip_to_string(gateway + i as u32)).unwrap();
+        let addr = addr_pool.allocate().expect("no toaster address left");
+        let ip = CString::new(addr.to_string()).unwrap();
 
         let uid0 = CString::new(uid.as_bytes()).unwrap();
         let subvolume_name0 = [btrfs_file_system_cstring.to_bytes(), b"/", i.to_string().as_bytes()].concat();
@@ -360,6 +379,7 @@ fn pool_test(
             ovdir.mount_point.clone(),
             num_cpus,
             ip,
+            &nm,
             &gw,
             String::from("/"),
             true,
@@ -378,6 +398,8 @@ fn pool_test(
         pool.push(scheduler::pool::Item {
             ovdir: ovdir,
             jconf: jconf,
+            ip: addr,
+            ip6: None,
         });
 
         // std::thread::sleep(std::time::Duration::from_millis(100));
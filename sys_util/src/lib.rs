@@ -32,9 +32,10 @@ pub mod errno;
 mod eventfd;
 pub mod execv;
 pub mod fcntl;
-pub mod libnl;
 pub mod mount;
+pub mod nftables;
 pub mod num_cpu;
+pub mod rtnetlink;
 pub mod sched;
 pub mod signal;
 pub mod socket;
@@ -1,2 +0,0 @@
-pub mod socket;
-pub mod link;
\ No newline at end of file
@@ -0,0 +1,742 @@
+//! Minimal nf_tables client, enough to install the egress firewall of a jail: create a table and its base chains and
+//! append rules built from a handful of expressions, all in one transaction.
+//!
+//! Requests go through a NETLINK_NETFILTER socket as a batch, the kernel applies all of it or nothing, see
+//! https://wiki.nftables.org/wiki-nftables/index.php/Portal:DeveloperDocs/nftables_internals. Unlike rtnetlink most
+//! nf_tables attributes are in network byte order.
+
+use super::errno::Errno;
+use super::rtnetlink::message::{
+    parse_replies, read_i32, Message, NLMSG_DONE, NLMSG_ERROR, NLM_F_ACK, NLM_F_CREATE,
+};
+
+use std::fmt;
+use std::mem;
+use std::net::IpAddr;
+use std::os::unix::io::RawFd;
+
+// values from <linux/netfilter.h>, <linux/netfilter/nfnetlink.h> and <linux/netfilter/nf_tables.h>
+const NETLINK_NETFILTER: libc::c_int = 12;
+const NFNL_SUBSYS_NFTABLES: u16 = 10;
+const NFNL_MSG_BATCH_BEGIN: u16 = 16;
+const NFNL_MSG_BATCH_END: u16 = 17;
+const NLM_F_APPEND: u16 = 0x800;
+
+const NFT_MSG_NEWTABLE: u16 = 0;
+const NFT_MSG_DELTABLE: u16 = 2;
+const NFT_MSG_NEWCHAIN: u16 = 3;
+const NFT_MSG_NEWRULE: u16 = 6;
+
+const NFTA_TABLE_NAME: u16 = 1;
+const NFTA_TABLE_FLAGS: u16 = 2;
+const NFT_TABLE_F_OWNER: u32 = 0x2;
+
+const NFTA_CHAIN_TABLE: u16 = 1;
+const NFTA_CHAIN_NAME: u16 = 3;
+const NFTA_CHAIN_HOOK: u16 = 4;
+const NFTA_CHAIN_POLICY: u16 = 5;
+const NFTA_CHAIN_TYPE: u16 = 7;
+const NFTA_HOOK_HOOKNUM: u16 = 1;
+const NFTA_HOOK_PRIORITY: u16 = 2;
+
+const NFTA_RULE_TABLE: u16 = 1;
+const NFTA_RULE_CHAIN: u16 = 2;
+const NFTA_RULE_EXPRESSIONS: u16 = 4;
+const NFTA_LIST_ELEM: u16 = 1;
+const NFTA_EXPR_NAME: u16 = 1;
+const NFTA_EXPR_DATA: u16 = 2;
+
+const NFTA_DATA_VALUE: u16 = 1;
+const NFTA_DATA_VERDICT: u16 = 2;
+const NFTA_VERDICT_CODE: u16 = 1;
+
+const NFT_REG_VERDICT: u32 = 0;
+const NFT_REG_1: u32 = 1;
+
+const NFTA_META_DREG: u16 = 1;
+const NFTA_META_KEY: u16 = 2;
+const NFTA_PAYLOAD_DREG: u16 = 1;
+const NFTA_PAYLOAD_BASE: u16 = 2;
+const NFTA_PAYLOAD_OFFSET: u16 = 3;
+const NFTA_PAYLOAD_LEN: u16 = 4;
+const NFTA_BITWISE_SREG: u16 = 1;
+const NFTA_BITWISE_DREG: u16 = 2;
+const NFTA_BITWISE_LEN: u16 = 3;
+const NFTA_BITWISE_MASK: u16 = 4;
+const NFTA_BITWISE_XOR: u16 = 5;
+const NFTA_CT_DREG: u16 = 1;
+const NFTA_CT_KEY: u16 = 2;
+const NFTA_IMMEDIATE_DREG: u16 = 1;
+const NFTA_IMMEDIATE_DATA: u16 = 2;
+const NFTA_CMP_SREG: u16 = 1;
+const NFTA_CMP_OP: u16 = 2;
+const NFTA_CMP_DATA: u16 = 3;
+const NFTA_RANGE_SREG: u16 = 1;
+const NFTA_RANGE_OP: u16 = 2;
+const NFTA_RANGE_FROM_DATA: u16 = 3;
+const NFTA_RANGE_TO_DATA: u16 = 4;
+
+const NFT_CT_STATE: u32 = 0;
+const NFT_RANGE_EQ: u32 = 0;
+
+pub const NFPROTO_INET: u8 = 1;
+pub const NFPROTO_IPV4: u8 = 2;
+pub const NFPROTO_IPV6: u8 = 10;
+
+pub const NF_DROP: u32 = 0;
+pub const NF_ACCEPT: u32 = 1;
+
+pub const NF_INET_LOCAL_IN: u32 = 1;
+pub const NF_INET_LOCAL_OUT: u32 = 3;
+
+pub const NFT_META_OIFNAME: u32 = 7;
+pub const NFT_META_NFPROTO: u32 = 15;
+pub const NFT_META_L4PROTO: u32 = 16;
+
+pub const NFT_PAYLOAD_NETWORK_HEADER: u32 = 1;
+pub const NFT_PAYLOAD_TRANSPORT_HEADER: u32 = 2;
+
+pub const NFT_CMP_EQ: u32 = 0;
+pub const NFT_CMP_NEQ: u32 = 1;
+
+// NF_CT_STATE_BIT of IP_CT_ESTABLISHED and IP_CT_RELATED
+pub const CT_STATE_ESTABLISHED: u32 = 1 << 1;
+pub const CT_STATE_RELATED: u32 = 1 << 2;
+
+const RECV_BUF_SIZE: usize = 32 * 1024;
+
+#[derive(Debug)]
+pub enum Error {
+    Socket(Errno),
+    Send(Errno),
+    Recv(Errno),
+    /// the kernel refused a message of the batch, the whole batch was then discarded
+    Kernel(&'static str, Errno),
+    Malformed(&'static str),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Socket(ref e) => write!(f, "nftables socket: {}", e),
+            Error::Send(ref e) => write!(f, "nftables send: {}", e),
+            Error::Recv(ref e) => write!(f, "nftables recv: {}", e),
+            Error::Kernel(op, ref e) => write!(f, "nftables {}: {}", op, e),
+            Error::Malformed(what) => write!(f, "nftables: malformed reply: {}", what),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+struct NfGenMsg {
+    family: u8,
+    version: u8,
+    res_id: u16, // big endian
+}
+
+/**
+ * One nf_tables expression, all of them but Verdict work on register 1 which holds up to 16 bytes.
+ * A rule is a list of expressions evaluated in order, a Cmp or Range that does not match ends the rule
+ */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Expr {
+    /// loads a meta key, e.g. NFT_META_L4PROTO
+    Meta(u32),
+    /// loads len bytes at offset of the network or transport header
+    Payload {
+        base: u32,
+        offset: u32,
+        len: u32,
+    },
+    /// register = (register & mask) ^ xor, over mask.len() bytes
+    Bitwise {
+        mask: Vec<u8>,
+        xor: Vec<u8>,
+    },
+    /// loads the conntrack state bits, in host byte order
+    CtState,
+    Cmp {
+        op: u32,
+        data: Vec<u8>,
+    },
+    /// from and to are inclusive and compared as big endian numbers
+    Range {
+        from: Vec<u8>,
+        to: Vec<u8>,
+    },
+    /// NF_ACCEPT or NF_DROP
+    Verdict(u32),
+}
+
+impl Expr {
+    fn name(&self) -> &'static str {
+        match *self {
+            Expr::Meta(_) => "meta",
+            Expr::Payload { .. } => "payload",
+            Expr::Bitwise { .. } => "bitwise",
+            Expr::CtState => "ct",
+            Expr::Cmp { .. } => "cmp",
+            Expr::Range { .. } => "range",
+            Expr::Verdict(_) => "immediate",
+        }
+    }
+
+    fn encode(&self, msg: &mut Message) {
+        match *self {
+            Expr::Meta(key) => {
+                attr_be32(msg, NFTA_META_DREG, NFT_REG_1);
+                attr_be32(msg, NFTA_META_KEY, key);
+            }
+            Expr::Payload { base, offset, len } => {
+                attr_be32(msg, NFTA_PAYLOAD_DREG, NFT_REG_1);
+                attr_be32(msg, NFTA_PAYLOAD_BASE, base);
+                attr_be32(msg, NFTA_PAYLOAD_OFFSET, offset);
+                attr_be32(msg, NFTA_PAYLOAD_LEN, len);
+            }
+            Expr::Bitwise { ref mask, ref xor } => {
+                attr_be32(msg, NFTA_BITWISE_SREG, NFT_REG_1);
+                attr_be32(msg, NFTA_BITWISE_DREG, NFT_REG_1);
+                attr_be32(msg, NFTA_BITWISE_LEN, mask.len() as u32);
+                attr_data(msg, NFTA_BITWISE_MASK, mask);
+                attr_data(msg, NFTA_BITWISE_XOR, xor);
+            }
+            Expr::CtState => {
+                attr_be32(msg, NFTA_CT_DREG, NFT_REG_1);
+                attr_be32(msg, NFTA_CT_KEY, NFT_CT_STATE);
+            }
+            Expr::Cmp { op, ref data } => {
+                attr_be32(msg, NFTA_CMP_SREG, NFT_REG_1);
+                attr_be32(msg, NFTA_CMP_OP, op);
+                attr_data(msg, NFTA_CMP_DATA, data);
+            }
+            Expr::Range { ref from, ref to } => {
+                attr_be32(msg, NFTA_RANGE_SREG, NFT_REG_1);
+                attr_be32(msg, NFTA_RANGE_OP, NFT_RANGE_EQ);
+                attr_data(msg, NFTA_RANGE_FROM_DATA, from);
+                attr_data(msg, NFTA_RANGE_TO_DATA, to);
+            }
+            Expr::Verdict(code) => {
+                attr_be32(msg, NFTA_IMMEDIATE_DREG, NFT_REG_VERDICT);
+                let data = msg.nest_start(NFTA_IMMEDIATE_DATA);
+                let verdict = msg.nest_start(NFTA_DATA_VERDICT);
+                attr_be32(msg, NFTA_VERDICT_CODE, code);
+                msg.nest_end(verdict);
+                msg.nest_end(data);
+            }
+        }
+    }
+}
+
+/// Matches the family of the packet, NFPROTO_IPV4 or NFPROTO_IPV6, needed before reading addresses in an inet table
+pub fn match_nfproto(family: u8) -> Vec<Expr> {
+    vec![
+        Expr::Meta(NFT_META_NFPROTO),
+        Expr::Cmp {
+            op: NFT_CMP_EQ,
+            data: vec![family],
+        },
+    ]
+}
+
+/// Matches the transport protocol, e.g. libc::IPPROTO_TCP
+pub fn match_l4proto(proto: u8) -> Vec<Expr> {
+    vec![
+        Expr::Meta(NFT_META_L4PROTO),
+        Expr::Cmp {
+            op: NFT_CMP_EQ,
+            data: vec![proto],
+        },
+    ]
+}
+
+/// Matches the name of the output interface
+pub fn match_oifname(name: &str) -> Vec<Expr> {
+    let mut data = vec![0u8; libc::IFNAMSIZ];
+    let len = name.len().min(libc::IFNAMSIZ - 1);
+    data[..len].copy_from_slice(&name.as_bytes()[..len]);
+    vec![
+        Expr::Meta(NFT_META_OIFNAME),
+        Expr::Cmp {
+            op: NFT_CMP_EQ,
+            data,
+        },
+    ]
+}
+
+/// Matches packets of a connection conntrack already accepted, in either direction
+pub fn match_ct_established() -> Vec<Expr> {
+    vec![
+        Expr::CtState,
+        Expr::Bitwise {
+            mask: (CT_STATE_ESTABLISHED | CT_STATE_RELATED)
+                .to_ne_bytes()
+                .to_vec(),
+            xor: vec![0; 4],
+        },
+        Expr::Cmp {
+            op: NFT_CMP_NEQ,
+            data: vec![0; 4],
+        },
+    ]
+}
+
+/// Matches the destination address against net/prefix_len, the packet family is checked first
+pub fn match_daddr(net: IpAddr, prefix_len: u8) -> Vec<Expr> {
+    let (family, offset, bytes) = match net {
+        IpAddr::V4(a) => (NFPROTO_IPV4, 16, a.octets().to_vec()),
+        IpAddr::V6(a) => (NFPROTO_IPV6, 24, a.octets().to_vec()),
+    };
+    let mask: Vec<u8> = (0..bytes.len())
+        .map(|i| {
+            let bits = (prefix_len as usize).saturating_sub(8 * i).min(8);
+            (0xff00u16 >> bits) as u8
+        })
+        .collect();
+
+    let mut exprs = match_nfproto(family);
+    exprs.push(Expr::Payload {
+        base: NFT_PAYLOAD_NETWORK_HEADER,
+        offset,
+        len: bytes.len() as u32,
+    });
+    if mask.iter().any(|&m| m != 0xff) {
+        exprs.push(Expr::Bitwise {
+            xor: vec![0; mask.len()],
+            mask: mask.clone(),
+        });
+    }
+    exprs.push(Expr::Cmp {
+        op: NFT_CMP_EQ,
+        data: bytes.iter().zip(mask.iter()).map(|(b, m)| b & m).collect(),
+    });
+    exprs
+}
+
+/// Matches the destination port of TCP and UDP, from and to are inclusive, the transport protocol must be matched first
+pub fn match_dport(from: u16, to: u16) -> Vec<Expr> {
+    let load = Expr::Payload {
+        base: NFT_PAYLOAD_TRANSPORT_HEADER,
+        offset: 2,
+        len: 2,
+    };
+    if from == to {
+        return vec![
+            load,
+            Expr::Cmp {
+                op: NFT_CMP_EQ,
+                data: from.to_be_bytes().to_vec(),
+            },
+        ];
+    }
+    vec![
+        load,
+        Expr::Range {
+            from: from.to_be_bytes().to_vec(),
+            to: to.to_be_bytes().to_vec(),
+        },
+    ]
+}
+
+/// Matches the first byte of the transport header, e.g. the ICMPv6 type, between from and to inclusive
+pub fn match_l4_type(from: u8, to: u8) -> Vec<Expr> {
+    vec![
+        Expr::Payload {
+            base: NFT_PAYLOAD_TRANSPORT_HEADER,
+            offset: 0,
+            len: 1,
+        },
+        Expr::Range {
+            from: vec![from],
+            to: vec![to],
+        },
+    ]
+}
+
+/// nf_tables requests applied together by NfTables::apply
+#[derive(Default)]
+pub struct Batch {
+    msgs: Vec<(&'static str, Message)>,
+}
+
+impl Batch {
+    pub fn new() -> Batch {
+        Batch::default()
+    }
+
+    /**
+     * Creates a table, an owned table can only be changed through the socket that created it and is removed with all
+     * its chains when that socket is closed, even a process with CAP_NET_ADMIN in the network namespace cannot touch it
+     */
+    pub fn add_table(&mut self, family: u8, name: &str, owned: bool) -> &mut Self {
+        let mut msg = nft_message(NFT_MSG_NEWTABLE, NLM_F_CREATE, family);
+        msg.attr_str(NFTA_TABLE_NAME, name);
+        if owned {
+            attr_be32(&mut msg, NFTA_TABLE_FLAGS, NFT_TABLE_F_OWNER);
+        }
+        self.msgs.push(("NEWTABLE", msg));
+        self
+    }
+
+    pub fn delete_table(&mut self, family: u8, name: &str) -> &mut Self {
+        let mut msg = nft_message(NFT_MSG_DELTABLE, 0, family);
+        msg.attr_str(NFTA_TABLE_NAME, name);
+        self.msgs.push(("DELTABLE", msg));
+        self
+    }
+
+    /// Creates a filter chain attached to hook, e.g. NF_INET_LOCAL_OUT, policy is applied to packets no rule decided about
+    pub fn add_filter_chain(
+        &mut self,
+        family: u8,
+        table: &str,
+        name: &str,
+        hook: u32,
+        priority: i32,
+        policy: u32,
+    ) -> &mut Self {
+        let mut msg = nft_message(NFT_MSG_NEWCHAIN, NLM_F_CREATE, family);
+        msg.attr_str(NFTA_CHAIN_TABLE, table)
+            .attr_str(NFTA_CHAIN_NAME, name);
+        let hook_attr = msg.nest_start(NFTA_CHAIN_HOOK);
+        attr_be32(&mut msg, NFTA_HOOK_HOOKNUM, hook);
+        attr_be32(&mut msg, NFTA_HOOK_PRIORITY, priority as u32);
+        msg.nest_end(hook_attr);
+        attr_be32(&mut msg, NFTA_CHAIN_POLICY, policy);
+        msg.attr_str(NFTA_CHAIN_TYPE, "filter");
+        self.msgs.push(("NEWCHAIN", msg));
+        self
+    }
+
+    /// Appends a rule at the end of chain
+    pub fn add_rule(&mut self, family: u8, table: &str, chain: &str, exprs: &[Expr]) -> &mut Self {
+        let mut msg = nft_message(NFT_MSG_NEWRULE, NLM_F_CREATE | NLM_F_APPEND, family);
+        msg.attr_str(NFTA_RULE_TABLE, table)
+            .attr_str(NFTA_RULE_CHAIN, chain);
+        let list = msg.nest_start(NFTA_RULE_EXPRESSIONS);
+        for expr in exprs {
+            let elem = msg.nest_start(NFTA_LIST_ELEM);
+            msg.attr_str(NFTA_EXPR_NAME, expr.name());
+            let data = msg.nest_start(NFTA_EXPR_DATA);
+            expr.encode(&mut msg);
+            msg.nest_end(data);
+            msg.nest_end(elem);
+        }
+        msg.nest_end(list);
+        self.msgs.push(("NEWRULE", msg));
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.msgs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.msgs.is_empty()
+    }
+}
+
+/// A NETLINK_NETFILTER socket bound to the network namespace of the calling thread at creation time
+pub struct NfTables {
+    fd: RawFd,
+    seq: u32,
+}
+
+impl Drop for NfTables {
+    fn drop(&mut self) {
+        if self.fd >= 0 {
+            unsafe { libc::close(self.fd) };
+        }
+    }
+}
+
+impl NfTables {
+    pub fn connect() -> Result<NfTables> {
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+                NETLINK_NETFILTER,
+            )
+        };
+        if fd < 0 {
+            return Err(Error::Socket(Errno::last()));
+        }
+        let nft = NfTables { fd, seq: 0 };
+
+        let mut addr: libc::sockaddr_nl = unsafe { mem::zeroed() };
+        addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        if unsafe {
+            libc::bind(
+                fd,
+                &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            )
+        } < 0
+        {
+            return Err(Error::Socket(Errno::last()));
+        }
+
+        Ok(nft)
+    }
+
+    /// Gives up the socket without closing it, the tables it owns live as long as the returned fd is open
+    pub fn into_raw_fd(mut self) -> RawFd {
+        mem::replace(&mut self.fd, -1)
+    }
+
+    /**
+     * Sends the whole batch in one datagram and checks every message was acknowledged. The kernel handles the batch
+     * while we send it, so every reply is already queued when send returns
+     */
+    pub fn apply(&mut self, batch: Batch) -> Result<()> {
+        let mut buf = Vec::new();
+        let mut begin = Message::new(NFNL_MSG_BATCH_BEGIN, 0);
+        begin.header(&NfGenMsg {
+            res_id: NFNL_SUBSYS_NFTABLES.to_be(),
+            ..Default::default()
+        });
+        buf.extend(begin.finish(self.next_seq(), 0));
+
+        let mut pending = Vec::with_capacity(batch.msgs.len());
+        for (op, msg) in batch.msgs {
+            let seq = self.next_seq();
+            buf.extend(msg.finish(seq, NLM_F_ACK));
+            pending.push((seq, op));
+        }
+
+        let mut end = Message::new(NFNL_MSG_BATCH_END, 0);
+        end.header(&NfGenMsg {
+            res_id: NFNL_SUBSYS_NFTABLES.to_be(),
+            ..Default::default()
+        });
+        buf.extend(end.finish(self.next_seq(), 0));
+
+        loop {
+            let sent =
+                unsafe { libc::send(self.fd, buf.as_ptr() as *const libc::c_void, buf.len(), 0) };
+            if sent >= 0 {
+                break;
+            }
+            if Errno::last() != Errno::EINTR {
+                return Err(Error::Send(Errno::last()));
+            }
+        }
+
+        let mut recv_buf = vec![0u8; RECV_BUF_SIZE];
+        while !pending.is_empty() {
+            let n = unsafe {
+                libc::recv(
+                    self.fd,
+                    recv_buf.as_mut_ptr() as *mut libc::c_void,
+                    recv_buf.len(),
+                    libc::MSG_DONTWAIT,
+                )
+            };
+            if n < 0 {
+                match Errno::last() {
+                    Errno::EINTR => continue,
+                    Errno::EAGAIN => return Err(Error::Malformed("missing acknowledgment")),
+                    e => return Err(Error::Recv(e)),
+                }
+            }
+
+            let replies = parse_replies(&recv_buf[..n as usize])
+                .ok_or(Error::Malformed("truncated netlink message"))?;
+            for reply in replies {
+                if reply.kind != NLMSG_ERROR && reply.kind != NLMSG_DONE {
+                    continue;
+                }
+                let code = if reply.kind == NLMSG_ERROR {
+                    read_i32(reply.payload, 0).ok_or(Error::Malformed("short NLMSG_ERROR"))?
+                } else {
+                    0
+                };
+                // a failed commit is reported once, with the sequence number of the batch begin message
+                let op = match pending.iter().position(|&(seq, _)| seq == reply.seq) {
+                    Some(i) => pending.remove(i).1,
+                    None => "commit",
+                };
+                if code != 0 {
+                    return Err(Error::Kernel(op, Errno::from_i32(-code)));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn next_seq(&mut self) -> u32 {
+        self.seq = self.seq.wrapping_add(1);
+        self.seq
+    }
+}
+
+fn nft_message(kind: u16, flags: u16, family: u8) -> Message {
+    let mut msg = Message::new((NFNL_SUBSYS_NFTABLES << 8) | kind, flags);
+    msg.header(&NfGenMsg {
+        family,
+        ..Default::default()
+    });
+    msg
+}
+
+fn attr_be32(msg: &mut Message, kind: u16, value: u32) {
+    msg.attr(kind, &value.to_be_bytes());
+}
+
+fn attr_data(msg: &mut Message, kind: u16, data: &[u8]) {
+    let nest = msg.nest_start(kind);
+    msg.attr(NFTA_DATA_VALUE, data);
+    msg.nest_end(nest);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rtnetlink::RtNetlink;
+    use std::net::UdpSocket;
+
+    #[test]
+    fn test_match_daddr() {
+        let exprs = match_daddr("10.166.3.4".parse().unwrap(), 22);
+        assert_eq!(exprs[..2], match_nfproto(NFPROTO_IPV4)[..]);
+        assert_eq!(
+            exprs[2],
+            Expr::Payload {
+                base: NFT_PAYLOAD_NETWORK_HEADER,
+                offset: 16,
+                len: 4
+            }
+        );
+        assert_eq!(
+            exprs[3],
+            Expr::Bitwise {
+                mask: vec![255, 255, 252, 0],
+                xor: vec![0; 4]
+            }
+        );
+        assert_eq!(
+            exprs[4],
+            Expr::Cmp {
+                op: NFT_CMP_EQ,
+                data: vec![10, 166, 0, 0]
+            }
+        );
+
+        // a host address needs no mask
+        let exprs = match_daddr("fd00:ec2::254".parse().unwrap(), 128);
+        assert_eq!(exprs.len(), 4);
+        assert_eq!(
+            exprs[2],
+            Expr::Payload {
+                base: NFT_PAYLOAD_NETWORK_HEADER,
+                offset: 24,
+                len: 16
+            }
+        );
+    }
+
+    /// Same setup as the rtnetlink test, the work happens in a forked child in a fresh user and network namespace
+    #[test]
+    fn test_owned_table_in_new_netns() {
+        let pid = unsafe { libc::fork() };
+        assert!(pid >= 0);
+        if pid == 0 {
+            if unsafe { libc::unshare(libc::CLONE_NEWUSER | libc::CLONE_NEWNET) } != 0 {
+                unsafe { libc::_exit(77) };
+            }
+            let code = match filter_egress() {
+                Ok(()) => 0,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    1
+                }
+            };
+            unsafe { libc::_exit(code) };
+        }
+
+        let mut status = 0;
+        assert_eq!(unsafe { libc::waitpid(pid, &mut status, 0) }, pid);
+        let code = libc::WEXITSTATUS(status);
+        if code == 77 {
+            eprintln!("skipped: unprivileged user namespaces are not available");
+            return;
+        }
+        assert_eq!(code, 0);
+    }
+
+    fn send_to(addr: &str) -> std::io::Result<usize> {
+        UdpSocket::bind("0.0.0.0:0")?.send_to(b"x", addr)
+    }
+
+    fn filter_egress() -> std::result::Result<(), String> {
+        let mut nl = RtNetlink::connect().map_err(|e| e.to_string())?;
+        nl.set_link_up("lo").map_err(|e| e.to_string())?;
+        nl.add_veth("tveth0", "tveth1", None, None)
+            .map_err(|e| e.to_string())?;
+        nl.set_link_up("tveth0").map_err(|e| e.to_string())?;
+        nl.add_address("tveth0", "10.166.0.2".parse().unwrap(), 16)
+            .map_err(|e| e.to_string())?;
+
+        let mut owner = NfTables::connect().map_err(|e| e.to_string())?;
+        let mut batch = Batch::new();
+        batch
+            .add_table(NFPROTO_INET, "jail", true)
+            .add_filter_chain(
+                NFPROTO_INET,
+                "jail",
+                "output",
+                NF_INET_LOCAL_OUT,
+                0,
+                NF_DROP,
+            );
+        let mut rule = match_l4proto(libc::IPPROTO_UDP as u8);
+        rule.extend(match_daddr("10.166.1.0".parse().unwrap(), 24));
+        rule.extend(match_dport(53, 54));
+        rule.push(Expr::Verdict(NF_ACCEPT));
+        batch.add_rule(NFPROTO_INET, "jail", "output", &rule);
+        let mut rule = match_ct_established();
+        rule.push(Expr::Verdict(NF_ACCEPT));
+        batch.add_rule(NFPROTO_INET, "jail", "output", &rule);
+        owner.apply(batch).map_err(|e| e.to_string())?;
+
+        send_to("10.166.1.7:54").map_err(|e| format!("allowed: {}", e))?;
+        match send_to("10.166.1.7:55") {
+            Err(ref e) if e.raw_os_error() == Some(libc::EPERM) => {}
+            res => return Err(format!("port 55 must be dropped, got {:?}", res)),
+        }
+        match send_to("10.166.2.7:53") {
+            Err(ref e) if e.raw_os_error() == Some(libc::EPERM) => {}
+            res => return Err(format!("10.166.2.7 must be dropped, got {:?}", res)),
+        }
+
+        // a failed batch changes nothing
+        let mut batch = Batch::new();
+        batch
+            .add_filter_chain(NFPROTO_INET, "jail", "input", NF_INET_LOCAL_IN, 0, NF_DROP)
+            .add_rule(NFPROTO_INET, "jail", "nope", &[Expr::Verdict(NF_ACCEPT)]);
+        match owner.apply(batch) {
+            Err(Error::Kernel("NEWRULE", Errno::ENOENT)) => {}
+            res => return Err(format!("expected ENOENT for the rule, got {:?}", res)),
+        }
+
+        // even with CAP_NET_ADMIN, another socket cannot remove the table
+        let mut other = NfTables::connect().map_err(|e| e.to_string())?;
+        let mut batch = Batch::new();
+        batch.delete_table(NFPROTO_INET, "jail");
+        match other.apply(batch) {
+            Err(Error::Kernel("DELTABLE", Errno::EPERM)) => {}
+            res => return Err(format!("expected EPERM, got {:?}", res)),
+        }
+
+        // the table goes away with its owner
+        drop(owner);
+        send_to("10.166.2.7:53").map_err(|e| format!("after close: {}", e))?;
+        Ok(())
+    }
+}
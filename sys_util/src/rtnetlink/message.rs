@@ -0,0 +1,262 @@
+// Wire format of the rtnetlink messages we send, values from <linux/netlink.h>, <linux/rtnetlink.h>,
+// <linux/if_link.h>, <linux/if_addr.h> and <linux/veth.h>. They are part of the kernel ABI so they never change,
+// they are defined here since not all of them are in the libc versions we support.
+
+use std::mem;
+use std::slice;
+
+pub const NLMSG_ALIGNTO: usize = 4;
+pub const NLMSG_HDRLEN: usize = 16;
+
+pub const NLMSG_ERROR: u16 = 2;
+pub const NLMSG_DONE: u16 = 3;
+
+pub const NLM_F_REQUEST: u16 = 0x1;
+pub const NLM_F_ACK: u16 = 0x4;
+pub const NLM_F_EXCL: u16 = 0x200;
+pub const NLM_F_CREATE: u16 = 0x400;
+
+pub const RTM_NEWLINK: u16 = 16;
+pub const RTM_DELLINK: u16 = 17;
+pub const RTM_GETLINK: u16 = 18;
+pub const RTM_SETLINK: u16 = 19;
+pub const RTM_NEWADDR: u16 = 20;
+pub const RTM_NEWROUTE: u16 = 24;
+
+pub const IFLA_ADDRESS: u16 = 1;
+pub const IFLA_IFNAME: u16 = 3;
+pub const IFLA_LINK: u16 = 5;
+pub const IFLA_MASTER: u16 = 10;
+pub const IFLA_LINKINFO: u16 = 18;
+pub const IFLA_NET_NS_PID: u16 = 19;
+pub const IFLA_NET_NS_FD: u16 = 28;
+
+pub const IFLA_INFO_KIND: u16 = 1;
+pub const IFLA_INFO_DATA: u16 = 2;
+
+pub const VETH_INFO_PEER: u16 = 1;
+
+pub const IFA_ADDRESS: u16 = 1;
+pub const IFA_LOCAL: u16 = 2;
+pub const IFA_BROADCAST: u16 = 4;
+
+pub const IFA_F_NODAD: u8 = 0x02;
+
+pub const RTA_DST: u16 = 1;
+pub const RTA_OIF: u16 = 4;
+pub const RTA_GATEWAY: u16 = 5;
+
+pub const RT_TABLE_MAIN: u8 = 254;
+pub const RTPROT_BOOT: u8 = 3;
+pub const RT_SCOPE_UNIVERSE: u8 = 0;
+pub const RT_SCOPE_LINK: u8 = 253;
+pub const RTN_UNICAST: u8 = 1;
+
+pub const IFF_UP: u32 = 0x1;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct IfInfoMsg {
+    pub family: u8,
+    pub pad: u8,
+    pub kind: u16,
+    pub index: i32,
+    pub flags: u32,
+    pub change: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct IfAddrMsg {
+    pub family: u8,
+    pub prefix_len: u8,
+    pub flags: u8,
+    pub scope: u8,
+    pub index: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RtMsg {
+    pub family: u8,
+    pub dst_len: u8,
+    pub src_len: u8,
+    pub tos: u8,
+    pub table: u8,
+    pub protocol: u8,
+    pub scope: u8,
+    pub kind: u8,
+    pub flags: u32,
+}
+
+pub fn align(len: usize) -> usize {
+    (len + NLMSG_ALIGNTO - 1) & !(NLMSG_ALIGNTO - 1)
+}
+
+/// A netlink request under construction: a nlmsghdr, the family specific header then route attributes
+pub struct Message {
+    buf: Vec<u8>,
+}
+
+impl Message {
+    pub fn new(kind: u16, flags: u16) -> Message {
+        let mut buf = Vec::with_capacity(256);
+        buf.extend_from_slice(&0u32.to_ne_bytes()); // length, set by finish
+        buf.extend_from_slice(&kind.to_ne_bytes());
+        buf.extend_from_slice(&(flags | NLM_F_REQUEST).to_ne_bytes());
+        buf.extend_from_slice(&0u32.to_ne_bytes()); // sequence number, set by finish
+        buf.extend_from_slice(&0u32.to_ne_bytes()); // port id, 0 is the kernel
+        Message { buf }
+    }
+
+    /// Appends one of the repr(C) family headers above
+    pub fn header<T: Copy>(&mut self, hdr: &T) -> &mut Self {
+        let bytes =
+            unsafe { slice::from_raw_parts(hdr as *const T as *const u8, mem::size_of::<T>()) };
+        self.buf.extend_from_slice(bytes);
+        self.pad();
+        self
+    }
+
+    pub fn attr(&mut self, kind: u16, data: &[u8]) -> &mut Self {
+        self.buf
+            .extend_from_slice(&((4 + data.len()) as u16).to_ne_bytes());
+        self.buf.extend_from_slice(&kind.to_ne_bytes());
+        self.buf.extend_from_slice(data);
+        self.pad();
+        self
+    }
+
+    pub fn attr_u32(&mut self, kind: u16, value: u32) -> &mut Self {
+        self.attr(kind, &value.to_ne_bytes())
+    }
+
+    /// Nul terminated, like the kernel expects for IFLA_IFNAME and IFLA_INFO_KIND
+    pub fn attr_str(&mut self, kind: u16, value: &str) -> &mut Self {
+        let mut data = Vec::with_capacity(value.len() + 1);
+        data.extend_from_slice(value.as_bytes());
+        data.push(0);
+        self.attr(kind, &data)
+    }
+
+    /// Opens a nested attribute, every attribute added until the matching nest_end goes inside it
+    pub fn nest_start(&mut self, kind: u16) -> usize {
+        let start = self.buf.len();
+        self.attr(kind, &[]);
+        start
+    }
+
+    pub fn nest_end(&mut self, start: usize) -> &mut Self {
+        let len = (self.buf.len() - start) as u16;
+        self.buf[start..start + 2].copy_from_slice(&len.to_ne_bytes());
+        self
+    }
+
+    /// Sets the length and sequence number, extra_flags are or-ed to the ones given to new
+    pub fn finish(mut self, seq: u32, extra_flags: u16) -> Vec<u8> {
+        let len = self.buf.len() as u32;
+        self.buf[0..4].copy_from_slice(&len.to_ne_bytes());
+        let flags = read_u16(&self.buf, 6).unwrap_or(0) | extra_flags;
+        self.buf[6..8].copy_from_slice(&flags.to_ne_bytes());
+        self.buf[8..12].copy_from_slice(&seq.to_ne_bytes());
+        self.buf
+    }
+
+    fn pad(&mut self) {
+        let len = align(self.buf.len());
+        self.buf.resize(len, 0);
+    }
+}
+
+/// A message received from the kernel, payload excludes the nlmsghdr
+pub struct Reply<'a> {
+    pub kind: u16,
+    pub seq: u32,
+    pub payload: &'a [u8],
+}
+
+/// Splits a datagram into its netlink messages, None if it is truncated or malformed
+pub fn parse_replies(mut buf: &[u8]) -> Option<Vec<Reply<'_>>> {
+    let mut replies = Vec::new();
+    while buf.len() >= NLMSG_HDRLEN {
+        let len = read_u32(buf, 0)? as usize;
+        if len < NLMSG_HDRLEN || len > buf.len() {
+            return None;
+        }
+        replies.push(Reply {
+            kind: read_u16(buf, 4)?,
+            seq: read_u32(buf, 8)?,
+            payload: &buf[NLMSG_HDRLEN..len],
+        });
+        buf = &buf[align(len).min(buf.len())..];
+    }
+    Some(replies)
+}
+
+pub fn read_u16(buf: &[u8], off: usize) -> Option<u16> {
+    let mut b = [0u8; 2];
+    b.copy_from_slice(buf.get(off..off + 2)?);
+    Some(u16::from_ne_bytes(b))
+}
+
+pub fn read_u32(buf: &[u8], off: usize) -> Option<u32> {
+    let mut b = [0u8; 4];
+    b.copy_from_slice(buf.get(off..off + 4)?);
+    Some(u32::from_ne_bytes(b))
+}
+
+pub fn read_i32(buf: &[u8], off: usize) -> Option<i32> {
+    read_u32(buf, off).map(|v| v as i32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message_layout() {
+        let mut msg = Message::new(RTM_NEWLINK, NLM_F_CREATE);
+        msg.header(&IfInfoMsg::default())
+            .attr_str(IFLA_IFNAME, "vs_0");
+        let linkinfo = msg.nest_start(IFLA_LINKINFO);
+        msg.attr_str(IFLA_INFO_KIND, "veth");
+        msg.nest_end(linkinfo);
+        let buf = msg.finish(7, NLM_F_ACK);
+
+        // 16 nlmsghdr + 16 ifinfomsg + 12 ifname ("vs_0\0" padded) + 4 linkinfo + 12 kind ("veth\0" padded)
+        assert_eq!(buf.len(), 60);
+        assert_eq!(read_u32(&buf, 0), Some(60));
+        assert_eq!(read_u16(&buf, 4), Some(RTM_NEWLINK));
+        assert_eq!(
+            read_u16(&buf, 6),
+            Some(NLM_F_CREATE | NLM_F_REQUEST | NLM_F_ACK)
+        );
+        assert_eq!(read_u32(&buf, 8), Some(7));
+
+        assert_eq!(read_u16(&buf, 32), Some(9));
+        assert_eq!(read_u16(&buf, 34), Some(IFLA_IFNAME));
+        assert_eq!(&buf[36..41], b"vs_0\0");
+
+        assert_eq!(read_u16(&buf, 44), Some(16));
+        assert_eq!(read_u16(&buf, 46), Some(IFLA_LINKINFO));
+        assert_eq!(read_u16(&buf, 48), Some(9));
+        assert_eq!(read_u16(&buf, 50), Some(IFLA_INFO_KIND));
+    }
+
+    #[test]
+    fn test_parse_replies() {
+        let mut ack = Message::new(NLMSG_ERROR, 0);
+        ack.attr(0, &[]);
+        let ack = ack.finish(3, 0);
+        let mut both = ack.clone();
+        both.extend_from_slice(&ack);
+
+        let replies = parse_replies(&both).unwrap();
+        assert_eq!(replies.len(), 2);
+        assert_eq!(replies[1].kind, NLMSG_ERROR);
+        assert_eq!(replies[1].seq, 3);
+        assert_eq!(replies[1].payload.len(), 4);
+
+        assert!(parse_replies(&both[..both.len() - 2]).is_none());
+    }
+}
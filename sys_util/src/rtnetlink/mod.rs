@@ -0,0 +1,568 @@
+//! Minimal rtnetlink client, enough to set up the network namespace of a jail: create macvlan, veth and bridge
+//! links, attach links to a bridge, move links into another network namespace, add addresses and routes and bring links up.
+//!
+//! Every request is sent with NLM_F_ACK and blocks until the kernel acknowledges it, see
+//! http://man7.org/linux/man-pages/man7/rtnetlink.7.html
+
+pub(crate) mod message;
+
+use self::message::*;
+use super::errno::Errno;
+
+use std::fmt;
+use std::mem;
+use std::net::{IpAddr, Ipv4Addr};
+use std::os::unix::io::RawFd;
+
+const RECV_BUF_SIZE: usize = 32 * 1024;
+
+#[derive(Debug)]
+pub enum Error {
+    Socket(Errno),
+    Send(Errno),
+    Recv(Errno),
+    /// the kernel refused the request, e.g. EEXIST when a link of the same name already exists
+    Kernel(&'static str, Errno),
+    NoSuchLink(String),
+    InvalidName(String),
+    InvalidPrefix(IpAddr, u8),
+    Malformed(&'static str),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Socket(ref e) => write!(f, "rtnetlink socket: {}", e),
+            Error::Send(ref e) => write!(f, "rtnetlink send: {}", e),
+            Error::Recv(ref e) => write!(f, "rtnetlink recv: {}", e),
+            Error::Kernel(op, ref e) => write!(f, "rtnetlink {}: {}", op, e),
+            Error::NoSuchLink(ref name) => {
+                write!(f, "rtnetlink: no network interface named {:?}", name)
+            }
+            Error::InvalidName(ref name) => {
+                write!(f, "rtnetlink: invalid interface name {:?}", name)
+            }
+            Error::InvalidPrefix(ref addr, len) => {
+                write!(f, "rtnetlink: invalid prefix length {} for {}", len, addr)
+            }
+            Error::Malformed(what) => write!(f, "rtnetlink: malformed reply: {}", what),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Network namespace a link is moved into, either the one of a process or one opened from /proc/<pid>/ns/net
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NetNs {
+    Pid(libc::pid_t),
+    Fd(RawFd),
+}
+
+impl NetNs {
+    fn attr(self, msg: &mut Message) {
+        match self {
+            NetNs::Pid(pid) => msg.attr_u32(IFLA_NET_NS_PID, pid as u32),
+            NetNs::Fd(fd) => msg.attr_u32(IFLA_NET_NS_FD, fd as u32),
+        };
+    }
+}
+
+/// A NETLINK_ROUTE socket bound to the network namespace of the calling thread at creation time
+pub struct RtNetlink {
+    fd: RawFd,
+    seq: u32,
+}
+
+impl Drop for RtNetlink {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd) };
+    }
+}
+
+impl RtNetlink {
+    pub fn connect() -> Result<RtNetlink> {
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+                libc::NETLINK_ROUTE,
+            )
+        };
+        if fd < 0 {
+            return Err(Error::Socket(Errno::last()));
+        }
+        let nl = RtNetlink { fd, seq: 0 };
+
+        let mut addr: libc::sockaddr_nl = unsafe { mem::zeroed() };
+        addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        if unsafe {
+            libc::bind(
+                fd,
+                &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            )
+        } < 0
+        {
+            return Err(Error::Socket(Errno::last()));
+        }
+
+        Ok(nl)
+    }
+
+    /// Interface index of a link in the network namespace of the socket
+    pub fn link_index(&mut self, name: &str) -> Result<u32> {
+        check_name(name)?;
+        let mut msg = Message::new(RTM_GETLINK, 0);
+        msg.header(&IfInfoMsg::default())
+            .attr_str(IFLA_IFNAME, name);
+
+        let reply = match self.request("RTM_GETLINK", msg) {
+            Err(Error::Kernel(_, Errno::ENODEV)) => return Err(Error::NoSuchLink(name.to_owned())),
+            res => res?,
+        };
+        match reply.and_then(|payload| read_i32(&payload, 4)) {
+            Some(index) if index > 0 => Ok(index as u32),
+            _ => Err(Error::Malformed(
+                "RTM_GETLINK reply without interface index",
+            )),
+        }
+    }
+
+    /// Creates a macvlan link on top of parent, directly in ns if given. The kernel picks a random MAC address if none is given
+    pub fn add_macvlan(
+        &mut self,
+        name: &str,
+        parent: &str,
+        mac: Option<[u8; 6]>,
+        ns: Option<NetNs>,
+    ) -> Result<()> {
+        check_name(name)?;
+        let parent_index = self.link_index(parent)?;
+
+        let mut msg = Message::new(RTM_NEWLINK, NLM_F_CREATE | NLM_F_EXCL);
+        msg.header(&IfInfoMsg::default())
+            .attr_str(IFLA_IFNAME, name)
+            .attr_u32(IFLA_LINK, parent_index);
+        if let Some(mac) = mac {
+            msg.attr(IFLA_ADDRESS, &mac);
+        }
+        if let Some(ns) = ns {
+            ns.attr(&mut msg);
+        }
+        let linkinfo = msg.nest_start(IFLA_LINKINFO);
+        msg.attr_str(IFLA_INFO_KIND, "macvlan");
+        msg.nest_end(linkinfo);
+
+        self.request("RTM_NEWLINK macvlan", msg).map(|_| ())
+    }
+
+    /**
+     * Creates a veth pair, name stays in the namespace of the socket and peer goes in peer_ns if given.
+     * The kernel picks a random MAC address for peer if peer_mac is not given
+     */
+    pub fn add_veth(
+        &mut self,
+        name: &str,
+        peer: &str,
+        peer_mac: Option<[u8; 6]>,
+        peer_ns: Option<NetNs>,
+    ) -> Result<()> {
+        check_name(name)?;
+        check_name(peer)?;
+
+        let mut msg = Message::new(RTM_NEWLINK, NLM_F_CREATE | NLM_F_EXCL);
+        msg.header(&IfInfoMsg::default())
+            .attr_str(IFLA_IFNAME, name);
+        let linkinfo = msg.nest_start(IFLA_LINKINFO);
+        msg.attr_str(IFLA_INFO_KIND, "veth");
+        let data = msg.nest_start(IFLA_INFO_DATA);
+        let peer_info = msg.nest_start(VETH_INFO_PEER);
+        msg.header(&IfInfoMsg::default())
+            .attr_str(IFLA_IFNAME, peer);
+        if let Some(mac) = peer_mac {
+            msg.attr(IFLA_ADDRESS, &mac);
+        }
+        if let Some(ns) = peer_ns {
+            ns.attr(&mut msg);
+        }
+        msg.nest_end(peer_info);
+        msg.nest_end(data);
+        msg.nest_end(linkinfo);
+
+        self.request("RTM_NEWLINK veth", msg).map(|_| ())
+    }
+
+    pub fn add_bridge(&mut self, name: &str) -> Result<()> {
+        check_name(name)?;
+
+        let mut msg = Message::new(RTM_NEWLINK, NLM_F_CREATE | NLM_F_EXCL);
+        msg.header(&IfInfoMsg::default())
+            .attr_str(IFLA_IFNAME, name);
+        let linkinfo = msg.nest_start(IFLA_LINKINFO);
+        msg.attr_str(IFLA_INFO_KIND, "bridge");
+        msg.nest_end(linkinfo);
+
+        self.request("RTM_NEWLINK bridge", msg).map(|_| ())
+    }
+
+    /// Enslaves link name to master, e.g. attaches a veth end to a bridge
+    pub fn set_link_master(&mut self, name: &str, master: &str) -> Result<()> {
+        let master_index = self.link_index(master)?;
+        let index = self.link_index(name)?;
+        let mut msg = Message::new(RTM_SETLINK, 0);
+        msg.header(&IfInfoMsg {
+            index: index as i32,
+            ..Default::default()
+        });
+        msg.attr_u32(IFLA_MASTER, master_index);
+        self.request("RTM_SETLINK master", msg).map(|_| ())
+    }
+
+    pub fn delete_link(&mut self, name: &str) -> Result<()> {
+        let index = self.link_index(name)?;
+        let mut msg = Message::new(RTM_DELLINK, 0);
+        msg.header(&IfInfoMsg {
+            index: index as i32,
+            ..Default::default()
+        });
+        self.request("RTM_DELLINK", msg).map(|_| ())
+    }
+
+    /// Moves an existing link into another network namespace, it keeps its name
+    pub fn set_link_ns(&mut self, name: &str, ns: NetNs) -> Result<()> {
+        let index = self.link_index(name)?;
+        let mut msg = Message::new(RTM_SETLINK, 0);
+        msg.header(&IfInfoMsg {
+            index: index as i32,
+            ..Default::default()
+        });
+        ns.attr(&mut msg);
+        self.request("RTM_SETLINK netns", msg).map(|_| ())
+    }
+
+    pub fn set_link_up(&mut self, name: &str) -> Result<()> {
+        let index = self.link_index(name)?;
+        let mut msg = Message::new(RTM_SETLINK, 0);
+        msg.header(&IfInfoMsg {
+            index: index as i32,
+            flags: IFF_UP,
+            change: IFF_UP,
+            ..Default::default()
+        });
+        self.request("RTM_SETLINK up", msg).map(|_| ())
+    }
+
+    /**
+     * Adds addr/prefix_len to a link, IPv4 addresses also get the broadcast address of their subnet.
+     * IPv6 addresses skip duplicate address detection, they would otherwise stay unusable for a second or so after
+     * the link is up, the caller is in charge of handing out unique addresses
+     */
+    pub fn add_address(&mut self, name: &str, addr: IpAddr, prefix_len: u8) -> Result<()> {
+        check_prefix(addr, prefix_len)?;
+        let index = self.link_index(name)?;
+
+        let mut msg = Message::new(RTM_NEWADDR, NLM_F_CREATE | NLM_F_EXCL);
+        msg.header(&IfAddrMsg {
+            family: family(addr),
+            prefix_len,
+            flags: if addr.is_ipv6() { IFA_F_NODAD } else { 0 },
+            index,
+            ..Default::default()
+        });
+        let bytes = addr_bytes(addr);
+        msg.attr(IFA_LOCAL, &bytes).attr(IFA_ADDRESS, &bytes);
+        if let IpAddr::V4(v4) = addr {
+            if prefix_len < 31 {
+                let host_mask = u32::MAX >> prefix_len;
+                let brd = Ipv4Addr::from(u32::from(v4) | host_mask);
+                msg.attr(IFA_BROADCAST, &brd.octets());
+            }
+        }
+
+        self.request("RTM_NEWADDR", msg).map(|_| ())
+    }
+
+    /**
+     * Adds a route to dst/dst_len through link name, via gateway if given, otherwise dst is considered directly
+     * reachable on the link. A dst_len of 0 makes it a default route.
+     */
+    pub fn add_route(
+        &mut self,
+        name: &str,
+        dst: IpAddr,
+        dst_len: u8,
+        gateway: Option<IpAddr>,
+    ) -> Result<()> {
+        check_prefix(dst, dst_len)?;
+        if let Some(gw) = gateway {
+            if family(gw) != family(dst) {
+                return Err(Error::InvalidPrefix(gw, dst_len));
+            }
+        }
+        let index = self.link_index(name)?;
+
+        let mut msg = Message::new(RTM_NEWROUTE, NLM_F_CREATE | NLM_F_EXCL);
+        msg.header(&RtMsg {
+            family: family(dst),
+            dst_len,
+            table: RT_TABLE_MAIN,
+            protocol: RTPROT_BOOT,
+            scope: if gateway.is_some() {
+                RT_SCOPE_UNIVERSE
+            } else {
+                RT_SCOPE_LINK
+            },
+            kind: RTN_UNICAST,
+            ..Default::default()
+        });
+        if dst_len > 0 {
+            msg.attr(RTA_DST, &addr_bytes(dst));
+        }
+        if let Some(gw) = gateway {
+            msg.attr(RTA_GATEWAY, &addr_bytes(gw));
+        }
+        msg.attr_u32(RTA_OIF, index);
+
+        self.request("RTM_NEWROUTE", msg).map(|_| ())
+    }
+
+    pub fn add_default_route(&mut self, name: &str, gateway: IpAddr) -> Result<()> {
+        let any = match gateway {
+            IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            IpAddr::V6(_) => IpAddr::V6(std::net::Ipv6Addr::UNSPECIFIED),
+        };
+        self.add_route(name, any, 0, Some(gateway))
+    }
+
+    /// Sends msg and waits for its acknowledgment, returns the payload of the first non ack reply if any
+    fn request(&mut self, op: &'static str, msg: Message) -> Result<Option<Vec<u8>>> {
+        self.seq = self.seq.wrapping_add(1);
+        let seq = self.seq;
+        let buf = msg.finish(seq, NLM_F_ACK);
+
+        loop {
+            let sent =
+                unsafe { libc::send(self.fd, buf.as_ptr() as *const libc::c_void, buf.len(), 0) };
+            if sent >= 0 {
+                break;
+            }
+            if Errno::last() != Errno::EINTR {
+                return Err(Error::Send(Errno::last()));
+            }
+        }
+
+        let mut data = None;
+        let mut recv_buf = vec![0u8; RECV_BUF_SIZE];
+        loop {
+            let n = unsafe {
+                libc::recv(
+                    self.fd,
+                    recv_buf.as_mut_ptr() as *mut libc::c_void,
+                    recv_buf.len(),
+                    0,
+                )
+            };
+            if n < 0 {
+                if Errno::last() == Errno::EINTR {
+                    continue;
+                }
+                return Err(Error::Recv(Errno::last()));
+            }
+
+            let replies = parse_replies(&recv_buf[..n as usize])
+                .ok_or(Error::Malformed("truncated netlink message"))?;
+            for reply in replies {
+                if reply.seq != seq {
+                    continue;
+                }
+                match reply.kind {
+                    NLMSG_ERROR => {
+                        let code = read_i32(reply.payload, 0)
+                            .ok_or(Error::Malformed("short NLMSG_ERROR"))?;
+                        if code == 0 {
+                            return Ok(data);
+                        }
+                        return Err(Error::Kernel(op, Errno::from_i32(-code)));
+                    }
+                    NLMSG_DONE => return Ok(data),
+                    _ => {
+                        if data.is_none() {
+                            data = Some(reply.payload.to_vec());
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Prefix length of a contiguous netmask, e.g. 24 for 255.255.255.0
+pub fn prefix_len(netmask: IpAddr) -> Option<u8> {
+    let (bits, width) = match netmask {
+        IpAddr::V4(m) => ((u32::from(m) as u128) << 96, 32),
+        IpAddr::V6(m) => (u128::from(m), 128),
+    };
+    let ones = bits.leading_ones();
+    if ones < 128 && bits << ones != 0 {
+        return None;
+    }
+    Some(ones.min(width) as u8)
+}
+
+/// Parses a MAC address written as 6 hex bytes separated by ':', e.g. "ba:ad:ba:be:45:00"
+pub fn parse_mac(s: &str) -> Option<[u8; 6]> {
+    let mut mac = [0u8; 6];
+    let mut parts = s.split(':');
+    for byte in mac.iter_mut() {
+        let part = parts.next()?;
+        if part.len() != 2 {
+            return None;
+        }
+        *byte = u8::from_str_radix(part, 16).ok()?;
+    }
+    if parts.next().is_some() {
+        return None;
+    }
+    Some(mac)
+}
+
+fn check_name(name: &str) -> Result<()> {
+    if name.is_empty()
+        || name.len() >= libc::IFNAMSIZ
+        || name
+            .bytes()
+            .any(|b| b == b'/' || b == 0 || b.is_ascii_whitespace())
+    {
+        return Err(Error::InvalidName(name.to_owned()));
+    }
+    Ok(())
+}
+
+fn check_prefix(addr: IpAddr, len: u8) -> Result<()> {
+    let max = match addr {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    };
+    if len > max {
+        return Err(Error::InvalidPrefix(addr, len));
+    }
+    Ok(())
+}
+
+fn family(addr: IpAddr) -> u8 {
+    match addr {
+        IpAddr::V4(_) => libc::AF_INET as u8,
+        IpAddr::V6(_) => libc::AF_INET6 as u8,
+    }
+}
+
+fn addr_bytes(addr: IpAddr) -> Vec<u8> {
+    match addr {
+        IpAddr::V4(a) => a.octets().to_vec(),
+        IpAddr::V6(a) => a.octets().to_vec(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv6Addr;
+
+    #[test]
+    fn test_prefix_len_and_mac() {
+        assert_eq!(prefix_len("255.255.255.0".parse().unwrap()), Some(24));
+        assert_eq!(prefix_len("255.255.0.0".parse().unwrap()), Some(16));
+        assert_eq!(prefix_len("255.255.255.255".parse().unwrap()), Some(32));
+        assert_eq!(prefix_len("0.0.0.0".parse().unwrap()), Some(0));
+        assert_eq!(prefix_len("255.0.255.0".parse().unwrap()), None);
+        assert_eq!(
+            prefix_len("ffff:ffff:ffff:ffff::".parse().unwrap()),
+            Some(64)
+        );
+
+        assert_eq!(
+            parse_mac("ba:ad:ba:be:45:00"),
+            Some([0xba, 0xad, 0xba, 0xbe, 0x45, 0x00])
+        );
+        assert_eq!(parse_mac("ba:ad:ba:be:45"), None);
+        assert_eq!(parse_mac("ba:ad:ba:be:45:00:01"), None);
+        assert_eq!(parse_mac("ba:ad:ba:be:45:0g"), None);
+    }
+
+    /**
+     * Runs against the kernel in a fresh user and network namespace, so it needs unprivileged user namespaces.
+     * The work happens in a forked child since a multithreaded process cannot unshare its user namespace.
+     */
+    #[test]
+    fn test_configure_veth_in_new_netns() {
+        let pid = unsafe { libc::fork() };
+        assert!(pid >= 0);
+        if pid == 0 {
+            if unsafe { libc::unshare(libc::CLONE_NEWUSER | libc::CLONE_NEWNET) } != 0 {
+                unsafe { libc::_exit(77) };
+            }
+            let code = match configure_veth() {
+                Ok(()) => 0,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    1
+                }
+            };
+            unsafe { libc::_exit(code) };
+        }
+
+        let mut status = 0;
+        assert_eq!(unsafe { libc::waitpid(pid, &mut status, 0) }, pid);
+        let code = libc::WEXITSTATUS(status);
+        if code == 77 {
+            eprintln!("skipped: unprivileged user namespaces are not available");
+            return;
+        }
+        assert_eq!(code, 0);
+    }
+
+    fn configure_veth() -> Result<()> {
+        let mut nl = RtNetlink::connect()?;
+        nl.set_link_up("lo")?;
+        match nl.link_index("nope0") {
+            Err(Error::NoSuchLink(_)) => {}
+            _ => return Err(Error::Malformed("expected NoSuchLink")),
+        }
+
+        nl.add_veth("tveth0", "tveth1", None, None)?;
+        match nl.add_veth("tveth0", "tveth2", None, None) {
+            Err(Error::Kernel(_, Errno::EEXIST)) => {}
+            _ => return Err(Error::Malformed("expected EEXIST")),
+        }
+        nl.set_link_up("tveth0")?;
+        nl.set_link_up("tveth1")?;
+        nl.add_address("tveth0", "10.166.0.2".parse().unwrap(), 16)?;
+        nl.add_address("tveth0", "fd00::2".parse().unwrap(), 64)?;
+        nl.add_default_route("tveth0", "10.166.0.1".parse().unwrap())?;
+        nl.add_route(
+            "tveth0",
+            IpAddr::V6("fd01::".parse::<Ipv6Addr>().unwrap()),
+            64,
+            None,
+        )?;
+
+        nl.add_bridge("tbr0")?;
+        nl.add_veth("tveth2", "tveth3", parse_mac("ba:ad:ba:be:45:00"), None)?;
+        nl.set_link_master("tveth2", "tbr0")?;
+        match nl.set_link_master("tveth3", "nope0") {
+            Err(Error::NoSuchLink(_)) => {}
+            _ => return Err(Error::Malformed("expected NoSuchLink for the master")),
+        }
+        nl.delete_link("tbr0")?;
+        nl.delete_link("tveth3")?;
+
+        nl.delete_link("tveth1")?;
+        match nl.link_index("tveth0") {
+            Err(Error::NoSuchLink(_)) => Ok(()),
+            _ => Err(Error::Malformed("deleting a veth end must delete its peer")),
+        }
+    }
+}
@@ -1,10 +1,6 @@
 use std::mem;
 use super::errno::Errno;
 
-
-// TODO: see how to implement all calls from nsjail:cpu.cc which is not the case right now, (for example libc lacks CPU_ALLOC and CPU_ALLOC_SIZE)
-// For now it is implemented through cpp_bindings package
-
 pub fn unshare(flags: libc::c_int) -> Result<(), Errno> {
     let res = unsafe { libc::unshare(flags) };
 
@@ -182,3 +182,46 @@ fn pack_mhdr_to_send<'a>(
 
     mhdr
 }
+
+/// Receives data and at most one file descriptor sent with sendmsg, the descriptor is close on exec
+pub fn recvmsg_fd(fd: i32, buf: &mut [u8]) -> Result<(usize, Option<RawFd>), Errno> {
+    let mut iov = [IoVec::from_mut_slice(buf)];
+    let capacity = unsafe { libc::CMSG_SPACE(mem::size_of::<RawFd>() as libc::c_uint) as usize };
+    let mut cmsg_buffer = vec![0u8; capacity];
+
+    let mut mhdr = unsafe {
+        let mut mhdr = mem::MaybeUninit::<libc::msghdr>::zeroed();
+        let p = mhdr.as_mut_ptr();
+        (*p).msg_iov = iov.as_mut_ptr() as *mut _;
+        (*p).msg_iovlen = iov.len() as _;
+        (*p).msg_control = cmsg_buffer.as_mut_ptr() as *mut libc::c_void;
+        (*p).msg_controllen = capacity as _;
+        mhdr.assume_init()
+    };
+
+    let n = Errno::result(unsafe { libc::recvmsg(fd, &mut mhdr, libc::MSG_CMSG_CLOEXEC) })?;
+
+    let mut received = None;
+    let mut pmhdr: *mut libc::cmsghdr = unsafe { libc::CMSG_FIRSTHDR(&mhdr) };
+    while !pmhdr.is_null() {
+        unsafe {
+            if (*pmhdr).cmsg_level == libc::SOL_SOCKET && (*pmhdr).cmsg_type == libc::SCM_RIGHTS {
+                let data = libc::CMSG_DATA(pmhdr) as *const RawFd;
+                let count = ((*pmhdr).cmsg_len as usize - (data as usize - pmhdr as usize))
+                    / mem::size_of::<RawFd>();
+                for i in 0..count {
+                    let fd = ptr::read_unaligned(data.add(i));
+                    // only one is expected, more would leak
+                    if received.is_none() {
+                        received = Some(fd);
+                    } else {
+                        libc::close(fd);
+                    }
+                }
+            }
+            pmhdr = libc::CMSG_NXTHDR(&mhdr, pmhdr);
+        }
+    }
+
+    Ok((n as usize, received))
+}